@@ -0,0 +1,104 @@
+extern crate clap;
+extern crate rust_nes;
+
+use clap::Clap;
+use rust_nes::cartridge::read_rom_file;
+use rust_nes::disassembler::{Disassembler, Instruction, VECTORS};
+use std::fs;
+
+#[derive(Clap)]
+#[clap(version = "1.0", author = "David Tyler <davet.code@gmail.com>")]
+struct Opts {
+    rom_file: String,
+    /// The size (in KB) of the PRG ROM banks the mapper switches
+    #[clap(short = 'b', long = "bank_size", default_value = "16", possible_values = &["8", "16", "32"])]
+    bank_size: usize,
+    /// Only dump this bank
+    #[clap(short = 'n', long = "bank")]
+    bank: Option<usize>,
+    /// A VICE label file (as written by ld65 -Ln) to name addresses with
+    #[clap(short = 's', long = "symbols")]
+    symbols: Option<String>,
+}
+
+fn print_instruction(disassembler: &Disassembler, instruction: &Instruction) {
+    if let Some(symbol) = disassembler.symbol(instruction.address) {
+        println!("{}:", symbol);
+    }
+    let bytes = instruction
+        .bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<String>>()
+        .join(" ");
+    println!(
+        "{:04X}  {:<8} {}{}",
+        instruction.address,
+        bytes,
+        if instruction.illegal { "*" } else { " " },
+        instruction
+    );
+}
+
+fn main() -> std::io::Result<()> {
+    let opts: Opts = Opts::parse();
+    let (header, prg_rom, _) = match read_rom_file(&opts.rom_file) {
+        Err(why) => panic!("Failed to load cartridge: {}", why.message),
+        Ok(rom) => rom,
+    };
+
+    // Almost every mapper has the vectors in a bank fixed at the top of the address space
+    let mut disassembler = Disassembler::with_hardware_labels();
+    let vectors = &prg_rom[prg_rom.len() - 6..];
+    disassembler.add_vector_labels(|address| vectors[(address - 0xFFFA) as usize]);
+    if let Some(symbols) = opts.symbols {
+        disassembler.add_vice_labels(&fs::read_to_string(symbols)?);
+    }
+
+    println!("; {}", header);
+
+    let banks: Vec<&[u8]> = prg_rom.chunks(opts.bank_size * 0x400).collect();
+    for (index, bank) in banks.iter().enumerate() {
+        if matches!(opts.bank, Some(bank) if bank != index) {
+            continue;
+        }
+
+        // Switchable banks are shown at 8000 and the last bank at the top of the address space
+        let is_last = index == banks.len() - 1;
+        let base = if is_last { (0x10000 - bank.len()) as u16 } else { 0x8000 };
+        let code = if is_last { &bank[..bank.len() - 6] } else { bank };
+        println!("\n; Bank {} at ${:04X}", index, base);
+
+        let instructions = disassembler.disassemble_bytes(code, base);
+        for instruction in instructions.iter() {
+            print_instruction(&disassembler, instruction);
+        }
+
+        // Anything too short to be an instruction is left as data
+        let decoded: usize = instructions.iter().map(|i| i.bytes.len()).sum();
+        for (offset, byte) in code.iter().enumerate().skip(decoded) {
+            println!(
+                "{:04X}  {:02X}        .byte ${:02X}",
+                base as usize + offset,
+                byte,
+                byte
+            );
+        }
+
+        if is_last {
+            for (vector, name) in VECTORS.iter() {
+                let handler =
+                    vectors[(vector - 0xFFFA) as usize] as u16 | (vectors[(vector - 0xFFFA + 1) as usize] as u16) << 8;
+                println!(
+                    "{:04X}  {:02X} {:02X}     .word {}",
+                    vector,
+                    handler & 0xFF,
+                    handler >> 8,
+                    disassembler.symbol(handler).unwrap_or(name)
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
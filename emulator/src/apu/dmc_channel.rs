@@ -1,7 +1,15 @@
-const RATE_TABLE: [u16; 0x10] = [
+use log::{debug, info};
+use region::Region;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+const NTSC_RATE_TABLE: [u16; 0x10] = [
     428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
 ];
 
+const PAL_RATE_TABLE: [u16; 0x10] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
 #[derive(Debug)]
 struct DmcOutputUnit {
     shift_register: u8,
@@ -12,7 +20,6 @@ struct DmcOutputUnit {
 
 #[derive(Debug)]
 pub(super) struct DmcChannel {
-    enabled: bool,
     /// The rate determines for how many CPU cycles happen between changes in the output level
     /// during automatic delta-encoded sample playback. For example, on NTSC (1.789773 MHz),
     /// a rate of 428 gives a frequency of 1789773/428 Hz = 4181.71 Hz. These periods are all
@@ -31,14 +38,26 @@ pub(super) struct DmcChannel {
     sample_address: u16,
     /// The number of bytes read from memory
     sample_length: u16,
+    /// The address of the next byte the memory reader will fetch
+    current_address: u16,
+    /// The number of bytes the memory reader has still to fetch
+    bytes_remaining: u16,
+    /// Filled by the memory reader (via DMA) and emptied by the output unit
+    sample_buffer: Option<u8>,
+    rate_table: &'static [u16; 0x10],
 }
 
 impl DmcChannel {
-    pub(super) fn new() -> Self {
+    pub(super) fn new(region: Region) -> Self {
+        let rate_table = if region.has_pal_apu() {
+            &PAL_RATE_TABLE
+        } else {
+            &NTSC_RATE_TABLE
+        };
+
         DmcChannel {
-            enabled: false,
-            rate: RATE_TABLE[0],
-            timer_countdown: RATE_TABLE[0],
+            rate: rate_table[0],
+            timer_countdown: rate_table[0],
             irq_enabled_flag: false,
             irq_flag: false,
             loop_flag: false,
@@ -50,6 +69,10 @@ impl DmcChannel {
             },
             sample_address: 0xC000,
             sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            rate_table,
         }
     }
 
@@ -60,7 +83,7 @@ impl DmcChannel {
             self.irq_flag = false;
         }
         self.loop_flag = value & 0b0100_0000 == 0b0100_0000;
-        self.rate = RATE_TABLE[value as usize & 0b1111];
+        self.rate = self.rate_table[value as usize & 0b1111];
     }
 
     /// Corresponds to 0x4011 on CPU address bus
@@ -78,11 +101,210 @@ impl DmcChannel {
         self.sample_length = value as u16 * 16 + 1;
     }
 
+    /// Corresponds to bit 4 of writes to 0x4015, note that any write to 0x4015 also clears the interrupt flag
     pub(super) fn set_enabled(&mut self, enabled: bool) {
-        self.enabled = enabled;
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart_sample();
+        }
+    }
+
+    /// The reset line clears all but the lowest bit of the output level
+    pub(super) fn reset(&mut self) {
+        self.output_unit.output_level &= 1;
+    }
+
+    fn restart_sample(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Reported in bit 4 of 0x4015
+    pub(super) fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    /// Reported in bit 7 of 0x4015 & used to trigger the IRQ line
+    pub(super) fn irq_flag(&self) -> bool {
+        self.irq_flag
     }
 
+    /// The memory reader wants a new byte as soon as the sample buffer is
+    /// empty, this returns the address it needs the CPU to read via DMA
+    pub(super) fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Called with the result of the DMA read requested by `dma_request`
+    pub(super) fn load_sample_byte(&mut self, value: u8) {
+        debug!("DMC loaded sample byte {:02X} from {:04X}", value, self.current_address);
+        self.sample_buffer = Some(value);
+
+        // The address wraps around to 0x8000 rather than 0x0000
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart_sample();
+            } else if self.irq_enabled_flag {
+                info!("DMC sample finished, raising IRQ");
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    /// Called once per CPU clock, the rate table is in CPU cycles
     pub(super) fn clock_timer(&mut self) {
-        // TODO
+        if self.timer_countdown > 1 {
+            self.timer_countdown -= 1;
+            return;
+        }
+        self.timer_countdown = self.rate;
+
+        let unit = &mut self.output_unit;
+        if !unit.silence_flag {
+            if unit.shift_register & 1 == 1 {
+                if unit.output_level <= 125 {
+                    unit.output_level += 2;
+                }
+            } else if unit.output_level >= 2 {
+                unit.output_level -= 2;
+            }
+        }
+        unit.shift_register >>= 1;
+
+        unit.bits_remaining_counter -= 1;
+        if unit.bits_remaining_counter == 0 {
+            // Start a new output cycle, silenced if the memory reader hasn't kept up
+            unit.bits_remaining_counter = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    unit.silence_flag = false;
+                    unit.shift_register = sample;
+                }
+                None => unit.silence_flag = true,
+            }
+        }
+    }
+
+    /// The output volume for the channel
+    pub(super) fn mixer_value(&self) -> u8 {
+        self.output_unit.output_level
+    }
+}
+
+impl SaveState for DmcChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.rate);
+        writer.write_u16(self.timer_countdown);
+        writer.write_bool(self.irq_enabled_flag);
+        writer.write_bool(self.irq_flag);
+        writer.write_bool(self.loop_flag);
+        writer.write_u8(self.output_unit.shift_register);
+        writer.write_u8(self.output_unit.bits_remaining_counter);
+        writer.write_u8(self.output_unit.output_level);
+        writer.write_bool(self.output_unit.silence_flag);
+        writer.write_u16(self.sample_address);
+        writer.write_u16(self.sample_length);
+        writer.write_u16(self.current_address);
+        writer.write_u16(self.bytes_remaining);
+        writer.write_option_u8(self.sample_buffer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.rate = reader.read_u16()?;
+        self.timer_countdown = reader.read_u16()?;
+        self.irq_enabled_flag = reader.read_bool()?;
+        self.irq_flag = reader.read_bool()?;
+        self.loop_flag = reader.read_bool()?;
+        self.output_unit.shift_register = reader.read_u8()?;
+        self.output_unit.bits_remaining_counter = reader.read_u8()?.clamp(1, 8);
+        self.output_unit.output_level = reader.read_u8()?;
+        self.output_unit.silence_flag = reader.read_bool()?;
+        self.sample_address = reader.read_u16()?;
+        self.sample_length = reader.read_u16()?;
+        self.current_address = reader.read_u16()?;
+        self.bytes_remaining = reader.read_u16()?;
+        self.sample_buffer = reader.read_option_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod dmc_channel_tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_fetches_wrap_to_8000() {
+        let mut dmc = DmcChannel::new(Region::Ntsc);
+        dmc.set_sample_address(0xFF);
+        dmc.set_sample_length(4);
+        dmc.set_enabled(true);
+
+        let mut addresses = vec![];
+        while let Some(address) = dmc.dma_request() {
+            addresses.push(address);
+            dmc.load_sample_byte(0);
+            dmc.sample_buffer = None;
+        }
+        assert_eq!(addresses.len(), 65);
+        assert_eq!(addresses[63], 0xFFFF);
+        assert_eq!(addresses[64], 0x8000);
+        assert!(!dmc.is_active());
+    }
+
+    #[test]
+    fn test_irq_raised_at_end_of_sample_unless_looping() {
+        let mut dmc = DmcChannel::new(Region::Ntsc);
+        dmc.write_flag_and_rate(0b1000_0000);
+        dmc.set_enabled(true);
+        dmc.load_sample_byte(0);
+        assert!(dmc.irq_flag());
+        assert!(!dmc.is_active());
+
+        // Any write to 0x4015 acknowledges the IRQ
+        dmc.set_enabled(false);
+        assert!(!dmc.irq_flag());
+
+        let mut dmc = DmcChannel::new(Region::Ntsc);
+        dmc.write_flag_and_rate(0b1100_0000);
+        dmc.set_enabled(true);
+        dmc.load_sample_byte(0);
+        assert!(!dmc.irq_flag());
+        assert!(dmc.is_active());
+    }
+
+    #[test]
+    fn test_output_level_follows_sample_bits() {
+        let mut dmc = DmcChannel::new(Region::Ntsc);
+        dmc.direct_load(64);
+        dmc.set_enabled(true);
+        dmc.load_sample_byte(0b0000_1111);
+
+        // The first output cycle is silent since the buffer was empty when it started
+        for _ in 0..8 * NTSC_RATE_TABLE[0] {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.mixer_value(), 64);
+
+        for _ in 0..4 * NTSC_RATE_TABLE[0] {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.mixer_value(), 72);
+        for _ in 0..4 * NTSC_RATE_TABLE[0] {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.mixer_value(), 64);
     }
 }
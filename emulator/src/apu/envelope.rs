@@ -0,0 +1,89 @@
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// The volume envelope shared by the pulse and noise channels.
+///
+/// Produces either a constant volume or a sawtooth decaying from 15 to 0
+/// (optionally looping) with the decay rate set by the same 4 bits.
+#[derive(Debug)]
+pub(crate) struct Envelope {
+    constant_volume_flag: bool,
+    loop_flag: bool,
+    /// Either the constant volume or the period of the divider
+    volume: u8,
+    start_flag: bool,
+    divider: u8,
+    decay_level: u8,
+}
+
+impl Envelope {
+    pub(crate) fn new() -> Self {
+        Envelope {
+            constant_volume_flag: false,
+            loop_flag: false,
+            volume: 0,
+            start_flag: false,
+            divider: 0,
+            decay_level: 0,
+        }
+    }
+
+    /// Takes the full value written to 0x4000/0x4004/0x400C, the loop flag
+    /// doubles as the length counter halt flag so is bit 5
+    pub(crate) fn write_register(&mut self, value: u8) {
+        self.loop_flag = value & 0b0010_0000 != 0;
+        self.constant_volume_flag = value & 0b0001_0000 != 0;
+        self.volume = value & 0b1111;
+    }
+
+    /// Triggered by writes to the channels length counter register
+    pub(crate) fn restart(&mut self) {
+        self.start_flag = true;
+    }
+
+    /// Clocked by the frame counter on each quarter frame
+    pub(crate) fn clock(&mut self) {
+        if self.start_flag {
+            self.start_flag = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay_level > 0 {
+                self.decay_level -= 1;
+            } else if self.loop_flag {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub(crate) fn output(&self) -> u8 {
+        if self.constant_volume_flag {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
+
+impl SaveState for Envelope {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.constant_volume_flag);
+        writer.write_bool(self.loop_flag);
+        writer.write_u8(self.volume);
+        writer.write_bool(self.start_flag);
+        writer.write_u8(self.divider);
+        writer.write_u8(self.decay_level);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.constant_volume_flag = reader.read_bool()?;
+        self.loop_flag = reader.read_bool()?;
+        self.volume = reader.read_u8()? & 0b1111;
+        self.start_flag = reader.read_bool()?;
+        self.divider = reader.read_u8()? & 0b1111;
+        self.decay_level = reader.read_u8()? & 0b1111;
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
 pub(crate) const LENGTH_COUNTER_MAP: [u8; 0x20] = [
     0x0A, 0xFE, 0x14, 0x02, 0x28, 0x04, 0x50, 0x06, 0xA0, 0x08, 0x3C, 0x0A, 0x0E, 0x0C, 0x1A, 0x0E, 0x0C, 0x10, 0x18,
     0x12, 0x30, 0x14, 0x60, 0x16, 0xC0, 0x18, 0x48, 0x1A, 0x10, 0x1C, 0x20, 0x1E,
@@ -39,3 +41,16 @@ impl LengthCounter {
         self.length_counter > 0
     }
 }
+
+impl SaveState for LengthCounter {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.length_counter);
+        writer.write_bool(self.length_counter_halt);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.length_counter = reader.read_u8()?;
+        self.length_counter_halt = reader.read_bool()?;
+        Ok(())
+    }
+}
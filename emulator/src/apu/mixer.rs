@@ -0,0 +1,65 @@
+/// Combines the channel outputs into a single amplitude using the lookup
+/// table approximation of the nonlinear NES DAC described at
+/// https://wiki.nesdev.com/w/index.php/APU_Mixer
+///
+/// The output is in the range 0.0..=1.0 (roughly, the TND table tops out
+/// just under 0.75 and the pulse table just under 0.26).
+pub(super) struct Mixer {
+    /// Indexed by pulse1 + pulse2 (each 0-15)
+    pulse_table: [f32; 31],
+    /// Indexed by 3 * triangle + 2 * noise + dmc
+    tnd_table: [f32; 203],
+}
+
+impl Mixer {
+    pub(super) fn new() -> Self {
+        let mut pulse_table = [0f32; 31];
+        for (n, entry) in pulse_table.iter_mut().enumerate().skip(1) {
+            *entry = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+
+        let mut tnd_table = [0f32; 203];
+        for (n, entry) in tnd_table.iter_mut().enumerate().skip(1) {
+            *entry = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+
+        Mixer { pulse_table, tnd_table }
+    }
+
+    pub(super) fn mix(&self, pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse_out = self.pulse_table[pulse_1 as usize + pulse_2 as usize];
+        let tnd_out = self.tnd_table[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+
+        pulse_out + tnd_out
+    }
+}
+
+#[cfg(test)]
+mod mixer_tests {
+    use super::*;
+
+    #[test]
+    fn test_silence_is_zero() {
+        assert_eq!(Mixer::new().mix(0, 0, 0, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn test_table_matches_formula() {
+        let mixer = Mixer::new();
+
+        // Compare against the nonlinear formula with no lookup tables
+        let pulse = 95.88 / (8128.0 / (15.0 + 15.0) + 100.0);
+        let tnd = 159.79 / (1.0 / (15.0 / 8227.0 + 15.0 / 12241.0 + 127.0 / 22638.0) + 100.0);
+        let mixed = mixer.mix(15, 15, 15, 15, 127);
+        assert!((mixed - (pulse + tnd)).abs() < 0.02, "{} vs {}", mixed, pulse + tnd);
+    }
+
+    #[test]
+    fn test_mixing_is_nonlinear() {
+        let mixer = Mixer::new();
+        let single = mixer.mix(15, 0, 0, 0, 0);
+        let double = mixer.mix(15, 15, 0, 0, 0);
+        assert!(double < 2.0 * single);
+        assert!(double > single);
+    }
+}
@@ -1,15 +1,25 @@
 use apu::dmc_channel::DmcChannel;
+use apu::mixer::Mixer;
 use apu::noise_channel::NoiseChannel;
 use apu::pulse_channel::PulseChannel;
+use apu::resampler::Resampler;
 use apu::triangle_channel::TriangleChannel;
 use log::info;
+use region::Region;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 mod dmc_channel;
+mod envelope;
 mod length_counter;
+mod mixer;
 mod noise_channel;
-mod pulse_channel;
+pub(crate) mod pulse_channel;
+mod resampler;
 mod triangle_channel;
 
+/// The host sample rate audio is generated at unless otherwise configured
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
 /// This type is used to represent an APU cycle to make it clearer when
 /// we're talking about cycles which type (PPU, CPU, APU) we mean.
 /// An APU cycle occurs once for every two CPU cycles.
@@ -21,11 +31,39 @@ enum FrameCounterMode {
     FiveStep,
 }
 
+/// The APU cycles (counted from a write to 0x4017) at which the frame
+/// sequencer clocks the envelopes, length counters etc. The last step of the
+/// four step sequence is when it wraps, the five step sequence wraps later.
+#[derive(Debug)]
+struct FrameSequence {
+    quarter_frame_1: ApuCycle,
+    half_frame_1: ApuCycle,
+    quarter_frame_2: ApuCycle,
+    four_step_length: ApuCycle,
+    five_step_length: ApuCycle,
+}
+
+const NTSC_FRAME_SEQUENCE: FrameSequence = FrameSequence {
+    quarter_frame_1: 3729,
+    half_frame_1: 7457,
+    quarter_frame_2: 11186,
+    four_step_length: 14915,
+    five_step_length: 18641,
+};
+
+const PAL_FRAME_SEQUENCE: FrameSequence = FrameSequence {
+    quarter_frame_1: 4157,
+    half_frame_1: 8314,
+    quarter_frame_2: 12470,
+    four_step_length: 16627,
+    five_step_length: 20783,
+};
+
 impl FrameCounterMode {
-    fn wrapping_number(&self) -> u32 {
+    fn wrapping_number(&self, sequence: &FrameSequence) -> u32 {
         match self {
-            FrameCounterMode::FourStep => 14915,
-            FrameCounterMode::FiveStep => 18641,
+            FrameCounterMode::FourStep => sequence.four_step_length,
+            FrameCounterMode::FiveStep => sequence.five_step_length,
         }
     }
 }
@@ -58,19 +96,26 @@ pub struct Apu {
     noise_channel: NoiseChannel,
     dmc_channel: DmcChannel,
     frame_counter: FrameCounter,
+    frame_sequence: &'static FrameSequence,
+    /// Used to convert the output from CPU cycles to the host sample rate
+    cpu_clock_rate: f64,
     total_apu_cycles: ApuCycle,
     is_apu_cycle: bool,
     interrupt_triggered_cycles: Option<ApuCycle>,
+    mixer: Mixer,
+    /// Output of any audio chip on the cartridge for the current cycle
+    expansion_audio: f32,
+    resampler: Resampler,
 }
 
 impl Apu {
-    pub fn new() -> Self {
+    pub fn new(region: Region) -> Self {
         Apu {
-            pulse_channel_1: PulseChannel::new("Pulse 1".to_string()),
-            pulse_channel_2: PulseChannel::new("Pulse 2".to_string()),
+            pulse_channel_1: PulseChannel::new("Pulse 1".to_string(), true),
+            pulse_channel_2: PulseChannel::new("Pulse 2".to_string(), false),
             triangle_channel: TriangleChannel::new(),
-            noise_channel: NoiseChannel::new(),
-            dmc_channel: DmcChannel::new(),
+            noise_channel: NoiseChannel::new(region),
+            dmc_channel: DmcChannel::new(region),
             frame_counter: FrameCounter {
                 inhibit_interrupts: false,
                 mode: FrameCounterMode::FourStep,
@@ -78,10 +123,73 @@ impl Apu {
                 sequence_cycles: 4,
                 timer_reset_countdown: 0,
             },
+            frame_sequence: if region.has_pal_apu() {
+                &PAL_FRAME_SEQUENCE
+            } else {
+                &NTSC_FRAME_SEQUENCE
+            },
+            cpu_clock_rate: region.cpu_clock_rate(),
             total_apu_cycles: 4, // TODO - What's the total number of APU cycles that occur during startup? 8/2?
             is_apu_cycle: false, // TODO - Guesswork, does the APU clock on cpu cycle 0 or 1?
             interrupt_triggered_cycles: None,
+            mixer: Mixer::new(),
+            expansion_audio: 0.0,
+            resampler: Resampler::new(DEFAULT_SAMPLE_RATE, region.cpu_clock_rate()),
+        }
+    }
+
+    /// Change the rate at which output samples are generated, any samples
+    /// which haven't yet been consumed are discarded
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler = Resampler::new(sample_rate, self.cpu_clock_rate);
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    /// The samples generated since the last call to `clear_samples`
+    pub(crate) fn samples(&self) -> &[f32] {
+        self.resampler.samples()
+    }
+
+    pub(crate) fn clear_samples(&mut self) {
+        self.resampler.clear_samples();
+    }
+
+    /// Set the output of the cartridge's expansion audio for the next cycle,
+    /// it's added to the APU output after the nonlinear mixer
+    pub(crate) fn set_expansion_audio(&mut self, amplitude: f32) {
+        self.expansion_audio = amplitude;
+    }
+
+    /// The reset line silences every channel as if 00 was written to
+    /// 0x4015 and rewrites the last value written to 0x4017 (so the frame
+    /// counter mode is kept), the frame IRQ flag is cleared
+    pub(crate) fn reset(&mut self) {
+        self.write_status_register(0);
+        self.interrupt_triggered_cycles = None;
+        self.triangle_channel.reset();
+        self.dmc_channel.reset();
+
+        let mut frame_counter = 0;
+        if self.frame_counter.mode == FrameCounterMode::FiveStep {
+            frame_counter |= 0b1000_0000;
+        }
+        if self.frame_counter.inhibit_interrupts {
+            frame_counter |= 0b0100_0000;
         }
+        self.write_byte(0x4017, frame_counter);
+    }
+
+    fn mixer_output(&self) -> f32 {
+        self.mixer.mix(
+            self.pulse_channel_1.mixer_value(),
+            self.pulse_channel_2.mixer_value(),
+            self.triangle_channel.mixer_value(),
+            self.noise_channel.mixer_value(),
+            self.dmc_channel.mixer_value(),
+        )
     }
 
     fn write_status_register(&mut self, value: u8) {
@@ -106,9 +214,12 @@ impl Apu {
         if self.noise_channel.non_zero_length_counter() {
             mask |= 0b1000
         };
-        // TODO - Read active flag from DMC channel
-
-        // TODO - Set DMC interrupt flag
+        if self.dmc_channel.is_active() {
+            mask |= 0b1_0000
+        };
+        if self.dmc_channel.irq_flag() {
+            mask |= 0b1000_0000
+        };
         if let Some(c) = self.interrupt_triggered_cycles {
             mask |= 0b0100_0000;
 
@@ -123,11 +234,24 @@ impl Apu {
     }
 
     pub(crate) fn check_trigger_irq(&mut self) -> bool {
-        if let Some(c) = self.interrupt_triggered_cycles {
+        let frame_counter_irq = if let Some(c) = self.interrupt_triggered_cycles {
             self.total_apu_cycles - c > 4
         } else {
             false
-        }
+        };
+
+        frame_counter_irq || self.dmc_channel.irq_flag()
+    }
+
+    /// The DMC memory reader needs the CPU to halt and read a sample byte
+    /// from this address
+    pub(crate) fn dmc_dma_request(&self) -> Option<u16> {
+        self.dmc_channel.dma_request()
+    }
+
+    /// Called by the CPU with the byte read on behalf of the DMC
+    pub(crate) fn dmc_dma_complete(&mut self, value: u8) {
+        self.dmc_channel.load_sample_byte(value);
     }
 
     pub(crate) fn read_byte(&mut self, address: u16) -> u8 {
@@ -183,7 +307,7 @@ impl Apu {
         info!("Running quarter frame update: apu_cycles={}", self.total_apu_cycles);
         self.pulse_channel_1.clock_envelope();
         self.pulse_channel_2.clock_envelope();
-        // TODO - Update envelopes on other channels
+        self.noise_channel.clock_envelope();
         self.triangle_channel.clock_linear_counter();
     }
 
@@ -213,7 +337,7 @@ impl Iterator for Apu {
 
         if self.is_apu_cycle {
             self.frame_counter.sequence_cycles =
-                (self.frame_counter.sequence_cycles + 1) % self.frame_counter.mode.wrapping_number();
+                (self.frame_counter.sequence_cycles + 1) % self.frame_counter.mode.wrapping_number(self.frame_sequence);
 
             // Note that the timers are not clocked by the frame counter but on every apu cycle
             self.pulse_channel_1.clock_timer();
@@ -231,17 +355,21 @@ impl Iterator for Apu {
             self.total_apu_cycles = self.total_apu_cycles.wrapping_add(1);
         } else {
             // Note that the clocking here actually occurs on the NON APU cycle deliberately
+            let sequence = self.frame_sequence;
             match self.frame_counter.sequence_cycles {
-                3729 => self.quarter_frame(),
-                7457 => self.half_frame(),
-                11186 => self.quarter_frame(),
+                c if c == sequence.quarter_frame_1 || c == sequence.quarter_frame_2 => self.quarter_frame(),
+                c if c == sequence.half_frame_1 => self.half_frame(),
                 0 => self.half_frame(),
                 _ => (),
             };
         }
 
-        // Note this is clocked on all CPU cycles
+        // Note these are clocked on all CPU cycles
         self.triangle_channel.clock_timer();
+        self.dmc_channel.clock_timer();
+
+        let amplitude = self.mixer_output() + self.expansion_audio;
+        self.resampler.add_amplitude(amplitude);
 
         // Every other cycle is an APU cycle (as clocked by the CPU)
         self.is_apu_cycle = !self.is_apu_cycle;
@@ -250,3 +378,42 @@ impl Iterator for Apu {
         None
     }
 }
+
+impl SaveState for Apu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.pulse_channel_1.save_state(writer);
+        self.pulse_channel_2.save_state(writer);
+        self.triangle_channel.save_state(writer);
+        self.noise_channel.save_state(writer);
+        self.dmc_channel.save_state(writer);
+        writer.write_bool(self.frame_counter.inhibit_interrupts);
+        writer.write_bool(self.frame_counter.mode == FrameCounterMode::FiveStep);
+        writer.write_u8(self.frame_counter.step);
+        writer.write_u32(self.frame_counter.sequence_cycles);
+        writer.write_u8(self.frame_counter.timer_reset_countdown);
+        writer.write_u32(self.total_apu_cycles);
+        writer.write_bool(self.is_apu_cycle);
+        writer.write_option_u32(self.interrupt_triggered_cycles);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.pulse_channel_1.load_state(reader)?;
+        self.pulse_channel_2.load_state(reader)?;
+        self.triangle_channel.load_state(reader)?;
+        self.noise_channel.load_state(reader)?;
+        self.dmc_channel.load_state(reader)?;
+        self.frame_counter.inhibit_interrupts = reader.read_bool()?;
+        self.frame_counter.mode = if reader.read_bool()? {
+            FrameCounterMode::FiveStep
+        } else {
+            FrameCounterMode::FourStep
+        };
+        self.frame_counter.step = reader.read_u8()?;
+        self.frame_counter.sequence_cycles = reader.read_u32()?;
+        self.frame_counter.timer_reset_countdown = reader.read_u8()?;
+        self.total_apu_cycles = reader.read_u32()?;
+        self.is_apu_cycle = reader.read_bool()?;
+        self.interrupt_triggered_cycles = reader.read_option_u32()?;
+        Ok(())
+    }
+}
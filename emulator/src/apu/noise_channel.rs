@@ -1,29 +1,42 @@
+use apu::envelope::Envelope;
 use apu::length_counter::LengthCounter;
 use log::{debug, info};
+use region::Region;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
-const TIMER_PERIOD_TABLE: [u16; 16] = [
+const NTSC_TIMER_PERIOD_TABLE: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
 ];
 
+const PAL_TIMER_PERIOD_TABLE: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
 pub(super) struct NoiseChannel {
     enabled: bool,
     length_counter: LengthCounter,
+    envelope: Envelope,
     lsfr_use_bit_6: bool,
     period: u16,
     timer: u16,
     /// 15 bit wide shift register for the LSFR
     shift_register: u16,
+    period_table: &'static [u16; 16],
 }
 
 impl NoiseChannel {
-    pub(super) fn new() -> Self {
+    pub(super) fn new(region: Region) -> Self {
         NoiseChannel {
             enabled: false,
             length_counter: LengthCounter::new(),
+            envelope: Envelope::new(),
             lsfr_use_bit_6: false,
             period: 0,
             timer: 0,
             shift_register: 1,
+            period_table: if region.has_pal_apu() {
+                &PAL_TIMER_PERIOD_TABLE
+            } else {
+                &NTSC_TIMER_PERIOD_TABLE
+            },
         }
     }
 
@@ -37,13 +50,13 @@ impl NoiseChannel {
     /// Corresponds to writes to 0x400C
     pub(super) fn write_length_halt_envelope_register(&mut self, value: u8) {
         self.length_counter.set_halt(value & 0b0010_0000 != 0);
-        // TODO - Envelope and constant volume flags
+        self.envelope.write_register(value);
     }
 
     /// Corresponds to write to 400E
     pub(super) fn set_mode_and_period(&mut self, value: u8) {
         self.lsfr_use_bit_6 = value & 0b1000_0000 == 0b1000_0000;
-        self.period = TIMER_PERIOD_TABLE[value as usize & 0b0000_1111];
+        self.period = self.period_table[value as usize & 0b0000_1111];
     }
 
     /// Corresponds to writes to 0x400F
@@ -55,6 +68,7 @@ impl NoiseChannel {
                 value, self.length_counter
             );
         }
+        self.envelope.restart();
     }
 
     pub(crate) fn non_zero_length_counter(&self) -> bool {
@@ -62,10 +76,14 @@ impl NoiseChannel {
     }
 
     pub(super) fn clock_length_counter(&mut self) {
-        info!("Clocking length counter for triangle channel {:?}", self.length_counter);
+        info!("Clocking length counter for noise channel {:?}", self.length_counter);
         self.length_counter.clock();
     }
 
+    pub(super) fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
     /// Noise channel is clocked on every APU cycle
     pub(super) fn clock_timer(&mut self) {
         if self.timer == 0 {
@@ -74,7 +92,7 @@ impl NoiseChannel {
             // Step the LSFR
             debug!("Updating LSFR {:b}", self.shift_register);
             let feedback = self.shift_register & 0b1
-                | if self.lsfr_use_bit_6 {
+                ^ if self.lsfr_use_bit_6 {
                     (self.shift_register & 0b0100_0000) >> 6
                 } else {
                     (self.shift_register & 0b10) >> 1
@@ -90,9 +108,32 @@ impl NoiseChannel {
     /// The output volume for the channel
     pub(super) fn mixer_value(&self) -> u8 {
         if self.length_counter.is_non_zero() && self.shift_register & 0b1 == 0 {
-            0 // TODO - This should be the envelope/constant volume
+            self.envelope.output()
         } else {
             0
         }
     }
 }
+
+impl SaveState for NoiseChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        self.length_counter.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.write_bool(self.lsfr_use_bit_6);
+        writer.write_u16(self.period);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.shift_register);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.read_bool()?;
+        self.length_counter.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.lsfr_use_bit_6 = reader.read_bool()?;
+        self.period = reader.read_u16()?;
+        self.timer = reader.read_u16()?;
+        self.shift_register = reader.read_u16()?;
+        Ok(())
+    }
+}
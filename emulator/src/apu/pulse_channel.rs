@@ -1,5 +1,7 @@
+use apu::envelope::Envelope;
 use apu::length_counter::LengthCounter;
 use log::{debug, info};
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 const EIGHTH_DUTY_CYCLE: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
 const QUARTER_DUTY_CYCLE: [u8; 8] = [0, 0, 0, 0, 0, 0, 1, 1];
@@ -12,15 +14,23 @@ struct SweepUnit {
     divider_period: u8,
     is_negate: bool,
     shift_count: u8,
+    divider: u8,
+    reload_flag: bool,
+    /// Pulse 1 negates using ones' complement (subtracting an extra 1) where
+    /// pulse 2 uses twos' complement
+    ones_complement: bool,
 }
 
 impl SweepUnit {
-    fn new() -> Self {
+    fn new(ones_complement: bool) -> Self {
         SweepUnit {
             enabled: false,
             divider_period: 0,
             is_negate: false,
             shift_count: 0,
+            divider: 0,
+            reload_flag: false,
+            ones_complement,
         }
     }
 
@@ -29,31 +39,34 @@ impl SweepUnit {
         self.divider_period = (value & 0b0111_0000) >> 4;
         self.is_negate = value & 0b0000_1000 == 0b0000_1000;
         self.shift_count = value & 0b0000_0111;
+        self.reload_flag = true;
     }
-}
-
-#[derive(Debug)]
-struct Envelope {
-    constant_volume: u8,
-    use_envelope: bool,
-    envelope_value: u8,
-    start_flag: bool,
-}
 
-impl Envelope {
-    fn new() -> Self {
-        Envelope {
-            constant_volume: 0,
-            use_envelope: false,
-            envelope_value: 0,
-            start_flag: false,
+    /// The sweep unit continuously calculates the period it would move the
+    /// channel to, regardless of whether it is enabled
+    fn target_period(&self, timer_load: u16) -> u16 {
+        let change = timer_load >> self.shift_count;
+        if self.is_negate {
+            let change = if self.ones_complement { change + 1 } else { change };
+            timer_load.saturating_sub(change)
+        } else {
+            timer_load + change
         }
     }
+
+    /// The channel is silenced if the current period is too small or the
+    /// target period overflows 11 bits, even when the sweep is disabled
+    fn is_muting(&self, timer_load: u16) -> bool {
+        timer_load < 8 || self.target_period(timer_load) > 0x7FF
+    }
 }
 
 #[derive(Debug)]
-pub(super) struct PulseChannel {
+pub(crate) struct PulseChannel {
     name: String,
+    /// The MMC5 pulse channels are copies of these without the sweep unit,
+    /// which means they're never muted by it either
+    has_sweep_unit: bool,
     enabled: bool,
     length_counter: LengthCounter,
     duty_cycle: [u8; 8],
@@ -65,21 +78,30 @@ pub(super) struct PulseChannel {
 }
 
 impl PulseChannel {
-    pub(super) fn new(name: String) -> Self {
+    pub(crate) fn new(name: String, ones_complement_sweep: bool) -> Self {
         PulseChannel {
             name,
+            has_sweep_unit: true,
             enabled: false,
             length_counter: LengthCounter::new(),
             duty_cycle: EIGHTH_DUTY_CYCLE,
             sequence: 0,
             timer_load: 0,
             timer: 0,
-            sweep_unit: SweepUnit::new(),
+            sweep_unit: SweepUnit::new(ones_complement_sweep),
             envelope: Envelope::new(),
         }
     }
 
-    pub(super) fn set_enabled(&mut self, enabled: bool) {
+    /// A pulse channel with no sweep unit as found on the MMC5
+    pub(crate) fn without_sweep_unit(name: String) -> Self {
+        PulseChannel {
+            has_sweep_unit: false,
+            ..PulseChannel::new(name, false)
+        }
+    }
+
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
         self.enabled = enabled;
         if !enabled {
             self.length_counter.disable();
@@ -87,7 +109,7 @@ impl PulseChannel {
     }
 
     /// Corresponds to writes to 0x4000 (pulse 1) & 0x4004 (pulse 2)
-    pub(super) fn write_duty_length_halt_envelope_register(&mut self, value: u8) {
+    pub(crate) fn write_duty_length_halt_envelope_register(&mut self, value: u8) {
         self.duty_cycle = match value >> 6 {
             0b00 => EIGHTH_DUTY_CYCLE,
             0b01 => QUARTER_DUTY_CYCLE,
@@ -96,18 +118,17 @@ impl PulseChannel {
             _ => panic!(),
         };
         self.length_counter.set_halt(value & 0b0010_0000 != 0);
-        self.envelope.use_envelope = value & 0b0001_0000 != 0;
-        self.envelope.constant_volume = value & 0b1111;
+        self.envelope.write_register(value);
     }
 
     /// Corresponds to writes to 0x4002 (pulse 1) & 0x4006 (pulse 2)
-    pub(super) fn load_timer_low(&mut self, value: u8) {
+    pub(crate) fn load_timer_low(&mut self, value: u8) {
         info!("Loading timer low for {} with {:02X}", self.name, value);
         self.timer_load = (self.timer_load & 0b0111_0000_0000) | value as u16;
     }
 
     /// Corresponds to writes to 0x4003 (pulse 1) & 0x4007 (pulse 2)
-    pub(super) fn load_length_timer_high(&mut self, value: u8) {
+    pub(crate) fn load_length_timer_high(&mut self, value: u8) {
         if self.enabled {
             self.length_counter.set(value);
             info!(
@@ -118,7 +139,7 @@ impl PulseChannel {
         self.timer_load = (self.timer_load & 0b1111_1111) | ((value as u16 & 0b111) << 8);
         self.timer = self.timer_load;
         self.sequence = 0;
-        // TODO - Restart envelope
+        self.envelope.restart();
     }
 
     /// Corresponds to writes to 0x4001 (pulse 1) & 0x4005 (pulse 2)
@@ -131,26 +152,35 @@ impl PulseChannel {
         self.length_counter.is_non_zero()
     }
 
-    pub(super) fn clock_length_counter(&mut self) {
+    pub(crate) fn clock_length_counter(&mut self) {
         info!("Clocking length counter for {} {:?}", self.name, self.length_counter);
         self.length_counter.clock();
     }
 
     pub(super) fn clock_sweep_unit(&mut self) {
-        // TODO
-    }
+        if self.sweep_unit.divider == 0
+            && self.sweep_unit.enabled
+            && self.sweep_unit.shift_count > 0
+            && !self.sweep_unit.is_muting(self.timer_load)
+        {
+            self.timer_load = self.sweep_unit.target_period(self.timer_load);
+            info!("Sweep unit for {} updated period to {:04X}", self.name, self.timer_load);
+        }
 
-    pub(super) fn clock_envelope(&mut self) {
-        if self.envelope.start_flag {
-            self.envelope.start_flag = false;
-        // TODO - Other things that happen when clearing start flag
+        if self.sweep_unit.divider == 0 || self.sweep_unit.reload_flag {
+            self.sweep_unit.divider = self.sweep_unit.divider_period;
+            self.sweep_unit.reload_flag = false;
         } else {
-            // TODO
+            self.sweep_unit.divider -= 1;
         }
     }
 
+    pub(crate) fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
     /// Called once per APU clock (once every two CPU clocks) and steps the timer
-    pub(super) fn clock_timer(&mut self) {
+    pub(crate) fn clock_timer(&mut self) {
         if self.timer == 0 {
             self.timer = self.timer_load;
 
@@ -164,4 +194,51 @@ impl PulseChannel {
             self.timer -= 1;
         }
     }
+
+    /// The output volume for the channel
+    pub(crate) fn mixer_value(&self) -> u8 {
+        if self.length_counter.is_non_zero()
+            && !(self.has_sweep_unit && self.sweep_unit.is_muting(self.timer_load))
+            && self.duty_cycle[self.sequence] == 1
+        {
+            self.envelope.output()
+        } else {
+            0
+        }
+    }
+}
+
+impl SaveState for PulseChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        self.length_counter.save_state(writer);
+        writer.write_bytes(&self.duty_cycle);
+        writer.write_usize(self.sequence);
+        writer.write_u16(self.timer_load);
+        writer.write_u16(self.timer);
+        writer.write_bool(self.sweep_unit.enabled);
+        writer.write_u8(self.sweep_unit.divider_period);
+        writer.write_bool(self.sweep_unit.is_negate);
+        writer.write_u8(self.sweep_unit.shift_count);
+        writer.write_u8(self.sweep_unit.divider);
+        writer.write_bool(self.sweep_unit.reload_flag);
+        self.envelope.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.read_bool()?;
+        self.length_counter.load_state(reader)?;
+        reader.read_bytes(&mut self.duty_cycle)?;
+        self.sequence = reader.read_usize()? & 7;
+        self.timer_load = reader.read_u16()?;
+        self.timer = reader.read_u16()?;
+        self.sweep_unit.enabled = reader.read_bool()?;
+        self.sweep_unit.divider_period = reader.read_u8()?;
+        self.sweep_unit.is_negate = reader.read_bool()?;
+        self.sweep_unit.shift_count = reader.read_u8()?;
+        self.sweep_unit.divider = reader.read_u8()?;
+        self.sweep_unit.reload_flag = reader.read_bool()?;
+        self.envelope.load_state(reader)?;
+        Ok(())
+    }
 }
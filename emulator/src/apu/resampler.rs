@@ -0,0 +1,275 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Number of output samples either side of a step which are affected by it
+const HALF_WIDTH: usize = 8;
+const KERNEL_WIDTH: usize = HALF_WIDTH * 2;
+/// Number of sub-sample positions the kernel is precalculated for
+const PHASES: usize = 64;
+
+/// Converts the amplitude stream from the mixer (one per CPU cycle, so
+/// 1.789773 MHz on NTSC) down to the host sample rate.
+///
+/// Naively picking every ~40th amplitude aliases the (plentiful) high
+/// frequency content back into the audible range, so instead every change in
+/// amplitude is added to the output as a band limited step (a windowed sinc
+/// impulse which is integrated when the sample is read out). This is the same
+/// approach taken by blargg's blip_buf and means the cost is per amplitude
+/// change rather than per input cycle.
+///
+/// The output then goes through the same filters as the NES itself (two
+/// high pass filters at 90Hz & 440Hz and a low pass at 14kHz) which also
+/// removes the DC offset of the mixer.
+pub(crate) struct Resampler {
+    sample_rate: u32,
+    /// Output samples per CPU cycle
+    step: f64,
+    /// Position of the current CPU cycle in output samples relative to
+    /// `deltas[0]`, offset by `HALF_WIDTH` so a new step never lands before
+    /// the start of the buffer
+    position: f64,
+    /// Pending steps for output samples which aren't yet complete
+    deltas: VecDeque<f32>,
+    kernel: Box<[[f32; KERNEL_WIDTH]; PHASES + 1]>,
+    last_amplitude: f32,
+    integrator: f32,
+    high_pass_90: HighPassFilter,
+    high_pass_440: HighPassFilter,
+    low_pass_14k: LowPassFilter,
+    samples: Vec<f32>,
+}
+
+impl Resampler {
+    pub(crate) fn new(sample_rate: u32, cpu_clock_rate: f64) -> Self {
+        let sample_rate_f = sample_rate as f64;
+        Resampler {
+            sample_rate,
+            step: sample_rate_f / cpu_clock_rate,
+            position: HALF_WIDTH as f64,
+            deltas: VecDeque::from(vec![0.0; KERNEL_WIDTH + 2]),
+            kernel: Box::new(build_kernel(sample_rate_f)),
+            last_amplitude: 0.0,
+            integrator: 0.0,
+            high_pass_90: HighPassFilter::new(90.0, sample_rate_f),
+            high_pass_440: HighPassFilter::new(440.0, sample_rate_f),
+            low_pass_14k: LowPassFilter::new(14_000.0, sample_rate_f),
+            samples: Vec::with_capacity(sample_rate as usize / 30),
+        }
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Called once per CPU cycle with the output of the mixer
+    pub(crate) fn add_amplitude(&mut self, amplitude: f32) {
+        if amplitude != self.last_amplitude {
+            self.add_step(amplitude - self.last_amplitude);
+            self.last_amplitude = amplitude;
+        }
+
+        // More than one sample completes per cycle if the output rate is
+        // above the CPU clock rate
+        self.position += self.step;
+        while self.position >= (HALF_WIDTH + 1) as f64 {
+            self.complete_sample();
+        }
+    }
+
+    fn add_step(&mut self, delta: f32) {
+        let whole = self.position.floor();
+        let phase = ((self.position - whole) * PHASES as f64).round() as usize;
+        let start = whole as usize + 1 - HALF_WIDTH;
+
+        for (ix, weight) in self.kernel[phase].iter().enumerate() {
+            self.deltas[start + ix] += delta * weight;
+        }
+    }
+
+    /// The oldest pending sample can no longer be affected by future steps
+    /// so is integrated, filtered and moved to the output buffer
+    fn complete_sample(&mut self) {
+        let delta = self.deltas.pop_front().unwrap_or(0.0);
+        self.deltas.push_back(0.0);
+        self.position -= 1.0;
+
+        self.integrator += delta;
+        let sample = self.high_pass_90.process(self.integrator);
+        let sample = self.high_pass_440.process(sample);
+        let sample = self.low_pass_14k.process(sample);
+
+        // Don't grow without bound if nothing is consuming the audio (e.g.
+        // headless test runs), a full second is far more than a frontend
+        // would ever buffer
+        if self.samples.len() < self.sample_rate as usize {
+            self.samples.push(sample);
+        }
+    }
+
+    pub(crate) fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub(crate) fn clear_samples(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Precalculate a windowed sinc impulse for each sub-sample phase, each
+/// phase is normalised so that a step of 1.0 always integrates to 1.0
+fn build_kernel(sample_rate: f64) -> [[f32; KERNEL_WIDTH]; PHASES + 1] {
+    // Cut off a little below nyquist (or below the 20kHz limit of hearing
+    // for high output rates) to leave room for the transition band
+    let cutoff = (20_000.0 / (sample_rate / 2.0)).min(0.9);
+
+    let mut kernel = [[0f32; KERNEL_WIDTH]; PHASES + 1];
+    for (phase, taps) in kernel.iter_mut().enumerate() {
+        let fraction = phase as f64 / PHASES as f64;
+        let mut values = [0f64; KERNEL_WIDTH];
+        for (ix, value) in values.iter_mut().enumerate() {
+            // Distance from the step to this output sample
+            let x = ix as f64 + 1.0 - HALF_WIDTH as f64 - fraction;
+            let sinc = if x == 0.0 {
+                cutoff
+            } else {
+                (PI * x * cutoff).sin() / (PI * x)
+            };
+            // Blackman window over the kernel width
+            let w = (x + HALF_WIDTH as f64) / KERNEL_WIDTH as f64;
+            let window = if (0.0..=1.0).contains(&w) {
+                0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos()
+            } else {
+                0.0
+            };
+            *value = sinc * window;
+        }
+
+        let total: f64 = values.iter().sum();
+        for (tap, value) in taps.iter_mut().zip(values.iter()) {
+            *tap = (value / total) as f32;
+        }
+    }
+
+    kernel
+}
+
+/// First order high pass filter
+struct HighPassFilter {
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl HighPassFilter {
+    fn new(cutoff: f64, sample_rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        HighPassFilter {
+            alpha: (rc / (rc + dt)) as f32,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.previous_output = self.alpha * (self.previous_output + input - self.previous_input);
+        self.previous_input = input;
+        self.previous_output
+    }
+}
+
+/// First order low pass filter
+struct LowPassFilter {
+    alpha: f32,
+    previous_output: f32,
+}
+
+impl LowPassFilter {
+    fn new(cutoff: f64, sample_rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        LowPassFilter {
+            alpha: (dt / (rc + dt)) as f32,
+            previous_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        self.previous_output += self.alpha * (input - self.previous_output);
+        self.previous_output
+    }
+}
+
+#[cfg(test)]
+mod resampler_tests {
+    use super::*;
+
+    const CPU_CLOCK_RATE: f64 = 1_789_773.0;
+
+    fn run_cycles(resampler: &mut Resampler, cycles: u32, amplitude: impl Fn(u32) -> f32) {
+        for cycle in 0..cycles {
+            resampler.add_amplitude(amplitude(cycle));
+        }
+    }
+
+    #[test]
+    fn test_output_sample_rate() {
+        for &rate in &[44_100, 48_000] {
+            let mut resampler = Resampler::new(rate, CPU_CLOCK_RATE);
+            run_cycles(&mut resampler, CPU_CLOCK_RATE as u32 / 10, |_| 0.0);
+
+            let expected = rate as usize / 10;
+            assert!((resampler.samples().len() as i64 - expected as i64).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_output_sample_rate_above_cpu_clock_rate() {
+        let rate = CPU_CLOCK_RATE as u32 * 2 + 1000;
+        let mut resampler = Resampler::new(rate, CPU_CLOCK_RATE);
+        run_cycles(&mut resampler, CPU_CLOCK_RATE as u32 / 10, |cycle| (cycle & 1) as f32);
+
+        let expected = rate as usize / 10;
+        assert!((resampler.samples().len() as i64 - expected as i64).abs() <= 1);
+    }
+
+    #[test]
+    fn test_kernel_phases_are_normalised() {
+        for taps in build_kernel(44_100.0).iter() {
+            let total: f32 = taps.iter().sum();
+            assert!((total - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_square_wave_is_audible_and_dc_is_removed() {
+        let mut resampler = Resampler::new(44_100, CPU_CLOCK_RATE);
+
+        // A 440Hz square wave alternating between 0.0 and 0.5
+        let half_period = (CPU_CLOCK_RATE / 880.0) as u32;
+        run_cycles(&mut resampler, CPU_CLOCK_RATE as u32, |cycle| {
+            if (cycle / half_period) & 1 == 0 {
+                0.5
+            } else {
+                0.0
+            }
+        });
+
+        // Skip the first half second to let the high pass filters settle
+        let settled = &resampler.samples()[22_050..];
+        let mean = settled.iter().sum::<f32>() / settled.len() as f32;
+        let peak = settled.iter().fold(0f32, |max, s| max.max(s.abs()));
+        assert!(mean.abs() < 0.01, "mean {}", mean);
+        assert!(peak > 0.1 && peak < 0.5, "peak {}", peak);
+    }
+
+    #[test]
+    fn test_output_is_capped_when_not_drained() {
+        let mut resampler = Resampler::new(44_100, CPU_CLOCK_RATE);
+        run_cycles(&mut resampler, CPU_CLOCK_RATE as u32 * 2, |_| 0.0);
+        assert_eq!(resampler.samples().len(), 44_100);
+
+        resampler.clear_samples();
+        assert!(resampler.samples().is_empty());
+    }
+}
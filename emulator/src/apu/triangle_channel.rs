@@ -1,5 +1,6 @@
 use apu::length_counter::LengthCounter;
 use log::{debug, info};
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 const TRIANGLE_SEQUENCE: [u8; 32] = [
     15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
@@ -39,6 +40,11 @@ impl TriangleChannel {
         }
     }
 
+    /// The reset line restarts the sequence, nothing else is affected
+    pub(super) fn reset(&mut self) {
+        self.sequence = 0;
+    }
+
     /// Corresponds to writes to 0x4008
     pub(super) fn load_linear_counter(&mut self, value: u8) {
         self.linear_counter_reload = value & 0b0111_1111;
@@ -112,3 +118,30 @@ impl TriangleChannel {
         }
     }
 }
+
+impl SaveState for TriangleChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u16(self.timer_load);
+        writer.write_u16(self.timer);
+        writer.write_u8(self.sequence);
+        self.length_counter.save_state(writer);
+        writer.write_bool(self.control_flag);
+        writer.write_bool(self.linear_counter_reload_flag);
+        writer.write_u8(self.linear_counter_reload);
+        writer.write_u8(self.linear_counter);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.read_bool()?;
+        self.timer_load = reader.read_u16()?;
+        self.timer = reader.read_u16()?;
+        self.sequence = reader.read_u8()? & 31;
+        self.length_counter.load_state(reader)?;
+        self.control_flag = reader.read_bool()?;
+        self.linear_counter_reload_flag = reader.read_bool()?;
+        self.linear_counter_reload = reader.read_u8()?;
+        self.linear_counter = reader.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+use cartridge::mirroring::MirroringMode;
+use cartridge::CartridgeError;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Which version of the header format the rom was dumped with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaderFormat {
+    /// The original iNES format, only bytes 4-7 are reliable
+    INes,
+    /// NES 2.0, c.f. http://wiki.nesdev.com/w/index.php/NES_2.0
+    Nes2,
+}
+
+/// CPU/PPU timing the cartridge was designed for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimingMode {
+    Ntsc,
+    Pal,
+    /// Runs on both NTSC and PAL consoles
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem {
+        ppu_type: u8,
+        hardware_type: u8,
+    },
+    Playchoice10,
+    /// Extended console types (Famiclone with decimal mode, VT01 etc), the
+    /// value is the raw extended console type from byte 13
+    Extended(u8),
+}
+
+/// Represents flags/details about the rom from the header
+/// c.f. http://wiki.nesdev.com/w/index.php/INES and http://wiki.nesdev.com/w/index.php/NES_2.0 for details
+///
+/// RAM sizes are in bytes. For iNES headers (which can't describe them) they
+/// are the values commonly assumed by other emulators: 8KB of PRG RAM (battery
+/// backed if flag 6 says so) and 8KB of CHR RAM only if there's no CHR ROM.
+#[derive(Debug)]
+pub struct CartridgeHeader {
+    pub format: HeaderFormat,
+    pub prg_rom_16kb_units: u16,
+    pub chr_rom_8kb_units: u16,
+    /// Size in bytes of the PRG ROM, only differs from the number of units
+    /// for NES 2.0 headers using the exponent-multiplier notation
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: MirroringMode,
+    pub ram_is_battery_backed: bool,
+    /// A 512 byte trainer sits between the header and the PRG ROM
+    pub has_trainer: bool,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: TimingMode,
+    pub console_type: ConsoleType,
+    pub misc_rom_count: u8,
+    /// The default expansion device from byte 15 (0 is unspecified, 1 is a
+    /// standard controller), c.f. the NES 2.0 wiki page for the full list
+    pub expansion_device: u8,
+}
+
+/// NES 2.0 RAM sizes are stored as a shift count where 0 means no RAM
+fn shift_count_size(value: u8) -> usize {
+    match value & 0b1111 {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
+
+/// NES 2.0 ROM sizes are either a 12 bit unit count or, when the MSB nibble is
+/// 0xF, an exponent-multiplier pair of the form 2^E * (MM * 2 + 1)
+///
+/// Returns the size in bytes and in (rounded up) units, the latter can't hold
+/// the largest exponent-multiplier sizes so those are rejected
+fn rom_size(lsb: u8, msb: u8, unit_size: usize) -> Result<(usize, u16), CartridgeError> {
+    let size = if msb == 0xF {
+        let exponent = lsb >> 2;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl(u32::from(exponent))
+            .and_then(|size| size.checked_mul(multiplier))
+    } else {
+        Some(((msb as usize) << 8 | lsb as usize) * unit_size)
+    };
+
+    match size.map(|size| (size, u16::try_from(size.div_ceil(unit_size)))) {
+        Some((size, Ok(units))) => Ok((size, units)),
+        _ => Err(CartridgeError {
+            message: format!("ROM size {:X}{:02X} is too large", msb, lsb),
+            mapper: None,
+        }),
+    }
+}
+
+impl CartridgeHeader {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        if bytes.len() < 0x10 || bytes[0..4] != [b'N', b'E', b'S', 0x1A] {
+            return Err(CartridgeError {
+                message: "missing or truncated iNES header".to_string(),
+                mapper: None,
+            });
+        }
+
+        let flags_6 = bytes[6];
+        let flags_7 = bytes[7];
+        let mirroring = match (flags_6 & 1 == 0, flags_6 & 0b1000 == 0) {
+            (true, true) => MirroringMode::Horizontal,
+            (false, true) => MirroringMode::Vertical,
+            (_, false) => MirroringMode::FourScreen,
+        };
+        let ram_is_battery_backed = flags_6 & 0b10 == 0b10;
+        let has_trainer = flags_6 & 0b100 == 0b100;
+
+        let format = if flags_7 & 0b1100 == 0b1000 {
+            HeaderFormat::Nes2
+        } else {
+            HeaderFormat::INes
+        };
+
+        let header = match format {
+            HeaderFormat::Nes2 => {
+                let (prg_rom_size, prg_rom_16kb_units) = rom_size(bytes[4], bytes[9] & 0b1111, 0x4000)?;
+                let (chr_rom_size, chr_rom_8kb_units) = rom_size(bytes[5], bytes[9] >> 4, 0x2000)?;
+
+                CartridgeHeader {
+                    format,
+                    prg_rom_16kb_units,
+                    chr_rom_8kb_units,
+                    prg_rom_size,
+                    chr_rom_size,
+                    mapper: (flags_6 >> 4) as u16 | (flags_7 & 0b1111_0000) as u16 | ((bytes[8] & 0b1111) as u16) << 8,
+                    submapper: bytes[8] >> 4,
+                    mirroring,
+                    ram_is_battery_backed,
+                    has_trainer,
+                    prg_ram_size: shift_count_size(bytes[10]),
+                    prg_nvram_size: shift_count_size(bytes[10] >> 4),
+                    chr_ram_size: shift_count_size(bytes[11]),
+                    chr_nvram_size: shift_count_size(bytes[11] >> 4),
+                    timing: match bytes[12] & 0b11 {
+                        0 => TimingMode::Ntsc,
+                        1 => TimingMode::Pal,
+                        2 => TimingMode::MultiRegion,
+                        _ => TimingMode::Dendy,
+                    },
+                    console_type: match flags_7 & 0b11 {
+                        0 => ConsoleType::Nes,
+                        1 => ConsoleType::VsSystem {
+                            ppu_type: bytes[13] & 0b1111,
+                            hardware_type: bytes[13] >> 4,
+                        },
+                        2 => ConsoleType::Playchoice10,
+                        _ => ConsoleType::Extended(bytes[13] & 0b1111),
+                    },
+                    misc_rom_count: bytes[14] & 0b11,
+                    expansion_device: bytes[15] & 0b11_1111,
+                }
+            }
+            HeaderFormat::INes => {
+                // Old dumping tools wrote junk (e.g. "DiskDude!") into bytes
+                // 7-15, if there's anything in the last 4 bytes then flag 7 &
+                // byte 9 can't be trusted either
+                let is_clean = bytes[12..16].iter().all(|b| *b == 0);
+                let flags_7 = if is_clean { flags_7 } else { 0 };
+
+                CartridgeHeader {
+                    format,
+                    prg_rom_16kb_units: bytes[4] as u16,
+                    chr_rom_8kb_units: bytes[5] as u16,
+                    prg_rom_size: bytes[4] as usize * 0x4000,
+                    chr_rom_size: bytes[5] as usize * 0x2000,
+                    mapper: ((flags_6 >> 4) | (flags_7 & 0b1111_0000)) as u16,
+                    submapper: 0,
+                    mirroring,
+                    ram_is_battery_backed,
+                    has_trainer,
+                    prg_ram_size: if ram_is_battery_backed { 0 } else { 0x2000 },
+                    prg_nvram_size: if ram_is_battery_backed { 0x2000 } else { 0 },
+                    chr_ram_size: if bytes[5] == 0 { 0x2000 } else { 0 },
+                    chr_nvram_size: 0,
+                    timing: if is_clean && bytes[9] & 1 == 1 {
+                        TimingMode::Pal
+                    } else {
+                        TimingMode::Ntsc
+                    },
+                    console_type: match flags_7 & 0b11 {
+                        1 => ConsoleType::VsSystem {
+                            ppu_type: 0,
+                            hardware_type: 0,
+                        },
+                        2 => ConsoleType::Playchoice10,
+                        _ => ConsoleType::Nes,
+                    },
+                    misc_rom_count: 0,
+                    expansion_device: 0,
+                }
+            }
+        };
+
+        Ok(header)
+    }
+
+    /// Total PRG RAM (volatile & battery backed) mapped by the cartridge
+    pub fn total_prg_ram_size(&self) -> usize {
+        self.prg_ram_size + self.prg_nvram_size
+    }
+
+    /// Total CHR RAM (volatile & battery backed) on the cartridge
+    pub fn total_chr_ram_size(&self) -> usize {
+        self.chr_ram_size + self.chr_nvram_size
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format {
+            HeaderFormat::INes => write!(
+                f,
+                "PRG Units {}, CHR Units {}, Mapper {}",
+                self.prg_rom_16kb_units, self.chr_rom_8kb_units, self.mapper
+            ),
+            HeaderFormat::Nes2 => write!(
+                f,
+                "PRG Units {}, CHR Units {}, Mapper {}.{}",
+                self.prg_rom_16kb_units, self.chr_rom_8kb_units, self.mapper, self.submapper
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod header_tests {
+    use super::*;
+
+    fn header(bytes_4_15: [u8; 12]) -> CartridgeHeader {
+        let mut bytes = vec![b'N', b'E', b'S', 0x1A];
+        bytes.extend_from_slice(&bytes_4_15);
+        CartridgeHeader::from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let header = header([2, 1, 0b0001_0011, 0b0000_0000, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 1);
+        assert_eq!(header.mirroring, MirroringMode::Vertical);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0);
+        assert_eq!(header.timing, TimingMode::Ntsc);
+    }
+
+    #[test]
+    fn test_ines_header_ignores_junk() {
+        let mut bytes = b"NES\x1A".to_vec();
+        bytes.extend_from_slice(&[2, 0, 0b0100_0000]);
+        bytes.extend_from_slice(b"DiskDude!");
+        let header = CartridgeHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 4);
+        assert_eq!(header.chr_ram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let header = header([
+            0x20,
+            0x00,
+            0b1110_0010,
+            0b0100_1000,
+            0b0011_0001,
+            0x00,
+            0b0111_0000,
+            0b0000_0111,
+            0x01,
+            0x00,
+            0x00,
+            0x01,
+        ]);
+        assert_eq!(header.format, HeaderFormat::Nes2);
+        assert_eq!(header.mapper, 0x14E);
+        assert_eq!(header.submapper, 3);
+        assert_eq!(header.prg_rom_16kb_units, 0x20);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0x2000);
+        assert_eq!(header.chr_nvram_size, 0);
+        assert_eq!(header.timing, TimingMode::Pal);
+        assert_eq!(header.console_type, ConsoleType::Nes);
+        assert_eq!(header.expansion_device, 1);
+        assert_eq!(format!("{}", header), "PRG Units 32, CHR Units 0, Mapper 334.3");
+    }
+
+    #[test]
+    fn test_nes2_exponent_rom_size() {
+        // 2^10 * 3 bytes of PRG ROM
+        let header = header([0b0010_1001, 0, 0, 0b1000, 0, 0x0F, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.prg_rom_size, 3 * 1024);
+        assert_eq!(header.prg_rom_16kb_units, 1);
+    }
+
+    #[test]
+    fn test_nes2_exponent_rom_size_too_large() {
+        let mut bytes = vec![b'N', b'E', b'S', 0x1A, 0, 0, 0, 0b1000, 0, 0, 0, 0, 0, 0, 0, 0];
+        // 2^30 * 7 bytes of PRG ROM is more 16KB units than fit in the header struct
+        bytes[4] = 30 << 2 | 0b11;
+        bytes[9] = 0x0F;
+        assert!(CartridgeHeader::from_bytes(&bytes).is_err());
+
+        // 2^63 * 3 bytes of CHR ROM doesn't even fit in a usize
+        bytes[4] = 0;
+        bytes[5] = 63 << 2 | 0b01;
+        bytes[9] = 0xF0;
+        assert!(CartridgeHeader::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_missing_magic_is_rejected() {
+        assert!(CartridgeHeader::from_bytes(&[0; 16]).is_err());
+        assert!(CartridgeHeader::from_bytes(b"NES\x1A").is_err());
+    }
+}
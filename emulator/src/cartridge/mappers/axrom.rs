@@ -1,9 +1,10 @@
-use cartridge::mappers::{ChrBaseData, ChrData, SingleBankedPrgChip};
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, SingleBankedPrgChip};
 use cartridge::mirroring::MirroringMode;
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
 use cartridge::PpuCartridgeAddressBus;
 use log::info;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 /// AxROM doesn't bank it's CHRROM/RAM but it is possible to switch mirroring
 /// mode through PRG 4
@@ -19,6 +20,16 @@ impl AxRomChrChip {
     }
 }
 
+impl SaveState for AxRomChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl PpuCartridgeAddressBus for AxRomChrChip {
     fn check_trigger_irq(&mut self, _: bool) -> bool {
         false
@@ -63,14 +74,14 @@ pub(crate) fn from_header(
     (
         Box::new(SingleBankedPrgChip::new(
             prg_rom,
-            None,
+            prg_ram_from_header(&header, false),
             header.prg_rom_16kb_units as usize / 2,
             0b111,
             0,
             axrom_address_is_control,
         )),
         Box::new(AxRomChrChip::new(
-            ChrData::from(chr_rom),
+            ChrData::from_header(chr_rom, &header),
             MirroringMode::OneScreenLowerBank,
         )),
         header,
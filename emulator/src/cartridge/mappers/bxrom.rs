@@ -1,9 +1,10 @@
-use cartridge::mappers::{ChrBaseData, ChrData, NoBankChrChip, SingleBankedPrgChip};
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, NoBankChrChip, SingleBankedPrgChip};
 use cartridge::mirroring::MirroringMode;
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
 use cartridge::PpuCartridgeAddressBus;
 use log::info;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 #[inline]
 fn bxrom_address_is_control(address: u16) -> bool {
@@ -28,6 +29,16 @@ impl Nina001ChrChip {
     }
 }
 
+impl SaveState for Nina001ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl PpuCartridgeAddressBus for Nina001ChrChip {
     fn check_trigger_irq(&mut self, _: bool) -> bool {
         false
@@ -75,13 +86,16 @@ pub(crate) fn from_header(
             (
                 Box::new(SingleBankedPrgChip::new(
                     prg_rom,
-                    None,
+                    prg_ram_from_header(&header, false),
                     header.prg_rom_16kb_units as usize / 2,
                     0b11,
                     0,
                     bxrom_address_is_control,
                 )),
-                Box::new(NoBankChrChip::new(ChrData::from(chr_rom), header.mirroring)),
+                Box::new(NoBankChrChip::new(
+                    ChrData::from_header(chr_rom, &header),
+                    header.mirroring,
+                )),
                 header,
             )
         }
@@ -91,13 +105,13 @@ pub(crate) fn from_header(
             (
                 Box::new(SingleBankedPrgChip::new(
                     prg_rom,
-                    Some([0; 0x2000]),
+                    prg_ram_from_header(&header, true),
                     header.prg_rom_16kb_units as usize / 2,
                     0b1,
                     0,
                     nina_001_address_is_prg_control,
                 )),
-                Box::new(Nina001ChrChip::new(ChrData::from(chr_rom))),
+                Box::new(Nina001ChrChip::new(ChrData::from_header(chr_rom, &header))),
                 header,
             )
         }
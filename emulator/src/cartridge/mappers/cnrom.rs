@@ -1,4 +1,4 @@
-use cartridge::mappers::{ChrData, NoBankPrgChip, SingleBankedChrChip};
+use cartridge::mappers::{prg_ram_from_header, ChrData, NoBankPrgChip, SingleBankedChrChip};
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
 use cartridge::PpuCartridgeAddressBus;
@@ -20,9 +20,9 @@ pub(crate) fn from_header(
 ) {
     info!("Creating CNROM mapper for cartridge {:?}", header);
     (
-        Box::new(NoBankPrgChip::new(prg_rom)),
+        Box::new(NoBankPrgChip::new(prg_rom, prg_ram_from_header(&header, true))),
         Box::new(SingleBankedChrChip::new(
-            ChrData::from(chr_rom),
+            ChrData::from_header(chr_rom, &header),
             header.mirroring,
             0xFF,
             0,
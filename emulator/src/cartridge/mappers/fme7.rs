@@ -0,0 +1,803 @@
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
+use cartridge::mirroring::MirroringMode;
+use cartridge::CartridgeHeader;
+use cartridge::CpuCartridgeAddressBus;
+use cartridge::PpuCartridgeAddressBus;
+use cpu::CpuCycle;
+use log::{debug, info};
+use ppu::PpuCycle;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// Output level of a 5B channel at its loudest, chosen so that it's about as
+/// loud as an APU pulse at full volume
+const OUTPUT_LEVEL: f32 = 0.15;
+
+/// The 5B runs its tone & noise dividers at 1/16th of the CPU clock
+const TONE_PRESCALER: u8 = 16;
+
+/// The envelope steps through 32 levels so its divider runs twice as fast
+/// as the tone dividers (a full cycle is 256 * period CPU cycles)
+const ENVELOPE_PRESCALER: u8 = 8;
+
+/// A 12 bit tone generator, the square wave flips every `period` ticks
+struct Sunsoft5bTone {
+    period: u16,
+    counter: u16,
+    high: bool,
+}
+
+impl Sunsoft5bTone {
+    fn new() -> Self {
+        Sunsoft5bTone {
+            period: 0,
+            counter: 0,
+            high: false,
+        }
+    }
+
+    fn write_period_low(&mut self, value: u8) {
+        self.period = (self.period & 0xF00) | value as u16;
+    }
+
+    fn write_period_high(&mut self, value: u8) {
+        self.period = (self.period & 0xFF) | ((value & 0x0F) as u16) << 8;
+    }
+
+    fn clock(&mut self) {
+        // A period of 0 behaves the same as 1, checking >= rather than ==
+        // also catches the counter being past a newly shortened period
+        self.counter += 1;
+        if self.counter >= self.period {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+impl SaveState for Sunsoft5bTone {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.period);
+        writer.write_u16(self.counter);
+        writer.write_bool(self.high);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.period = reader.read_u16()? & 0xFFF;
+        self.counter = reader.read_u16()? & 0xFFF;
+        self.high = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+/// The noise generator is a 17 bit LFSR, stepped every `2 * period` ticks
+/// (noise runs at half the rate of a tone with the same period)
+struct Sunsoft5bNoise {
+    period: u8,
+    counter: u8,
+    shift_register: u32,
+}
+
+impl Sunsoft5bNoise {
+    fn new() -> Self {
+        Sunsoft5bNoise {
+            period: 0,
+            counter: 0,
+            shift_register: 1,
+        }
+    }
+
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period.max(1) * 2 {
+            self.counter = 0;
+            let feedback = (self.shift_register ^ (self.shift_register >> 3)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 16);
+        }
+    }
+
+    fn high(&self) -> bool {
+        self.shift_register & 1 != 0
+    }
+}
+
+impl SaveState for Sunsoft5bNoise {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.period);
+        writer.write_u8(self.counter);
+        writer.write_u32(self.shift_register);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.period = reader.read_u8()? & 0x1F;
+        self.counter = reader.read_u8()? & 0x3F;
+        self.shift_register = match reader.read_u32()? & 0x1FFFF {
+            0 => return Err(SaveStateError::new("Invalid 5B noise shift register")),
+            value => value,
+        };
+        Ok(())
+    }
+}
+
+/// The envelope ramps through 32 levels, register 0D picks the shape from
+/// four bits: continue (3), attack (2), alternate (1) & hold (0)
+struct Sunsoft5bEnvelope {
+    period: u16,
+    counter: u16,
+    /// 0..=31 through the current ramp
+    step: u8,
+    /// Ramping up rather than down, flipped on each ramp if alternating
+    attack: bool,
+    continue_cycle: bool,
+    alternate: bool,
+    hold: bool,
+    holding: bool,
+}
+
+impl Sunsoft5bEnvelope {
+    fn new() -> Self {
+        Sunsoft5bEnvelope {
+            period: 0,
+            counter: 0,
+            step: 0,
+            attack: false,
+            continue_cycle: false,
+            alternate: false,
+            hold: false,
+            holding: false,
+        }
+    }
+
+    fn write_period_low(&mut self, value: u8) {
+        self.period = (self.period & 0xFF00) | value as u16;
+    }
+
+    fn write_period_high(&mut self, value: u8) {
+        self.period = (self.period & 0xFF) | (value as u16) << 8;
+    }
+
+    /// Writing the shape restarts the envelope
+    fn write_shape(&mut self, value: u8) {
+        self.continue_cycle = value & 0b1000 != 0;
+        self.attack = value & 0b0100 != 0;
+        self.alternate = value & 0b0010 != 0;
+        self.hold = value & 0b0001 != 0;
+        self.holding = false;
+        self.step = 0;
+        self.counter = 0;
+    }
+
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter < self.period.max(1) {
+            return;
+        }
+        self.counter = 0;
+
+        if self.holding {
+            return;
+        }
+
+        self.step += 1;
+        if self.step == 32 {
+            if !self.continue_cycle {
+                // Always ends up silent
+                self.attack = false;
+                self.step = 31;
+                self.holding = true;
+            } else if self.hold {
+                if self.alternate {
+                    self.attack = !self.attack;
+                }
+                self.step = 31;
+                self.holding = true;
+            } else {
+                if self.alternate {
+                    self.attack = !self.attack;
+                }
+                self.step = 0;
+            }
+        }
+    }
+
+    fn level(&self) -> u8 {
+        match self.attack {
+            true => self.step,
+            false => 31 - self.step,
+        }
+    }
+}
+
+impl SaveState for Sunsoft5bEnvelope {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.period);
+        writer.write_u16(self.counter);
+        writer.write_u8(self.step);
+        writer.write_bool(self.attack);
+        writer.write_bool(self.continue_cycle);
+        writer.write_bool(self.alternate);
+        writer.write_bool(self.hold);
+        writer.write_bool(self.holding);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.period = reader.read_u16()?;
+        self.counter = reader.read_u16()?;
+        self.step = reader.read_u8()? & 0x1F;
+        self.attack = reader.read_bool()?;
+        self.continue_cycle = reader.read_bool()?;
+        self.alternate = reader.read_bool()?;
+        self.hold = reader.read_bool()?;
+        self.holding = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+/// The expansion audio on the Sunsoft 5B, a licensed YM2149 (itself a clone
+/// of the AY-3-8910) with three square wave channels which can each mix in
+/// the shared noise generator and take their volume from the shared envelope.
+///
+/// The channels are mixed linearly here, the real chip's output is slightly
+/// compressed at high volumes. The two I/O ports (registers 0E & 0F) aren't
+/// connected to anything on the cartridge so are ignored.
+struct Sunsoft5bAudio {
+    /// Written at C000, selects the register that E000 writes to
+    selected_register: u8,
+    tones: [Sunsoft5bTone; 3],
+    noise: Sunsoft5bNoise,
+    envelope: Sunsoft5bEnvelope,
+    /// Register 07, bits 0-2 disable each channel's tone & bits 3-5 its noise
+    mixer_disable: u8,
+    /// Registers 08-0A, bit 4 uses the envelope rather than the fixed volume
+    /// in the low 4 bits
+    volumes: [u8; 3],
+    tone_prescaler: u8,
+    envelope_prescaler: u8,
+    /// Amplitude of each of the 32 (envelope) levels, 1.5dB apart. Fixed
+    /// volumes are 3dB apart using the odd levels
+    level_table: [f32; 32],
+}
+
+impl Sunsoft5bAudio {
+    fn new() -> Self {
+        let mut level_table = [0.0; 32];
+        for (level, amplitude) in level_table.iter_mut().enumerate().skip(1) {
+            *amplitude = 10f32.powf(-1.5 * (31 - level) as f32 / 20.0) * OUTPUT_LEVEL;
+        }
+
+        Sunsoft5bAudio {
+            selected_register: 0,
+            tones: [Sunsoft5bTone::new(), Sunsoft5bTone::new(), Sunsoft5bTone::new()],
+            noise: Sunsoft5bNoise::new(),
+            envelope: Sunsoft5bEnvelope::new(),
+            mixer_disable: 0,
+            volumes: [0; 3],
+            tone_prescaler: TONE_PRESCALER,
+            envelope_prescaler: ENVELOPE_PRESCALER,
+            level_table,
+        }
+    }
+
+    fn select_register(&mut self, value: u8) {
+        self.selected_register = value & 0x0F;
+    }
+
+    fn write_register(&mut self, value: u8) {
+        match self.selected_register {
+            register @ 0x0..=0x5 => {
+                let tone = &mut self.tones[register as usize / 2];
+                match register & 1 {
+                    0 => tone.write_period_low(value),
+                    _ => tone.write_period_high(value),
+                }
+            }
+            0x6 => self.noise.period = value & 0x1F,
+            0x7 => self.mixer_disable = value & 0b0011_1111,
+            register @ 0x8..=0xA => self.volumes[register as usize - 0x8] = value & 0x1F,
+            0xB => self.envelope.write_period_low(value),
+            0xC => self.envelope.write_period_high(value),
+            0xD => self.envelope.write_shape(value),
+            _ => (),
+        }
+    }
+
+    fn clock(&mut self) -> f32 {
+        self.tone_prescaler -= 1;
+        if self.tone_prescaler == 0 {
+            self.tone_prescaler = TONE_PRESCALER;
+            for tone in self.tones.iter_mut() {
+                tone.clock();
+            }
+            self.noise.clock();
+        }
+
+        self.envelope_prescaler -= 1;
+        if self.envelope_prescaler == 0 {
+            self.envelope_prescaler = ENVELOPE_PRESCALER;
+            self.envelope.clock();
+        }
+
+        (0..3).map(|channel| self.channel_output(channel)).sum()
+    }
+
+    fn channel_output(&self, channel: usize) -> f32 {
+        // A disabled tone or noise counts as high so a channel with both
+        // disabled outputs its volume constantly
+        let tone = self.tones[channel].high || self.mixer_disable & (0b001 << channel) != 0;
+        let noise = self.noise.high() || self.mixer_disable & (0b1000 << channel) != 0;
+        if !(tone && noise) {
+            return 0.0;
+        }
+
+        let volume = self.volumes[channel];
+        let level = match volume {
+            0 => 0,
+            0x01..=0x0F => volume * 2 + 1,
+            _ => self.envelope.level(),
+        };
+
+        self.level_table[level as usize]
+    }
+}
+
+impl SaveState for Sunsoft5bAudio {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.selected_register);
+        for tone in self.tones.iter() {
+            tone.save_state(writer);
+        }
+        self.noise.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.write_u8(self.mixer_disable);
+        writer.write_bytes(&self.volumes);
+        writer.write_u8(self.tone_prescaler);
+        writer.write_u8(self.envelope_prescaler);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.selected_register = reader.read_u8()? & 0x0F;
+        for tone in self.tones.iter_mut() {
+            tone.load_state(reader)?;
+        }
+        self.noise.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.mixer_disable = reader.read_u8()? & 0b0011_1111;
+        reader.read_bytes(&mut self.volumes)?;
+        for volume in self.volumes.iter_mut() {
+            *volume &= 0x1F;
+        }
+        self.tone_prescaler = match reader.read_u8()? {
+            prescaler @ 1..=TONE_PRESCALER => prescaler,
+            _ => return Err(SaveStateError::new("Invalid 5B tone prescaler")),
+        };
+        self.envelope_prescaler = match reader.read_u8()? {
+            prescaler @ 1..=ENVELOPE_PRESCALER => prescaler,
+            _ => return Err(SaveStateError::new("Invalid 5B envelope prescaler")),
+        };
+        Ok(())
+    }
+}
+
+/// The CPU side of the FME-7 handles PRG banking (including ROM or RAM at
+/// 6000-7FFF), the IRQ counter (which is clocked by the CPU) and the 5B audio.
+///
+/// The FME-7 and 5B only differ in the audio so both are treated as a 5B,
+/// games on the FME-7 never write to the audio registers.
+pub(crate) struct Fme7PrgChip {
+    base: PrgBaseData,
+    /// Written at 8000, selects the register written by A000 writes
+    command: u8,
+    /// Command 8, the ROM bank at 6000-7FFF when RAM isn't selected
+    rom_6000_bank: usize,
+    /// Command 8 bit 6, RAM rather than ROM at 6000-7FFF
+    ram_selected: bool,
+    /// Command 8 bit 7, only applies when RAM is selected
+    ram_enabled: bool,
+    /// Command D bit 0
+    irq_enabled: bool,
+    /// Command D bit 7
+    irq_counter_enabled: bool,
+    /// Commands E & F, decremented every CPU cycle when enabled
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5bAudio,
+}
+
+impl Fme7PrgChip {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>) -> Self {
+        let total_banks = prg_rom.len() / 0x2000;
+        let banks = vec![0, 1, 2, total_banks - 1];
+        let bank_offsets = banks.iter().map(|bank| bank * 0x2000).collect();
+
+        Fme7PrgChip {
+            base: PrgBaseData::new(prg_rom, prg_ram, total_banks, 0x2000, banks, bank_offsets),
+            command: 0,
+            rom_6000_bank: 0,
+            ram_selected: false,
+            ram_enabled: false,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5bAudio::new(),
+        }
+    }
+
+    fn set_bank(&mut self, slot: usize, value: u8) {
+        self.base.banks[slot] = (value & 0b0011_1111) as usize % self.base.total_banks;
+        self.base.bank_offsets[slot] = self.base.banks[slot] * 0x2000;
+
+        info!(
+            "FME-7 PRG banks updated {:?} -> {:?}",
+            self.base.banks, self.base.bank_offsets
+        );
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0x8 => {
+                self.rom_6000_bank = (value & 0b0011_1111) as usize % self.base.total_banks;
+                self.ram_selected = value & 0b0100_0000 != 0;
+                self.ram_enabled = value & 0b1000_0000 != 0;
+                info!(
+                    "FME-7 6000 bank updated ram={} enabled={} rom bank={}",
+                    self.ram_selected, self.ram_enabled, self.rom_6000_bank
+                );
+            }
+            command @ 0x9..=0xB => self.set_bank(command as usize - 0x9, value),
+            0xD => {
+                self.irq_enabled = value & 0b0000_0001 != 0;
+                self.irq_counter_enabled = value & 0b1000_0000 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            0xF => self.irq_counter = (self.irq_counter & 0xFF) | (value as u16) << 8,
+            _ => (),
+        }
+    }
+}
+
+impl SaveState for Fme7PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_u8(self.command);
+        writer.write_usize(self.rom_6000_bank);
+        writer.write_bool(self.ram_selected);
+        writer.write_bool(self.ram_enabled);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_counter_enabled);
+        writer.write_u16(self.irq_counter);
+        writer.write_bool(self.irq_pending);
+        self.audio.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.command = reader.read_u8()? & 0x0F;
+        self.rom_6000_bank = reader.read_usize()? % self.base.total_banks;
+        self.ram_selected = reader.read_bool()?;
+        self.ram_enabled = reader.read_bool()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_counter_enabled = reader.read_bool()?;
+        self.irq_counter = reader.read_u16()?;
+        self.irq_pending = reader.read_bool()?;
+        self.audio.load_state(reader)
+    }
+}
+
+impl CpuCartridgeAddressBus for Fme7PrgChip {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if !self.ram_selected => {
+                self.base.prg_rom[self.rom_6000_bank * 0x2000 + (address as usize - 0x6000)]
+            }
+            0x6000..=0x7FFF if !self.ram_enabled => 0x0, // TODO - Should be open bus
+            _ => self.base.read_byte(address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        debug!("CPU write to FME-7 PRG bus {:04X}={:02X}", address, value);
+
+        match address {
+            0x6000..=0x7FFF if self.ram_selected && self.ram_enabled => self.base.write_byte(address, value),
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.select_register(value),
+            0xE000..=0xFFFF => self.audio.write_register(value),
+            _ => (),
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
+
+    fn clock_expansion_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+
+    fn clock_irq(&mut self) {
+        if !self.irq_counter_enabled {
+            return;
+        }
+
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+        if self.irq_counter == 0xFFFF && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn check_trigger_irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+/// The PPU side of the FME-7 handles CHR banking (8 x 1KB banks) and
+/// mirroring, it keeps its own copy of the command register.
+pub(crate) struct Fme7ChrChip {
+    base: ChrBaseData,
+    command: u8,
+}
+
+impl Fme7ChrChip {
+    fn new(chr_data: ChrData, mirroring_mode: MirroringMode) -> Self {
+        Fme7ChrChip {
+            base: ChrBaseData::new(
+                mirroring_mode,
+                chr_data,
+                0x400,
+                vec![0, 1, 2, 3, 4, 5, 6, 7],
+                vec![0x0000, 0x0400, 0x0800, 0x0C00, 0x1000, 0x1400, 0x1800, 0x1C00],
+            ),
+            command: 0,
+        }
+    }
+}
+
+impl SaveState for Fme7ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_u8(self.command);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.command = reader.read_u8()? & 0x0F;
+        Ok(())
+    }
+}
+
+impl PpuCartridgeAddressBus for Fme7ChrChip {
+    fn read_byte(&mut self, address: u16, _: PpuCycle) -> u8 {
+        self.base.read_byte(address)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        self.base.write_byte(address, value);
+    }
+
+    fn cpu_write_byte(&mut self, address: u16, value: u8, _: CpuCycle) {
+        match address {
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => match self.command {
+                slot @ 0x0..=0x7 => {
+                    let slot = slot as usize;
+                    self.base.banks[slot] = value as usize % self.base.total_banks;
+                    self.base.bank_offsets[slot] = self.base.banks[slot] * 0x400;
+
+                    info!(
+                        "FME-7 CHR banks updated {:?} -> {:?}",
+                        self.base.banks, self.base.bank_offsets
+                    );
+                }
+                0xC => {
+                    self.base.mirroring_mode = match value & 0b11 {
+                        0 => MirroringMode::Vertical,
+                        1 => MirroringMode::Horizontal,
+                        2 => MirroringMode::OneScreenLowerBank,
+                        _ => MirroringMode::OneScreenUpperBank,
+                    };
+                    info!("FME-7 mirroring mode change {:?}", self.base.mirroring_mode);
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+pub(crate) fn from_header(
+    prg_rom: Vec<u8>,
+    chr_rom: Option<Vec<u8>>,
+    header: CartridgeHeader,
+) -> (
+    Box<dyn CpuCartridgeAddressBus>,
+    Box<dyn PpuCartridgeAddressBus>,
+    CartridgeHeader,
+) {
+    info!("Creating FME-7 mapper for cartridge {:?}", header);
+    (
+        Box::new(Fme7PrgChip::new(prg_rom, prg_ram_from_header(&header, true))),
+        Box::new(Fme7ChrChip::new(
+            ChrData::from_header(chr_rom, &header),
+            header.mirroring,
+        )),
+        header,
+    )
+}
+
+#[cfg(test)]
+mod fme7_tests {
+    use super::{Fme7ChrChip, Fme7PrgChip, Sunsoft5bAudio, Sunsoft5bEnvelope, OUTPUT_LEVEL};
+    use cartridge::mappers::{numbered_banks, ChrData};
+    use cartridge::mirroring::MirroringMode;
+    use cartridge::{CpuCartridgeAddressBus, PpuCartridgeAddressBus};
+
+    fn prg_chip() -> Fme7PrgChip {
+        Fme7PrgChip::new(numbered_banks(32, 0x2000), Some(vec![0; 0x2000]))
+    }
+
+    fn chr_chip() -> Fme7ChrChip {
+        Fme7ChrChip::new(ChrData::Rom(numbered_banks(128, 0x400)), MirroringMode::Vertical)
+    }
+
+    fn command(fme7: &mut Fme7PrgChip, command: u8, value: u8) {
+        fme7.write_byte(0x8000, command, 0);
+        fme7.write_byte(0xA000, value, 0);
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut fme7 = prg_chip();
+        command(&mut fme7, 0x9, 3);
+        command(&mut fme7, 0xA, 4);
+        command(&mut fme7, 0xB, 5);
+        assert_eq!(fme7.read_byte(0x8000), 3);
+        assert_eq!(fme7.read_byte(0xA000), 4);
+        assert_eq!(fme7.read_byte(0xC000), 5);
+        assert_eq!(fme7.read_byte(0xE000), 31);
+
+        // Command 8 maps ROM, disabled RAM or RAM at 6000
+        command(&mut fme7, 0x8, 7);
+        assert_eq!(fme7.read_byte(0x6000), 7);
+        fme7.write_byte(0x6000, 0x12, 0);
+        assert_eq!(fme7.read_byte(0x6000), 7);
+
+        command(&mut fme7, 0x8, 0b0100_0000);
+        fme7.write_byte(0x6000, 0x12, 0);
+        assert_eq!(fme7.read_byte(0x6000), 0);
+
+        command(&mut fme7, 0x8, 0b1100_0000);
+        fme7.write_byte(0x6000, 0x12, 0);
+        assert_eq!(fme7.read_byte(0x6000), 0x12);
+    }
+
+    #[test]
+    fn test_chr_banking_and_mirroring() {
+        let mut fme7 = chr_chip();
+        for slot in 0..8 {
+            fme7.cpu_write_byte(0x8000, slot, 0);
+            fme7.cpu_write_byte(0xA000, 0x20 + slot, 0);
+        }
+        for slot in 0..8 {
+            assert_eq!(fme7.read_byte(slot * 0x400, 0), 0x20 + slot as u8);
+        }
+
+        fme7.cpu_write_byte(0x8000, 0xC, 0);
+        fme7.cpu_write_byte(0xA000, 1, 0);
+        assert_eq!(fme7.base.mirroring_mode, MirroringMode::Horizontal);
+        fme7.cpu_write_byte(0xA000, 3, 0);
+        assert_eq!(fme7.base.mirroring_mode, MirroringMode::OneScreenUpperBank);
+    }
+
+    #[test]
+    fn test_irq_fires_on_wrap() {
+        let mut fme7 = prg_chip();
+        command(&mut fme7, 0xE, 2);
+        command(&mut fme7, 0xF, 0);
+        command(&mut fme7, 0xD, 0b1000_0001);
+
+        fme7.clock_irq();
+        fme7.clock_irq();
+        assert!(!fme7.check_trigger_irq());
+        fme7.clock_irq();
+        assert!(fme7.check_trigger_irq());
+
+        // Any write to the control register acknowledges the IRQ
+        command(&mut fme7, 0xD, 0b1000_0001);
+        assert!(!fme7.check_trigger_irq());
+
+        // The counter keeps running with the IRQ disabled
+        command(&mut fme7, 0xD, 0b1000_0000);
+        for _ in 0..0x10000 {
+            fme7.clock_irq();
+        }
+        assert!(!fme7.check_trigger_irq());
+        assert_eq!(fme7.irq_counter, 0xFFFF);
+    }
+
+    #[test]
+    fn test_tone_period() {
+        let mut audio = Sunsoft5bAudio::new();
+        // Channel A, period 2 at full volume, noise disabled
+        for &(register, value) in [(0x0, 2), (0x1, 0), (0x7, 0b11_1000), (0x8, 0x0F)].iter() {
+            audio.select_register(register);
+            audio.write_register(value);
+        }
+
+        // The square wave flips every 2 * 16 CPU cycles
+        let samples: Vec<f32> = (0..32 * 4).map(|_| audio.clock()).collect();
+        assert!(samples[..31].iter().all(|&sample| sample == 0.0));
+        assert!(samples[31..63].iter().all(|&sample| sample > 0.0));
+        assert!(samples[63..95].iter().all(|&sample| sample == 0.0));
+        assert!((samples[31] - 0.15).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_envelope_shapes() {
+        let mut envelope = Sunsoft5bEnvelope::new();
+        envelope.period = 1;
+
+        // Attack & hold, ramps up and stays there
+        envelope.write_shape(0b1101);
+        assert_eq!(envelope.level(), 0);
+        for _ in 0..31 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 31);
+        for _ in 0..100 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 31);
+
+        // No continue, decays then stays silent
+        envelope.write_shape(0b0000);
+        assert_eq!(envelope.level(), 31);
+        for _ in 0..100 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 0);
+
+        // Alternating, a triangle wave
+        envelope.write_shape(0b1110);
+        for _ in 0..32 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.level(), 31);
+        envelope.clock();
+        assert_eq!(envelope.level(), 30);
+    }
+
+    #[test]
+    fn test_envelope_volume() {
+        let mut audio = Sunsoft5bAudio::new();
+        // Channel A with tone & noise disabled outputs its volume constantly,
+        // bit 4 of the volume takes it from the envelope instead
+        let writes = [(0x7, 0b00_1001), (0x8, 0x10), (0xB, 1), (0xC, 0), (0xD, 0b1101)];
+        for &(register, value) in writes.iter() {
+            audio.select_register(register);
+            audio.write_register(value);
+        }
+
+        // With a period of 1 the attack ramps up a level every 8 CPU cycles then holds
+        let samples: Vec<f32> = (0..512).map(|_| audio.clock()).collect();
+        assert!(samples[..7].iter().all(|&sample| sample == 0.0));
+        for level in 1..32 {
+            assert_eq!(samples[level * 8 - 1], audio.level_table[level], "{}", level);
+        }
+        assert!(samples[248..].iter().all(|&sample| sample == OUTPUT_LEVEL));
+
+        // Levels are 1.5dB apart, fixed volumes use every other level
+        let ratio = audio.level_table[29] / audio.level_table[31];
+        assert!((ratio - 10f32.powf(-3.0 / 20.0)).abs() < 0.0001, "{}", ratio);
+        audio.select_register(0x8);
+        audio.write_register(0x0F);
+        assert_eq!(audio.clock(), OUTPUT_LEVEL);
+        audio.write_register(0x07);
+        assert_eq!(audio.clock(), audio.level_table[15]);
+    }
+}
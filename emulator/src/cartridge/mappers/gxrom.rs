@@ -1,4 +1,4 @@
-use cartridge::mappers::{ChrData, SingleBankedChrChip, SingleBankedPrgChip};
+use cartridge::mappers::{prg_ram_from_header, ChrData, SingleBankedChrChip, SingleBankedPrgChip};
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
 use cartridge::PpuCartridgeAddressBus;
@@ -22,14 +22,14 @@ pub(crate) fn from_header(
     (
         Box::new(SingleBankedPrgChip::new(
             prg_rom,
-            None,
+            prg_ram_from_header(&header, false),
             header.prg_rom_16kb_units as usize / 2,
             0b11_0000,
             4,
             gxrom_address_is_control,
         )),
         Box::new(SingleBankedChrChip::new(
-            ChrData::from(chr_rom),
+            ChrData::from_header(chr_rom, &header),
             header.mirroring,
             0b11,
             0,
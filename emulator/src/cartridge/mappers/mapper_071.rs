@@ -1,20 +1,21 @@
-use cartridge::mappers::{ChrBaseData, ChrData, PrgBaseData};
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
 use cartridge::mirroring::MirroringMode;
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
 use cartridge::PpuCartridgeAddressBus;
 use log::info;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 struct Mapper71PrgChip {
     base: PrgBaseData,
 }
 
 impl Mapper71PrgChip {
-    fn new(prg_rom: Vec<u8>, total_banks: usize) -> Self {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>, total_banks: usize) -> Self {
         Mapper71PrgChip {
             base: PrgBaseData {
                 prg_rom,
-                prg_ram: None,
+                prg_ram,
                 bank_size: 0x4000,
                 total_banks,
                 banks: vec![0, total_banks - 1],
@@ -24,6 +25,16 @@ impl Mapper71PrgChip {
     }
 }
 
+impl SaveState for Mapper71PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl CpuCartridgeAddressBus for Mapper71PrgChip {
     fn read_byte(&self, address: u16) -> u8 {
         self.base.read_byte(address)
@@ -41,6 +52,14 @@ impl CpuCartridgeAddressBus for Mapper71PrgChip {
             );
         }
     }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
 }
 
 struct Mapper71ChrChip {
@@ -55,6 +74,16 @@ impl Mapper71ChrChip {
     }
 }
 
+impl SaveState for Mapper71ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl PpuCartridgeAddressBus for Mapper71ChrChip {
     fn check_trigger_irq(&mut self, _: bool) -> bool {
         false
@@ -95,8 +124,15 @@ pub(crate) fn from_header(
 ) {
     info!("Creating Mapper 71 for cartridge {:?}", header);
     (
-        Box::new(Mapper71PrgChip::new(prg_rom, header.prg_rom_16kb_units as usize)),
-        Box::new(Mapper71ChrChip::new(ChrData::from(chr_rom), header.mirroring)),
+        Box::new(Mapper71PrgChip::new(
+            prg_rom,
+            prg_ram_from_header(&header, false),
+            header.prg_rom_16kb_units as usize,
+        )),
+        Box::new(Mapper71ChrChip::new(
+            ChrData::from_header(chr_rom, &header),
+            header.mirroring,
+        )),
         header,
     )
 }
@@ -1,4 +1,4 @@
-use cartridge::mappers::{ChrBaseData, ChrData, PrgBaseData};
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
 use cartridge::mirroring::MirroringMode;
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
@@ -6,6 +6,7 @@ use cartridge::PpuCartridgeAddressBus;
 use cpu::CpuCycle;
 use log::{debug, info};
 use ppu::PpuCycle;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 #[derive(Debug, PartialEq)]
 enum PRGBankMode {
@@ -43,6 +44,21 @@ impl LoadRegister {
     }
 }
 
+impl SaveState for LoadRegister {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.shift_writes);
+        writer.write_u8(self.value);
+        writer.write_u32(self.last_write_cycle);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.shift_writes = reader.read_u8()?;
+        self.value = reader.read_u8()?;
+        self.last_write_cycle = reader.read_u32()?;
+        Ok(())
+    }
+}
+
 pub(crate) struct MMC1PrgChip {
     base: PrgBaseData,
     prg_ram_enabled: bool,
@@ -52,13 +68,13 @@ pub(crate) struct MMC1PrgChip {
 }
 
 impl MMC1PrgChip {
-    fn new(prg_rom: Vec<u8>, total_banks: usize, variant: MMC1Variant) -> Self {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>, total_banks: usize, variant: MMC1Variant) -> Self {
         debug_assert!(prg_rom.len() >= 0x4000);
 
         let mut chip = MMC1PrgChip {
             base: PrgBaseData::new(
                 prg_rom,
-                Some([0; 0x2000]), // TODO - I think this should be optional
+                prg_ram,
                 total_banks,
                 0x4000,
                 vec![0, total_banks - 1],
@@ -121,13 +137,38 @@ impl MMC1PrgChip {
     }
 }
 
+impl SaveState for MMC1PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_bool(self.prg_ram_enabled);
+        writer.write_u8(match self.prg_bank_mode {
+            PRGBankMode::Switch32KB => 0,
+            PRGBankMode::FixFirst16KB => 1,
+            PRGBankMode::FixLast16KB => 2,
+        });
+        self.load_register.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.prg_ram_enabled = reader.read_bool()?;
+        self.prg_bank_mode = match reader.read_u8()? {
+            0 => PRGBankMode::Switch32KB,
+            1 => PRGBankMode::FixFirst16KB,
+            2 => PRGBankMode::FixLast16KB,
+            _ => return Err(SaveStateError::new("Invalid MMC1 PRG bank mode")),
+        };
+        self.load_register.load_state(reader)
+    }
+}
+
 impl CpuCartridgeAddressBus for MMC1PrgChip {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
-            0x6000..=0x7FFF => match self.base.prg_ram {
+            0x6000..=0x7FFF => match &self.base.prg_ram {
                 Some(ram) => {
                     if self.prg_ram_enabled || self.variant == MMC1Variant::MMC1A {
-                        ram[(address - 0x6000) as usize]
+                        ram[(address - 0x6000) as usize % ram.len()]
                     } else {
                         0x0
                     }
@@ -159,7 +200,8 @@ impl CpuCartridgeAddressBus for MMC1PrgChip {
             0x6000..=0x7FFF => match &mut self.base.prg_ram {
                 Some(ram) => {
                     if self.prg_ram_enabled || self.variant == MMC1Variant::MMC1A {
-                        ram[(address - 0x6000) as usize] = value;
+                        let len = ram.len();
+                        ram[(address - 0x6000) as usize % len] = value;
                     }
                 }
                 None => {}
@@ -190,6 +232,14 @@ impl CpuCartridgeAddressBus for MMC1PrgChip {
             _ => (),
         }
     }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
 }
 
 pub(crate) struct MMC1ChrChip {
@@ -266,6 +316,25 @@ impl MMC1ChrChip {
     }
 }
 
+impl SaveState for MMC1ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        self.load_register.save_state(writer);
+        writer.write_bool(self.chr_bank_mode == CHRBankMode::Switch4KB);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.load_register.load_state(reader)?;
+        self.chr_bank_mode = if reader.read_bool()? {
+            CHRBankMode::Switch4KB
+        } else {
+            CHRBankMode::Switch8KB
+        };
+        Ok(())
+    }
+}
+
 impl PpuCartridgeAddressBus for MMC1ChrChip {
     fn check_trigger_irq(&mut self, _: bool) -> bool {
         false
@@ -330,6 +399,7 @@ pub(crate) fn from_header(
     (
         Box::new(MMC1PrgChip::new(
             prg_rom,
+            prg_ram_from_header(&header, true),
             header.prg_rom_16kb_units as usize,
             match header.mapper {
                 1 => MMC1Variant::MMC1,
@@ -337,7 +407,7 @@ pub(crate) fn from_header(
                 _ => panic!("Mapper {} isn't mapped to MMC1", header.mapper),
             },
         )),
-        Box::new(MMC1ChrChip::new(ChrData::from(chr_rom))),
+        Box::new(MMC1ChrChip::new(ChrData::from_header(chr_rom, &header))),
         header,
     )
 }
@@ -350,7 +420,7 @@ mod mmc1_tests {
 
     #[test]
     fn test_change_bank() {
-        let mut mmc1 = MMC1PrgChip::new(vec![0; 0x4000 * 16], 16, MMC1Variant::MMC1);
+        let mut mmc1 = MMC1PrgChip::new(vec![0; 0x4000 * 16], None, 16, MMC1Variant::MMC1);
         mmc1.write_byte(0xE000, 0b0001, 0);
         mmc1.write_byte(0xE000, 0b0000, 0);
         mmc1.write_byte(0xE000, 0b0000, 0);
@@ -362,7 +432,7 @@ mod mmc1_tests {
 
     #[test]
     fn test_change_bank_needs_wrap() {
-        let mut mmc1 = MMC1PrgChip::new(vec![0; 0x4000 * 2], 2, MMC1Variant::MMC1);
+        let mut mmc1 = MMC1PrgChip::new(vec![0; 0x4000 * 2], None, 2, MMC1Variant::MMC1);
         mmc1.write_byte(0xE000, 0b0011, 0);
         mmc1.write_byte(0xE000, 0b0001, 0);
         mmc1.write_byte(0xE000, 0b0000, 0);
@@ -374,7 +444,7 @@ mod mmc1_tests {
 
     #[test]
     fn test_ignore_sequential_writes() {
-        let mut mmc1 = MMC1PrgChip::new(vec![0; 0x4000 * 16], 16, MMC1Variant::MMC1);
+        let mut mmc1 = MMC1PrgChip::new(vec![0; 0x4000 * 16], None, 16, MMC1Variant::MMC1);
         mmc1.write_byte(0xE000, 0b0001, 0);
         mmc1.write_byte(0xE000, 0b0000, 2);
         mmc1.write_byte(0xE000, 0b0000, 4);
@@ -389,7 +459,7 @@ mod mmc1_tests {
     #[test]
     fn test_set_control_register() {
         let value = 0b1111;
-        let mut mmc1 = MMC1PrgChip::new(vec![0; 0x4000 * 16], 16, MMC1Variant::MMC1);
+        let mut mmc1 = MMC1PrgChip::new(vec![0; 0x4000 * 16], None, 16, MMC1Variant::MMC1);
         mmc1.write_byte(0x8000, 0, 0);
         mmc1.write_byte(0x8000, 0, 2);
         mmc1.write_byte(0x8000, 0, 4);
@@ -1,4 +1,4 @@
-use cartridge::mappers::{ChrBaseData, ChrData, PrgBaseData};
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
 use cartridge::mirroring::MirroringMode;
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
@@ -6,19 +6,20 @@ use cartridge::PpuCartridgeAddressBus;
 use cpu::CpuCycle;
 use log::{debug, info};
 use ppu::PpuCycle;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 struct Mmc2PrgChip {
     base: PrgBaseData,
 }
 
 impl Mmc2PrgChip {
-    fn new(prg_rom: Vec<u8>, total_banks: usize) -> Self {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>, total_banks: usize) -> Self {
         debug_assert!(total_banks >= 4);
 
         Mmc2PrgChip {
             base: PrgBaseData {
                 prg_rom,
-                prg_ram: None,
+                prg_ram,
                 total_banks,
                 bank_size: 0x2000,
                 banks: vec![0, total_banks - 3, total_banks - 2, total_banks - 1],
@@ -33,6 +34,16 @@ impl Mmc2PrgChip {
     }
 }
 
+impl SaveState for Mmc2PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl CpuCartridgeAddressBus for Mmc2PrgChip {
     fn read_byte(&self, address: u16) -> u8 {
         self.base.read_byte(address)
@@ -53,6 +64,14 @@ impl CpuCartridgeAddressBus for Mmc2PrgChip {
             );
         }
     }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
 }
 
 pub(crate) struct Mmc2Mmc4ChrChip {
@@ -76,6 +95,33 @@ impl Mmc2Mmc4ChrChip {
     }
 }
 
+impl SaveState for Mmc2Mmc4ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        for latch_value in 0..2 {
+            for bank in 0..2 {
+                writer.write_usize(self.chr_banks[latch_value][bank]);
+                writer.write_usize(self.chr_bank_offsets[latch_value][bank]);
+            }
+        }
+        writer.write_usize(self.latches[0]);
+        writer.write_usize(self.latches[1]);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        for latch_value in 0..2 {
+            for bank in 0..2 {
+                self.chr_banks[latch_value][bank] = reader.read_usize()?;
+                self.chr_bank_offsets[latch_value][bank] = reader.read_usize()?;
+            }
+        }
+        self.latches[0] = reader.read_usize()? & 1;
+        self.latches[1] = reader.read_usize()? & 1;
+        Ok(())
+    }
+}
+
 impl PpuCartridgeAddressBus for Mmc2Mmc4ChrChip {
     fn check_trigger_irq(&mut self, _: bool) -> bool {
         false
@@ -173,9 +219,13 @@ pub(crate) fn from_header(
     info!("Creating MMC2 mapper for cartridge {:?}", header);
 
     (
-        Box::new(Mmc2PrgChip::new(prg_rom, header.prg_rom_16kb_units as usize * 2)),
+        Box::new(Mmc2PrgChip::new(
+            prg_rom,
+            prg_ram_from_header(&header, false),
+            header.prg_rom_16kb_units as usize * 2,
+        )),
         Box::new(Mmc2Mmc4ChrChip::new(
-            ChrData::from(chr_rom),
+            ChrData::from_header(chr_rom, &header),
             MirroringMode::Vertical,
             false,
         )),
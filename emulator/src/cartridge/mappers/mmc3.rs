@@ -1,4 +1,4 @@
-use cartridge::mappers::{ChrBaseData, ChrData, PrgBaseData};
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
 use cartridge::mirroring::MirroringMode;
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
@@ -6,6 +6,7 @@ use cartridge::PpuCartridgeAddressBus;
 use cpu::CpuCycle;
 use log::{debug, info};
 use ppu::PpuCycle;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 #[derive(Debug)]
 enum PRGBankMode {
@@ -25,11 +26,11 @@ pub(crate) struct MMC3PrgChip {
 }
 
 impl MMC3PrgChip {
-    fn new(prg_rom: Vec<u8>, total_banks: usize) -> Self {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>, total_banks: usize) -> Self {
         MMC3PrgChip {
             base: PrgBaseData::new(
                 prg_rom,
-                Some([0; 0x2000]),
+                prg_ram,
                 total_banks,
                 0x2000,
                 vec![0, 1, total_banks - 2, total_banks - 1],
@@ -63,6 +64,29 @@ impl MMC3PrgChip {
     }
 }
 
+impl SaveState for MMC3PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_bool(self.prg_ram_readonly);
+        writer.write_bool(self.prg_ram_disabled);
+        writer.write_bool(matches!(self.bank_mode, PRGBankMode::HighBankSwappable));
+        writer.write_u8(self.bank_select);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.prg_ram_readonly = reader.read_bool()?;
+        self.prg_ram_disabled = reader.read_bool()?;
+        self.bank_mode = if reader.read_bool()? {
+            PRGBankMode::HighBankSwappable
+        } else {
+            PRGBankMode::LowBankSwappable
+        };
+        self.bank_select = reader.read_u8()? & 0b111;
+        Ok(())
+    }
+}
+
 impl CpuCartridgeAddressBus for MMC3PrgChip {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
@@ -71,7 +95,7 @@ impl CpuCartridgeAddressBus for MMC3PrgChip {
                     if self.prg_ram_disabled {
                         0x0 // TODO - Should be open bus
                     } else {
-                        ram[(address - 0x6000) as usize]
+                        ram[(address - 0x6000) as usize % ram.len()]
                     }
                 }
                 None => 0x0,
@@ -88,7 +112,8 @@ impl CpuCartridgeAddressBus for MMC3PrgChip {
             0x6000..=0x7FFF => match &mut self.base.prg_ram {
                 Some(ram) => {
                     if !self.prg_ram_disabled && !self.prg_ram_readonly {
-                        ram[(address - 0x6000) as usize] = value
+                        let len = ram.len();
+                        ram[(address - 0x6000) as usize % len] = value
                     }
                 }
                 None => {}
@@ -132,6 +157,14 @@ impl CpuCartridgeAddressBus for MMC3PrgChip {
             _ => (),
         }
     }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
 }
 
 #[derive(Debug)]
@@ -224,6 +257,37 @@ impl MMC3ChrChip {
     }
 }
 
+impl SaveState for MMC3ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_bool(matches!(self.bank_mode, CHRBankMode::HighBank2KB));
+        writer.write_u8(self.bank_select);
+        writer.write_option_u32(self.a12_cycles_at_last_low);
+        writer.write_u8(self.irq_latch);
+        writer.write_bool(self.reload_irq_next_rising_edge);
+        writer.write_u8(self.irq_counter);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_triggered);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.bank_mode = if reader.read_bool()? {
+            CHRBankMode::HighBank2KB
+        } else {
+            CHRBankMode::LowBank2KB
+        };
+        self.bank_select = reader.read_u8()? & 0b111;
+        self.a12_cycles_at_last_low = reader.read_option_u32()?;
+        self.irq_latch = reader.read_u8()?;
+        self.reload_irq_next_rising_edge = reader.read_bool()?;
+        self.irq_counter = reader.read_u8()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_triggered = reader.read_bool()?;
+        Ok(())
+    }
+}
+
 impl PpuCartridgeAddressBus for MMC3ChrChip {
     fn check_trigger_irq(&mut self, clear: bool) -> bool {
         let val = self.irq_triggered;
@@ -347,11 +411,15 @@ pub(crate) fn from_header(
     CartridgeHeader,
 ) {
     (
-        Box::new(MMC3PrgChip::new(prg_rom, header.prg_rom_16kb_units as usize * 2)),
-        Box::new(match chr_rom {
-            None => MMC3ChrChip::new(ChrData::Ram(Box::new([0; 0x2000])), header.mirroring),
-            Some(rom) => MMC3ChrChip::new(ChrData::Rom(rom), header.mirroring),
-        }),
+        Box::new(MMC3PrgChip::new(
+            prg_rom,
+            prg_ram_from_header(&header, true),
+            header.prg_rom_16kb_units as usize * 2,
+        )),
+        Box::new(MMC3ChrChip::new(
+            ChrData::from_header(chr_rom, &header),
+            header.mirroring,
+        )),
         header,
     )
 }
@@ -1,21 +1,22 @@
 use cartridge::mappers::mmc2::Mmc2Mmc4ChrChip;
-use cartridge::mappers::{ChrData, PrgBaseData};
+use cartridge::mappers::{prg_ram_from_header, ChrData, PrgBaseData};
 use cartridge::mirroring::MirroringMode;
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
 use cartridge::PpuCartridgeAddressBus;
 use log::info;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 struct Mmc4PrgChip {
     base: PrgBaseData,
 }
 
 impl Mmc4PrgChip {
-    fn new(prg_rom: Vec<u8>, total_banks: usize) -> Self {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>, total_banks: usize) -> Self {
         Mmc4PrgChip {
             base: PrgBaseData::new(
                 prg_rom,
-                None,
+                prg_ram,
                 total_banks,
                 0x4000,
                 vec![0, total_banks - 1],
@@ -25,6 +26,16 @@ impl Mmc4PrgChip {
     }
 }
 
+impl SaveState for Mmc4PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl CpuCartridgeAddressBus for Mmc4PrgChip {
     fn read_byte(&self, address: u16) -> u8 {
         self.base.read_byte(address)
@@ -45,6 +56,14 @@ impl CpuCartridgeAddressBus for Mmc4PrgChip {
             );
         }
     }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
 }
 
 pub(crate) fn from_header(
@@ -58,9 +77,13 @@ pub(crate) fn from_header(
 ) {
     info!("Creating MMC4 mapper for cartridge {:?}", header);
     (
-        Box::new(Mmc4PrgChip::new(prg_rom, header.prg_rom_16kb_units as usize)),
+        Box::new(Mmc4PrgChip::new(
+            prg_rom,
+            prg_ram_from_header(&header, false),
+            header.prg_rom_16kb_units as usize,
+        )),
         Box::new(Mmc2Mmc4ChrChip::new(
-            ChrData::from(chr_rom),
+            ChrData::from_header(chr_rom, &header),
             MirroringMode::Vertical,
             true,
         )),
@@ -0,0 +1,895 @@
+use apu::pulse_channel::PulseChannel;
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
+use cartridge::CartridgeHeader;
+use cartridge::CpuCartridgeAddressBus;
+use cartridge::PpuCartridgeAddressBus;
+use cpu::CpuCycle;
+use log::{debug, info};
+use ppu::PpuCycle;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// The MMC5 clocks the envelopes & length counters of its pulse channels at
+/// a fixed ~240Hz rather than relying on the APU frame counter
+const AUDIO_FRAME_CPU_CYCLES: u16 = 7457;
+
+/// Output level of each step of the PCM channel, chosen so that the channel
+/// at full scale is about as loud as the DMC at full scale
+const PCM_LEVEL: f32 = 0.56 / 255.0;
+
+/// The MMC5 notices that the PPU has stopped rendering when it sees no PPU
+/// reads for 3 CPU cycles
+const IDLE_PPU_CYCLES: PpuCycle = 9;
+
+/// Copy a 2 bit palette number into all four quadrants of an attribute byte
+fn attribute_byte(palette: u8) -> u8 {
+    (palette & 0b11) * 0b0101_0101
+}
+
+/// The expansion audio on the MMC5, two pulse channels (the same as the
+/// APU pulse channels without a sweep unit) and an 8 bit PCM channel.
+///
+/// Only PCM write mode is supported, read mode (and the IRQ that goes with
+/// it) isn't used by any released game.
+struct Mmc5Audio {
+    pulse_channel_1: PulseChannel,
+    pulse_channel_2: PulseChannel,
+    pcm_control: u8,
+    pcm_value: u8,
+    frame_cycles: u16,
+    is_apu_cycle: bool,
+}
+
+impl Mmc5Audio {
+    fn new() -> Self {
+        Mmc5Audio {
+            pulse_channel_1: PulseChannel::without_sweep_unit("MMC5 Pulse 1".to_string()),
+            pulse_channel_2: PulseChannel::without_sweep_unit("MMC5 Pulse 2".to_string()),
+            pcm_control: 0,
+            pcm_value: 0,
+            frame_cycles: 0,
+            is_apu_cycle: false,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000 => self.pulse_channel_1.write_duty_length_halt_envelope_register(value),
+            0x5002 => self.pulse_channel_1.load_timer_low(value),
+            0x5003 => self.pulse_channel_1.load_length_timer_high(value),
+            0x5004 => self.pulse_channel_2.write_duty_length_halt_envelope_register(value),
+            0x5006 => self.pulse_channel_2.load_timer_low(value),
+            0x5007 => self.pulse_channel_2.load_length_timer_high(value),
+            0x5010 => self.pcm_control = value,
+            // Writes of 0 are ignored in write mode as they're used to signal the end of a sample in read mode
+            0x5011 if self.pcm_control & 1 == 0 && value != 0 => self.pcm_value = value,
+            0x5015 => {
+                self.pulse_channel_1.set_enabled(value & 0b01 != 0);
+                self.pulse_channel_2.set_enabled(value & 0b10 != 0);
+            }
+            _ => (),
+        }
+    }
+
+    fn read_status(&self) -> u8 {
+        (self.pulse_channel_1.non_zero_length_counter() as u8)
+            | (self.pulse_channel_2.non_zero_length_counter() as u8) << 1
+    }
+
+    fn clock(&mut self) -> f32 {
+        self.frame_cycles += 1;
+        if self.frame_cycles == AUDIO_FRAME_CPU_CYCLES {
+            self.frame_cycles = 0;
+            self.pulse_channel_1.clock_envelope();
+            self.pulse_channel_2.clock_envelope();
+            self.pulse_channel_1.clock_length_counter();
+            self.pulse_channel_2.clock_length_counter();
+        }
+
+        if self.is_apu_cycle {
+            self.pulse_channel_1.clock_timer();
+            self.pulse_channel_2.clock_timer();
+        }
+        self.is_apu_cycle = !self.is_apu_cycle;
+
+        // The pulse channels go through the same nonlinear DAC as the APU pulses, c.f. apu::mixer
+        let pulses = self.pulse_channel_1.mixer_value() + self.pulse_channel_2.mixer_value();
+        let pulse_out = match pulses {
+            0 => 0.0,
+            _ => 95.52 / (8128.0 / pulses as f32 + 100.0),
+        };
+
+        pulse_out + self.pcm_value as f32 * PCM_LEVEL
+    }
+}
+
+impl SaveState for Mmc5Audio {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.pulse_channel_1.save_state(writer);
+        self.pulse_channel_2.save_state(writer);
+        writer.write_u8(self.pcm_control);
+        writer.write_u8(self.pcm_value);
+        writer.write_u16(self.frame_cycles);
+        writer.write_bool(self.is_apu_cycle);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.pulse_channel_1.load_state(reader)?;
+        self.pulse_channel_2.load_state(reader)?;
+        self.pcm_control = reader.read_u8()?;
+        self.pcm_value = reader.read_u8()?;
+        self.frame_cycles = reader.read_u16()? % AUDIO_FRAME_CPU_CYCLES;
+        self.is_apu_cycle = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+/// The CPU side of the MMC5 handles PRG banking, PRG RAM, the multiplier
+/// and audio. ExRAM and the scanline IRQ are handled by the PPU side as
+/// they depend on what the PPU is doing.
+pub(crate) struct Mmc5PrgChip {
+    /// Banks & offsets for the four 8KB ROM slots at 8000-FFFF
+    base: PrgBaseData,
+    prg_mode: u8,
+    /// Registers 5113-5117, the first selects the RAM bank at 6000-7FFF
+    prg_registers: [u8; 5],
+    /// Offset into PRG RAM for 6000-7FFF and each 8KB slot at 8000-FFFF
+    /// which is currently mapped to RAM rather than ROM
+    ram_offsets: [Option<usize>; 5],
+    /// Registers 5102 & 5103 which must be set to 0b10 & 0b01 to allow PRG RAM writes
+    ram_protect: [u8; 2],
+    multiplicand: u8,
+    multiplier: u8,
+    audio: Mmc5Audio,
+}
+
+impl Mmc5PrgChip {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>) -> Self {
+        let total_banks = prg_rom.len().max(0x8000) / 0x2000;
+        let mut chip = Mmc5PrgChip {
+            base: PrgBaseData::new(prg_rom, prg_ram, total_banks, 0x2000, vec![0; 4], vec![0; 4]),
+            prg_mode: 3,
+            prg_registers: [0xFF; 5],
+            ram_offsets: [None; 5],
+            ram_protect: [0; 2],
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            audio: Mmc5Audio::new(),
+        };
+        chip.update_banks();
+
+        chip
+    }
+
+    fn ram_offset(&self, bank: u8) -> Option<usize> {
+        self.base
+            .prg_ram
+            .as_ref()
+            .map(|ram| ((bank & 0b111) as usize * 0x2000) % ram.len())
+    }
+
+    fn update_banks(&mut self) {
+        let registers = self.prg_registers;
+        for slot in 0..4 {
+            // Each mode maps a different register to each slot, larger banks ignore the low bits of the register
+            let (register, bank) = match (self.prg_mode, slot) {
+                (0, _) => (4, (registers[4] & 0b0111_1100) | slot as u8),
+                (1, 0..=1) | (2, 0..=1) => (2, (registers[2] & 0b0111_1110) | slot as u8),
+                (1, _) => (4, (registers[4] & 0b0111_1110) | (slot as u8 & 1)),
+                (2, 2) => (3, registers[3]),
+                (2, _) => (4, registers[4]),
+                _ => (slot + 1, registers[slot + 1]),
+            };
+
+            // The top bit selects ROM over RAM, 5117 can only ever select ROM
+            if register == 4 || registers[register] & 0b1000_0000 != 0 {
+                let bank = (bank & 0b0111_1111) as usize % self.base.total_banks;
+                self.base.banks[slot] = bank;
+                self.base.bank_offsets[slot] = bank * 0x2000;
+                self.ram_offsets[slot + 1] = None;
+            } else {
+                self.ram_offsets[slot + 1] = self.ram_offset(bank);
+            }
+        }
+        self.ram_offsets[0] = self.ram_offset(registers[0]);
+
+        info!(
+            "MMC5 PRG banks updated {:?} -> {:?}, RAM {:?}",
+            self.base.banks, self.base.bank_offsets, self.ram_offsets
+        );
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_protect == [0b10, 0b01]
+    }
+}
+
+impl SaveState for Mmc5PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_u8(self.prg_mode);
+        writer.write_bytes(&self.prg_registers);
+        writer.write_bytes(&self.ram_protect);
+        writer.write_u8(self.multiplicand);
+        writer.write_u8(self.multiplier);
+        self.audio.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.prg_mode = reader.read_u8()? & 0b11;
+        reader.read_bytes(&mut self.prg_registers)?;
+        reader.read_bytes(&mut self.ram_protect)?;
+        self.multiplicand = reader.read_u8()?;
+        self.multiplier = reader.read_u8()?;
+        self.audio.load_state(reader)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+impl CpuCartridgeAddressBus for Mmc5PrgChip {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x5015 => self.audio.read_status(),
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x6000..=0xFFFF => {
+                let slot = (address as usize - 0x6000) / 0x2000;
+                match (&self.base.prg_ram, self.ram_offsets[slot]) {
+                    (Some(ram), Some(offset)) => ram[(offset + (address as usize & 0x1FFF)) % ram.len()],
+                    _ if slot == 0 => 0x0, // TODO - Should be open bus
+                    _ => self.base.read_byte(address),
+                }
+            }
+            _ => 0x0,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        debug!("CPU write to MMC5 PRG bus {:04X}={:02X}", address, value);
+
+        match address {
+            0x5000..=0x5015 => self.audio.write_register(address, value),
+            0x5100 => {
+                self.prg_mode = value & 0b11;
+                self.update_banks();
+            }
+            0x5102 | 0x5103 => self.ram_protect[address as usize - 0x5102] = value & 0b11,
+            0x5113..=0x5117 => {
+                self.prg_registers[address as usize - 0x5113] = value;
+                self.update_banks();
+            }
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x6000..=0xDFFF => {
+                let slot = (address as usize - 0x6000) / 0x2000;
+                let writable = self.ram_writable();
+                if let (Some(ram), Some(offset), true) = (&mut self.base.prg_ram, self.ram_offsets[slot], writable) {
+                    let len = ram.len();
+                    ram[(offset + (address as usize & 0x1FFF)) % len] = value;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
+
+    fn clock_expansion_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+}
+
+/// The PPU side of the MMC5 handles CHR banking, nametable mapping (CIRAM,
+/// ExRAM & fill mode), extended attributes, the vertical split and the
+/// scanline IRQ.
+///
+/// The MMC5 has no access to the PPU registers other than snooping writes
+/// to 2000/2001 so everything is based on the PPU reads it sees. A new
+/// scanline is detected when the same nametable address is read three
+/// times in a row (the two unused fetches at the end of a line followed by
+/// the first fetch of the next) and counting nametable fetches from there
+/// tells it whether the PPU is fetching background or sprite tiles.
+pub(crate) struct Mmc5ChrChip {
+    /// CHR data & CIRAM, the bank offsets are those of the sprite (5120-5127) registers
+    base: ChrBaseData,
+    chr_mode: u8,
+    /// Registers 5120-512B with the upper bits from 5130 at the time they were written
+    chr_registers: [u16; 12],
+    chr_upper_bits: u8,
+    /// Offsets of each 1KB slot using the background (5128-512B) registers
+    background_bank_offsets: [usize; 8],
+    /// Outside of 8x16 sprite rendering whichever set of registers was written last is used
+    last_written_background: bool,
+    large_sprites: bool,
+    rendering_enabled: bool,
+    exram_mode: u8,
+    exram: [u8; 0x400],
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+    last_read_cycle: PpuCycle,
+    last_read_address: u16,
+    /// Number of consecutive reads from the same address
+    matching_reads: u8,
+    /// Nametable fetches since the start of the scanline, 0-31 are
+    /// background tiles, 32-39 the garbage fetches during sprite loading and
+    /// 40-41 the first two tiles of the next line
+    nametable_fetches: u8,
+    /// The ExRAM byte for the current tile in extended attribute mode
+    extended_attribute: u8,
+    /// ExRAM index of the current tile if it is in the vertical split region
+    split_tile: Option<u16>,
+    split_fine_y: u8,
+}
+
+impl Mmc5ChrChip {
+    fn new(chr_data: ChrData, header: &CartridgeHeader) -> Self {
+        let mut chip = Mmc5ChrChip {
+            base: ChrBaseData::new(header.mirroring, chr_data, 0x400, vec![0; 8], vec![0; 8]),
+            chr_mode: 0,
+            chr_registers: [0; 12],
+            chr_upper_bits: 0,
+            background_bank_offsets: [0; 8],
+            last_written_background: false,
+            large_sprites: false,
+            rendering_enabled: false,
+            exram_mode: 0,
+            exram: [0; 0x400],
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            last_read_cycle: 0,
+            last_read_address: 0,
+            matching_reads: 0,
+            nametable_fetches: 0,
+            extended_attribute: 0,
+            split_tile: None,
+            split_fine_y: 0,
+        };
+        chip.update_banks();
+
+        chip
+    }
+
+    fn update_banks(&mut self) {
+        let registers = self.chr_registers.map(|r| r as usize);
+        for slot in 0..8 {
+            let sprite_bank = match self.chr_mode {
+                0 => registers[7] * 8 + slot,
+                1 => registers[if slot < 4 { 3 } else { 7 }] * 4 + (slot & 3),
+                2 => registers[slot | 1] * 2 + (slot & 1),
+                _ => registers[slot],
+            };
+            // The background registers only cover 4KB which is repeated in both pattern tables
+            let background_bank = match self.chr_mode {
+                0 => registers[11] * 8 + slot,
+                1 => registers[11] * 4 + (slot & 3),
+                2 => registers[if slot & 3 < 2 { 9 } else { 11 }] * 2 + (slot & 1),
+                _ => registers[8 + (slot & 3)],
+            };
+
+            self.base.banks[slot] = sprite_bank % self.base.total_banks;
+            self.base.bank_offsets[slot] = self.base.banks[slot] * 0x400;
+            self.background_bank_offsets[slot] = (background_bank % self.base.total_banks) * 0x400;
+        }
+
+        info!(
+            "MMC5 CHR bank offsets updated {:?}, {:?}",
+            self.base.bank_offsets, self.background_bank_offsets
+        );
+    }
+
+    fn read_chr(&self, offset: usize) -> u8 {
+        match &self.base.chr_data {
+            ChrData::Rom(data) | ChrData::Ram(data) => data[offset % data.len()],
+        }
+    }
+
+    /// Pattern table offset outside of rendering (e.g. 2007 reads)
+    fn chr_offset(&self, address: u16, use_background: bool) -> usize {
+        let slot = address as usize / 0x400;
+        let bank_offset = if use_background {
+            self.background_bank_offsets[slot]
+        } else {
+            self.base.bank_offsets[slot]
+        };
+
+        bank_offset + (address as usize & 0x3FF)
+    }
+
+    fn is_background_fetch(&self) -> bool {
+        !(32..40).contains(&self.nametable_fetches)
+    }
+
+    /// Checks whether the PPU has stopped rendering since the last read
+    fn check_idle(&mut self, cycles: PpuCycle) -> bool {
+        let idle = !self.rendering_enabled || cycles.wrapping_sub(self.last_read_cycle) > IDLE_PPU_CYCLES;
+        if idle {
+            self.in_frame = false;
+        }
+
+        idle
+    }
+
+    fn detect_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                info!("MMC5 IRQ pending on scanline {}", self.scanline);
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+        }
+    }
+
+    /// Called for every nametable (not attribute) fetch whilst rendering,
+    /// sets up the split & extended attribute state for the tile
+    fn nametable_fetch(&mut self, address: u16) {
+        if self.matching_reads >= 2 {
+            if self.matching_reads == 2 {
+                self.detect_scanline();
+            }
+            self.nametable_fetches = 0;
+        } else {
+            self.nametable_fetches = self.nametable_fetches.saturating_add(1);
+        }
+
+        self.split_tile = None;
+        if !self.is_background_fetch() {
+            return;
+        }
+
+        if self.exram_mode <= 1 && self.split_control & 0b1000_0000 != 0 {
+            // The first fetch of the line is for the third tile, the last two are for the next line
+            let column = (self.nametable_fetches + 2) % 42;
+            let delimiter = self.split_control & 0b1_1111;
+            let in_split = match self.split_control & 0b0100_0000 {
+                0 => column < delimiter,
+                _ => column >= delimiter,
+            };
+
+            if column < 34 && in_split {
+                let line = match (self.in_frame, self.nametable_fetches >= 40) {
+                    (false, _) => 0,
+                    (true, false) => self.scanline as u16,
+                    (true, true) => self.scanline as u16 + 1,
+                };
+                let y = (self.split_scroll as u16 + line) % 240;
+                self.split_tile = Some((y / 8) * 32 + (column as u16 & 0b1_1111));
+                self.split_fine_y = (y & 0b111) as u8;
+            }
+        }
+
+        if self.exram_mode == 1 {
+            self.extended_attribute = self.exram[address as usize & 0x3FF];
+        }
+    }
+
+    fn read_nametable(&self, address: u16) -> u8 {
+        let offset = address as usize & 0x3FF;
+        match (self.nametable_mapping >> (((address >> 10) & 0b11) * 2)) & 0b11 {
+            0 => self.base.ppu_vram[offset],
+            1 => self.base.ppu_vram[0x400 + offset],
+            2 if self.exram_mode <= 1 => self.exram[offset],
+            2 => 0x0,
+            _ if offset < 0x3C0 => self.fill_tile,
+            _ => attribute_byte(self.fill_attribute),
+        }
+    }
+
+    fn render_read(&mut self, address: u16) -> u8 {
+        let offset = address as usize & 0x3FF;
+        match address {
+            0x0000..=0x1FFF => {
+                let background = self.is_background_fetch();
+                match (background, self.split_tile, self.exram_mode) {
+                    (true, Some(_), _) => self.read_chr(
+                        self.split_bank as usize * 0x1000 + (address as usize & 0xFF8) + self.split_fine_y as usize,
+                    ),
+                    (true, None, 1) => {
+                        let bank = (self.extended_attribute & 0b11_1111) as usize | (self.chr_upper_bits as usize) << 6;
+                        self.read_chr(bank * 0x1000 + (address as usize & 0xFFF))
+                    }
+                    _ => {
+                        let use_background = if self.large_sprites {
+                            background
+                        } else {
+                            self.last_written_background
+                        };
+                        self.read_chr(self.chr_offset(address, use_background))
+                    }
+                }
+            }
+            _ if offset < 0x3C0 => {
+                self.nametable_fetch(address);
+                match self.split_tile {
+                    Some(tile) => self.exram[tile as usize],
+                    None => self.read_nametable(address),
+                }
+            }
+            _ => match (self.is_background_fetch(), self.split_tile, self.exram_mode) {
+                (true, Some(tile), _) => {
+                    let (row, column) = (tile >> 5, tile & 0b1_1111);
+                    let attribute = self.exram[0x3C0 + (row as usize / 4) * 8 + column as usize / 4];
+                    attribute_byte(attribute >> ((row & 0b10) * 2 + (column & 0b10)))
+                }
+                (true, None, 1) => attribute_byte(self.extended_attribute >> 6),
+                _ => self.read_nametable(address),
+            },
+        }
+    }
+}
+
+impl SaveState for Mmc5ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_u8(self.chr_mode);
+        for register in &self.chr_registers {
+            writer.write_u16(*register);
+        }
+        writer.write_u8(self.chr_upper_bits);
+        writer.write_bool(self.last_written_background);
+        writer.write_bool(self.large_sprites);
+        writer.write_bool(self.rendering_enabled);
+        writer.write_u8(self.exram_mode);
+        writer.write_bytes(&self.exram);
+        writer.write_u8(self.nametable_mapping);
+        writer.write_u8(self.fill_tile);
+        writer.write_u8(self.fill_attribute);
+        writer.write_u8(self.split_control);
+        writer.write_u8(self.split_scroll);
+        writer.write_u8(self.split_bank);
+        writer.write_u8(self.irq_compare);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending);
+        writer.write_bool(self.in_frame);
+        writer.write_u8(self.scanline);
+        writer.write_u32(self.last_read_cycle);
+        writer.write_u16(self.last_read_address);
+        writer.write_u8(self.matching_reads);
+        writer.write_u8(self.nametable_fetches);
+        writer.write_u8(self.extended_attribute);
+        writer.write_option_u16(self.split_tile);
+        writer.write_u8(self.split_fine_y);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.chr_mode = reader.read_u8()? & 0b11;
+        for register in self.chr_registers.iter_mut() {
+            *register = reader.read_u16()?;
+        }
+        self.chr_upper_bits = reader.read_u8()? & 0b11;
+        self.last_written_background = reader.read_bool()?;
+        self.large_sprites = reader.read_bool()?;
+        self.rendering_enabled = reader.read_bool()?;
+        self.exram_mode = reader.read_u8()? & 0b11;
+        reader.read_bytes(&mut self.exram)?;
+        self.nametable_mapping = reader.read_u8()?;
+        self.fill_tile = reader.read_u8()?;
+        self.fill_attribute = reader.read_u8()? & 0b11;
+        self.split_control = reader.read_u8()?;
+        self.split_scroll = reader.read_u8()?;
+        self.split_bank = reader.read_u8()?;
+        self.irq_compare = reader.read_u8()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        self.in_frame = reader.read_bool()?;
+        self.scanline = reader.read_u8()?;
+        self.last_read_cycle = reader.read_u32()?;
+        self.last_read_address = reader.read_u16()?;
+        self.matching_reads = reader.read_u8()?;
+        self.nametable_fetches = reader.read_u8()?;
+        self.extended_attribute = reader.read_u8()?;
+        self.split_tile = reader.read_option_u16()?;
+        if matches!(self.split_tile, Some(tile) if tile >= 0x3C0) {
+            return Err(SaveStateError::new("Invalid MMC5 split tile"));
+        }
+        self.split_fine_y = reader.read_u8()? & 0b111;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+impl PpuCartridgeAddressBus for Mmc5ChrChip {
+    fn check_trigger_irq(&mut self, _: bool) -> bool {
+        // The IRQ is only acknowledged by reading 5204
+        self.irq_enabled && self.irq_pending
+    }
+
+    fn update_vram_address(&mut self, _: u16, _: PpuCycle) {}
+
+    fn read_byte(&mut self, address: u16, cycles: PpuCycle) -> u8 {
+        let rendering = !self.check_idle(cycles);
+        self.last_read_cycle = cycles;
+
+        self.matching_reads = if address == self.last_read_address {
+            self.matching_reads.saturating_add(1)
+        } else {
+            0
+        };
+        self.last_read_address = address;
+
+        if rendering {
+            self.render_read(address)
+        } else {
+            // The first fetch after the PPU starts rendering is the first nametable fetch of a line
+            self.nametable_fetches = 0;
+            self.split_tile = None;
+            match address {
+                0x0000..=0x1FFF => self.read_chr(self.chr_offset(address, self.last_written_background)),
+                _ => self.read_nametable(address),
+            }
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        match address {
+            0x0000..=0x1FFF => {
+                let offset = self.chr_offset(address, self.last_written_background);
+                if let ChrData::Ram(ram) = &mut self.base.chr_data {
+                    let len = ram.len();
+                    ram[offset % len] = value;
+                }
+            }
+            _ => {
+                let offset = address as usize & 0x3FF;
+                match (self.nametable_mapping >> (((address >> 10) & 0b11) * 2)) & 0b11 {
+                    0 => self.base.ppu_vram[offset] = value,
+                    1 => self.base.ppu_vram[0x400 + offset] = value,
+                    2 if self.exram_mode <= 1 => self.exram[offset] = value,
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn cpu_write_byte(&mut self, address: u16, value: u8, _: CpuCycle) {
+        debug!("CPU write to MMC5 CHR bus {:04X}={:02X}", address, value);
+
+        match address {
+            0x5101 => {
+                self.chr_mode = value & 0b11;
+                self.update_banks();
+            }
+            0x5104 => self.exram_mode = value & 0b11,
+            0x5105 => self.nametable_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0b11,
+            0x5120..=0x512B => {
+                let register = address as usize - 0x5120;
+                self.chr_registers[register] = value as u16 | (self.chr_upper_bits as u16) << 8;
+                self.last_written_background = register >= 8;
+                self.update_banks();
+            }
+            0x5130 => self.chr_upper_bits = value & 0b11,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0b1000_0000 != 0,
+            // In the nametable modes ExRAM can only be written whilst rendering, otherwise 0 is written
+            0x5C00..=0x5FFF => match self.exram_mode {
+                0 | 1 => self.exram[address as usize - 0x5C00] = if self.in_frame { value } else { 0 },
+                2 => self.exram[address as usize - 0x5C00] = value,
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    fn cpu_read_byte(&mut self, address: u16, cycles: PpuCycle) -> Option<u8> {
+        self.check_idle(cycles);
+
+        match address {
+            0x5204 => {
+                let status = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending = false;
+                Some(status)
+            }
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[address as usize - 0x5C00]),
+            // The NMI vector being read also marks the end of the frame
+            0xFFFA | 0xFFFB => {
+                self.in_frame = false;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x2000 => self.large_sprites = value & 0b0010_0000 != 0,
+            0x2001 => {
+                self.rendering_enabled = value & 0b0001_1000 != 0;
+                if !self.rendering_enabled {
+                    self.in_frame = false;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+pub(crate) fn from_header(
+    prg_rom: Vec<u8>,
+    chr_rom: Option<Vec<u8>>,
+    header: CartridgeHeader,
+) -> (
+    Box<dyn CpuCartridgeAddressBus>,
+    Box<dyn PpuCartridgeAddressBus>,
+    CartridgeHeader,
+) {
+    info!("Creating MMC5 mapper for cartridge {:?}", header);
+    (
+        Box::new(Mmc5PrgChip::new(prg_rom, prg_ram_from_header(&header, true))),
+        Box::new(Mmc5ChrChip::new(ChrData::from_header(chr_rom, &header), &header)),
+        header,
+    )
+}
+
+#[cfg(test)]
+mod mmc5_tests {
+    use super::{Mmc5ChrChip, Mmc5PrgChip};
+    use cartridge::mappers::{numbered_banks, ChrData};
+    use cartridge::{CartridgeHeader, CpuCartridgeAddressBus, PpuCartridgeAddressBus};
+    use ppu::PpuCycle;
+
+    fn prg_chip() -> Mmc5PrgChip {
+        Mmc5PrgChip::new(numbered_banks(16, 0x2000), Some(vec![0; 0x8000]))
+    }
+
+    fn chr_chip() -> Mmc5ChrChip {
+        let header =
+            CartridgeHeader::from_bytes(&[b'N', b'E', b'S', 0x1A, 8, 16, 0x50, 0, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        let mut chip = Mmc5ChrChip::new(ChrData::Rom(vec![0; 0x20000]), &header);
+        chip.ppu_register_write(0x2001, 0b0001_1000);
+        chip
+    }
+
+    /// Feed the chip the sequence of reads the PPU makes for a scanline
+    fn render_line(chip: &mut Mmc5ChrChip, cycles: &mut PpuCycle) {
+        let mut read = |chip: &mut Mmc5ChrChip, address: u16| {
+            *cycles += 2;
+            chip.read_byte(address, *cycles);
+        };
+        for tile in (2..34).chain(0..2) {
+            read(chip, 0x2000 + tile);
+            read(chip, 0x23C0);
+            read(chip, 0x0000);
+            read(chip, 0x0008);
+            if tile == 33 {
+                for _ in 0..8 {
+                    read(chip, 0x2000);
+                    read(chip, 0x2000);
+                    read(chip, 0x1000);
+                    read(chip, 0x1008);
+                }
+            }
+        }
+        read(chip, 0x2002);
+        read(chip, 0x2002);
+    }
+
+    #[test]
+    fn test_prg_banking_modes() {
+        let mut mmc5 = prg_chip();
+        assert_eq!(mmc5.base.banks, vec![15, 15, 15, 15]);
+
+        mmc5.write_byte(0x5100, 1, 0);
+        mmc5.write_byte(0x5115, 0x85, 0);
+        assert_eq!(mmc5.read_byte(0x8000), 4);
+        assert_eq!(mmc5.read_byte(0xA000), 5);
+        assert_eq!(mmc5.read_byte(0xC000), 14);
+        assert_eq!(mmc5.read_byte(0xE000), 15);
+
+        mmc5.write_byte(0x5100, 2, 0);
+        mmc5.write_byte(0x5116, 0x02, 0);
+        mmc5.write_byte(0x5117, 0x07, 0);
+        assert_eq!(mmc5.ram_offsets[3], Some(0x4000));
+        assert_eq!(mmc5.read_byte(0xE000), 7);
+
+        mmc5.write_byte(0x5100, 0, 0);
+        assert_eq!(mmc5.base.banks, vec![4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_prg_ram_write_protect() {
+        let mut mmc5 = prg_chip();
+        mmc5.write_byte(0x6000, 0x12, 0);
+        assert_eq!(mmc5.read_byte(0x6000), 0);
+
+        mmc5.write_byte(0x5102, 0b10, 0);
+        mmc5.write_byte(0x5103, 0b01, 0);
+        mmc5.write_byte(0x6000, 0x12, 0);
+        assert_eq!(mmc5.read_byte(0x6000), 0x12);
+
+        // The same RAM bank can be mapped into the ROM area
+        mmc5.write_byte(0x5100, 3, 0);
+        mmc5.write_byte(0x5114, 0x07, 0);
+        mmc5.write_byte(0x5113, 0x07, 0);
+        assert_eq!(mmc5.read_byte(0x8000), 0x12);
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let mut mmc5 = prg_chip();
+        mmc5.write_byte(0x5205, 0xFE, 0);
+        mmc5.write_byte(0x5206, 0x13, 0);
+        assert_eq!(mmc5.read_byte(0x5205), 0xDA);
+        assert_eq!(mmc5.read_byte(0x5206), 0x12);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc5 = chr_chip();
+        let mut cycles = 100;
+        mmc5.cpu_write_byte(0x5203, 3, 0);
+        mmc5.cpu_write_byte(0x5204, 0x80, 0);
+
+        // The first line is the pre-render line, the IRQ is on the 4th visible line
+        for _ in 0..4 {
+            render_line(&mut mmc5, &mut cycles);
+        }
+        assert!(!mmc5.check_trigger_irq(false));
+        render_line(&mut mmc5, &mut cycles);
+        assert!(mmc5.check_trigger_irq(false));
+
+        assert_eq!(mmc5.cpu_read_byte(0x5204, cycles), Some(0xC0));
+        assert_eq!(mmc5.cpu_read_byte(0x5204, cycles), Some(0x40));
+        assert!(!mmc5.check_trigger_irq(false));
+
+        // No reads for a while means the PPU is no longer rendering
+        assert_eq!(mmc5.cpu_read_byte(0x5204, cycles + 20), Some(0x00));
+    }
+
+    #[test]
+    fn test_nametable_mapping() {
+        let mut mmc5 = chr_chip();
+        mmc5.cpu_write_byte(0x5105, 0b11_10_01_00, 0);
+        mmc5.cpu_write_byte(0x5106, 0x42, 0);
+        mmc5.cpu_write_byte(0x5107, 0x02, 0);
+        mmc5.write_byte(0x2000, 1, 0);
+        mmc5.write_byte(0x2400, 2, 0);
+        mmc5.write_byte(0x2800, 3, 0);
+
+        assert_eq!(mmc5.read_byte(0x2000, 0), 1);
+        assert_eq!(mmc5.read_byte(0x2400, 0), 2);
+        assert_eq!(mmc5.read_byte(0x2800, 0), 3);
+        assert_eq!(mmc5.read_byte(0x2C00, 0), 0x42);
+        assert_eq!(mmc5.read_byte(0x2FC0, 0), 0b1010_1010);
+
+        // ExRAM is only readable by the CPU in modes 2 & 3 and not usable as a nametable in those modes
+        assert_eq!(mmc5.cpu_read_byte(0x5C00, 0), None);
+        mmc5.cpu_write_byte(0x5104, 2, 0);
+        mmc5.cpu_write_byte(0x5C00, 0x99, 0);
+        assert_eq!(mmc5.cpu_read_byte(0x5C00, 0), Some(0x99));
+        assert_eq!(mmc5.read_byte(0x2800, 0), 0);
+    }
+}
@@ -1,36 +1,73 @@
 use cartridge::mirroring::MirroringMode;
-use cartridge::{CpuCartridgeAddressBus, PpuCartridgeAddressBus};
+use cartridge::{CartridgeHeader, CpuCartridgeAddressBus, HeaderFormat, PpuCartridgeAddressBus};
 use log::{debug, info};
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 pub(super) mod axrom; // Mapper 7
 pub(super) mod bxrom; // Mapper 34 (note this is both BxROM and NINA-001 boards)
 pub(super) mod cnrom; // Mapper 3
 pub(super) mod color_dreams; // Mapper 11
+pub(super) mod fme7; // Mapper 69
 pub(super) mod gxrom; // Mapper 66
 pub(super) mod mapper_071; // Mapper 71
 pub(super) mod mmc1; // Mapper 1
 pub(super) mod mmc2; // Mapper 9
 pub(super) mod mmc3; // Mapper 4
 pub(super) mod mmc4; // Mapper 10
+pub(super) mod mmc5; // Mapper 5
+pub(super) mod namco_163; // Mapper 19
 pub(super) mod nina_003_006; // Mapper 079
 pub(super) mod nrom; // Mapper 0
+pub(super) mod opll; // FM synthesiser on the VRC7
 pub(super) mod uxrom; // Mapper 2, 94, 180
+pub(super) mod vrc2_4; // Mapper 21, 22, 23, 25
+pub(super) mod vrc6; // Mapper 24, 26
+pub(super) mod vrc7; // Mapper 85
+pub(super) mod vrc_irq; // IRQ counter shared by the Konami VRC mappers
 
 #[derive(Debug)]
 pub(crate) enum ChrData {
     Rom(Vec<u8>),
-    Ram(Box<[u8; 0x2000]>),
+    Ram(Vec<u8>),
 }
 
-impl From<Option<Vec<u8>>> for ChrData {
-    fn from(chr_rom: Option<Vec<u8>>) -> Self {
+impl ChrData {
+    /// CHR RAM is sized from the header, falling back to 8KB if there's
+    /// neither CHR ROM nor declared CHR RAM
+    pub(crate) fn from_header(chr_rom: Option<Vec<u8>>, header: &CartridgeHeader) -> Self {
         match chr_rom {
             Some(rom) => ChrData::Rom(rom),
-            None => ChrData::Ram(Box::new([0; 0x2000])),
+            None => match header.total_chr_ram_size() {
+                0 => ChrData::Ram(vec![0; 0x2000]),
+                size => ChrData::Ram(vec![0; size]),
+            },
         }
     }
 }
 
+/// PRG RAM at 0x6000-0x7FFF, NES 2.0 headers declare whether it exists and
+/// its size. iNES headers can't so the mapper says whether the board
+/// normally has RAM and it's assumed to be the header default of 8KB
+pub(crate) fn prg_ram_from_header(header: &CartridgeHeader, ines_has_ram: bool) -> Option<Vec<u8>> {
+    let size = match header.format {
+        HeaderFormat::Nes2 => header.total_prg_ram_size(),
+        HeaderFormat::INes if ines_has_ram => header.total_prg_ram_size(),
+        HeaderFormat::INes => 0,
+    };
+
+    match size {
+        0 => None,
+        _ => Some(vec![0; size]),
+    }
+}
+
+/// Test ROM where each bank is filled with its own bank number so a read
+/// shows which bank is mapped in
+#[cfg(test)]
+pub(crate) fn numbered_banks(total_banks: usize, bank_size: usize) -> Vec<u8> {
+    (0..total_banks).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+}
+
 /// This structure contains common information used by all CHR units on all mappers
 #[derive(Debug)]
 pub(crate) struct ChrBaseData {
@@ -54,7 +91,7 @@ impl ChrBaseData {
         debug_assert!(banks.len() == bank_offsets.len());
 
         let total_banks = match &chr_data {
-            ChrData::Ram(_) => 0x2000 / bank_size,
+            ChrData::Ram(ram) => ram.len() / bank_size,
             ChrData::Rom(rom) => rom.len() / bank_size,
         };
 
@@ -114,11 +151,14 @@ impl ChrBaseData {
     }
 }
 
+/// This structure contains common information used by all PRG units on all mappers
 pub(crate) struct PrgBaseData {
     prg_rom: Vec<u8>,
-    prg_ram: Option<[u8; 0x2000]>,
+    prg_ram: Option<Vec<u8>>,
     total_banks: usize,
     bank_size: usize,
+    /// The bank in each `bank_size` slot from 8000-FFFF, `bank_offsets` holds
+    /// where each one starts in PRG ROM
     banks: Vec<usize>,
     bank_offsets: Vec<usize>,
 }
@@ -126,7 +166,7 @@ pub(crate) struct PrgBaseData {
 impl PrgBaseData {
     pub(super) fn new(
         prg_rom: Vec<u8>,
-        prg_ram: Option<[u8; 0x2000]>,
+        prg_ram: Option<Vec<u8>>,
         total_banks: usize,
         bank_size: usize,
         banks: Vec<usize>,
@@ -165,7 +205,7 @@ impl PrgBaseData {
         match address {
             0x6000..=0x7FFF => match &self.prg_ram {
                 None => 0x0,
-                Some(ram) => ram[(address - 0x6000) as usize],
+                Some(ram) => ram[(address - 0x6000) as usize % ram.len()],
             },
             0x8000..=0xFFFF => {
                 let bank = (address as usize - 0x8000) / self.bank_size;
@@ -177,30 +217,137 @@ impl PrgBaseData {
         }
     }
 
+    pub(crate) fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.as_deref()
+    }
+
+    pub(crate) fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.as_deref_mut()
+    }
+
     pub(crate) fn write_byte(&mut self, address: u16, value: u8) {
         debug!("Mapper write {:04X}={:02X}", address, value);
 
         if let 0x6000..=0x7FFF = address {
             match &mut self.prg_ram {
                 None => (),
-                Some(ram) => ram[(address - 0x6000) as usize] = value,
+                Some(ram) => {
+                    let len = ram.len();
+                    ram[(address - 0x6000) as usize % len] = value
+                }
             }
         };
     }
 }
 
+/// Bank numbers & offsets are stored as a length prefixed list, the length is
+/// fixed by the mapper so a mismatch means the state came from another cartridge
+fn save_banks(banks: &[usize], writer: &mut StateWriter) {
+    writer.write_usize(banks.len());
+    for bank in banks {
+        writer.write_usize(*bank);
+    }
+}
+
+fn load_banks(banks: &mut [usize], reader: &mut StateReader) -> Result<(), SaveStateError> {
+    if reader.read_usize()? != banks.len() {
+        return Err(SaveStateError::new("Mapper bank count mismatch"));
+    }
+    for bank in banks.iter_mut() {
+        *bank = reader.read_usize()?;
+    }
+    Ok(())
+}
+
+impl SaveState for ChrBaseData {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.mirroring_mode.save_state(writer);
+        if let ChrData::Ram(ram) = &self.chr_data {
+            writer.write_vec(&ram[..]);
+        }
+        writer.write_bytes(&self.ppu_vram);
+        save_banks(&self.banks, writer);
+        save_banks(&self.bank_offsets, writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.mirroring_mode.load_state(reader)?;
+        if let ChrData::Ram(ram) = &mut self.chr_data {
+            reader.read_vec_into(&mut ram[..])?;
+        }
+        reader.read_bytes(&mut self.ppu_vram)?;
+        load_banks(&mut self.banks, reader)?;
+        load_banks(&mut self.bank_offsets, reader)?;
+
+        if self
+            .bank_offsets
+            .iter()
+            .any(|offset| offset + self.bank_size > self.chr_data_len())
+        {
+            return Err(SaveStateError::new("CHR bank offset out of range"));
+        }
+        Ok(())
+    }
+}
+
+impl ChrBaseData {
+    fn chr_data_len(&self) -> usize {
+        match &self.chr_data {
+            ChrData::Rom(rom) => rom.len(),
+            ChrData::Ram(ram) => ram.len(),
+        }
+    }
+}
+
+impl SaveState for PrgBaseData {
+    fn save_state(&self, writer: &mut StateWriter) {
+        if let Some(ram) = &self.prg_ram {
+            writer.write_vec(ram);
+        }
+        save_banks(&self.banks, writer);
+        save_banks(&self.bank_offsets, writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        if let Some(ram) = &mut self.prg_ram {
+            reader.read_vec_into(ram)?;
+        }
+        load_banks(&mut self.banks, reader)?;
+        load_banks(&mut self.bank_offsets, reader)?;
+
+        if self
+            .bank_offsets
+            .iter()
+            .any(|offset| offset + self.bank_size > self.prg_rom.len())
+        {
+            return Err(SaveStateError::new("PRG bank offset out of range"));
+        }
+        Ok(())
+    }
+}
+
 pub(crate) struct NoBankPrgChip {
     base: PrgBaseData,
 }
 
 impl NoBankPrgChip {
-    pub(super) fn new(prg_rom: Vec<u8>) -> Self {
+    pub(super) fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>) -> Self {
         NoBankPrgChip {
-            base: PrgBaseData::new(prg_rom, Some([0; 0x2000]), 1, 0x8000, vec![0], vec![0]),
+            base: PrgBaseData::new(prg_rom, prg_ram, 1, 0x8000, vec![0], vec![0]),
         }
     }
 }
 
+impl SaveState for NoBankPrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl CpuCartridgeAddressBus for NoBankPrgChip {
     fn read_byte(&self, address: u16) -> u8 {
         self.base.read_byte(address)
@@ -209,6 +356,14 @@ impl CpuCartridgeAddressBus for NoBankPrgChip {
     fn write_byte(&mut self, address: u16, value: u8, _: u32) {
         self.base.write_byte(address, value)
     }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
 }
 
 /// NRom is a chip with no CHR banking and fixed soldered mirroring mode from the cartridge itself
@@ -224,6 +379,16 @@ impl NoBankChrChip {
     }
 }
 
+impl SaveState for NoBankChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl PpuCartridgeAddressBus for NoBankChrChip {
     fn check_trigger_irq(&mut self, _: bool) -> bool {
         false
@@ -256,7 +421,7 @@ struct SingleBankedPrgChip {
 impl SingleBankedPrgChip {
     fn new(
         prg_rom: Vec<u8>,
-        prg_ram: Option<[u8; 0x2000]>,
+        prg_ram: Option<Vec<u8>>,
         total_banks: usize,
         mask: u8,
         shift: u8,
@@ -271,6 +436,16 @@ impl SingleBankedPrgChip {
     }
 }
 
+impl SaveState for SingleBankedPrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl CpuCartridgeAddressBus for SingleBankedPrgChip {
     fn read_byte(&self, address: u16) -> u8 {
         self.base.read_byte(address)
@@ -290,6 +465,14 @@ impl CpuCartridgeAddressBus for SingleBankedPrgChip {
             info!("PRG Bank switch {:?} -> {:?}", self.base.banks, self.base.bank_offsets);
         }
     }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
 }
 
 /// Straightforward CHR banked chip with one bank switched on 0x8000..0xFFFF
@@ -321,6 +504,16 @@ impl SingleBankedChrChip {
     }
 }
 
+impl SaveState for SingleBankedChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl PpuCartridgeAddressBus for SingleBankedChrChip {
     fn check_trigger_irq(&mut self, _: bool) -> bool {
         false
@@ -0,0 +1,633 @@
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
+use cartridge::mirroring::MirroringMode;
+use cartridge::CartridgeHeader;
+use cartridge::CpuCartridgeAddressBus;
+use cartridge::PpuCartridgeAddressBus;
+use cpu::CpuCycle;
+use log::{debug, info};
+use ppu::PpuCycle;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// Output level of each step of a channel's (signed) sample * volume, chosen
+/// so that a lone channel at full volume is about as loud as an APU pulse at
+/// full volume
+const OUTPUT_LEVEL: f32 = 0.15 / 225.0;
+
+/// The chip spends 15 CPU cycles updating each enabled channel in turn
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+/// Bank register values from E0 upwards select a page of the console's
+/// internal VRAM (CIRAM) rather than CHR ROM
+const CIRAM_BANKS: u8 = 0xE0;
+
+/// The wavetable synthesiser on the Namco 163. Everything about the
+/// channels (frequency, phase, wave position & length, volume) lives in the
+/// top of the chip's 128 bytes of internal RAM alongside the waveforms
+/// themselves (as packed 4 bit samples), channel 7 at 78-7F down to channel 0
+/// at 40-47.
+///
+/// The chip updates a single channel every 15 CPU cycles and outputs only
+/// that channel until the next update, so the more channels that are enabled
+/// the less often each is updated and the quieter each one is. Here each
+/// channel's latest output is held and the enabled channels are averaged,
+/// which is what the (filtered) multiplexed output sounds like without the
+/// aliased whine at the multiplexing rate.
+struct Namco163Audio {
+    ram: [u8; 0x80],
+    /// Written at F800, the RAM address for the data port at 4800
+    address: u8,
+    /// F800 bit 7, step the address after each data port access
+    auto_increment: bool,
+    /// The channel which will be updated next, counting down from 7
+    channel: u8,
+    cycles_until_update: u8,
+    /// Latest (sample - 8) * volume for each channel
+    outputs: [i8; 8],
+}
+
+impl Namco163Audio {
+    fn new() -> Self {
+        Namco163Audio {
+            ram: [0; 0x80],
+            address: 0,
+            auto_increment: false,
+            channel: 7,
+            cycles_until_update: CYCLES_PER_CHANNEL,
+            outputs: [0; 8],
+        }
+    }
+
+    fn write_address(&mut self, value: u8) {
+        self.address = value & 0x7F;
+        self.auto_increment = value & 0b1000_0000 != 0;
+    }
+
+    fn read_data(&self) -> u8 {
+        self.ram[self.address as usize]
+    }
+
+    fn write_data(&mut self, value: u8) {
+        self.ram[self.address as usize] = value;
+        self.step_address();
+    }
+
+    fn step_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7F;
+        }
+    }
+
+    /// Channels 7 down to 8 - count are enabled, the count is held in the
+    /// same byte as channel 7's volume
+    fn enabled_channels(&self) -> u8 {
+        ((self.ram[0x7F] >> 4) & 0b111) + 1
+    }
+
+    fn update_channel(&mut self, channel: u8) {
+        let base = 0x40 + channel as usize * 8;
+        let registers = &self.ram[base..base + 8];
+
+        let frequency = registers[0] as u32 | (registers[2] as u32) << 8 | ((registers[4] & 0b11) as u32) << 16;
+        let length = 0x100 - (registers[4] & 0b1111_1100) as u32;
+        let phase = registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        let phase = (phase + frequency) % (length << 16);
+
+        let sample_address = ((phase >> 16) + registers[6] as u32) & 0xFF;
+        let sample = (self.ram[sample_address as usize / 2] >> ((sample_address & 1) * 4)) & 0x0F;
+        let volume = registers[7] & 0x0F;
+        self.outputs[channel as usize] = (sample as i8 - 8) * volume as i8;
+
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+    }
+
+    fn clock(&mut self) -> f32 {
+        self.cycles_until_update -= 1;
+        if self.cycles_until_update == 0 {
+            self.cycles_until_update = CYCLES_PER_CHANNEL;
+            let channel = self.channel;
+            self.update_channel(channel);
+            self.channel = match channel <= 8 - self.enabled_channels() {
+                true => 7,
+                false => channel - 1,
+            };
+        }
+
+        let enabled_channels = self.enabled_channels();
+        let total: i32 = self.outputs[(8 - enabled_channels as usize)..]
+            .iter()
+            .map(|&output| output as i32)
+            .sum();
+        total as f32 * OUTPUT_LEVEL / enabled_channels as f32
+    }
+}
+
+impl SaveState for Namco163Audio {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+        writer.write_u8(self.address);
+        writer.write_bool(self.auto_increment);
+        writer.write_u8(self.channel);
+        writer.write_u8(self.cycles_until_update);
+        for &output in self.outputs.iter() {
+            writer.write_u8(output as u8);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes(&mut self.ram)?;
+        self.address = reader.read_u8()? & 0x7F;
+        self.auto_increment = reader.read_bool()?;
+        self.channel = reader.read_u8()? & 0b111;
+        self.cycles_until_update = match reader.read_u8()? {
+            cycles @ 1..=CYCLES_PER_CHANNEL => cycles,
+            _ => return Err(SaveStateError::new("Invalid Namco 163 audio cycle count")),
+        };
+        for output in self.outputs.iter_mut() {
+            *output = reader.read_u8()? as i8;
+        }
+        Ok(())
+    }
+}
+
+/// The CPU side of the Namco 163 handles PRG banking, PRG RAM write
+/// protection, the chip's internal RAM, the IRQ counter (which is clocked by
+/// the CPU) and the audio.
+///
+/// Some games battery back only the internal RAM, NES 2.0 headers for those
+/// declare 128 bytes of PRG NVRAM. That's the internal RAM rather than RAM
+/// at 6000 so it's exported as the cartridge's PRG RAM instead.
+pub(crate) struct Namco163PrgChip {
+    base: PrgBaseData,
+    /// E000 bit 6
+    sound_disabled: bool,
+    /// Written at F800, writes to 6000-7FFF need the top nibble to be 0100
+    /// and each of the low bits protects a 2KB quarter of the RAM
+    write_protect: u8,
+    /// 15 bit counter at 5000 (low) & 5800 (high), counts up every CPU
+    /// cycle when enabled and holds at 7FFF
+    irq_counter: u16,
+    /// 5800 bit 7
+    irq_enabled: bool,
+    irq_pending: bool,
+    audio: Namco163Audio,
+}
+
+impl Namco163PrgChip {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>) -> Self {
+        let total_banks = prg_rom.len() / 0x2000;
+        let banks = vec![0, 1, total_banks - 2, total_banks - 1];
+        let bank_offsets = banks.iter().map(|bank| bank * 0x2000).collect();
+
+        Namco163PrgChip {
+            base: PrgBaseData::new(prg_rom, prg_ram, total_banks, 0x2000, banks, bank_offsets),
+            sound_disabled: false,
+            write_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: Namco163Audio::new(),
+        }
+    }
+
+    fn set_bank(&mut self, slot: usize, value: u8) {
+        self.base.banks[slot] = (value & 0b0011_1111) as usize % self.base.total_banks;
+        self.base.bank_offsets[slot] = self.base.banks[slot] * 0x2000;
+
+        info!(
+            "Namco 163 PRG banks updated {:?} -> {:?}",
+            self.base.banks, self.base.bank_offsets
+        );
+    }
+
+    fn ram_writable(&self, address: u16) -> bool {
+        let quarter = (address - 0x6000) / 0x800;
+        self.write_protect & 0xF0 == 0x40 && self.write_protect & (1 << quarter) == 0
+    }
+}
+
+impl SaveState for Namco163PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_bool(self.sound_disabled);
+        writer.write_u8(self.write_protect);
+        writer.write_u16(self.irq_counter);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending);
+        self.audio.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.sound_disabled = reader.read_bool()?;
+        self.write_protect = reader.read_u8()?;
+        self.irq_counter = reader.read_u16()? & 0x7FFF;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        self.audio.load_state(reader)
+    }
+}
+
+impl CpuCartridgeAddressBus for Namco163PrgChip {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.audio.read_data(),
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            _ => self.base.read_byte(address),
+        }
+    }
+
+    fn after_read(&mut self, address: u16) {
+        if let 0x4800..=0x4FFF = address {
+            self.audio.step_address();
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        debug!("CPU write to Namco 163 PRG bus {:04X}={:02X}", address, value);
+
+        match address {
+            0x4800..=0x4FFF => self.audio.write_data(value),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0xFF) | ((value & 0x7F) as u16) << 8;
+                self.irq_enabled = value & 0b1000_0000 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF if self.ram_writable(address) => self.base.write_byte(address, value),
+            0xE000..=0xE7FF => {
+                self.set_bank(0, value);
+                self.sound_disabled = value & 0b0100_0000 != 0;
+            }
+            0xE800..=0xEFFF => self.set_bank(1, value),
+            0xF000..=0xF7FF => self.set_bank(2, value),
+            0xF800..=0xFFFF => {
+                self.write_protect = value;
+                self.audio.write_address(value);
+            }
+            _ => (),
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        match self.base.prg_ram {
+            Some(_) => self.base.prg_ram(),
+            None => Some(&self.audio.ram),
+        }
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        match self.base.prg_ram {
+            Some(_) => self.base.prg_ram_mut(),
+            None => Some(&mut self.audio.ram),
+        }
+    }
+
+    fn clock_expansion_audio(&mut self) -> f32 {
+        match self.sound_disabled {
+            true => 0.0,
+            false => self.audio.clock(),
+        }
+    }
+
+    fn clock_irq(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn check_trigger_irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+/// The PPU side of the Namco 163 handles CHR banking, each of the eight
+/// pattern table & four nametable 1KB slots can hold either a CHR ROM bank
+/// or a page of CIRAM.
+pub(crate) struct Namco163ChrChip {
+    /// Banks & offsets into CHR ROM for all 12 slots, only used for slots
+    /// which aren't mapped to CIRAM
+    base: ChrBaseData,
+    /// The values written to the 12 bank registers at 8000-DFFF
+    registers: [u8; 12],
+    /// E800 bits 6 & 7, pattern table slots at 0000-0FFF & 1000-1FFF
+    /// always use CHR ROM (the nametable slots can always select CIRAM)
+    ciram_disabled: [bool; 2],
+}
+
+impl Namco163ChrChip {
+    fn new(chr_data: ChrData, mirroring_mode: MirroringMode) -> Self {
+        // The nametable registers aren't initialised at power on, start with
+        // the mirroring from the header
+        let mut registers = [0; 12];
+        registers[8..].copy_from_slice(match mirroring_mode {
+            MirroringMode::Horizontal => &[0xE0, 0xE0, 0xE1, 0xE1],
+            _ => &[0xE0, 0xE1, 0xE0, 0xE1],
+        });
+
+        Namco163ChrChip {
+            base: ChrBaseData::new(mirroring_mode, chr_data, 0x400, vec![0; 12], vec![0; 12]),
+            registers,
+            ciram_disabled: [false, false],
+        }
+    }
+
+    fn set_bank(&mut self, slot: usize, value: u8) {
+        self.registers[slot] = value;
+        self.base.banks[slot] = value as usize % self.base.total_banks;
+        self.base.bank_offsets[slot] = self.base.banks[slot] * 0x400;
+
+        info!(
+            "Namco 163 CHR banks updated {:?} -> {:?}",
+            self.registers, self.base.bank_offsets
+        );
+    }
+
+    /// The CIRAM page mapped into a slot or None if it's a CHR ROM bank
+    fn ciram_page(&self, slot: usize) -> Option<usize> {
+        let value = self.registers[slot];
+        match value >= CIRAM_BANKS && (slot >= 8 || !self.ciram_disabled[slot / 4]) {
+            true => Some((value & 1) as usize),
+            false => None,
+        }
+    }
+
+    /// The slot (0-11) & offset within it, 3000-3EFF mirrors 2000-2EFF
+    fn slot(address: u16) -> (usize, usize) {
+        let address = match address {
+            0x3000..=0x3EFF => address - 0x1000,
+            _ => address,
+        };
+        (address as usize / 0x400, address as usize & 0x3FF)
+    }
+}
+
+impl SaveState for Namco163ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_bytes(&self.registers);
+        writer.write_bool(self.ciram_disabled[0]);
+        writer.write_bool(self.ciram_disabled[1]);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        reader.read_bytes(&mut self.registers)?;
+        self.ciram_disabled[0] = reader.read_bool()?;
+        self.ciram_disabled[1] = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+impl PpuCartridgeAddressBus for Namco163ChrChip {
+    fn read_byte(&mut self, address: u16, _: PpuCycle) -> u8 {
+        let (slot, offset) = Namco163ChrChip::slot(address);
+        match self.ciram_page(slot) {
+            Some(page) => self.base.ppu_vram[page * 0x400 + offset],
+            None => match &self.base.chr_data {
+                ChrData::Rom(data) | ChrData::Ram(data) => data[self.base.bank_offsets[slot] + offset],
+            },
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        let (slot, offset) = Namco163ChrChip::slot(address);
+        match self.ciram_page(slot) {
+            Some(page) => self.base.ppu_vram[page * 0x400 + offset] = value,
+            None => {
+                if let ChrData::Ram(ram) = &mut self.base.chr_data {
+                    ram[self.base.bank_offsets[slot] + offset] = value;
+                }
+            }
+        }
+    }
+
+    fn cpu_write_byte(&mut self, address: u16, value: u8, _: CpuCycle) {
+        match address {
+            0x8000..=0xDFFF => self.set_bank((address as usize - 0x8000) / 0x800, value),
+            0xE800..=0xEFFF => {
+                self.ciram_disabled = [value & 0b0100_0000 != 0, value & 0b1000_0000 != 0];
+                info!("Namco 163 CIRAM disabled for pattern tables {:?}", self.ciram_disabled);
+            }
+            _ => (),
+        }
+    }
+}
+
+pub(crate) fn from_header(
+    prg_rom: Vec<u8>,
+    chr_rom: Option<Vec<u8>>,
+    header: CartridgeHeader,
+) -> (
+    Box<dyn CpuCartridgeAddressBus>,
+    Box<dyn PpuCartridgeAddressBus>,
+    CartridgeHeader,
+) {
+    info!("Creating Namco 163 mapper for cartridge {:?}", header);
+    let prg_ram = match prg_ram_from_header(&header, true) {
+        Some(ref ram) if ram.len() <= 0x80 => None,
+        prg_ram => prg_ram,
+    };
+    (
+        Box::new(Namco163PrgChip::new(prg_rom, prg_ram)),
+        Box::new(Namco163ChrChip::new(
+            ChrData::from_header(chr_rom, &header),
+            header.mirroring,
+        )),
+        header,
+    )
+}
+
+#[cfg(test)]
+mod namco_163_tests {
+    use super::{Namco163Audio, Namco163ChrChip, Namco163PrgChip, CYCLES_PER_CHANNEL, OUTPUT_LEVEL};
+    use cartridge::mappers::{numbered_banks, ChrData};
+    use cartridge::mirroring::MirroringMode;
+    use cartridge::{CpuCartridgeAddressBus, PpuCartridgeAddressBus};
+
+    fn prg_chip() -> Namco163PrgChip {
+        Namco163PrgChip::new(numbered_banks(32, 0x2000), Some(vec![0; 0x2000]))
+    }
+
+    fn chr_chip() -> Namco163ChrChip {
+        Namco163ChrChip::new(ChrData::Rom(numbered_banks(256, 0x400)), MirroringMode::Vertical)
+    }
+
+    #[test]
+    fn test_prg_banking_and_ram_write_protect() {
+        let mut n163 = prg_chip();
+        n163.write_byte(0xE000, 3, 0);
+        n163.write_byte(0xE800, 4, 0);
+        n163.write_byte(0xF000, 5, 0);
+        assert_eq!(n163.read_byte(0x8000), 3);
+        assert_eq!(n163.read_byte(0xA000), 4);
+        assert_eq!(n163.read_byte(0xC000), 5);
+        assert_eq!(n163.read_byte(0xE000), 31);
+
+        n163.write_byte(0x6000, 0x12, 0);
+        assert_eq!(n163.read_byte(0x6000), 0);
+
+        // Writes enabled apart from the second 2KB
+        n163.write_byte(0xF800, 0b0100_0010, 0);
+        n163.write_byte(0x6000, 0x12, 0);
+        n163.write_byte(0x6800, 0x34, 0);
+        assert_eq!(n163.read_byte(0x6000), 0x12);
+        assert_eq!(n163.read_byte(0x6800), 0);
+    }
+
+    #[test]
+    fn test_internal_ram_auto_increment() {
+        let mut n163 = prg_chip();
+        n163.write_byte(0xF800, 0x80 | 0x7F, 0);
+        n163.write_byte(0x4800, 0x12, 0);
+        n163.write_byte(0x4800, 0x34, 0);
+
+        // The address wraps at 0x80, peeks don't move it
+        n163.write_byte(0xF800, 0x80 | 0x7F, 0);
+        assert_eq!(n163.read_byte(0x4800), 0x12);
+        assert_eq!(n163.read_byte(0x4800), 0x12);
+        n163.after_read(0x4800);
+        assert_eq!(n163.read_byte(0x4800), 0x34);
+
+        // Without auto increment the address stays put
+        n163.write_byte(0xF800, 0x00, 0);
+        n163.write_byte(0x4800, 0x56, 0);
+        n163.write_byte(0x4800, 0x78, 0);
+        n163.after_read(0x4800);
+        assert_eq!(n163.read_byte(0x4800), 0x78);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut n163 = prg_chip();
+        n163.write_byte(0x5000, 0xFD, 0);
+        n163.write_byte(0x5800, 0x80 | 0x7F, 0);
+        assert_eq!(n163.read_byte(0x5800), 0xFF);
+
+        n163.clock_irq();
+        assert!(!n163.check_trigger_irq());
+        n163.clock_irq();
+        assert!(n163.check_trigger_irq());
+
+        // The counter holds at 7FFF
+        n163.clock_irq();
+        assert_eq!(n163.read_byte(0x5000), 0xFF);
+
+        // Writing the low half of the counter acknowledges the IRQ without disabling it
+        n163.write_byte(0x5000, 0xFE, 0);
+        assert!(!n163.check_trigger_irq());
+        n163.clock_irq();
+        assert!(n163.check_trigger_irq());
+
+        // As does the high half, which here also stops the counter
+        n163.write_byte(0x5800, 0x00, 0);
+        assert!(!n163.check_trigger_irq());
+        n163.clock_irq();
+        assert_eq!(n163.read_byte(0x5000), 0xFF);
+        assert_eq!(n163.read_byte(0x5800), 0x00);
+    }
+
+    #[test]
+    fn test_chr_banking_and_ciram() {
+        let mut n163 = chr_chip();
+        for slot in 0..8 {
+            n163.cpu_write_byte(0x8000 + slot * 0x800, 0x20 + slot as u8, 0);
+        }
+        for slot in 0..8 {
+            assert_eq!(n163.read_byte(slot * 0x400, 0), 0x20 + slot as u8);
+        }
+
+        // Nametables default to the header mirroring
+        n163.write_byte(0x2000, 0x12, 0);
+        n163.write_byte(0x2400, 0x34, 0);
+        assert_eq!(n163.read_byte(0x2800, 0), 0x12);
+        assert_eq!(n163.read_byte(0x2C00, 0), 0x34);
+
+        // A nametable slot can hold a CHR ROM bank
+        n163.cpu_write_byte(0xC000, 0x40, 0);
+        assert_eq!(n163.read_byte(0x2000, 0), 0x40);
+        assert_eq!(n163.read_byte(0x3000, 0), 0x40);
+
+        // And a pattern table slot can hold CIRAM unless disabled
+        n163.cpu_write_byte(0x8000, 0xE1, 0);
+        assert_eq!(n163.read_byte(0x0000, 0), 0x34);
+        n163.cpu_write_byte(0xE800, 0b0100_0000, 0);
+        assert_eq!(n163.read_byte(0x0000, 0), 0xE1);
+    }
+
+    #[test]
+    fn test_audio_channel_count_mixing() {
+        let mut audio = Namco163Audio::new();
+        // A 4 sample wave at 00 of 0xF, 0x0, 0x0, 0x0
+        audio.ram[0] = 0x0F;
+        // Channel 7, frequency 0x10000 (one sample per update), length 4, full volume
+        audio.ram[0x7C] = 0xFC | 0x1;
+        audio.ram[0x7F] = 0x0F;
+
+        let output = |audio: &mut Namco163Audio, channels: u8| {
+            audio.ram[0x7F] = 0x0F | (channels - 1) << 4;
+            (0..CYCLES_PER_CHANNEL as usize * 16)
+                .map(|_| audio.clock())
+                .fold(0.0f32, |max, sample| max.max(sample.abs()))
+        };
+
+        let one_channel = output(&mut audio, 1);
+        let eight_channels = output(&mut audio, 8);
+        assert!(one_channel > 0.05);
+        assert!((eight_channels * 8.0 - one_channel).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_wavetable_playback() {
+        let mut n163 = prg_chip();
+        // A 16 sample ramp from 0 to F at 00, then channel 7 at frequency
+        // 0x10000 (one sample per update), length 16 & full volume
+        n163.write_byte(0xF800, 0x80, 0);
+        for sample in 0..8 {
+            n163.write_byte(0x4800, (sample * 2) | ((sample * 2 + 1) << 4), 0);
+        }
+        n163.write_byte(0xF800, 0x80 | 0x78, 0);
+        for &value in [0x00, 0x00, 0x00, 0x00, 0xF0 | 0x1, 0x00, 0x00, 0x0F].iter() {
+            n163.write_byte(0x4800, value, 0);
+        }
+
+        // Each update moves the phase on a sample before outputting it
+        for update in 1..=32 {
+            let samples: Vec<f32> = (0..CYCLES_PER_CHANNEL).map(|_| n163.clock_expansion_audio()).collect();
+            let expected = ((update % 16) as f32 - 8.0) * 15.0 * OUTPUT_LEVEL;
+            assert_eq!(samples[CYCLES_PER_CHANNEL as usize - 1], expected, "{}", update);
+        }
+
+        // The phase is kept in the chip's RAM, reading it back through the data port
+        n163.write_byte(0xF800, 0x80 | 0x79, 0);
+        let phase: Vec<u8> = (0..5)
+            .map(|_| {
+                let value = n163.read_byte(0x4800);
+                n163.after_read(0x4800);
+                value
+            })
+            .collect();
+        assert_eq!(phase, vec![0x00, 0x00, 0x00, 0xF1, 0x00]);
+
+        // Enabling a second channel halves how often channel 7 is updated
+        n163.write_byte(0xF800, 0x7F, 0);
+        n163.write_byte(0x4800, 0x1F, 0);
+        for _ in 0..CYCLES_PER_CHANNEL as usize * 20 {
+            n163.clock_expansion_audio();
+        }
+        n163.write_byte(0xF800, 0x7D, 0);
+        assert_eq!(n163.read_byte(0x4800), 10);
+
+        // E000 bit 6 silences the chip
+        n163.write_byte(0xE000, 0b0100_0000, 0);
+        assert_eq!(n163.clock_expansion_audio(), 0.0);
+    }
+}
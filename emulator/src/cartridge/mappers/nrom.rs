@@ -1,4 +1,4 @@
-use cartridge::mappers::{ChrData, NoBankChrChip, NoBankPrgChip};
+use cartridge::mappers::{prg_ram_from_header, ChrData, NoBankChrChip, NoBankPrgChip};
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
 use cartridge::PpuCartridgeAddressBus;
@@ -15,8 +15,11 @@ pub(crate) fn from_header(
 ) {
     info!("Creating NROM mapper for cartridge");
     (
-        Box::new(NoBankPrgChip::new(prg_rom)),
-        Box::new(NoBankChrChip::new(ChrData::from(chr_rom), header.mirroring)),
+        Box::new(NoBankPrgChip::new(prg_rom, prg_ram_from_header(&header, true))),
+        Box::new(NoBankChrChip::new(
+            ChrData::from_header(chr_rom, &header),
+            header.mirroring,
+        )),
         header,
     )
 }
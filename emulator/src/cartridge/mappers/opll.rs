@@ -0,0 +1,694 @@
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+use std::f32::consts::PI;
+
+/// The OPLL runs from the cartridge's 3.58MHz crystal and produces a sample
+/// every 72 of its clocks, which is 36 CPU cycles
+const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+
+/// Phase counters count 2^20 steps per cycle of the sine wave
+const PHASE_BITS: u32 = 20;
+const PHASE_MASK: u32 = (1 << PHASE_BITS) - 1;
+
+/// Envelope levels are 23 bit attenuations from 0dB to 48dB (silent)
+const ENVELOPE_MAX: u32 = 1 << 23;
+const ENVELOPE_DB: f32 = 48.0;
+
+/// Operator outputs are 12 bit signed values
+const OPERATOR_MAX: f32 = 4095.0;
+
+/// Tremolo of 4.8dB at 3.7Hz and vibrato of 13.75 cents at 6.4Hz, both as
+/// periods in samples
+const AM_PERIOD: u16 = 13_437;
+const AM_DEPTH_DB: f32 = 4.8;
+const VIBRATO_PERIOD: u16 = 7_768;
+const VIBRATO_CENTS: f32 = 13.75;
+
+/// Output level of each step of a channel's output, chosen so that a
+/// channel at full volume is about as loud as an APU pulse at full volume
+const OUTPUT_LEVEL: f32 = 0.15 / OPERATOR_MAX;
+
+/// The 15 built in instruments of the VRC7, instrument 0 is the custom
+/// patch written to registers 00-07. These differ from the YM2413's set,
+/// c.f. http://wiki.nesdev.com/w/index.php/VRC7_audio
+const INSTRUMENTS: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // Buzzy bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // Guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // Wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // Flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // Clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // Synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // Trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // Organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // Bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // Vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // Vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // Tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // Fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // Synth bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06], // Sweep
+];
+
+/// Frequency multipliers (doubled, the first is x0.5)
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// Key scale level attenuation in dB at octave 7 for the top 4 bits of the
+/// frequency, each octave down is 6dB less
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0,
+];
+
+/// The parameters of one operator from an instrument
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    /// Holds at the sustain level while the key is on rather than
+    /// continuing to decay as a percussive tone does
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    /// Half sine wave, the negative half of the wave is silent
+    rectified: bool,
+    attack_rate: u8,
+    decay_rate: u8,
+    sustain_level: u8,
+    release_rate: u8,
+}
+
+impl OperatorPatch {
+    fn new(flags: u8, key_scale_level: u8, rectified: bool, rates: u8, levels: u8) -> Self {
+        OperatorPatch {
+            tremolo: flags & 0b1000_0000 != 0,
+            vibrato: flags & 0b0100_0000 != 0,
+            sustained: flags & 0b0010_0000 != 0,
+            key_scale_rate: flags & 0b0001_0000 != 0,
+            multiplier: flags & 0x0F,
+            key_scale_level,
+            rectified,
+            attack_rate: rates >> 4,
+            decay_rate: rates & 0x0F,
+            sustain_level: levels >> 4,
+            release_rate: levels & 0x0F,
+        }
+    }
+}
+
+/// An instrument, from either the built in set or the custom registers
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Patch {
+    modulator: OperatorPatch,
+    carrier: OperatorPatch,
+    /// Attenuation of the modulator in 0.75dB steps
+    total_level: u8,
+    feedback: u8,
+}
+
+impl Patch {
+    fn from_bytes(bytes: &[u8; 8]) -> Self {
+        Patch {
+            modulator: OperatorPatch::new(bytes[0], bytes[2] >> 6, bytes[3] & 0b0000_1000 != 0, bytes[4], bytes[6]),
+            carrier: OperatorPatch::new(bytes[1], bytes[3] >> 6, bytes[3] & 0b0001_0000 != 0, bytes[5], bytes[7]),
+            total_level: bytes[2] & 0b0011_1111,
+            feedback: bytes[3] & 0b111,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+/// The output of a single operator, `phase` is the position within the sine
+/// wave (PHASE_BITS), `modulation` is added to it in cycles
+fn operator_output(phase: u32, modulation: f32, attenuation_db: f32, rectified: bool) -> i16 {
+    let sine = (2.0 * PI * (phase as f32 / (1 << PHASE_BITS) as f32 + modulation)).sin();
+    if rectified && sine < 0.0 {
+        return 0;
+    }
+
+    (sine * OPERATOR_MAX * 10f32.powf(-attenuation_db / 20.0)).round() as i16
+}
+
+/// Key scale level attenuation in dB for a note
+fn key_scale_attenuation(key_scale_level: u8, frequency: u16, octave: u8) -> f32 {
+    match key_scale_level {
+        0 => 0.0,
+        _ => {
+            let attenuation = KEY_SCALE_LEVELS[(frequency >> 5) as usize] - 6.0 * (7 - octave) as f32;
+            attenuation.max(0.0) / (1 << (3 - key_scale_level)) as f32
+        }
+    }
+}
+
+/// A sine wave oscillator with an envelope, each channel has two, the
+/// output of the modulator changes the phase of the carrier
+#[derive(Clone, Copy)]
+struct Operator {
+    phase: u32,
+    envelope: EnvelopeState,
+    /// Current attenuation from the envelope, 0 to ENVELOPE_MAX
+    level: u32,
+}
+
+impl Operator {
+    fn new() -> Self {
+        Operator {
+            phase: 0,
+            envelope: EnvelopeState::Off,
+            level: ENVELOPE_MAX,
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0;
+        self.envelope = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.envelope != EnvelopeState::Off {
+            self.envelope = EnvelopeState::Release;
+        }
+    }
+
+    /// Advance the phase by one sample, `vibrato` is the factor the
+    /// frequency is currently multiplied by for the vibrato
+    fn clock_phase(&mut self, patch: &OperatorPatch, frequency: u16, octave: u8, vibrato: f32) {
+        let increment = ((frequency as u32) << octave) * MULTIPLIERS[patch.multiplier as usize];
+        let increment = match patch.vibrato {
+            true => (increment as f32 * vibrato).round() as u32,
+            false => increment,
+        };
+        self.phase = (self.phase + increment) & PHASE_MASK;
+    }
+
+    /// Advance the envelope by one sample, `channel_sustain` is the sustain
+    /// bit of the channel which slows down the release
+    fn clock_envelope(&mut self, patch: &OperatorPatch, frequency: u16, octave: u8, channel_sustain: bool) {
+        let rate = match self.envelope {
+            EnvelopeState::Attack => patch.attack_rate,
+            EnvelopeState::Decay => patch.decay_rate,
+            EnvelopeState::Sustain if patch.sustained => 0,
+            EnvelopeState::Sustain => patch.release_rate,
+            EnvelopeState::Release if channel_sustain => 5,
+            EnvelopeState::Release if patch.sustained => patch.release_rate,
+            EnvelopeState::Release => 7,
+            EnvelopeState::Off => return,
+        };
+        if rate == 0 {
+            return;
+        }
+
+        // Higher notes have faster envelopes when key scale rate is set
+        let key_scale = match patch.key_scale_rate {
+            true => octave << 1 | (frequency >> 8) as u8,
+            false => octave >> 1,
+        };
+        let rate = (rate * 4 + key_scale).min(63) as u32;
+        let step = (4 + (rate & 0b11)) << (rate >> 2);
+
+        match self.envelope {
+            // The attack is exponential, covering the first dBs of attenuation slowest
+            EnvelopeState::Attack if rate >= 60 => self.level = 0,
+            EnvelopeState::Attack => {
+                let decrease = ((self.level as u64 * step as u64) >> 16).max(1) as u32;
+                self.level = self.level.saturating_sub(decrease);
+            }
+            _ => self.level = (self.level + (step >> 1)).min(ENVELOPE_MAX),
+        }
+
+        let sustain_level = patch.sustain_level as u32 * (ENVELOPE_MAX / 16);
+        match self.envelope {
+            EnvelopeState::Attack if self.level == 0 => self.envelope = EnvelopeState::Decay,
+            EnvelopeState::Decay if self.level >= sustain_level => self.envelope = EnvelopeState::Sustain,
+            _ if self.level == ENVELOPE_MAX => self.envelope = EnvelopeState::Off,
+            _ => (),
+        }
+    }
+
+    /// Output for the current sample given the attenuation from the
+    /// volume/total level, key scaling & tremolo
+    fn output(&self, patch: &OperatorPatch, attenuation_db: f32, modulation: f32) -> i16 {
+        match self.envelope {
+            EnvelopeState::Off => 0,
+            _ => {
+                let envelope_db = self.level as f32 * ENVELOPE_DB / ENVELOPE_MAX as f32;
+                operator_output(self.phase, modulation, attenuation_db + envelope_db, patch.rectified)
+            }
+        }
+    }
+}
+
+impl SaveState for Operator {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.phase);
+        writer.write_u8(match self.envelope {
+            EnvelopeState::Attack => 0,
+            EnvelopeState::Decay => 1,
+            EnvelopeState::Sustain => 2,
+            EnvelopeState::Release => 3,
+            EnvelopeState::Off => 4,
+        });
+        writer.write_u32(self.level);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.phase = reader.read_u32()? & PHASE_MASK;
+        self.envelope = match reader.read_u8()? {
+            0 => EnvelopeState::Attack,
+            1 => EnvelopeState::Decay,
+            2 => EnvelopeState::Sustain,
+            3 => EnvelopeState::Release,
+            4 => EnvelopeState::Off,
+            _ => return Err(SaveStateError::new("Invalid OPLL envelope state")),
+        };
+        self.level = reader.read_u32()?.min(ENVELOPE_MAX);
+        Ok(())
+    }
+}
+
+/// One of the six FM channels, a modulator & carrier pair
+#[derive(Clone, Copy)]
+struct Channel {
+    /// 9 bit frequency (F-Number)
+    frequency: u16,
+    octave: u8,
+    sustain: bool,
+    key_on: bool,
+    instrument: u8,
+    /// Attenuation of the carrier in 3dB steps
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+    /// The last two outputs of the modulator, fed back into its phase
+    feedback: [i16; 2],
+}
+
+impl Channel {
+    fn new() -> Self {
+        Channel {
+            frequency: 0,
+            octave: 0,
+            sustain: false,
+            key_on: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+            feedback: [0; 2],
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0xFF) | ((value & 1) as u16) << 8;
+        self.octave = (value >> 1) & 0b111;
+        self.sustain = value & 0b0010_0000 != 0;
+
+        let key_on = value & 0b0001_0000 != 0;
+        if key_on && !self.key_on {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key_on && self.key_on {
+            // Only the carrier is released, the modulator carries on as if the key were still on
+            self.carrier.key_off();
+        }
+        self.key_on = key_on;
+    }
+
+    /// Produce the next sample for the channel
+    fn clock(&mut self, patch: &Patch, tremolo_db: f32, vibrato: f32) -> i16 {
+        let (frequency, octave) = (self.frequency, self.octave);
+        for (operator, patch) in [
+            (&mut self.modulator, &patch.modulator),
+            (&mut self.carrier, &patch.carrier),
+        ] {
+            operator.clock_phase(patch, frequency, octave, vibrato);
+            operator.clock_envelope(patch, frequency, octave, self.sustain);
+        }
+
+        let attenuation = |patch: &OperatorPatch, level_db: f32| {
+            let tremolo = if patch.tremolo { tremolo_db } else { 0.0 };
+            level_db + key_scale_attenuation(patch.key_scale_level, frequency, octave) + tremolo
+        };
+
+        // Feedback and the modulator's output are phase offsets of up to 4pi & 8pi respectively
+        let feedback = match patch.feedback {
+            0 => 0.0,
+            feedback => (self.feedback[0] as f32 + self.feedback[1] as f32) / (1 << (19 - feedback)) as f32,
+        };
+        let modulator = self.modulator.output(
+            &patch.modulator,
+            attenuation(&patch.modulator, patch.total_level as f32 * 0.75),
+            feedback,
+        );
+        self.feedback = [self.feedback[1], modulator];
+
+        self.carrier.output(
+            &patch.carrier,
+            attenuation(&patch.carrier, self.volume as f32 * 3.0),
+            modulator as f32 / 1024.0,
+        )
+    }
+}
+
+impl SaveState for Channel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.frequency);
+        writer.write_u8(self.octave);
+        writer.write_bool(self.sustain);
+        writer.write_bool(self.key_on);
+        writer.write_u8(self.instrument);
+        writer.write_u8(self.volume);
+        self.modulator.save_state(writer);
+        self.carrier.save_state(writer);
+        writer.write_u16(self.feedback[0] as u16);
+        writer.write_u16(self.feedback[1] as u16);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.frequency = reader.read_u16()? & 0x1FF;
+        self.octave = reader.read_u8()? & 0b111;
+        self.sustain = reader.read_bool()?;
+        self.key_on = reader.read_bool()?;
+        self.instrument = reader.read_u8()? & 0x0F;
+        self.volume = reader.read_u8()? & 0x0F;
+        self.modulator.load_state(reader)?;
+        self.carrier.load_state(reader)?;
+        self.feedback = [reader.read_u16()? as i16, reader.read_u16()? as i16];
+        Ok(())
+    }
+}
+
+/// The VRC7's FM synthesiser, a cut down YM2413 (OPLL) with six channels
+/// of two operator FM synthesis, its own set of built in instruments and no
+/// rhythm mode.
+///
+/// Rather than the chip's log-sin & exponent tables this calculates the
+/// sine waves and attenuations directly, so output is close to but not a
+/// bit exact match for the hardware.
+pub(super) struct Opll {
+    custom_patch: [u8; 8],
+    channels: [Channel; 6],
+    register_select: u8,
+    /// Held in reset (silent, registers cleared) by bit 6 of E000
+    in_reset: bool,
+    cycles: u8,
+    tremolo_counter: u16,
+    vibrato_counter: u16,
+    /// Sum of the channel outputs for the latest sample
+    output: i32,
+}
+
+impl Opll {
+    pub(super) fn new() -> Self {
+        Opll {
+            custom_patch: [0; 8],
+            channels: [Channel::new(); 6],
+            register_select: 0,
+            in_reset: false,
+            cycles: 0,
+            tremolo_counter: 0,
+            vibrato_counter: 0,
+            output: 0,
+        }
+    }
+
+    pub(super) fn select_register(&mut self, value: u8) {
+        self.register_select = value;
+    }
+
+    pub(super) fn write_register(&mut self, value: u8) {
+        if self.in_reset {
+            return;
+        }
+
+        let channel = (self.register_select & 0x0F) as usize;
+        match self.register_select {
+            0x00..=0x07 => self.custom_patch[self.register_select as usize] = value,
+            0x10..=0x15 => self.channels[channel].frequency = (self.channels[channel].frequency & 0x100) | value as u16,
+            0x20..=0x25 => self.channels[channel].write_control(value),
+            0x30..=0x35 => {
+                self.channels[channel].instrument = value >> 4;
+                self.channels[channel].volume = value & 0x0F;
+            }
+            _ => (),
+        }
+    }
+
+    pub(super) fn set_reset(&mut self, in_reset: bool) {
+        if in_reset {
+            *self = Opll {
+                in_reset,
+                ..Opll::new()
+            };
+        }
+        self.in_reset = in_reset;
+    }
+
+    fn patch(&self, instrument: u8) -> Patch {
+        match instrument {
+            0 => Patch::from_bytes(&self.custom_patch),
+            _ => Patch::from_bytes(&INSTRUMENTS[instrument as usize - 1]),
+        }
+    }
+
+    /// Called once per CPU cycle, the output is only updated once per
+    /// sample and is held in between
+    pub(super) fn clock(&mut self) -> f32 {
+        if self.in_reset {
+            return 0.0;
+        }
+
+        self.cycles += 1;
+        if self.cycles == CPU_CYCLES_PER_SAMPLE {
+            self.cycles = 0;
+            self.generate_sample();
+        }
+
+        self.output as f32 * OUTPUT_LEVEL
+    }
+
+    fn generate_sample(&mut self) {
+        self.tremolo_counter = (self.tremolo_counter + 1) % AM_PERIOD;
+        self.vibrato_counter = (self.vibrato_counter + 1) % VIBRATO_PERIOD;
+        let tremolo_angle = 2.0 * PI * self.tremolo_counter as f32 / AM_PERIOD as f32;
+        let vibrato_angle = 2.0 * PI * self.vibrato_counter as f32 / VIBRATO_PERIOD as f32;
+        let tremolo_db = (1.0 - tremolo_angle.cos()) / 2.0 * AM_DEPTH_DB;
+        let vibrato = 2f32.powf(VIBRATO_CENTS * vibrato_angle.sin() / 1200.0);
+
+        let mut output = 0;
+        for index in 0..self.channels.len() {
+            let patch = self.patch(self.channels[index].instrument);
+            output += self.channels[index].clock(&patch, tremolo_db, vibrato) as i32;
+        }
+        self.output = output;
+    }
+}
+
+impl SaveState for Opll {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.custom_patch);
+        for channel in self.channels.iter() {
+            channel.save_state(writer);
+        }
+        writer.write_u8(self.register_select);
+        writer.write_bool(self.in_reset);
+        writer.write_u8(self.cycles);
+        writer.write_u16(self.tremolo_counter);
+        writer.write_u16(self.vibrato_counter);
+        writer.write_u32(self.output as u32);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes(&mut self.custom_patch)?;
+        for channel in self.channels.iter_mut() {
+            channel.load_state(reader)?;
+        }
+        self.register_select = reader.read_u8()?;
+        self.in_reset = reader.read_bool()?;
+        self.cycles = reader.read_u8()? % CPU_CYCLES_PER_SAMPLE;
+        self.tremolo_counter = reader.read_u16()? % AM_PERIOD;
+        self.vibrato_counter = reader.read_u16()? % VIBRATO_PERIOD;
+        self.output = reader.read_u32()? as i32;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod opll_tests {
+    use super::{
+        operator_output, EnvelopeState, Opll, Patch, CPU_CYCLES_PER_SAMPLE, ENVELOPE_MAX, INSTRUMENTS, OPERATOR_MAX,
+        PHASE_BITS,
+    };
+
+    /// Instrument 0 with a plain sine carrier and a silent modulator
+    const SINE_PATCH: [u8; 8] = [0x00, 0x21, 0x3F, 0x00, 0x00, 0xF0, 0x00, 0x0F];
+
+    fn sample(opll: &mut Opll) -> f32 {
+        for _ in 0..CPU_CYCLES_PER_SAMPLE - 1 {
+            opll.clock();
+        }
+        opll.clock()
+    }
+
+    fn write(opll: &mut Opll, register: u8, value: u8) {
+        opll.select_register(register);
+        opll.write_register(value);
+    }
+
+    /// Number of samples until the carrier of channel 0 reaches `envelope`
+    fn samples_until(opll: &mut Opll, envelope: EnvelopeState) -> usize {
+        (1..1_000_000)
+            .find(|_| {
+                sample(opll);
+                opll.channels[0].carrier.envelope == envelope
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_operator_output_is_a_sine_wave() {
+        let quarter = 1 << (PHASE_BITS - 2);
+        assert_eq!(operator_output(0, 0.0, 0.0, false), 0);
+        assert_eq!(operator_output(quarter, 0.0, 0.0, false), OPERATOR_MAX as i16);
+        assert_eq!(operator_output(quarter * 3, 0.0, 0.0, false), -OPERATOR_MAX as i16);
+
+        // Modulation is in whole cycles of the wave
+        assert_eq!(operator_output(0, 0.25, 0.0, false), OPERATOR_MAX as i16);
+        assert_eq!(operator_output(quarter, 1.0, 0.0, false), OPERATOR_MAX as i16);
+    }
+
+    #[test]
+    fn test_operator_attenuation() {
+        let quarter = 1 << (PHASE_BITS - 2);
+        // 6dB (roughly) halves the output, 48dB is almost nothing
+        assert_eq!(operator_output(quarter, 0.0, 6.0, false), 2052);
+        assert_eq!(operator_output(quarter, 0.0, 48.0, false), 16);
+    }
+
+    #[test]
+    fn test_rectified_operator_output() {
+        let quarter = 1 << (PHASE_BITS - 2);
+        assert_eq!(operator_output(quarter, 0.0, 0.0, true), OPERATOR_MAX as i16);
+        assert_eq!(operator_output(quarter * 3, 0.0, 0.0, true), 0);
+    }
+
+    #[test]
+    fn test_patch_decoding() {
+        let patch = Patch::from_bytes(&INSTRUMENTS[0]);
+        assert_eq!(patch.modulator.multiplier, 3);
+        assert!(patch.carrier.sustained);
+        assert_eq!(patch.carrier.multiplier, 1);
+        assert_eq!(patch.total_level, 5);
+        assert_eq!(patch.feedback, 6);
+        assert_eq!(patch.modulator.attack_rate, 0xE);
+        assert_eq!(patch.carrier.decay_rate, 0x1);
+        assert_eq!(patch.carrier.sustain_level, 0x2);
+        assert_eq!(patch.carrier.release_rate, 0x7);
+    }
+
+    #[test]
+    fn test_channel_frequency() {
+        let mut opll = Opll::new();
+        for (register, &value) in SINE_PATCH.iter().enumerate() {
+            write(&mut opll, register as u8, value);
+        }
+
+        // Frequency 256 in octave 4 is 1/128th of a cycle per sample
+        write(&mut opll, 0x10, 0x00);
+        write(&mut opll, 0x30, 0x00);
+        write(&mut opll, 0x20, 0b0001_1001);
+
+        let samples: Vec<f32> = (0..1280).map(|_| sample(&mut opll)).collect();
+        assert_eq!(opll.channels[0].carrier.envelope, EnvelopeState::Decay);
+        let rising_edges = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        assert_eq!(rising_edges, 10);
+        let peak = samples.iter().cloned().fold(0.0, f32::max);
+        assert!((peak - 0.15).abs() < 0.001, "{}", peak);
+
+        // Each step of volume is 3dB
+        write(&mut opll, 0x30, 0x02);
+        let peak = (0..128).map(|_| sample(&mut opll)).fold(0.0, f32::max);
+        assert!((peak - 0.15 / 2.0).abs() < 0.001, "{}", peak);
+    }
+
+    #[test]
+    fn test_key_off_releases_channel() {
+        let mut opll = Opll::new();
+        write(&mut opll, 0x10, 0x80);
+        write(&mut opll, 0x30, 0x30);
+        write(&mut opll, 0x20, 0b0001_0110);
+        let peak = (0..1000).map(|_| sample(&mut opll)).fold(0.0, f32::max);
+        assert!(peak > 0.0);
+
+        write(&mut opll, 0x20, 0b0000_0110);
+        assert_eq!(opll.channels[0].carrier.envelope, EnvelopeState::Release);
+        for _ in 0..200_000 {
+            sample(&mut opll);
+        }
+        assert_eq!(opll.channels[0].carrier.envelope, EnvelopeState::Off);
+        assert_eq!(opll.output, 0);
+    }
+
+    #[test]
+    fn test_reset_silences_and_ignores_writes() {
+        let mut opll = Opll::new();
+        write(&mut opll, 0x30, 0x30);
+        write(&mut opll, 0x20, 0b0001_0110);
+        opll.set_reset(true);
+        write(&mut opll, 0x20, 0b0001_0110);
+        assert!(!opll.channels[0].key_on);
+        assert_eq!(sample(&mut opll), 0.0);
+    }
+
+    #[test]
+    fn test_envelope_stages() {
+        // SINE_PATCH with a carrier attack rate of A, decay rate 8, sustain level 4 (12dB) & release rate F
+        let mut patch = SINE_PATCH;
+        patch[5] = 0xA8;
+        patch[7] = 0x4F;
+        let mut opll = Opll::new();
+        for (register, &value) in patch.iter().enumerate() {
+            write(&mut opll, register as u8, value);
+        }
+        write(&mut opll, 0x30, 0x00);
+        write(&mut opll, 0x20, 0b0001_1000);
+        assert_eq!(opll.channels[0].carrier.envelope, EnvelopeState::Attack);
+
+        let attack = samples_until(&mut opll, EnvelopeState::Decay);
+        assert_eq!(opll.channels[0].carrier.level, 0);
+
+        // Decay rate 8 in octave 4 adds 768 to the attenuation each sample
+        let decay = samples_until(&mut opll, EnvelopeState::Sustain);
+        assert!(attack < decay / 10, "{} {}", attack, decay);
+        assert_eq!(decay, 2731);
+        let sustain_level = opll.channels[0].carrier.level;
+        assert_eq!(sustain_level, 2731 * 768);
+
+        // A sustained tone holds its level until the key is released
+        for _ in 0..10_000 {
+            sample(&mut opll);
+        }
+        assert_eq!(opll.channels[0].carrier.envelope, EnvelopeState::Sustain);
+        assert_eq!(opll.channels[0].carrier.level, sustain_level);
+
+        write(&mut opll, 0x20, 0b0000_1000);
+        assert!(samples_until(&mut opll, EnvelopeState::Off) < 100);
+        assert_eq!(opll.channels[0].carrier.level, ENVELOPE_MAX);
+
+        // A percussive tone carries on decaying at the release rate with the key held
+        write(&mut opll, 0x01, patch[1] & !0b0010_0000);
+        write(&mut opll, 0x20, 0b0001_1000);
+        samples_until(&mut opll, EnvelopeState::Sustain);
+        assert!(samples_until(&mut opll, EnvelopeState::Off) < 100);
+        assert!(opll.channels[0].key_on);
+    }
+}
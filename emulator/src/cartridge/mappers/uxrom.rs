@@ -1,8 +1,9 @@
-use cartridge::mappers::{ChrData, NoBankChrChip, PrgBaseData};
+use cartridge::mappers::{prg_ram_from_header, ChrData, NoBankChrChip, PrgBaseData};
 use cartridge::CartridgeHeader;
 use cartridge::CpuCartridgeAddressBus;
 use cartridge::PpuCartridgeAddressBus;
 use log::info;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 /// UxRom board comes in a variety of variants which subtly change how
 /// banking is achieved
@@ -19,12 +20,12 @@ struct UxRom {
 }
 
 impl UxRom {
-    fn new(prg_rom: Vec<u8>, total_banks: usize, variant: UxRomVariant) -> Self {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>, total_banks: usize, variant: UxRomVariant) -> Self {
         UxRom {
             variant,
             base: PrgBaseData {
                 prg_rom,
-                prg_ram: None,
+                prg_ram,
                 bank_size: 0x4000,
                 total_banks,
                 banks: vec![0, total_banks - 1],
@@ -34,6 +35,16 @@ impl UxRom {
     }
 }
 
+impl SaveState for UxRom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
 impl CpuCartridgeAddressBus for UxRom {
     fn read_byte(&self, address: u16) -> u8 {
         self.base.read_byte(address)
@@ -58,6 +69,14 @@ impl CpuCartridgeAddressBus for UxRom {
             );
         }
     }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
 }
 
 pub(crate) fn from_header(
@@ -73,6 +92,7 @@ pub(crate) fn from_header(
     (
         Box::new(UxRom::new(
             prg_rom,
+            prg_ram_from_header(&header, false),
             header.prg_rom_16kb_units as usize,
             match header.mapper {
                 2 => UxRomVariant::Unrom,
@@ -81,7 +101,10 @@ pub(crate) fn from_header(
                 _ => panic!("Can't create UxROM from mapper {}", header.mapper),
             },
         )),
-        Box::new(NoBankChrChip::new(ChrData::from(chr_rom), header.mirroring)),
+        Box::new(NoBankChrChip::new(
+            ChrData::from_header(chr_rom, &header),
+            header.mirroring,
+        )),
         header,
     )
 }
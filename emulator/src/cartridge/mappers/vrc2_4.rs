@@ -0,0 +1,664 @@
+use cartridge::mappers::vrc_irq::VrcIrq;
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
+use cartridge::mirroring::MirroringMode;
+use cartridge::CartridgeHeader;
+use cartridge::CpuCartridgeAddressBus;
+use cartridge::PpuCartridgeAddressBus;
+use cpu::CpuCycle;
+use log::{debug, info};
+use ppu::PpuCycle;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// The CPU address lines wired to the chip's two register select pins, the
+/// only real difference between the VRC2 & VRC4 boards
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct RegisterLines {
+    a0: u16,
+    a1: u16,
+}
+
+impl RegisterLines {
+    fn union(&self, other: &RegisterLines) -> RegisterLines {
+        RegisterLines {
+            a0: self.a0 | other.a0,
+            a1: self.a1 | other.a1,
+        }
+    }
+
+    fn mask(&self) -> u16 {
+        self.a0 | self.a1
+    }
+
+    /// The register written to as xxx0-xxx3
+    fn register(&self, address: u16) -> u16 {
+        (address & 0xF000) | (address & self.a0 != 0) as u16 | ((address & self.a1 != 0) as u16) << 1
+    }
+}
+
+const VRC4A: RegisterLines = RegisterLines { a0: 0x02, a1: 0x04 };
+const VRC4C: RegisterLines = RegisterLines { a0: 0x40, a1: 0x80 };
+const VRC2A: RegisterLines = RegisterLines { a0: 0x02, a1: 0x01 };
+const VRC4F: RegisterLines = RegisterLines { a0: 0x01, a1: 0x02 };
+const VRC4E: RegisterLines = RegisterLines { a0: 0x04, a1: 0x08 };
+const VRC4B: RegisterLines = RegisterLines { a0: 0x02, a1: 0x01 };
+const VRC4D: RegisterLines = RegisterLines { a0: 0x08, a1: 0x04 };
+
+/// Which board the cartridge is, worked out from the mapper & submapper
+///
+/// iNES headers (submapper 0) don't say which of the two boards sharing a
+/// mapper number it is. Until the game writes to a register using address
+/// lines which only one of them connects both sets of lines are decoded,
+/// after that only those of the board that matched. They can't tell a VRC2
+/// from a VRC4 either, the VRC4 is used as it's a superset (with PRG RAM
+/// standing in for the VRC2's microwire latch) of what VRC2 games use. The
+/// exception is 9002, which on the VRC2 is another mirroring register, so
+/// it's ignored until the game uses the IRQ or PRG swap mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Variant {
+    lines: RegisterLines,
+    /// The other board it might be when the header doesn't say
+    alternative: Option<RegisterLines>,
+    /// Which of the two it's turned out to be from the game's writes
+    detected: Option<RegisterLines>,
+    vrc2: bool,
+    /// Known to be a VRC4, either from the header or from the game's writes
+    vrc4: bool,
+    /// The VRC2a ignores the lowest bit of CHR bank numbers
+    chr_shift: u8,
+}
+
+impl Variant {
+    fn from_header(header: &CartridgeHeader) -> Self {
+        let (lines, alternative, vrc2, vrc4) = match (header.mapper, header.submapper) {
+            (21, 1) => (VRC4A, None, false, true),
+            (21, 2) => (VRC4C, None, false, true),
+            (21, _) => (VRC4A, Some(VRC4C), false, true),
+            (22, _) => (VRC2A, None, true, false),
+            (23, 1) => (VRC4F, None, false, true),
+            (23, 2) => (VRC4E, None, false, true),
+            (23, 3) => (VRC4F, None, true, false), // VRC2b
+            (23, _) => (VRC4F, Some(VRC4E), false, false),
+            (25, 1) => (VRC4B, None, false, true),
+            (25, 2) => (VRC4D, None, false, true),
+            (25, 3) => (VRC4B, None, true, false), // VRC2c
+            (25, _) => (VRC4B, Some(VRC4D), false, false),
+            _ => panic!("Mapper {} isn't a VRC2/VRC4 board", header.mapper),
+        };
+
+        Variant {
+            lines,
+            alternative,
+            detected: None,
+            vrc2,
+            vrc4,
+            chr_shift: (header.mapper == 22) as u8,
+        }
+    }
+
+    /// Decode a write to 8000-FFFF as `register` does, also noting when it
+    /// shows the chip is a VRC4 (only the VRC4 has the IRQ registers and a
+    /// VRC2 game writing mirroring to 9002 never sets bit 1)
+    fn write(&mut self, address: u16, value: u8) -> u16 {
+        let register = self.register(address);
+        if !self.vrc2 && !self.vrc4 {
+            self.vrc4 = match register {
+                0x9002 => value & 0b10 != 0,
+                0xF000..=0xF003 => true,
+                _ => false,
+            };
+            if self.vrc4 {
+                info!("VRC2/4 board detected as a VRC4");
+            }
+        }
+
+        register
+    }
+
+    /// Decode a write to 8000-FFFF into one of the registers xxx0-xxx3
+    fn register(&mut self, address: u16) -> u16 {
+        match (self.alternative, self.detected) {
+            (None, _) => self.lines.register(address),
+            (Some(_), Some(detected)) => detected.register(address),
+            (Some(alternative), None) => {
+                self.detected = match (address & self.lines.mask() != 0, address & alternative.mask() != 0) {
+                    (true, false) => Some(self.lines),
+                    (false, true) => Some(alternative),
+                    _ => None,
+                };
+                if let Some(lines) = self.detected {
+                    info!("VRC2/4 board detected as {:?}", lines);
+                }
+
+                self.lines.union(&alternative).register(address)
+            }
+        }
+    }
+}
+
+impl SaveState for Variant {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(match self.detected {
+            None => 0,
+            Some(lines) if lines == self.lines => 1,
+            Some(_) => 2,
+        });
+        writer.write_bool(self.vrc4);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.detected = match (reader.read_u8()?, self.alternative) {
+            (0, _) => None,
+            (1, Some(_)) => Some(self.lines),
+            (2, Some(alternative)) => Some(alternative),
+            _ => return Err(SaveStateError::new("VRC2/4 board variant mismatch")),
+        };
+        self.vrc4 = match (reader.read_bool()?, self.vrc2) {
+            (true, true) => return Err(SaveStateError::new("VRC2/4 board variant mismatch")),
+            (vrc4, _) => vrc4,
+        };
+        Ok(())
+    }
+}
+
+/// The CPU side of the VRC2/VRC4 handles PRG banking, PRG RAM (or the
+/// VRC2's microwire latch) and the VRC4's IRQ counter.
+pub(crate) struct Vrc4PrgChip {
+    base: PrgBaseData,
+    variant: Variant,
+    /// Banks selected by 8000 & A000
+    prg_registers: [u8; 2],
+    /// VRC4 only, swaps 8000 with the fixed bank at C000
+    swap_mode: bool,
+    /// VRC4 only, set by 9002. Starts enabled as iNES games are treated as
+    /// a VRC2 here until they're seen to be a VRC4, and may already be using
+    /// their PRG RAM by then
+    ram_enabled: bool,
+    /// VRC2 boards without PRG RAM have a 1 bit latch at 6000-6FFF which
+    /// was meant for an EEPROM but is used as a copy protection check
+    microwire_latch: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc4PrgChip {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>, variant: Variant) -> Self {
+        let total_banks = prg_rom.len() / 0x2000;
+        let mut chip = Vrc4PrgChip {
+            base: PrgBaseData::new(prg_rom, prg_ram, total_banks, 0x2000, vec![0; 4], vec![0; 4]),
+            variant,
+            prg_registers: [0, 1],
+            swap_mode: false,
+            ram_enabled: true,
+            microwire_latch: 0,
+            irq: VrcIrq::new(),
+        };
+        chip.update_banks();
+
+        chip
+    }
+
+    fn has_microwire_latch(&self) -> bool {
+        self.variant.vrc2 && self.base.prg_ram.is_none()
+    }
+
+    fn update_banks(&mut self) {
+        let second_last = self.base.total_banks - 2;
+        let (first, third) = match self.swap_mode {
+            false => (self.prg_registers[0] as usize, second_last),
+            true => (second_last, self.prg_registers[0] as usize),
+        };
+        let banks = [first, self.prg_registers[1] as usize, third, self.base.total_banks - 1];
+        for (slot, bank) in banks.iter().enumerate() {
+            self.base.banks[slot] = bank % self.base.total_banks;
+            self.base.bank_offsets[slot] = self.base.banks[slot] * 0x2000;
+        }
+
+        info!(
+            "VRC2/4 PRG banks updated {:?} -> {:?}",
+            self.base.banks, self.base.bank_offsets
+        );
+    }
+}
+
+impl SaveState for Vrc4PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        self.variant.save_state(writer);
+        writer.write_bytes(&self.prg_registers);
+        writer.write_bool(self.swap_mode);
+        writer.write_bool(self.ram_enabled);
+        writer.write_u8(self.microwire_latch);
+        self.irq.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.variant.load_state(reader)?;
+        reader.read_bytes(&mut self.prg_registers)?;
+        self.swap_mode = reader.read_bool()?;
+        self.ram_enabled = reader.read_bool()?;
+        self.microwire_latch = reader.read_u8()? & 1;
+        self.irq.load_state(reader)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+impl CpuCartridgeAddressBus for Vrc4PrgChip {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            // TODO - The upper bits should be open bus
+            0x6000..=0x6FFF if self.has_microwire_latch() => self.microwire_latch,
+            0x6000..=0x7FFF if !self.ram_enabled => 0x0, // TODO - Should be open bus
+            _ => self.base.read_byte(address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        debug!("CPU write to VRC2/4 PRG bus {:04X}={:02X}", address, value);
+
+        match address {
+            0x6000..=0x6FFF if self.has_microwire_latch() => self.microwire_latch = value & 1,
+            0x6000..=0x7FFF if self.ram_enabled => self.base.write_byte(address, value),
+            0x8000..=0xFFFF => {
+                let register = self.variant.write(address, value);
+                let vrc4 = self.variant.vrc4;
+                match register {
+                    0x8000..=0x8003 => {
+                        self.prg_registers[0] = value & 0x1F;
+                        self.update_banks();
+                    }
+                    0xA000..=0xA003 => {
+                        self.prg_registers[1] = value & 0x1F;
+                        self.update_banks();
+                    }
+                    0x9002 if vrc4 => {
+                        self.ram_enabled = value & 1 != 0;
+                        self.swap_mode = value & 0b10 != 0;
+                        self.update_banks();
+                    }
+                    0xF000 if vrc4 => self.irq.write_latch_nibble(value, false),
+                    0xF001 if vrc4 => self.irq.write_latch_nibble(value, true),
+                    0xF002 if vrc4 => self.irq.write_control(value),
+                    0xF003 if vrc4 => self.irq.acknowledge(),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
+
+    fn clock_irq(&mut self) {
+        self.irq.clock();
+    }
+
+    fn check_trigger_irq(&self) -> bool {
+        self.irq.pending()
+    }
+}
+
+/// The PPU side of the VRC2/VRC4 handles CHR banking (8 x 1KB banks, each
+/// set 4 bits at a time) and mirroring.
+pub(crate) struct Vrc4ChrChip {
+    base: ChrBaseData,
+    variant: Variant,
+    /// 9 bit bank numbers (8 bit on the VRC2) before the VRC2a shift
+    chr_registers: [u16; 8],
+}
+
+impl Vrc4ChrChip {
+    fn new(chr_data: ChrData, mirroring_mode: MirroringMode, variant: Variant) -> Self {
+        Vrc4ChrChip {
+            base: ChrBaseData::new(mirroring_mode, chr_data, 0x400, vec![0; 8], vec![0; 8]),
+            variant,
+            chr_registers: [0; 8],
+        }
+    }
+
+    fn update_banks(&mut self) {
+        for (slot, register) in self.chr_registers.iter().enumerate() {
+            self.base.banks[slot] = (*register >> self.variant.chr_shift) as usize % self.base.total_banks;
+            self.base.bank_offsets[slot] = self.base.banks[slot] * 0x400;
+        }
+
+        info!(
+            "VRC2/4 CHR banks updated {:?} -> {:?}",
+            self.base.banks, self.base.bank_offsets
+        );
+    }
+}
+
+impl SaveState for Vrc4ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        self.variant.save_state(writer);
+        for register in self.chr_registers.iter() {
+            writer.write_u16(*register);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.variant.load_state(reader)?;
+        for register in self.chr_registers.iter_mut() {
+            *register = reader.read_u16()? & 0x1FF;
+        }
+        self.update_banks();
+        Ok(())
+    }
+}
+
+impl PpuCartridgeAddressBus for Vrc4ChrChip {
+    fn read_byte(&mut self, address: u16, _: PpuCycle) -> u8 {
+        self.base.read_byte(address)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        self.base.write_byte(address, value);
+    }
+
+    fn cpu_write_byte(&mut self, address: u16, value: u8, _: CpuCycle) {
+        if address < 0x8000 {
+            return;
+        }
+
+        match self.variant.write(address, value) {
+            // The VRC2 only has one bit of mirroring control, on all four registers
+            0x9000..=0x9003 if self.variant.vrc2 => {
+                self.base.mirroring_mode = match value & 1 {
+                    0 => MirroringMode::Vertical,
+                    _ => MirroringMode::Horizontal,
+                };
+            }
+            0x9000 | 0x9001 => {
+                self.base.mirroring_mode = match value & 0b11 {
+                    0 => MirroringMode::Vertical,
+                    1 => MirroringMode::Horizontal,
+                    2 => MirroringMode::OneScreenLowerBank,
+                    _ => MirroringMode::OneScreenUpperBank,
+                };
+                info!("VRC4 mirroring mode change {:?}", self.base.mirroring_mode);
+            }
+            register @ 0xB000..=0xE003 => {
+                let slot = ((register >> 12) as usize - 0xB) * 2 + (register as usize & 0b10) / 2;
+                self.chr_registers[slot] = match register & 1 {
+                    0 => (self.chr_registers[slot] & 0x1F0) | (value & 0x0F) as u16,
+                    _ => {
+                        let high_bits = if self.variant.vrc2 { 0x0F } else { 0x1F };
+                        (self.chr_registers[slot] & 0x0F) | ((value & high_bits) as u16) << 4
+                    }
+                };
+                self.update_banks();
+            }
+            _ => (),
+        }
+    }
+}
+
+pub(crate) fn from_header(
+    prg_rom: Vec<u8>,
+    chr_rom: Option<Vec<u8>>,
+    header: CartridgeHeader,
+) -> (
+    Box<dyn CpuCartridgeAddressBus>,
+    Box<dyn PpuCartridgeAddressBus>,
+    CartridgeHeader,
+) {
+    info!("Creating VRC2/VRC4 mapper for cartridge {:?}", header);
+    let variant = Variant::from_header(&header);
+    (
+        Box::new(Vrc4PrgChip::new(
+            prg_rom,
+            prg_ram_from_header(&header, !variant.vrc2),
+            variant,
+        )),
+        Box::new(Vrc4ChrChip::new(
+            ChrData::from_header(chr_rom, &header),
+            header.mirroring,
+            variant,
+        )),
+        header,
+    )
+}
+
+#[cfg(test)]
+mod vrc2_4_tests {
+    use super::{Variant, Vrc4ChrChip, Vrc4PrgChip, VRC4C, VRC4E};
+    use cartridge::mappers::{numbered_banks, ChrData};
+    use cartridge::mirroring::MirroringMode;
+    use cartridge::{CartridgeHeader, CpuCartridgeAddressBus, PpuCartridgeAddressBus};
+
+    /// NES 2.0 header for the mapper & submapper
+    fn variant(mapper: u8, submapper: u8) -> Variant {
+        let header = CartridgeHeader::from_bytes(&[
+            b'N',
+            b'E',
+            b'S',
+            0x1A,
+            16,
+            32,
+            mapper << 4,
+            (mapper & 0xF0) | 0b1000,
+            submapper << 4,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ])
+        .unwrap();
+        Variant::from_header(&header)
+    }
+
+    fn prg_chip(variant: Variant, prg_ram: Option<Vec<u8>>) -> Vrc4PrgChip {
+        Vrc4PrgChip::new(numbered_banks(32, 0x2000), prg_ram, variant)
+    }
+
+    fn chr_chip(variant: Variant) -> Vrc4ChrChip {
+        Vrc4ChrChip::new(
+            ChrData::Rom(numbered_banks(256, 0x400)),
+            MirroringMode::Vertical,
+            variant,
+        )
+    }
+
+    #[test]
+    fn test_register_lines_for_each_board() {
+        // The address of register 1, 2 & 3 on each board
+        let boards = [
+            ((21, 1), [0x02, 0x04, 0x06]),
+            ((21, 2), [0x40, 0x80, 0xC0]),
+            ((22, 0), [0x02, 0x01, 0x03]),
+            ((23, 1), [0x01, 0x02, 0x03]),
+            ((23, 2), [0x04, 0x08, 0x0C]),
+            ((23, 3), [0x01, 0x02, 0x03]),
+            ((25, 1), [0x02, 0x01, 0x03]),
+            ((25, 2), [0x08, 0x04, 0x0C]),
+            ((25, 3), [0x02, 0x01, 0x03]),
+        ];
+        for &((mapper, submapper), addresses) in boards.iter() {
+            let mut variant = variant(mapper, submapper);
+            assert_eq!(variant.register(0xB000), 0xB000);
+            for (register, &address) in addresses.iter().enumerate() {
+                assert_eq!(
+                    variant.register(0xB000 | address),
+                    0xB001 + register as u16,
+                    "mapper {} submapper {}",
+                    mapper,
+                    submapper
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_ines_board_detection() {
+        let mut mapper_21 = variant(21, 0);
+        assert_eq!(mapper_21.register(0x8000), 0x8000);
+        assert_eq!(mapper_21.detected, None);
+        assert_eq!(mapper_21.register(0xF080), 0xF002);
+        assert_eq!(mapper_21.detected, Some(VRC4C));
+        // Once locked only that board's lines are decoded
+        assert_eq!(mapper_21.register(0xF004), 0xF000);
+
+        let mut mapper_23 = variant(23, 0);
+        assert_eq!(mapper_23.register(0x9008), 0x9002);
+        assert_eq!(mapper_23.detected, Some(VRC4E));
+        assert!(!mapper_23.vrc2);
+    }
+
+    #[test]
+    fn test_prg_banking_and_swap_mode() {
+        let mut vrc4 = prg_chip(variant(25, 1), Some(vec![0; 0x2000]));
+        vrc4.write_byte(0x8000, 3, 0);
+        vrc4.write_byte(0xA000, 4, 0);
+        assert_eq!(vrc4.read_byte(0x8000), 3);
+        assert_eq!(vrc4.read_byte(0xA000), 4);
+        assert_eq!(vrc4.read_byte(0xC000), 30);
+        assert_eq!(vrc4.read_byte(0xE000), 31);
+
+        // 9002 on VRC4b is 9001 on the bus
+        vrc4.write_byte(0x9001, 0b11, 0);
+        assert_eq!(vrc4.read_byte(0x8000), 30);
+        assert_eq!(vrc4.read_byte(0xC000), 3);
+
+        vrc4.write_byte(0x6000, 0x12, 0);
+        assert_eq!(vrc4.read_byte(0x6000), 0x12);
+    }
+
+    #[test]
+    fn test_vrc4_prg_ram_enable() {
+        let mut vrc4 = prg_chip(variant(21, 2), Some(vec![0; 0x2000]));
+        vrc4.write_byte(0x7FFF, 0x34, 0);
+
+        vrc4.write_byte(0x9080, 0b10, 0);
+        assert_eq!(vrc4.read_byte(0x7FFF), 0x0);
+        vrc4.write_byte(0x7FFF, 0x56, 0);
+
+        vrc4.write_byte(0x9080, 0b01, 0);
+        assert_eq!(vrc4.read_byte(0x7FFF), 0x34);
+
+        // The VRC2 has no enable bit
+        let mut vrc2 = prg_chip(variant(23, 3), Some(vec![0; 0x2000]));
+        vrc2.write_byte(0x9002, 0, 0);
+        vrc2.write_byte(0x6000, 0x12, 0);
+        assert_eq!(vrc2.read_byte(0x6000), 0x12);
+    }
+
+    #[test]
+    fn test_ines_vrc2_mirroring_writes_leave_prg_alone() {
+        let mut vrc2 = prg_chip(variant(23, 0), Some(vec![0; 0x2000]));
+        vrc2.write_byte(0x6000, 0x12, 0);
+        vrc2.write_byte(0x8000, 3, 0);
+
+        // The VRC2b mirroring register on every alias, 9002 is the VRC4's
+        // PRG RAM enable & swap mode
+        for address in 0x9000..=0x9003 {
+            for &value in [1, 0].iter() {
+                vrc2.write_byte(address, value, 0);
+            }
+        }
+        assert!(!vrc2.variant.vrc4);
+        assert!(!vrc2.swap_mode);
+        assert_eq!(vrc2.read_byte(0x8000), 3);
+        assert_eq!(vrc2.read_byte(0x6000), 0x12);
+        vrc2.write_byte(0x6000, 0x34, 0);
+        assert_eq!(vrc2.read_byte(0x6000), 0x34);
+
+        // Until the IRQ is used and it turns out to be a VRC4 after all
+        let mut vrc4 = prg_chip(variant(23, 0), Some(vec![0; 0x2000]));
+        vrc4.write_byte(0xF002, 0, 0);
+        assert!(vrc4.variant.vrc4);
+        vrc4.write_byte(0x9002, 0, 0);
+        assert_eq!(vrc4.read_byte(0x6000), 0x0);
+    }
+
+    #[test]
+    fn test_vrc2_has_no_swap_mode_or_irq() {
+        let mut vrc2 = prg_chip(variant(23, 3), None);
+        vrc2.write_byte(0x8000, 3, 0);
+        vrc2.write_byte(0x9002, 0b10, 0);
+        assert_eq!(vrc2.read_byte(0x8000), 3);
+
+        vrc2.write_byte(0xF002, 0b110, 0);
+        vrc2.clock_irq();
+        vrc2.clock_irq();
+        assert!(!vrc2.check_trigger_irq());
+    }
+
+    #[test]
+    fn test_vrc2_microwire_latch() {
+        let mut vrc2 = prg_chip(variant(22, 0), None);
+        vrc2.write_byte(0x6000, 0xFF, 0);
+        assert_eq!(vrc2.read_byte(0x6000), 1);
+        vrc2.write_byte(0x6100, 0xFE, 0);
+        assert_eq!(vrc2.read_byte(0x6000), 0);
+    }
+
+    #[test]
+    fn test_vrc4_irq() {
+        let mut vrc4 = prg_chip(variant(21, 1), None);
+        vrc4.write_byte(0xF000, 0x0E, 0);
+        vrc4.write_byte(0xF002, 0x0F, 0);
+        vrc4.write_byte(0xF004, 0b110, 0);
+        vrc4.clock_irq();
+        assert!(!vrc4.check_trigger_irq());
+        vrc4.clock_irq();
+        assert!(vrc4.check_trigger_irq());
+
+        // Acknowledging copies the A bit into the enable bit, here clear so the counter stops
+        vrc4.write_byte(0xF006, 0, 0);
+        assert!(!vrc4.check_trigger_irq());
+        for _ in 0..0x100 {
+            vrc4.clock_irq();
+        }
+        assert!(!vrc4.check_trigger_irq());
+
+        // With it set counting carries on from the latch
+        vrc4.write_byte(0xF004, 0b111, 0);
+        vrc4.clock_irq();
+        vrc4.clock_irq();
+        assert!(vrc4.check_trigger_irq());
+        vrc4.write_byte(0xF006, 0, 0);
+        vrc4.clock_irq();
+        assert!(!vrc4.check_trigger_irq());
+        vrc4.clock_irq();
+        assert!(vrc4.check_trigger_irq());
+    }
+
+    #[test]
+    fn test_chr_banking_and_mirroring() {
+        let mut vrc4 = chr_chip(variant(23, 2));
+        vrc4.cpu_write_byte(0xB000, 0x05, 0);
+        vrc4.cpu_write_byte(0xB004, 0x0A, 0);
+        vrc4.cpu_write_byte(0xE008, 0x03, 0);
+        vrc4.cpu_write_byte(0xE00C, 0x0F, 0);
+        assert_eq!(vrc4.read_byte(0x0000, 0), 0xA5);
+        assert_eq!(vrc4.read_byte(0x1C00, 0), 0xF3);
+
+        // VRC2a drops the bottom bit of the bank number
+        let mut vrc2 = chr_chip(variant(22, 0));
+        vrc2.cpu_write_byte(0xC000, 0x07, 0);
+        vrc2.cpu_write_byte(0xC002, 0x01, 0);
+        assert_eq!(vrc2.read_byte(0x0800, 0), 0x0B);
+
+        // The VRC4 has one screen mirroring as well
+        let mut vrc4 = chr_chip(variant(21, 1));
+        vrc4.cpu_write_byte(0x9000, 0x03, 0);
+        assert_eq!(vrc4.base.mirroring_mode, MirroringMode::OneScreenUpperBank);
+
+        // Only one bit of mirroring on the VRC2, on every register
+        let mut vrc2 = chr_chip(variant(23, 3));
+        vrc2.cpu_write_byte(0x9002, 0x03, 0);
+        assert_eq!(vrc2.base.mirroring_mode, MirroringMode::Horizontal);
+    }
+}
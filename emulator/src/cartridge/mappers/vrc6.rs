@@ -0,0 +1,688 @@
+use cartridge::mappers::vrc_irq::VrcIrq;
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
+use cartridge::mirroring::MirroringMode;
+use cartridge::CartridgeHeader;
+use cartridge::CpuCartridgeAddressBus;
+use cartridge::PpuCartridgeAddressBus;
+use cpu::CpuCycle;
+use log::{debug, info};
+use ppu::PpuCycle;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// Output level of each step of the (linearly mixed) VRC6 channels, chosen
+/// so that a VRC6 pulse at full volume is about as loud as an APU pulse at
+/// full volume
+const OUTPUT_LEVEL: f32 = 0.15 / 15.0;
+
+/// Mapper 26 boards have A0 & A1 wired the other way round to the chip, put
+/// them back so that registers can be decoded the same way for both
+fn register_address(address: u16, swap_address_lines: bool) -> u16 {
+    let address = address & 0xF003;
+    match swap_address_lines {
+        true => (address & 0xF000) | (address & 0b01) << 1 | (address & 0b10) >> 1,
+        false => address,
+    }
+}
+
+/// The 12 bit frequency divider common to all three channels, the channel
+/// steps once every `period + 1` CPU cycles
+struct Vrc6Timer {
+    period: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl Vrc6Timer {
+    fn new() -> Self {
+        Vrc6Timer {
+            period: 0,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    fn write_period_low(&mut self, value: u8) {
+        self.period = (self.period & 0xF00) | value as u16;
+    }
+
+    fn write_period_high_enabled(&mut self, value: u8) {
+        self.period = (self.period & 0xFF) | ((value & 0x0F) as u16) << 8;
+        self.enabled = value & 0b1000_0000 != 0;
+    }
+
+    /// Returns true when the channel should step, `shift` is from the
+    /// frequency control register which speeds up all channels at once
+    fn clock(&mut self, shift: u8) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        if self.counter == 0 {
+            self.counter = self.period >> shift;
+            true
+        } else {
+            self.counter -= 1;
+            false
+        }
+    }
+}
+
+impl SaveState for Vrc6Timer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.period);
+        writer.write_u16(self.counter);
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.period = reader.read_u16()? & 0xFFF;
+        self.counter = reader.read_u16()? & 0xFFF;
+        self.enabled = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+/// A pulse channel with 16 step duty cycles and a fixed volume, unlike
+/// the APU pulses there's no envelope, sweep or length counter
+struct Vrc6Pulse {
+    timer: Vrc6Timer,
+    volume: u8,
+    /// The channel is high for steps 0..=duty of 16
+    duty: u8,
+    /// Mode bit, the channel outputs its volume constantly
+    ignore_duty: bool,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn new() -> Self {
+        Vrc6Pulse {
+            timer: Vrc6Timer::new(),
+            volume: 0,
+            duty: 0,
+            ignore_duty: false,
+            step: 15,
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.ignore_duty = value & 0b1000_0000 != 0;
+                self.duty = (value >> 4) & 0b111;
+                self.volume = value & 0x0F;
+            }
+            1 => self.timer.write_period_low(value),
+            _ => {
+                self.timer.write_period_high_enabled(value);
+                // Disabling the channel resets the duty cycle
+                if !self.timer.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if self.timer.clock(shift) {
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        match self.timer.enabled && (self.ignore_duty || self.step <= self.duty) {
+            true => self.volume,
+            false => 0,
+        }
+    }
+}
+
+impl SaveState for Vrc6Pulse {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.timer.save_state(writer);
+        writer.write_u8(self.volume);
+        writer.write_u8(self.duty);
+        writer.write_bool(self.ignore_duty);
+        writer.write_u8(self.step);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.timer.load_state(reader)?;
+        self.volume = reader.read_u8()? & 0x0F;
+        self.duty = reader.read_u8()? & 0b111;
+        self.ignore_duty = reader.read_bool()?;
+        self.step = reader.read_u8()? & 0x0F;
+        Ok(())
+    }
+}
+
+/// The sawtooth adds its rate to an accumulator every other step and resets
+/// it every 14 steps, the top 5 bits of the accumulator are the output
+struct Vrc6Sawtooth {
+    timer: Vrc6Timer,
+    rate: u8,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Sawtooth {
+    fn new() -> Self {
+        Vrc6Sawtooth {
+            timer: Vrc6Timer::new(),
+            rate: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0b0011_1111,
+            1 => self.timer.write_period_low(value),
+            _ => {
+                self.timer.write_period_high_enabled(value);
+                if !self.timer.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if self.timer.clock(shift) {
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 1 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+impl SaveState for Vrc6Sawtooth {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.timer.save_state(writer);
+        writer.write_u8(self.rate);
+        writer.write_u8(self.step);
+        writer.write_u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.timer.load_state(reader)?;
+        self.rate = reader.read_u8()? & 0b0011_1111;
+        self.step = reader.read_u8()? % 14;
+        self.accumulator = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+/// The expansion audio on the VRC6, two pulse channels and a sawtooth which
+/// are mixed linearly on the cartridge
+struct Vrc6Audio {
+    pulse_1: Vrc6Pulse,
+    pulse_2: Vrc6Pulse,
+    sawtooth: Vrc6Sawtooth,
+    /// Register 9003, stops all channels stepping (they keep outputting their current level)
+    halt: bool,
+    /// Register 9003, right shift applied to every channel's period
+    frequency_shift: u8,
+}
+
+impl Vrc6Audio {
+    fn new() -> Self {
+        Vrc6Audio {
+            pulse_1: Vrc6Pulse::new(),
+            pulse_2: Vrc6Pulse::new(),
+            sawtooth: Vrc6Sawtooth::new(),
+            halt: false,
+            frequency_shift: 0,
+        }
+    }
+
+    /// `address` has already had the mapper 26 address lines put back
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x9003 => {
+                self.halt = value & 0b001 != 0;
+                self.frequency_shift = if value & 0b100 != 0 {
+                    8
+                } else if value & 0b010 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0x9000..=0x9002 => self.pulse_1.write_register(address & 0b11, value),
+            0xA000..=0xA002 => self.pulse_2.write_register(address & 0b11, value),
+            0xB000..=0xB002 => self.sawtooth.write_register(address & 0b11, value),
+            _ => (),
+        }
+    }
+
+    fn clock(&mut self) -> f32 {
+        if !self.halt {
+            self.pulse_1.clock(self.frequency_shift);
+            self.pulse_2.clock(self.frequency_shift);
+            self.sawtooth.clock(self.frequency_shift);
+        }
+
+        let output = self.pulse_1.output() + self.pulse_2.output() + self.sawtooth.output();
+        output as f32 * OUTPUT_LEVEL
+    }
+}
+
+impl SaveState for Vrc6Audio {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.pulse_1.save_state(writer);
+        self.pulse_2.save_state(writer);
+        self.sawtooth.save_state(writer);
+        writer.write_bool(self.halt);
+        writer.write_u8(self.frequency_shift);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.pulse_1.load_state(reader)?;
+        self.pulse_2.load_state(reader)?;
+        self.sawtooth.load_state(reader)?;
+        self.halt = reader.read_bool()?;
+        self.frequency_shift = match reader.read_u8()? {
+            shift @ 0 | shift @ 4 | shift @ 8 => shift,
+            _ => return Err(SaveStateError::new("Invalid VRC6 frequency shift")),
+        };
+        Ok(())
+    }
+}
+
+/// The CPU side of the VRC6 handles PRG banking, PRG RAM, the IRQ counter
+/// (which is clocked by the CPU) and audio.
+pub(crate) struct Vrc6PrgChip {
+    /// 8KB slots where the first two hold a single 16KB bank
+    base: PrgBaseData,
+    swap_address_lines: bool,
+    /// Bit 7 of B003, PRG RAM is disabled until it's set
+    ram_enabled: bool,
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6PrgChip {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>, swap_address_lines: bool) -> Self {
+        let total_banks = prg_rom.len() / 0x2000;
+        let banks = vec![0, 1, total_banks - 2, total_banks - 1];
+        let bank_offsets = banks.iter().map(|bank| bank * 0x2000).collect();
+
+        Vrc6PrgChip {
+            base: PrgBaseData::new(prg_rom, prg_ram, total_banks, 0x2000, banks, bank_offsets),
+            swap_address_lines,
+            ram_enabled: false,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    fn set_bank(&mut self, slot: usize, bank: usize) {
+        self.base.banks[slot] = bank % self.base.total_banks;
+        self.base.bank_offsets[slot] = self.base.banks[slot] * 0x2000;
+
+        info!(
+            "VRC6 PRG banks updated {:?} -> {:?}",
+            self.base.banks, self.base.bank_offsets
+        );
+    }
+}
+
+impl SaveState for Vrc6PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_bool(self.ram_enabled);
+        self.irq.save_state(writer);
+        self.audio.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.ram_enabled = reader.read_bool()?;
+        self.irq.load_state(reader)?;
+        self.audio.load_state(reader)
+    }
+}
+
+impl CpuCartridgeAddressBus for Vrc6PrgChip {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if !self.ram_enabled => 0x0, // TODO - Should be open bus
+            _ => self.base.read_byte(address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        debug!("CPU write to VRC6 PRG bus {:04X}={:02X}", address, value);
+
+        match address {
+            0x6000..=0x7FFF if self.ram_enabled => self.base.write_byte(address, value),
+            0x8000..=0xFFFF => match register_address(address, self.swap_address_lines) {
+                0x8000..=0x8003 => {
+                    let bank = (value & 0x0F) as usize * 2;
+                    self.set_bank(0, bank);
+                    self.set_bank(1, bank + 1);
+                }
+                0xB003 => self.ram_enabled = value & 0b1000_0000 != 0,
+                register @ 0x9000..=0xB002 => self.audio.write_register(register, value),
+                0xC000..=0xC003 => self.set_bank(2, (value & 0x1F) as usize),
+                0xF000 => self.irq.write_latch(value),
+                0xF001 => self.irq.write_control(value),
+                0xF002 => self.irq.acknowledge(),
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
+
+    fn clock_expansion_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+
+    fn clock_irq(&mut self) {
+        self.irq.clock();
+    }
+
+    fn check_trigger_irq(&self) -> bool {
+        self.irq.pending()
+    }
+}
+
+/// The PPU side of the VRC6 handles CHR banking (8 x 1KB banks) and
+/// mirroring.
+///
+/// Only the banking mode used by all three released games is supported, the
+/// 2KB & mixed CHR modes and using CHR ROM as nametables are not.
+pub(crate) struct Vrc6ChrChip {
+    base: ChrBaseData,
+    swap_address_lines: bool,
+}
+
+impl Vrc6ChrChip {
+    fn new(chr_data: ChrData, mirroring_mode: MirroringMode, swap_address_lines: bool) -> Self {
+        Vrc6ChrChip {
+            base: ChrBaseData::new(
+                mirroring_mode,
+                chr_data,
+                0x400,
+                vec![0, 1, 2, 3, 4, 5, 6, 7],
+                vec![0x0000, 0x0400, 0x0800, 0x0C00, 0x1000, 0x1400, 0x1800, 0x1C00],
+            ),
+            swap_address_lines,
+        }
+    }
+}
+
+impl SaveState for Vrc6ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
+impl PpuCartridgeAddressBus for Vrc6ChrChip {
+    fn read_byte(&mut self, address: u16, _: PpuCycle) -> u8 {
+        self.base.read_byte(address)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        self.base.write_byte(address, value);
+    }
+
+    fn cpu_write_byte(&mut self, address: u16, value: u8, _: CpuCycle) {
+        if address < 0x8000 {
+            return;
+        }
+
+        match register_address(address, self.swap_address_lines) {
+            0xB003 => {
+                self.base.mirroring_mode = match (value >> 2) & 0b11 {
+                    0 => MirroringMode::Vertical,
+                    1 => MirroringMode::Horizontal,
+                    2 => MirroringMode::OneScreenLowerBank,
+                    _ => MirroringMode::OneScreenUpperBank,
+                };
+                info!("VRC6 mirroring mode change {:?}", self.base.mirroring_mode);
+            }
+            register @ 0xD000..=0xD003 | register @ 0xE000..=0xE003 => {
+                let slot = ((register >> 12) as usize - 0xD) * 4 + (register & 0b11) as usize;
+                self.base.banks[slot] = value as usize % self.base.total_banks;
+                self.base.bank_offsets[slot] = self.base.banks[slot] * 0x400;
+
+                info!(
+                    "VRC6 CHR banks updated {:?} -> {:?}",
+                    self.base.banks, self.base.bank_offsets
+                );
+            }
+            _ => (),
+        }
+    }
+}
+
+pub(crate) fn from_header(
+    prg_rom: Vec<u8>,
+    chr_rom: Option<Vec<u8>>,
+    header: CartridgeHeader,
+) -> (
+    Box<dyn CpuCartridgeAddressBus>,
+    Box<dyn PpuCartridgeAddressBus>,
+    CartridgeHeader,
+) {
+    info!("Creating VRC6 mapper for cartridge {:?}", header);
+    let swap_address_lines = header.mapper == 26;
+    (
+        Box::new(Vrc6PrgChip::new(
+            prg_rom,
+            prg_ram_from_header(&header, true),
+            swap_address_lines,
+        )),
+        Box::new(Vrc6ChrChip::new(
+            ChrData::from_header(chr_rom, &header),
+            header.mirroring,
+            swap_address_lines,
+        )),
+        header,
+    )
+}
+
+#[cfg(test)]
+mod vrc6_tests {
+    use super::{Vrc6Audio, Vrc6ChrChip, Vrc6PrgChip};
+    use cartridge::mappers::{numbered_banks, ChrData};
+    use cartridge::mirroring::MirroringMode;
+    use cartridge::{CpuCartridgeAddressBus, PpuCartridgeAddressBus};
+
+    fn prg_chip(swap_address_lines: bool) -> Vrc6PrgChip {
+        Vrc6PrgChip::new(numbered_banks(32, 0x2000), Some(vec![0; 0x2000]), swap_address_lines)
+    }
+
+    fn chr_chip(swap_address_lines: bool) -> Vrc6ChrChip {
+        Vrc6ChrChip::new(
+            ChrData::Rom(numbered_banks(128, 0x400)),
+            MirroringMode::Vertical,
+            swap_address_lines,
+        )
+    }
+
+    #[test]
+    fn test_prg_banking_and_ram_enable() {
+        let mut vrc6 = prg_chip(false);
+        vrc6.write_byte(0x8000, 3, 0);
+        vrc6.write_byte(0xC000, 9, 0);
+        assert_eq!(vrc6.read_byte(0x8000), 6);
+        assert_eq!(vrc6.read_byte(0xA000), 7);
+        assert_eq!(vrc6.read_byte(0xC000), 9);
+        assert_eq!(vrc6.read_byte(0xE000), 31);
+
+        vrc6.write_byte(0x6000, 0x12, 0);
+        assert_eq!(vrc6.read_byte(0x6000), 0);
+        vrc6.write_byte(0xB003, 0x80, 0);
+        vrc6.write_byte(0x6000, 0x12, 0);
+        assert_eq!(vrc6.read_byte(0x6000), 0x12);
+    }
+
+    #[test]
+    fn test_chr_banking_and_mirroring() {
+        let mut vrc6 = chr_chip(false);
+        for register in 0..4 {
+            vrc6.cpu_write_byte(0xD000 + register, 0x10 + register as u8, 0);
+            vrc6.cpu_write_byte(0xE000 + register, 0x20 + register as u8, 0);
+        }
+        for slot in 0..4 {
+            assert_eq!(vrc6.read_byte(slot * 0x400, 0), 0x10 + slot as u8);
+            assert_eq!(vrc6.read_byte(0x1000 + slot * 0x400, 0), 0x20 + slot as u8);
+        }
+
+        vrc6.cpu_write_byte(0xB003, 0b0010_0100, 0);
+        assert_eq!(vrc6.base.mirroring_mode, MirroringMode::Horizontal);
+        vrc6.cpu_write_byte(0xB003, 0b0010_1100, 0);
+        assert_eq!(vrc6.base.mirroring_mode, MirroringMode::OneScreenUpperBank);
+    }
+
+    #[test]
+    fn test_mapper_26_address_lines() {
+        let mut chr = chr_chip(true);
+        chr.cpu_write_byte(0xD001, 0x11, 0);
+        chr.cpu_write_byte(0xD002, 0x22, 0);
+        assert_eq!(chr.read_byte(0x0800, 0), 0x11);
+        assert_eq!(chr.read_byte(0x0400, 0), 0x22);
+
+        // F002 is the IRQ control register & F001 the acknowledge
+        let mut prg = prg_chip(true);
+        prg.write_byte(0xF000, 0xFF, 0);
+        prg.write_byte(0xF002, 0b110, 0);
+        prg.clock_irq();
+        assert!(prg.check_trigger_irq());
+        prg.write_byte(0xF001, 0, 0);
+        assert!(!prg.check_trigger_irq());
+    }
+
+    #[test]
+    fn test_irq_scanline_mode() {
+        let mut vrc6 = prg_chip(false);
+        vrc6.write_byte(0xF000, 0xFD, 0);
+        vrc6.write_byte(0xF001, 0b011, 0);
+
+        // The prescaler clocks the counter every 341 / 3 CPU cycles, here
+        // after 114, 114 & 113 cycles for the three counts to overflow
+        for _ in 0..340 {
+            vrc6.clock_irq();
+        }
+        assert!(!vrc6.check_trigger_irq());
+        vrc6.clock_irq();
+        assert!(vrc6.check_trigger_irq());
+
+        // Acknowledging with the A bit set keeps counting from the latch
+        vrc6.write_byte(0xF002, 0, 0);
+        assert!(!vrc6.check_trigger_irq());
+        for _ in 0..341 {
+            vrc6.clock_irq();
+        }
+        assert!(vrc6.check_trigger_irq());
+
+        // Without it the counter stops once acknowledged
+        vrc6.write_byte(0xF001, 0b010, 0);
+        vrc6.write_byte(0xF002, 0, 0);
+        for _ in 0..1000 {
+            vrc6.clock_irq();
+        }
+        assert!(!vrc6.check_trigger_irq());
+    }
+
+    #[test]
+    fn test_pulse_duty_cycle() {
+        let mut audio = Vrc6Audio::new();
+        audio.write_register(0x9000, 0b0011_1111);
+        audio.write_register(0x9001, 0);
+        audio.write_register(0x9002, 0x80);
+
+        // Period 0 steps the channel every cycle, a duty of 3 is high for 4 of the 16 steps
+        let outputs: Vec<u8> = (0..32)
+            .map(|_| {
+                audio.clock();
+                audio.pulse_1.output()
+            })
+            .collect();
+        assert_eq!(outputs.iter().filter(|&&output| output == 15).count(), 8);
+        assert_eq!(outputs.iter().filter(|&&output| output == 0).count(), 24);
+
+        // Mode bit ignores the duty cycle
+        audio.write_register(0x9000, 0b1000_0111);
+        for _ in 0..16 {
+            audio.clock();
+            assert_eq!(audio.pulse_1.output(), 7);
+        }
+
+        audio.write_register(0x9002, 0x00);
+        assert_eq!(audio.clock(), 0.0);
+    }
+
+    #[test]
+    fn test_sawtooth() {
+        let mut audio = Vrc6Audio::new();
+        audio.write_register(0xB000, 42);
+        audio.write_register(0xB001, 0);
+        audio.write_register(0xB002, 0x80);
+
+        let outputs: Vec<u8> = (0..14)
+            .map(|_| {
+                audio.clock();
+                audio.sawtooth.output()
+            })
+            .collect();
+        assert_eq!(outputs, vec![0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0]);
+
+        // Halting stops the channels where they are
+        audio.clock();
+        audio.clock();
+        audio.write_register(0x9003, 1);
+        for _ in 0..20 {
+            audio.clock();
+            assert_eq!(audio.sawtooth.output(), 5);
+        }
+    }
+
+    #[test]
+    fn test_frequency_shift() {
+        // A pulse with duty 7 first goes high on its 8th step, the period of
+        // 0xF0 is shifted right by 4 or 8 depending on 9003
+        let cycles_until_high = |frequency_control: u8| {
+            let mut audio = Vrc6Audio::new();
+            audio.write_register(0x9000, 0b0111_1111);
+            audio.write_register(0x9001, 0xF0);
+            audio.write_register(0x9002, 0x80);
+            audio.write_register(0x9003, frequency_control);
+            (1..)
+                .find(|_| {
+                    audio.clock();
+                    audio.pulse_1.output() != 0
+                })
+                .unwrap()
+        };
+        assert_eq!(cycles_until_high(0b000), 1 + 7 * 0xF1);
+        assert_eq!(cycles_until_high(0b010), 1 + 7 * 0x10);
+        assert_eq!(cycles_until_high(0b100), 8);
+        assert_eq!(cycles_until_high(0b110), 8);
+    }
+}
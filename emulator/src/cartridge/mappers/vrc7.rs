@@ -0,0 +1,302 @@
+use cartridge::mappers::opll::Opll;
+use cartridge::mappers::vrc_irq::VrcIrq;
+use cartridge::mappers::{prg_ram_from_header, ChrBaseData, ChrData, PrgBaseData};
+use cartridge::mirroring::MirroringMode;
+use cartridge::CartridgeHeader;
+use cartridge::CpuCartridgeAddressBus;
+use cartridge::PpuCartridgeAddressBus;
+use cpu::CpuCycle;
+use log::{debug, info};
+use ppu::PpuCycle;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// Registers come in pairs, VRC7a boards (Lagrange Point) select the second
+/// of each pair with A4 and VRC7b boards (Tiny Toon Adventures 2) with A3.
+/// Accepting either works for both so the second is returned as xxx0 | 0x10
+fn register_address(address: u16) -> u16 {
+    match address & 0xF038 {
+        // The audio registers are only on VRC7a boards and also decode A5
+        0x9010 | 0x9030 => address & 0xF030,
+        _ if address & 0x18 != 0 => (address & 0xF000) | 0x10,
+        _ => address & 0xF000,
+    }
+}
+
+/// The CPU side of the VRC7 handles PRG banking, PRG RAM, the IRQ counter
+/// (which is clocked by the CPU) and the FM synthesiser.
+pub(crate) struct Vrc7PrgChip {
+    base: PrgBaseData,
+    /// Bit 7 of E000, PRG RAM is disabled until it's set
+    ram_enabled: bool,
+    irq: VrcIrq,
+    audio: Opll,
+}
+
+impl Vrc7PrgChip {
+    fn new(prg_rom: Vec<u8>, prg_ram: Option<Vec<u8>>) -> Self {
+        let total_banks = prg_rom.len() / 0x2000;
+        let banks = vec![0, 1, total_banks - 2, total_banks - 1];
+        let bank_offsets = banks.iter().map(|bank| bank * 0x2000).collect();
+
+        Vrc7PrgChip {
+            base: PrgBaseData::new(prg_rom, prg_ram, total_banks, 0x2000, banks, bank_offsets),
+            ram_enabled: false,
+            irq: VrcIrq::new(),
+            audio: Opll::new(),
+        }
+    }
+
+    fn set_bank(&mut self, slot: usize, value: u8) {
+        self.base.banks[slot] = (value & 0b0011_1111) as usize % self.base.total_banks;
+        self.base.bank_offsets[slot] = self.base.banks[slot] * 0x2000;
+
+        info!(
+            "VRC7 PRG banks updated {:?} -> {:?}",
+            self.base.banks, self.base.bank_offsets
+        );
+    }
+}
+
+impl SaveState for Vrc7PrgChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+        writer.write_bool(self.ram_enabled);
+        self.irq.save_state(writer);
+        self.audio.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)?;
+        self.ram_enabled = reader.read_bool()?;
+        self.irq.load_state(reader)?;
+        self.audio.load_state(reader)
+    }
+}
+
+impl CpuCartridgeAddressBus for Vrc7PrgChip {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if !self.ram_enabled => 0x0, // TODO - Should be open bus
+            _ => self.base.read_byte(address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        debug!("CPU write to VRC7 PRG bus {:04X}={:02X}", address, value);
+
+        match address {
+            0x6000..=0x7FFF if self.ram_enabled => self.base.write_byte(address, value),
+            0x8000..=0xFFFF => match register_address(address) {
+                0x8000 => self.set_bank(0, value),
+                0x8010 => self.set_bank(1, value),
+                0x9000 => self.set_bank(2, value),
+                0x9010 => self.audio.select_register(value),
+                0x9030 => self.audio.write_register(value),
+                0xE000 => {
+                    self.audio.set_reset(value & 0b0100_0000 != 0);
+                    self.ram_enabled = value & 0b1000_0000 != 0;
+                }
+                0xE010 => self.irq.write_latch(value),
+                0xF000 => self.irq.write_control(value),
+                0xF010 => self.irq.acknowledge(),
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.base.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.base.prg_ram_mut()
+    }
+
+    fn clock_expansion_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+
+    fn clock_irq(&mut self) {
+        self.irq.clock();
+    }
+
+    fn check_trigger_irq(&self) -> bool {
+        self.irq.pending()
+    }
+}
+
+/// The PPU side of the VRC7 handles CHR banking (8 x 1KB banks) and
+/// mirroring.
+pub(crate) struct Vrc7ChrChip {
+    base: ChrBaseData,
+}
+
+impl Vrc7ChrChip {
+    fn new(chr_data: ChrData, mirroring_mode: MirroringMode) -> Self {
+        Vrc7ChrChip {
+            base: ChrBaseData::new(
+                mirroring_mode,
+                chr_data,
+                0x400,
+                vec![0, 1, 2, 3, 4, 5, 6, 7],
+                vec![0x0000, 0x0400, 0x0800, 0x0C00, 0x1000, 0x1400, 0x1800, 0x1C00],
+            ),
+        }
+    }
+}
+
+impl SaveState for Vrc7ChrChip {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.base.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.base.load_state(reader)
+    }
+}
+
+impl PpuCartridgeAddressBus for Vrc7ChrChip {
+    fn read_byte(&mut self, address: u16, _: PpuCycle) -> u8 {
+        self.base.read_byte(address)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8, _: PpuCycle) {
+        self.base.write_byte(address, value);
+    }
+
+    fn cpu_write_byte(&mut self, address: u16, value: u8, _: CpuCycle) {
+        if address < 0x8000 {
+            return;
+        }
+
+        match register_address(address) {
+            register @ 0xA000..=0xD010 => {
+                let slot = ((register >> 12) as usize - 0xA) * 2 + (register & 0x10 != 0) as usize;
+                self.base.banks[slot] = value as usize % self.base.total_banks;
+                self.base.bank_offsets[slot] = self.base.banks[slot] * 0x400;
+
+                info!(
+                    "VRC7 CHR banks updated {:?} -> {:?}",
+                    self.base.banks, self.base.bank_offsets
+                );
+            }
+            0xE000 => {
+                self.base.mirroring_mode = match value & 0b11 {
+                    0 => MirroringMode::Vertical,
+                    1 => MirroringMode::Horizontal,
+                    2 => MirroringMode::OneScreenLowerBank,
+                    _ => MirroringMode::OneScreenUpperBank,
+                };
+                info!("VRC7 mirroring mode change {:?}", self.base.mirroring_mode);
+            }
+            _ => (),
+        }
+    }
+}
+
+pub(crate) fn from_header(
+    prg_rom: Vec<u8>,
+    chr_rom: Option<Vec<u8>>,
+    header: CartridgeHeader,
+) -> (
+    Box<dyn CpuCartridgeAddressBus>,
+    Box<dyn PpuCartridgeAddressBus>,
+    CartridgeHeader,
+) {
+    info!("Creating VRC7 mapper for cartridge {:?}", header);
+    (
+        Box::new(Vrc7PrgChip::new(prg_rom, prg_ram_from_header(&header, true))),
+        Box::new(Vrc7ChrChip::new(
+            ChrData::from_header(chr_rom, &header),
+            header.mirroring,
+        )),
+        header,
+    )
+}
+
+#[cfg(test)]
+mod vrc7_tests {
+    use super::{Vrc7ChrChip, Vrc7PrgChip};
+    use cartridge::mappers::{numbered_banks, ChrData};
+    use cartridge::mirroring::MirroringMode;
+    use cartridge::{CpuCartridgeAddressBus, PpuCartridgeAddressBus};
+
+    fn prg_chip() -> Vrc7PrgChip {
+        Vrc7PrgChip::new(numbered_banks(64, 0x2000), Some(vec![0; 0x2000]))
+    }
+
+    fn chr_chip() -> Vrc7ChrChip {
+        Vrc7ChrChip::new(ChrData::Rom(numbered_banks(128, 0x400)), MirroringMode::Vertical)
+    }
+
+    #[test]
+    fn test_prg_banking_and_ram_enable() {
+        let mut vrc7 = prg_chip();
+        vrc7.write_byte(0x8000, 3, 0);
+        vrc7.write_byte(0x8010, 4, 0);
+        vrc7.write_byte(0x9000, 5, 0);
+        assert_eq!(vrc7.read_byte(0x8000), 3);
+        assert_eq!(vrc7.read_byte(0xA000), 4);
+        assert_eq!(vrc7.read_byte(0xC000), 5);
+        assert_eq!(vrc7.read_byte(0xE000), 63);
+
+        // VRC7b boards use A3 rather than A4
+        vrc7.write_byte(0x8008, 6, 0);
+        assert_eq!(vrc7.read_byte(0xA000), 6);
+
+        vrc7.write_byte(0x6000, 0x12, 0);
+        assert_eq!(vrc7.read_byte(0x6000), 0);
+        vrc7.write_byte(0xE000, 0x80, 0);
+        vrc7.write_byte(0x6000, 0x12, 0);
+        assert_eq!(vrc7.read_byte(0x6000), 0x12);
+    }
+
+    #[test]
+    fn test_chr_banking_and_mirroring() {
+        let mut vrc7 = chr_chip();
+        for (slot, &address) in [0xA000, 0xA010, 0xB000, 0xB008, 0xC000, 0xC010, 0xD000, 0xD008]
+            .iter()
+            .enumerate()
+        {
+            vrc7.cpu_write_byte(address, 0x20 + slot as u8, 0);
+        }
+        for slot in 0..8 {
+            assert_eq!(vrc7.read_byte(slot * 0x400, 0), 0x20 + slot as u8);
+        }
+
+        vrc7.cpu_write_byte(0xE000, 0b1000_0001, 0);
+        assert_eq!(vrc7.base.mirroring_mode, MirroringMode::Horizontal);
+        vrc7.cpu_write_byte(0xE000, 0b1000_0010, 0);
+        assert_eq!(vrc7.base.mirroring_mode, MirroringMode::OneScreenLowerBank);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut vrc7 = prg_chip();
+        vrc7.write_byte(0xE010, 0xFF, 0);
+        vrc7.write_byte(0xF000, 0b110, 0);
+        vrc7.clock_irq();
+        assert!(vrc7.check_trigger_irq());
+
+        vrc7.write_byte(0xF008, 0, 0);
+        assert!(!vrc7.check_trigger_irq());
+    }
+
+    #[test]
+    fn test_audio_output() {
+        let mut vrc7 = prg_chip();
+        vrc7.write_byte(0x9010, 0x10, 0);
+        vrc7.write_byte(0x9030, 0x80, 0);
+        vrc7.write_byte(0x9010, 0x30, 0);
+        vrc7.write_byte(0x9030, 0x40, 0);
+        vrc7.write_byte(0x9010, 0x20, 0);
+        vrc7.write_byte(0x9030, 0b0001_1000, 0);
+        let playing = (0..36 * 1000).any(|_| vrc7.clock_expansion_audio() != 0.0);
+        assert!(playing);
+
+        // Bit 6 of E000 holds the synthesiser in reset
+        vrc7.write_byte(0xE000, 0b0100_0000, 0);
+        assert!((0..36 * 1000).all(|_| vrc7.clock_expansion_audio() == 0.0));
+    }
+}
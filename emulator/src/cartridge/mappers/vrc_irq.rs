@@ -0,0 +1,167 @@
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// CPU cycles per scanline are 341/3, the prescaler counts down by 3 each
+/// cycle to count out scanlines without seeing the PPU
+const PRESCALER_RELOAD: u16 = 341;
+
+/// The IRQ counter found on the Konami VRC4, VRC6 & VRC7. It's clocked by
+/// the CPU rather than the PPU, either every CPU cycle (cycle mode) or
+/// approximately once per scanline (scanline mode), and raises an IRQ when
+/// the 8 bit counter overflows before being reloaded from the latch.
+/// c.f. http://wiki.nesdev.com/w/index.php/VRC_IRQ
+pub(crate) struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: u16,
+    /// The "A" bit of the control register, copied to enabled on acknowledge
+    enable_after_ack: bool,
+    enabled: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub(crate) fn new() -> Self {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_RELOAD,
+            enable_after_ack: false,
+            enabled: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub(crate) fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    /// The VRC4 sets the latch 4 bits at a time through two registers
+    pub(crate) fn write_latch_nibble(&mut self, value: u8, high: bool) {
+        self.latch = match high {
+            true => (self.latch & 0x0F) | (value & 0x0F) << 4,
+            false => (self.latch & 0xF0) | (value & 0x0F),
+        };
+    }
+
+    pub(crate) fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 0b001 != 0;
+        self.enabled = value & 0b010 != 0;
+        self.cycle_mode = value & 0b100 != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_RELOAD;
+        }
+    }
+
+    pub(crate) fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub(crate) fn pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Called once per CPU cycle
+    pub(crate) fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycle_mode {
+            self.clock_counter();
+        } else if self.prescaler <= 3 {
+            self.prescaler += PRESCALER_RELOAD - 3;
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl SaveState for VrcIrq {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.latch);
+        writer.write_u8(self.counter);
+        writer.write_u16(self.prescaler);
+        writer.write_bool(self.enable_after_ack);
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.cycle_mode);
+        writer.write_bool(self.pending);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.latch = reader.read_u8()?;
+        self.counter = reader.read_u8()?;
+        self.prescaler = reader.read_u16()?;
+        if self.prescaler == 0 || self.prescaler > PRESCALER_RELOAD {
+            return Err(SaveStateError::new("VRC IRQ prescaler out of range"));
+        }
+        self.enable_after_ack = reader.read_bool()?;
+        self.enabled = reader.read_bool()?;
+        self.cycle_mode = reader.read_bool()?;
+        self.pending = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod vrc_irq_tests {
+    use super::VrcIrq;
+
+    #[test]
+    fn test_cycle_mode() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xFD);
+        irq.write_control(0b111);
+
+        irq.clock();
+        irq.clock();
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+
+        // Acknowledging copies the A bit into the enable bit, so counting continues from the latch
+        irq.acknowledge();
+        assert!(!irq.pending());
+        for _ in 0..3 {
+            irq.clock();
+        }
+        assert!(irq.pending());
+    }
+
+    #[test]
+    fn test_scanline_mode() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xFE);
+        irq.write_control(0b010);
+
+        // Two scanlines are 682 PPU dots, which is 227.33 CPU cycles
+        for _ in 0..227 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+
+        // Without the A bit acknowledging stops the counter
+        irq.acknowledge();
+        for _ in 0..1000 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+    }
+}
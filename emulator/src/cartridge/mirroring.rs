@@ -1,3 +1,5 @@
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MirroringMode {
     OneScreenLowerBank,
@@ -28,6 +30,30 @@ impl MirroringMode {
     }
 }
 
+impl SaveState for MirroringMode {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(match self {
+            MirroringMode::OneScreenLowerBank => 0,
+            MirroringMode::OneScreenUpperBank => 1,
+            MirroringMode::Vertical => 2,
+            MirroringMode::Horizontal => 3,
+            MirroringMode::FourScreen => 4,
+        });
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        *self = match reader.read_u8()? {
+            0 => MirroringMode::OneScreenLowerBank,
+            1 => MirroringMode::OneScreenUpperBank,
+            2 => MirroringMode::Vertical,
+            3 => MirroringMode::Horizontal,
+            4 => MirroringMode::FourScreen,
+            _ => return Err(SaveStateError::new("Invalid mirroring mode")),
+        };
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod mirroring_tests {
     use super::MirroringMode;
@@ -1,10 +1,13 @@
+mod header;
 mod mappers;
 mod mirroring;
 
-use cartridge::mirroring::MirroringMode;
+pub use cartridge::header::{CartridgeHeader, ConsoleType, HeaderFormat, TimingMode};
+pub use cartridge::mirroring::MirroringMode;
 use cpu::CpuCycle;
 use log::info;
 use ppu::PpuCycle;
+use save_state::SaveState;
 use std::error::Error;
 use std::ffi::OsStr;
 use std::fmt;
@@ -20,7 +23,7 @@ use Cartridge;
 #[derive(Debug)]
 pub struct CartridgeError {
     pub message: String,
-    pub mapper: Option<u8>,
+    pub mapper: Option<u16>,
 }
 impl Error for CartridgeError {}
 impl fmt::Display for CartridgeError {
@@ -46,68 +49,116 @@ impl From<ZipError> for CartridgeError {
 }
 
 /// A trait representing the CPU address bus into the cartridge
-pub trait CpuCartridgeAddressBus {
+///
+/// Cartridges are required to be `Send` so that a whole console can be moved
+/// onto (e.g.) an emulation thread, and to implement `SaveState` so that
+/// mapper registers & RAM are included in save states.
+pub trait CpuCartridgeAddressBus: Send + SaveState {
     /// Read from the 16 bit CPU address bus
     fn read_byte(&self, address: u16) -> u8;
+    /// Called after each `read_byte` made by the CPU (but not by debugger
+    /// peeks), for the few mappers (Namco 163) with registers that change
+    /// state when they're read
+    fn after_read(&mut self, _address: u16) {}
     /// Write to the 16 bit CPU address bus
     fn write_byte(&mut self, address: u16, value: u8, cycles: PpuCycle);
+    /// The PRG RAM on the cartridge, used to export battery backed RAM so
+    /// that it can be persisted between runs. Mappers without PRG RAM can
+    /// leave the default implementation
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+    /// Mutable access to the PRG RAM, used to import battery backed RAM
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+    /// Mappers with expansion audio (e.g. MMC5) are clocked once per CPU
+    /// cycle and return their output which is added to the APU output
+    fn clock_expansion_audio(&mut self) -> f32 {
+        0.0
+    }
+    /// Mappers with an IRQ counter driven by the CPU clock rather than by
+    /// watching the PPU (e.g. VRC6) are clocked once per CPU cycle
+    fn clock_irq(&mut self) {}
+    /// Whether a CPU clocked IRQ counter is asserting the IRQ line, the
+    /// counterpart of `PpuCartridgeAddressBus::check_trigger_irq`
+    fn check_trigger_irq(&self) -> bool {
+        false
+    }
+    /// Called when the console's reset button is pressed. The reset line
+    /// isn't on the cartridge connector so most mappers keep their state,
+    /// those that notice the CPU restarting can override this
+    fn reset(&mut self) {}
 }
 
 /// A trait representing the PPU address bus into the cartridge
-pub trait PpuCartridgeAddressBus {
+pub trait PpuCartridgeAddressBus: Send + SaveState {
     /// Certain mappers can trigger an IRQ based on scanline counting (MMC3)
     /// This function allows the CPU to poll and request state on whether an IRQ is ready to fire.
-    fn check_trigger_irq(&mut self, clear: bool) -> bool;
+    ///
+    /// Mappers with an IRQ counter clocked by the CPU (VRC4/6/7, FME-7, Namco 163)
+    /// leave this returning false and use `CpuCartridgeAddressBus::clock_irq` instead
+    fn check_trigger_irq(&mut self, _clear: bool) -> bool {
+        false
+    }
     /// Certain mappers can trigger an IRQ based on scanline counting (MMC3)
     /// This function allows the mapper to listen on address bus changes
-    fn update_vram_address(&mut self, address: u16, cycles: PpuCycle);
+    fn update_vram_address(&mut self, _address: u16, _cycles: PpuCycle) {}
     /// Read from the 14 bit PPU address bus
     fn read_byte(&mut self, address: u16, cycles: PpuCycle) -> u8;
     /// Write to the 14 bit PPU address bus
     fn write_byte(&mut self, address: u16, value: u8, cycles: PpuCycle);
     /// Write to the 16 bit CPU address bus, required to set mapper registers
     fn cpu_write_byte(&mut self, address: u16, value: u8, cycles: CpuCycle);
+    /// Read from the 16 bit CPU address bus, for the few mappers (MMC5) with
+    /// CPU readable registers or RAM that depend on the state of the PPU.
+    /// Returning None leaves the read to the CPU side of the cartridge
+    fn cpu_read_byte(&mut self, _address: u16, _cycles: PpuCycle) -> Option<u8> {
+        None
+    }
+    /// Writes to the PPU registers (0x2000-0x2007), some mappers (MMC5)
+    /// snoop on these to track the sprite size & whether rendering is enabled
+    fn ppu_register_write(&mut self, _address: u16, _value: u8) {}
+    /// Called when the console's reset button is pressed, c.f. `CpuCartridgeAddressBus::reset`
+    fn reset(&mut self) {}
 }
 
-/// Represents flags/details about the rom from the header
-/// c.f. http://wiki.nesdev.com/w/index.php/INES for details
-#[derive(Debug)]
-pub struct CartridgeHeader {
-    pub prg_rom_16kb_units: u8,
-    pub chr_rom_8kb_units: u8,
-    pub mapper: u8,
-    pub mirroring: MirroringMode,
-    pub ram_is_battery_backed: bool,
-    // TODO - Lots more flags and possible options
-}
+pub(crate) fn from_file(file_path: &str) -> Result<Cartridge, CartridgeError> {
+    let (header, prg_rom, chr_rom) = read_rom_file(file_path)?;
 
-impl CartridgeHeader {
-    fn new(prg_rom_16kb_units: u8, chr_rom_8kb_units: u8, flags_6: u8, flags_7: u8) -> Self {
-        CartridgeHeader {
-            prg_rom_16kb_units,
-            chr_rom_8kb_units,
-            mapper: (flags_6 >> 4) | (flags_7 & 0b1111_0000),
-            mirroring: match (flags_6 & 1 == 0, flags_6 & 0b1000 == 0) {
-                (true, true) => MirroringMode::Horizontal,
-                (false, true) => MirroringMode::Vertical,
-                (_, false) => MirroringMode::FourScreen,
-            },
-            ram_is_battery_backed: flags_6 & 0b10 == 0b10,
-        }
+    match header.mapper {
+        0 => Ok(mappers::nrom::from_header(prg_rom, chr_rom, header)),
+        1 | 155 => Ok(mappers::mmc1::from_header(prg_rom, chr_rom, header)),
+        2 | 94 | 180 => Ok(mappers::uxrom::from_header(prg_rom, chr_rom, header)),
+        3 => Ok(mappers::cnrom::from_header(prg_rom, chr_rom, header)),
+        4 => Ok(mappers::mmc3::from_header(prg_rom, chr_rom, header)),
+        5 => Ok(mappers::mmc5::from_header(prg_rom, chr_rom, header)),
+        7 => Ok(mappers::axrom::from_header(prg_rom, chr_rom, header)),
+        9 => Ok(mappers::mmc2::from_header(prg_rom, chr_rom, header)),
+        10 => Ok(mappers::mmc4::from_header(prg_rom, chr_rom, header)),
+        11 => Ok(mappers::color_dreams::from_header(prg_rom, chr_rom, header)),
+        19 => Ok(mappers::namco_163::from_header(prg_rom, chr_rom, header)),
+        21 | 22 | 23 | 25 => Ok(mappers::vrc2_4::from_header(prg_rom, chr_rom, header)),
+        24 | 26 => Ok(mappers::vrc6::from_header(prg_rom, chr_rom, header)),
+        34 => Ok(mappers::bxrom::from_header(prg_rom, chr_rom, header)),
+        66 => Ok(mappers::gxrom::from_header(prg_rom, chr_rom, header)),
+        69 => Ok(mappers::fme7::from_header(prg_rom, chr_rom, header)),
+        71 => Ok(mappers::mapper_071::from_header(prg_rom, chr_rom, header)),
+        79 => Ok(mappers::nina_003_006::from_header(prg_rom, chr_rom, header)),
+        85 => Ok(mappers::vrc7::from_header(prg_rom, chr_rom, header)),
+        _ => Err(CartridgeError {
+            message: format!("Mapper {} not yet implemented", header.mapper),
+            mapper: Some(header.mapper),
+        }),
     }
 }
 
-impl fmt::Display for CartridgeHeader {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "PRG Units {}, CHR Units {}, Mapper {}",
-            self.prg_rom_16kb_units, self.chr_rom_8kb_units, self.mapper
-        )
-    }
-}
+/// The header, PRG ROM and CHR ROM (if any) of a rom file
+pub type RomImage = (CartridgeHeader, Vec<u8>, Option<Vec<u8>>);
 
-pub(crate) fn from_file(file_path: &str) -> Result<Cartridge, CartridgeError> {
+/// Read a rom file (raw or zipped) and split it into its parts without
+/// creating a cartridge from it
+pub fn read_rom_file(file_path: &str) -> Result<RomImage, CartridgeError> {
     let file_extension = Path::new(file_path).extension().and_then(OsStr::to_str);
     let file = File::open(file_path)?;
 
@@ -144,55 +195,34 @@ pub(crate) fn from_file(file_path: &str) -> Result<Cartridge, CartridgeError> {
         _ => bytes = std::fs::read(file_path)?,
     };
 
-    if bytes.len() < 0x10 {
-        return Err(CartridgeError {
-            message: format!("Invalid cartridge file {}, header < 16 bytes", file_path),
-            mapper: None,
-        });
-    }
-
-    let header = CartridgeHeader::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+    let header = CartridgeHeader::from_bytes(&bytes).map_err(|e| CartridgeError {
+        message: format!("Invalid cartridge file {}, {}", file_path, e.message),
+        mapper: None,
+    })?;
 
     info!("{}: {:08b} {:08b}", header, bytes[6], bytes[7]);
 
-    let prg_rom_start = 0x10 as usize;
-    let prg_rom_end = prg_rom_start + (header.prg_rom_16kb_units as usize * 0x4000);
-    let chr_rom_end = prg_rom_end + (header.chr_rom_8kb_units as usize * 0x2000);
+    // The trainer (if present) isn't used by any supported mapper so is skipped
+    let prg_rom_start = if header.has_trainer { 0x210 } else { 0x10 };
+    let prg_rom_end = prg_rom_start + header.prg_rom_size;
+    let chr_rom_end = prg_rom_end + header.chr_rom_size;
 
     if bytes.len() < chr_rom_end {
         return Err(CartridgeError {
-          message: format!("Invalid cartridge file {}, header specified {:x} prg rom units and {:x} chr rom units but total length was {:x}",
+          message: format!("Invalid cartridge file {}, header specified {:x} prg rom bytes and {:x} chr rom bytes but total length was {:x}",
                            file_path,
-                           header.prg_rom_16kb_units,
-                           header.chr_rom_8kb_units,
+                           header.prg_rom_size,
+                           header.chr_rom_size,
                            bytes.len()),
           mapper: None,
         });
     }
 
-    let prg_rom = bytes[16..prg_rom_end].to_vec();
-    let chr_rom = match header.chr_rom_8kb_units {
+    let prg_rom = bytes[prg_rom_start..prg_rom_end].to_vec();
+    let chr_rom = match header.chr_rom_size {
         0 => None,
         _ => Some(bytes[prg_rom_end..chr_rom_end].to_vec()),
     };
 
-    match header.mapper {
-        0 => Ok(mappers::nrom::from_header(prg_rom, chr_rom, header)),
-        1 | 155 => Ok(mappers::mmc1::from_header(prg_rom, chr_rom, header)),
-        2 | 94 | 180 => Ok(mappers::uxrom::from_header(prg_rom, chr_rom, header)),
-        3 => Ok(mappers::cnrom::from_header(prg_rom, chr_rom, header)),
-        4 => Ok(mappers::mmc3::from_header(prg_rom, chr_rom, header)),
-        7 => Ok(mappers::axrom::from_header(prg_rom, chr_rom, header)),
-        9 => Ok(mappers::mmc2::from_header(prg_rom, chr_rom, header)),
-        10 => Ok(mappers::mmc4::from_header(prg_rom, chr_rom, header)),
-        11 => Ok(mappers::color_dreams::from_header(prg_rom, chr_rom, header)),
-        34 => Ok(mappers::bxrom::from_header(prg_rom, chr_rom, header)),
-        66 => Ok(mappers::gxrom::from_header(prg_rom, chr_rom, header)),
-        71 => Ok(mappers::mapper_071::from_header(prg_rom, chr_rom, header)),
-        79 => Ok(mappers::nina_003_006::from_header(prg_rom, chr_rom, header)),
-        _ => Err(CartridgeError {
-            message: format!("Mapper {} not yet implemented", header.mapper),
-            mapper: Some(header.mapper),
-        }),
-    }
+    Ok((header, prg_rom, chr_rom))
 }
@@ -1,4 +1,5 @@
 use ppu::PpuCycle;
+use save_state::{SaveStateError, StateReader, StateWriter};
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Copy, Clone)]
@@ -19,3 +20,29 @@ impl Interrupt {
         }
     }
 }
+
+/// Interrupts are stored in save states as a tag followed by the cycle they were raised on
+pub(crate) fn save_interrupt(interrupt: Option<Interrupt>, writer: &mut StateWriter) {
+    let (tag, cycles) = match interrupt {
+        None => (0, 0),
+        Some(Interrupt::NMI(c)) => (1, c),
+        Some(Interrupt::IRQ(c)) => (2, c),
+        Some(Interrupt::IRQ_BRK(c)) => (3, c),
+        Some(Interrupt::RESET(c)) => (4, c),
+    };
+    writer.write_u8(tag);
+    writer.write_u32(cycles);
+}
+
+pub(crate) fn load_interrupt(reader: &mut StateReader) -> Result<Option<Interrupt>, SaveStateError> {
+    let tag = reader.read_u8()?;
+    let cycles = reader.read_u32()?;
+    match tag {
+        0 => Ok(None),
+        1 => Ok(Some(Interrupt::NMI(cycles))),
+        2 => Ok(Some(Interrupt::IRQ(cycles))),
+        3 => Ok(Some(Interrupt::IRQ_BRK(cycles))),
+        4 => Ok(Some(Interrupt::RESET(cycles))),
+        _ => Err(SaveStateError::new("Invalid interrupt")),
+    }
+}
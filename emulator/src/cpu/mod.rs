@@ -5,11 +5,15 @@ mod status_flags;
 
 use apu::Apu;
 use cartridge::CpuCartridgeAddressBus;
-use cpu::interrupts::Interrupt;
+use cpu::interrupts::{load_interrupt, save_interrupt, Interrupt};
+use cpu::opcodes::InstructionType;
 use cpu::opcodes::Opcode;
-use cpu::opcodes::{AddressingMode, InstructionType, Operation, OPCODE_TABLE};
+pub(crate) use cpu::opcodes::OPCODE_TABLE;
+pub use cpu::opcodes::{AddressingMode, Operation};
 use cpu::registers::Registers;
 use cpu::status_flags::StatusFlags;
+use debugger::{AccessLog, AccessType, AddressSpace, CpuRegisters, MemoryAccess};
+use disassembler::Disassembler;
 use io::Button;
 use io::Controller;
 use io::Io;
@@ -17,6 +21,9 @@ use log::{debug, info};
 use ppu::Ppu;
 use ppu::SCREEN_HEIGHT;
 use ppu::SCREEN_WIDTH;
+use region::Region;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+use trace::TraceRecord;
 
 #[derive(Debug, Copy, Clone)]
 enum State {
@@ -33,6 +40,26 @@ enum DmaState {
     WriteCycle(u8),
 }
 
+/// The DMC memory reader steals cycles from the CPU to fetch sample bytes.
+/// The CPU can only be halted on a read cycle, after which it repeats that
+/// read on each cycle until the DMC has made its own read on a "get" (odd)
+/// cycle, so each fetch costs 3 or 4 cycles. During OAM DMA the CPU is already
+/// halted, any OAM DMA cycle counts as the halt & dummy cycles and the DMC
+/// read replaces an OAM read, so the fetch costs 2 cycles (1 if it lands
+/// just after the final OAM write).
+#[derive(Debug, Copy, Clone)]
+enum DmcDmaState {
+    /// Requested by the DMC, waiting for the CPU to reach a read cycle
+    Halt,
+    /// The CPU is halted and repeats the read it was attempting (if any)
+    Dummy(Option<u16>),
+    /// Extra cycle to make the DMC read land on a get cycle
+    Alignment(Option<u16>),
+    Read,
+    /// The DMC read replaced an OAM DMA read, OAM DMA then needs a cycle to realign
+    OamDmaAlignment,
+}
+
 #[derive(Debug, Copy, Clone)]
 enum InterruptState {
     InternalOps1(Interrupt),
@@ -105,32 +132,60 @@ enum CpuState {
         value: u8,
         dummy: bool,
     },
+    // A KIL opcode has locked up the CPU, only a reset gets it going again
+    Jammed,
 }
 
 pub(crate) type CpuCycle = u32;
 
-pub struct Cpu<'a> {
+/// The XAA magic value most commonly seen on real consoles
+pub const DEFAULT_XAA_MAGIC: u8 = 0xEE;
+
+/// The contents of the internal RAM after `Nes::power_cycle`. Real consoles
+/// power on with RAM in a mostly (but not entirely) repeatable state that
+/// varies between consoles, a few games depend on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RamPattern {
+    /// Every byte 00, as consoles are created by `Nes::new`
+    Zeros,
+    /// Every byte FF
+    Ones,
+    /// Four bytes of 00 followed by four of FF, repeated
+    Alternating,
+    /// Pseudo random bytes, the same seed always gives the same contents
+    Random(u64),
+}
+
+pub struct Cpu {
     state: State,
     registers: Registers,
     pub cycles: CpuCycle,
+    /// Master clock ticks until the next CPU cycle, each PPU dot takes a few
+    /// ticks off (c.f. `Region::cpu_clock_divider`)
     cpu_cycle_counter: u8,
+    region: Region,
     ram: [u8; 0x800],
-    apu: &'a mut Apu,
-    io: &'a mut Io,
-    ppu: &'a mut Ppu,
+    apu: Apu,
+    io: Io,
+    ppu: Ppu,
     prg_address_bus: Box<dyn CpuCartridgeAddressBus>,
     trigger_dma: bool,
     dma_address: u16,
+    dmc_dma: Option<DmcDmaState>,
+    /// Set while speculatively running the cycle the CPU is halted on to
+    /// find out which address it reads (the inner value once the read has happened)
+    halt_probe: Option<Option<u16>>,
     polled_interrupt: Option<Interrupt>,
+    accesses: AccessLog,
+    /// Set for the PPU cycle following the CPU finishing an instruction
+    /// (and at power on), cleared when DMC DMA steals the cycle instead
+    at_instruction_boundary: bool,
+    /// The value ORed with A by the unstable XAA opcode, it varies between consoles
+    xaa_magic: u8,
 }
 
-impl<'a> Cpu<'a> {
-    pub fn new(
-        prg_address_bus: Box<dyn CpuCartridgeAddressBus>,
-        apu: &'a mut Apu,
-        io: &'a mut Io,
-        ppu: &'a mut Ppu,
-    ) -> Self {
+impl Cpu {
+    pub fn new(prg_address_bus: Box<dyn CpuCartridgeAddressBus>, apu: Apu, io: Io, ppu: Ppu, region: Region) -> Self {
         // The processor starts at the RESET interrupt handler address
         let pc = prg_address_bus.read_byte(Interrupt::RESET(0).offset()) as u16
             | ((prg_address_bus.read_byte(Interrupt::RESET(0).offset().wrapping_add(1)) as u16) << 8);
@@ -139,7 +194,8 @@ impl<'a> Cpu<'a> {
             state: State::Cpu(CpuState::FetchOpcode),
             registers: Registers::new(pc),
             cycles: 8,
-            cpu_cycle_counter: 1,
+            cpu_cycle_counter: region.ppu_clock_divider(),
+            region,
             ram: [0; 0x800],
             apu,
             io,
@@ -147,14 +203,26 @@ impl<'a> Cpu<'a> {
             prg_address_bus,
             trigger_dma: false,
             dma_address: 0x0000,
+            dmc_dma: None,
+            halt_probe: None,
             polled_interrupt: None,
+            accesses: AccessLog::default(),
+            at_instruction_boundary: true,
+            xaa_magic: DEFAULT_XAA_MAGIC,
         }
     }
 
     fn read_byte(&mut self, address: u16) -> u8 {
         debug!("CPU address space read {:04X}", address);
 
-        match address {
+        match self.halt_probe {
+            // Only the first read of a halted cycle reaches the bus
+            Some(Some(_)) => return 0x00,
+            Some(None) => self.halt_probe = Some(Some(address)),
+            None => (),
+        }
+
+        let value = match address {
             0x0000..=0x1FFF => self.ram[(address & 0x7FF) as usize],
             0x2000..=0x2007 => self.ppu.read_register(address),
             0x2008..=0x3FFF => self.ppu.read_register((address & 7) + 0x2000),
@@ -162,13 +230,30 @@ impl<'a> Cpu<'a> {
             0x4014 => 0x00, // TODO - Is this correct? We read 0 on the DMA register?
             0x4016..=0x4017 => self.io.read_byte(address), // Controller registers
             0x4018..=0x401F => 0x00, // TODO - Unused APU & IO registers
-            0x4020..=0xFFFF => self.prg_address_bus.read_byte(address),
-        }
+            0x4020..=0xFFFF => match self.ppu.cpu_read_cartridge(address) {
+                Some(value) => value,
+                None => {
+                    let value = self.prg_address_bus.read_byte(address);
+                    self.prg_address_bus.after_read(address);
+                    value
+                }
+            },
+        };
+        self.accesses
+            .record(AddressSpace::Cpu, AccessType::Read, address, value);
+
+        value
     }
 
     fn write_byte(&mut self, address: u16, value: u8) {
         debug!("CPU address space write {:04X} = {:02X}", address, value);
 
+        if self.halt_probe.is_some() {
+            return;
+        }
+        self.accesses
+            .record(AddressSpace::Cpu, AccessType::Write, address, value);
+
         match address {
             0x0000..=0x1FFF => self.ram[(address & 0x7FF) as usize] = value,
             0x2000..=0x2007 => self.ppu.write_register(address, value),
@@ -189,28 +274,15 @@ impl<'a> Cpu<'a> {
         }
     }
 
-    fn nes_test_log(&mut self, opcode: &Opcode) -> String {
-        let pc_1 = self.read_byte(self.registers.program_counter);
-        let pc_2 = self.read_byte(self.registers.program_counter + 1);
-        format!(
-            "{:04X}  {:} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{:}",
-            self.registers.program_counter - 1,
-            opcode.nes_test_log(pc_1, pc_2),
-            self.registers.a,
-            self.registers.x,
-            self.registers.y,
-            self.registers.status_register.bits() | 0b0010_0000,
-            self.registers.stack_pointer,
-            self.ppu.current_scanline_cycle(),
-            self.ppu.current_scanline(),
-            self.cycles
-        )
-    }
-
     /// This routine simulates checking for IRQ/NMI and happens during the last
     /// cycle of an instruction based on the state of the registers at the
     /// _start_ of that instruction
     fn poll_for_interrupts(&mut self, clear_lines: bool) {
+        // The cycle is going to be rerun once the CPU is no longer halted
+        if self.halt_probe.is_some() {
+            return;
+        }
+
         // NMI takes precedence over an IRQ
         if let Some(interrupt) = self.ppu.check_ppu_nmi(clear_lines) {
             self.polled_interrupt = Some(interrupt);
@@ -220,7 +292,9 @@ impl<'a> Cpu<'a> {
             .registers
             .status_register
             .contains(StatusFlags::INTERRUPT_DISABLE_FLAG)
-            && (self.ppu.check_trigger_irq(clear_lines) || self.apu.check_trigger_irq())
+            && (self.ppu.check_trigger_irq(clear_lines)
+                || self.prg_address_bus.check_trigger_irq()
+                || self.apu.check_trigger_irq())
         {
             self.polled_interrupt = Some(Interrupt::IRQ(self.cycles * 3));
 
@@ -426,6 +500,19 @@ impl<'a> Cpu<'a> {
         }
     }
 
+    /// Reset runs the same sequence as the other interrupts but with the
+    /// CPU held in read mode, so the stack pointer moves without anything
+    /// being written
+    fn push_interrupt_byte(&mut self, interrupt: Interrupt, value: u8) {
+        match interrupt {
+            Interrupt::RESET(_) => {
+                let _ = self.read_byte(self.registers.stack_pointer as u16 | 0x0100);
+                self.registers.stack_pointer = self.registers.stack_pointer.wrapping_sub(1);
+            }
+            _ => self.push_to_stack(value),
+        }
+    }
+
     fn step_interrupt_handler(&mut self, state: InterruptState) -> State {
         info!("Interrupt state: {:?} at cycle {}", state, self.cycles);
 
@@ -433,12 +520,12 @@ impl<'a> Cpu<'a> {
             InterruptState::InternalOps1(i) => State::Interrupt(InterruptState::InternalOps2(i)),
             InterruptState::InternalOps2(i) => State::Interrupt(InterruptState::PushPCH(i)),
             InterruptState::PushPCH(i) => {
-                self.push_to_stack((self.registers.program_counter >> 8) as u8);
+                self.push_interrupt_byte(i, (self.registers.program_counter >> 8) as u8);
 
                 State::Interrupt(InterruptState::PushPCL(i))
             }
             InterruptState::PushPCL(i) => {
-                self.push_to_stack(self.registers.program_counter as u8);
+                self.push_interrupt_byte(i, self.registers.program_counter as u8);
                 State::Interrupt(InterruptState::PushStatusRegister(i))
             }
             InterruptState::PushStatusRegister(i) => {
@@ -462,10 +549,11 @@ impl<'a> Cpu<'a> {
                 };
                 self.polled_interrupt = None;
 
-                self.push_to_stack(match i {
+                let status = match i {
                     Interrupt::IRQ_BRK(_) => self.registers.status_register.bits() | 0b0011_0000,
                     _ => (self.registers.status_register.bits() | 0b0010_0000) & 0b1110_1111,
-                });
+                };
+                self.push_interrupt_byte(i, status);
 
                 // Set interrupt disable at this point, whether this is NMI, BRK or normal IRQ
                 self.registers
@@ -493,8 +581,6 @@ impl<'a> Cpu<'a> {
             CpuState::FetchOpcode => {
                 let opcode = &OPCODE_TABLE[self.read_and_inc_program_counter() as usize];
 
-                info!("{}", self.nes_test_log(opcode));
-
                 match opcode.address_mode {
                     AddressingMode::Accumulator => State::Cpu(CpuState::ThrowawayRead {
                         opcode,
@@ -716,8 +802,12 @@ impl<'a> Cpu<'a> {
                                         let _ = Some(self.read_byte(dummy_read_address));
                                         opcode.execute(self, None, Some(address))
                                     }
-                                    _ => {
-                                        if checked_page_boundary || (dummy_read_address == address) {
+                                    instruction_type => {
+                                        // Instructions which both read & write will always read twice
+                                        let is_final_read = checked_page_boundary
+                                            || (dummy_read_address == address
+                                                && instruction_type != InstructionType::ReadModifyWrite);
+                                        if is_final_read {
                                             let value = Some(self.read_byte(address));
                                             opcode.execute(self, value, Some(address))
                                         } else {
@@ -1011,6 +1101,12 @@ impl<'a> Cpu<'a> {
 
                 State::Cpu(CpuState::FetchOpcode)
             }
+            CpuState::Jammed => {
+                // The address bus is left at FFFF and interrupts are ignored
+                let _ = self.read_byte(0xFFFF);
+
+                State::Cpu(CpuState::Jammed)
+            }
         }
     }
 
@@ -1045,14 +1141,137 @@ impl<'a> Cpu<'a> {
         }
     }
 
-    /// Move the cpu on by a single clock cycle
-    fn clock(&mut self) {
-        self.state = match self.state {
+    fn step_state(&mut self, state: State) -> State {
+        match state {
             State::Cpu(state) => self.step_cpu(state),
             State::Interrupt(state) => self.step_interrupt_handler(state),
             State::Dma(state) => self.step_dma_handler(state),
+        }
+    }
+
+    /// Returns true if the cycle is one where the CPU writes to the bus and
+    /// therefore can't be halted
+    fn is_write_cycle(&self) -> bool {
+        matches!(
+            self.state,
+            State::Cpu(CpuState::WritingResult { .. })
+                | State::Cpu(CpuState::PushRegisterOnStack { .. })
+                | State::Cpu(CpuState::WritePCHToStack { .. })
+                | State::Cpu(CpuState::WritePCLToStack { .. })
+                | State::Interrupt(InterruptState::PushPCH(_))
+                | State::Interrupt(InterruptState::PushPCL(_))
+                | State::Interrupt(InterruptState::PushStatusRegister(_))
+                | State::Dma(DmaState::WriteCycle(_))
+        )
+    }
+
+    /// Halt the CPU on the current (read) cycle. The read still happens but
+    /// everything else the cycle would have done is discarded as the CPU
+    /// will run the cycle again once the DMA has finished.
+    ///
+    /// Returns the address that was read, if any
+    fn halt_cpu(&mut self) -> Option<u16> {
+        let state = self.state;
+        let registers = self.registers;
+        let polled_interrupt = self.polled_interrupt;
+
+        self.halt_probe = Some(None);
+        let _ = self.step_state(state);
+        let address = self.halt_probe.take().flatten();
+
+        self.state = state;
+        self.registers = registers;
+        self.polled_interrupt = polled_interrupt;
+
+        address
+    }
+
+    /// While halted the CPU keeps driving the read it was attempting
+    fn halted_read(&mut self, address: Option<u16>) {
+        match address {
+            // Joypad reads on consecutive cycles only clock the shift register once
+            Some(0x4016) | Some(0x4017) => (),
+            Some(address) => {
+                let _ = self.read_byte(address);
+            }
+            None => (),
+        }
+    }
+
+    /// A cycle where the DMC DMA is waiting with the CPU halted, returns
+    /// true if it takes the cycle. During OAM DMA the cycle is left to that
+    /// instead, otherwise the CPU repeats its read
+    fn halted_cycle(&mut self, address: Option<u16>) -> bool {
+        match self.state {
+            State::Dma(_) => false,
+            _ => {
+                self.halted_read(address);
+                true
+            }
+        }
+    }
+
+    fn dmc_dma_read(&mut self) {
+        if let Some(address) = self.apu.dmc_dma_request() {
+            let value = self.read_byte(address);
+            self.apu.dmc_dma_complete(value);
+        }
+    }
+
+    /// Steps the DMC DMA unit, returns true if it has taken the cycle from
+    /// the CPU (or OAM DMA)
+    fn step_dmc_dma(&mut self) -> bool {
+        let state = match self.dmc_dma {
+            Some(state) => state,
+            None if self.apu.dmc_dma_request().is_some() => DmcDmaState::Halt,
+            None => return false,
+        };
+        let is_get_cycle = self.cycles & 1 == 1;
+
+        let (next_state, took_cycle) = match state {
+            // The request can go away (e.g. channel disabled) before the CPU halts
+            DmcDmaState::Halt if self.apu.dmc_dma_request().is_none() => (None, false),
+            // OAM DMA has already halted the CPU, its cycles double as the DMC's halt & dummy cycles
+            DmcDmaState::Halt if matches!(self.state, State::Dma(_)) => (Some(DmcDmaState::Dummy(None)), false),
+            DmcDmaState::Halt if self.is_write_cycle() => (Some(DmcDmaState::Halt), false),
+            DmcDmaState::Halt => {
+                info!("Halting CPU for DMC DMA on cycle {}", self.cycles);
+                let address = self.halt_cpu();
+                (Some(DmcDmaState::Dummy(address)), true)
+            }
+            DmcDmaState::Dummy(address) => {
+                let took_cycle = self.halted_cycle(address);
+                if !is_get_cycle {
+                    (Some(DmcDmaState::Read), took_cycle)
+                } else {
+                    (Some(DmcDmaState::Alignment(address)), took_cycle)
+                }
+            }
+            DmcDmaState::Alignment(address) => (Some(DmcDmaState::Read), self.halted_cycle(address)),
+            DmcDmaState::Read => {
+                self.dmc_dma_read();
+                match self.state {
+                    State::Dma(DmaState::ReadCycle) => (Some(DmcDmaState::OamDmaAlignment), true),
+                    _ => (None, true),
+                }
+            }
+            DmcDmaState::OamDmaAlignment => (None, true),
         };
 
+        self.dmc_dma = next_state;
+        took_cycle
+    }
+
+    /// Move the cpu on by a single clock cycle
+    fn clock(&mut self) {
+        if self.step_dmc_dma() {
+            self.at_instruction_boundary = false;
+            self.cycles += 1;
+            return;
+        }
+
+        self.state = self.step_state(self.state);
+
         if let State::Cpu(CpuState::FetchOpcode) = self.state {
             if let Some(interrupt) = self.polled_interrupt {
                 self.polled_interrupt = None;
@@ -1066,6 +1285,7 @@ impl<'a> Cpu<'a> {
                 info!("Starting DMA transfer from {:04X}", self.dma_address);
             }
         }
+        self.at_instruction_boundary = matches!(self.state, State::Cpu(CpuState::FetchOpcode));
 
         self.cycles += 1;
     }
@@ -1082,6 +1302,73 @@ impl<'a> Cpu<'a> {
         self.ppu.output_cycle()
     }
 
+    /// Returns true if the CPU was clocked on the last cycle and is about to
+    /// fetch the next opcode, i.e. the previous instruction has fully completed.
+    /// Also true at power on, before the first instruction.
+    pub fn is_instruction_boundary(&self) -> bool {
+        self.at_instruction_boundary
+    }
+
+    /// Press the reset button. The CPU abandons whatever it was doing and
+    /// runs the reset sequence (SP decremented by 3 without writing to the
+    /// stack, I flag set, PC loaded from the RESET vector) while the APU,
+    /// PPU & cartridge see the reset line as they would on hardware.
+    /// RAM & the A/X/Y registers are left untouched.
+    pub(crate) fn reset(&mut self) {
+        self.apu.reset();
+        self.ppu.reset();
+        self.prg_address_bus.reset();
+        self.ppu.chr_address_bus.reset();
+
+        self.trigger_dma = false;
+        self.dmc_dma = None;
+        self.polled_interrupt = None;
+        self.at_instruction_boundary = false;
+        self.state = State::Interrupt(InterruptState::InternalOps1(Interrupt::RESET(self.ppu.total_cycles)));
+    }
+
+    /// Return to a state saved straight after `new`, with the internal RAM
+    /// filled with `ram_pattern`
+    pub(crate) fn power_cycle(&mut self, power_on_state: &[u8], ram_pattern: RamPattern) {
+        self.load_state(&mut StateReader::new(power_on_state))
+            .expect("Failed to restore power on state");
+        self.fill_ram(ram_pattern);
+
+        // As at power on the first instruction is yet to run
+        self.at_instruction_boundary = true;
+    }
+
+    fn fill_ram(&mut self, pattern: RamPattern) {
+        let mut random_state = match pattern {
+            RamPattern::Random(seed) => seed | 1,
+            _ => 0,
+        };
+        for (address, byte) in self.ram.iter_mut().enumerate() {
+            *byte = match pattern {
+                RamPattern::Zeros => 0x00,
+                RamPattern::Ones => 0xFF,
+                RamPattern::Alternating if address & 0b100 == 0 => 0x00,
+                RamPattern::Alternating => 0xFF,
+                RamPattern::Random(_) => {
+                    // xorshift64
+                    random_state ^= random_state << 13;
+                    random_state ^= random_state >> 7;
+                    random_state ^= random_state << 17;
+                    random_state as u8
+                }
+            };
+        }
+    }
+
+    /// Returns true once a KIL opcode has locked up the CPU
+    pub fn is_jammed(&self) -> bool {
+        matches!(self.state, State::Cpu(CpuState::Jammed))
+    }
+
+    pub(crate) fn set_xaa_magic(&mut self, magic: u8) {
+        self.xaa_magic = magic;
+    }
+
     pub fn get_framebuffer(&self) -> &[u8; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize] {
         &self.ppu.frame_buffer
     }
@@ -1089,19 +1376,141 @@ impl<'a> Cpu<'a> {
     pub fn dump_ppu_state(&mut self, vram_clone: &mut [u8; 0x4000]) -> &[u8; 0x100] {
         self.ppu.dump_state(vram_clone)
     }
+
+    pub(crate) fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+
+    pub(crate) fn audio_sample_rate(&self) -> u32 {
+        self.apu.sample_rate()
+    }
+
+    pub(crate) fn audio_samples(&self) -> &[f32] {
+        self.apu.samples()
+    }
+
+    pub(crate) fn clear_audio_samples(&mut self) {
+        self.apu.clear_samples();
+    }
+
+    pub(crate) fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_address_bus.prg_ram()
+    }
+
+    pub(crate) fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_address_bus.prg_ram_mut()
+    }
+
+    pub(crate) fn registers(&self) -> CpuRegisters {
+        CpuRegisters {
+            a: self.registers.a,
+            x: self.registers.x,
+            y: self.registers.y,
+            stack_pointer: self.registers.stack_pointer,
+            program_counter: self.registers.program_counter,
+            status: self.registers.status_register.bits() | 0b0011_0000,
+        }
+    }
+
+    pub(crate) fn set_registers(&mut self, registers: CpuRegisters) {
+        self.registers.a = registers.a;
+        self.registers.x = registers.x;
+        self.registers.y = registers.y;
+        self.registers.stack_pointer = registers.stack_pointer;
+        self.registers.program_counter = registers.program_counter;
+        self.registers.status_register = StatusFlags::from_bits_truncate(registers.status);
+    }
+
+    /// Read CPU address space without side effects, the PPU, APU & IO
+    /// registers read as FF (as in nestest.log) as reading them would change
+    /// their state
+    pub(crate) fn peek_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram[(address & 0x7FF) as usize],
+            0x2000..=0x401F => 0xFF,
+            0x4020..=0xFFFF => self.prg_address_bus.read_byte(address),
+        }
+    }
+
+    /// Describe the instruction about to be executed, only meaningful on an
+    /// instruction boundary
+    pub(crate) fn trace_record(&self, frame: u32) -> TraceRecord {
+        let program_counter = self.registers.program_counter;
+        let opcode = &OPCODE_TABLE[self.peek_byte(program_counter) as usize];
+        let instruction = Disassembler::default().decode(program_counter, |address| self.peek_byte(address));
+        let (disassembly, effective_address) = opcode.trace_disassembly(self, &instruction);
+
+        TraceRecord {
+            program_counter,
+            bytes: instruction.bytes,
+            disassembly,
+            illegal: opcode.is_illegal(),
+            effective_address,
+            registers: CpuRegisters {
+                status: self.registers.status_register.bits() | 0b0010_0000,
+                ..self.registers()
+            },
+            scanline: self.ppu.current_scanline(),
+            dot: self.ppu.current_scanline_cycle(),
+            cpu_cycles: self.cycles,
+            frame,
+        }
+    }
+
+    /// Write to CPU address space exactly as an instruction would
+    pub(crate) fn poke_byte(&mut self, address: u16, value: u8) {
+        self.write_byte(address, value);
+    }
+
+    /// The interrupt (other than BRK) whose handler the CPU is about to jump
+    /// to, only set during the final cycles of the interrupt sequence
+    pub(crate) fn entering_interrupt(&self) -> Option<Interrupt> {
+        match self.state {
+            State::Interrupt(InterruptState::PullIRQVecLow(i)) => match i {
+                Interrupt::NMI(_) | Interrupt::IRQ(_) => Some(i),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_access_logging(&mut self, cpu: bool, ppu: bool) {
+        self.accesses.enabled = cpu;
+        self.ppu.accesses.enabled = ppu;
+    }
+
+    /// Memory accesses recorded for the debugger since this was last called
+    pub(crate) fn drain_accesses(&mut self) -> impl Iterator<Item = MemoryAccess> + '_ {
+        self.accesses.drain().chain(self.ppu.accesses.drain())
+    }
+
+    pub(crate) fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub(crate) fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
 }
 
-impl<'a> Iterator for Cpu<'a> {
+impl Iterator for Cpu {
     type Item = ();
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Check if we need to clock the CPU
-        self.cpu_cycle_counter -= 1;
-        if self.cpu_cycle_counter == 0 {
-            self.cpu_cycle_counter = 3;
+        // Check if we need to clock the CPU, this is every third PPU dot on NTSC but PAL runs 16 PPU dots to 5 CPU
+        // cycles so the gap is occasionally 4
+        let ppu_clock_divider = self.region.ppu_clock_divider();
+        if self.cpu_cycle_counter > ppu_clock_divider {
+            self.cpu_cycle_counter -= ppu_clock_divider;
+            self.at_instruction_boundary = false;
+        } else {
+            self.cpu_cycle_counter += self.region.cpu_clock_divider() - ppu_clock_divider;
             self.clock();
+            self.prg_address_bus.clock_irq();
 
             // Clock the APU once every CPU cycle, it decides internally which things to clock at what speed
+            self.apu
+                .set_expansion_audio(self.prg_address_bus.clock_expansion_audio());
             self.apu.next();
         }
 
@@ -1113,3 +1522,281 @@ impl<'a> Iterator for Cpu<'a> {
         None
     }
 }
+
+fn save_opcode(opcode: &Opcode, writer: &mut StateWriter) {
+    writer.write_u8(opcode.opcode);
+}
+
+fn load_opcode(reader: &mut StateReader) -> Result<&'static Opcode, SaveStateError> {
+    Ok(&OPCODE_TABLE[reader.read_u8()? as usize])
+}
+
+fn save_operation(operation: Operation, writer: &mut StateWriter) {
+    writer.write_u8(operation as u8);
+}
+
+fn load_operation(reader: &mut StateReader) -> Result<Operation, SaveStateError> {
+    let value = reader.read_u8()?;
+    OPCODE_TABLE
+        .iter()
+        .find(|opcode| opcode.operation as u8 == value)
+        .map(|opcode| opcode.operation)
+        .ok_or_else(|| SaveStateError::new("Invalid operation"))
+}
+
+impl SaveState for State {
+    fn save_state(&self, writer: &mut StateWriter) {
+        match *self {
+            State::Cpu(CpuState::FetchOpcode) => writer.write_u8(0x00),
+            State::Cpu(CpuState::ThrowawayRead { opcode, operand }) => {
+                writer.write_u8(0x01);
+                save_opcode(opcode, writer);
+                writer.write_option_u8(operand);
+            }
+            State::Cpu(CpuState::ReadingOperand {
+                opcode,
+                address_low_byte,
+                address_high_byte,
+                pointer,
+                indirect_address_low_byte,
+                indirect_address_high_byte,
+                checked_page_boundary,
+            }) => {
+                writer.write_u8(0x02);
+                save_opcode(opcode, writer);
+                writer.write_option_u8(address_low_byte);
+                writer.write_option_u8(address_high_byte);
+                writer.write_option_u8(pointer);
+                writer.write_option_u8(indirect_address_low_byte);
+                writer.write_option_u8(indirect_address_high_byte);
+                writer.write_bool(checked_page_boundary);
+            }
+            State::Cpu(CpuState::BranchCrossesPageBoundary {
+                opcode,
+                address,
+                operand,
+            }) => {
+                writer.write_u8(0x03);
+                save_opcode(opcode, writer);
+                writer.write_option_u16(address);
+                writer.write_option_u8(operand);
+            }
+            State::Cpu(CpuState::PushRegisterOnStack { value }) => {
+                writer.write_u8(0x04);
+                writer.write_u8(value);
+            }
+            State::Cpu(CpuState::PreIncrementStackPointer { operation }) => {
+                writer.write_u8(0x05);
+                save_operation(operation, writer);
+            }
+            State::Cpu(CpuState::PullRegisterFromStack { operation }) => {
+                writer.write_u8(0x06);
+                save_operation(operation, writer);
+            }
+            State::Cpu(CpuState::PullPCLFromStack { operation }) => {
+                writer.write_u8(0x07);
+                save_operation(operation, writer);
+            }
+            State::Cpu(CpuState::PullPCHFromStack { operation, pcl }) => {
+                writer.write_u8(0x08);
+                save_operation(operation, writer);
+                writer.write_u8(pcl);
+            }
+            State::Cpu(CpuState::IncrementProgramCounter) => writer.write_u8(0x09),
+            State::Cpu(CpuState::WritePCHToStack { address }) => {
+                writer.write_u8(0x0A);
+                writer.write_u16(address);
+            }
+            State::Cpu(CpuState::WritePCLToStack { address }) => {
+                writer.write_u8(0x0B);
+                writer.write_u16(address);
+            }
+            State::Cpu(CpuState::SetProgramCounter {
+                address,
+                was_branch_instruction,
+            }) => {
+                writer.write_u8(0x0C);
+                writer.write_u16(address);
+                writer.write_bool(was_branch_instruction);
+            }
+            State::Cpu(CpuState::WritingResult { address, value, dummy }) => {
+                writer.write_u8(0x0D);
+                writer.write_u16(address);
+                writer.write_u8(value);
+                writer.write_bool(dummy);
+            }
+            State::Cpu(CpuState::Jammed) => writer.write_u8(0x0E),
+            State::Interrupt(state) => {
+                let (tag, interrupt) = match state {
+                    InterruptState::InternalOps1(i) => (0x20, i),
+                    InterruptState::InternalOps2(i) => (0x21, i),
+                    InterruptState::PushPCH(i) => (0x22, i),
+                    InterruptState::PushPCL(i) => (0x23, i),
+                    InterruptState::PushStatusRegister(i) => (0x24, i),
+                    InterruptState::PullIRQVecLow(i) => (0x25, i),
+                    InterruptState::PullIRQVecHigh(i) => (0x26, i),
+                };
+                writer.write_u8(tag);
+                save_interrupt(Some(interrupt), writer);
+            }
+            State::Dma(DmaState::DummyCycle) => writer.write_u8(0x40),
+            State::Dma(DmaState::OddCpuCycle) => writer.write_u8(0x41),
+            State::Dma(DmaState::ReadCycle) => writer.write_u8(0x42),
+            State::Dma(DmaState::WriteCycle(value)) => {
+                writer.write_u8(0x43);
+                writer.write_u8(value);
+            }
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        *self = match reader.read_u8()? {
+            0x00 => State::Cpu(CpuState::FetchOpcode),
+            0x01 => State::Cpu(CpuState::ThrowawayRead {
+                opcode: load_opcode(reader)?,
+                operand: reader.read_option_u8()?,
+            }),
+            0x02 => State::Cpu(CpuState::ReadingOperand {
+                opcode: load_opcode(reader)?,
+                address_low_byte: reader.read_option_u8()?,
+                address_high_byte: reader.read_option_u8()?,
+                pointer: reader.read_option_u8()?,
+                indirect_address_low_byte: reader.read_option_u8()?,
+                indirect_address_high_byte: reader.read_option_u8()?,
+                checked_page_boundary: reader.read_bool()?,
+            }),
+            0x03 => State::Cpu(CpuState::BranchCrossesPageBoundary {
+                opcode: load_opcode(reader)?,
+                address: reader.read_option_u16()?,
+                operand: reader.read_option_u8()?,
+            }),
+            0x04 => State::Cpu(CpuState::PushRegisterOnStack {
+                value: reader.read_u8()?,
+            }),
+            0x05 => State::Cpu(CpuState::PreIncrementStackPointer {
+                operation: load_operation(reader)?,
+            }),
+            0x06 => State::Cpu(CpuState::PullRegisterFromStack {
+                operation: load_operation(reader)?,
+            }),
+            0x07 => State::Cpu(CpuState::PullPCLFromStack {
+                operation: load_operation(reader)?,
+            }),
+            0x08 => State::Cpu(CpuState::PullPCHFromStack {
+                operation: load_operation(reader)?,
+                pcl: reader.read_u8()?,
+            }),
+            0x09 => State::Cpu(CpuState::IncrementProgramCounter),
+            0x0A => State::Cpu(CpuState::WritePCHToStack {
+                address: reader.read_u16()?,
+            }),
+            0x0B => State::Cpu(CpuState::WritePCLToStack {
+                address: reader.read_u16()?,
+            }),
+            0x0C => State::Cpu(CpuState::SetProgramCounter {
+                address: reader.read_u16()?,
+                was_branch_instruction: reader.read_bool()?,
+            }),
+            0x0D => State::Cpu(CpuState::WritingResult {
+                address: reader.read_u16()?,
+                value: reader.read_u8()?,
+                dummy: reader.read_bool()?,
+            }),
+            0x0E => State::Cpu(CpuState::Jammed),
+            tag @ 0x20..=0x26 => {
+                let interrupt = load_interrupt(reader)?.ok_or_else(|| SaveStateError::new("Missing interrupt"))?;
+                State::Interrupt(match tag {
+                    0x20 => InterruptState::InternalOps1(interrupt),
+                    0x21 => InterruptState::InternalOps2(interrupt),
+                    0x22 => InterruptState::PushPCH(interrupt),
+                    0x23 => InterruptState::PushPCL(interrupt),
+                    0x24 => InterruptState::PushStatusRegister(interrupt),
+                    0x25 => InterruptState::PullIRQVecLow(interrupt),
+                    _ => InterruptState::PullIRQVecHigh(interrupt),
+                })
+            }
+            0x40 => State::Dma(DmaState::DummyCycle),
+            0x41 => State::Dma(DmaState::OddCpuCycle),
+            0x42 => State::Dma(DmaState::ReadCycle),
+            0x43 => State::Dma(DmaState::WriteCycle(reader.read_u8()?)),
+            _ => return Err(SaveStateError::new("Invalid CPU state")),
+        };
+
+        Ok(())
+    }
+}
+
+/// The DMC DMA unit is stored as a tag followed by the halted read address
+fn save_dmc_dma(dmc_dma: Option<DmcDmaState>, writer: &mut StateWriter) {
+    let (tag, address) = match dmc_dma {
+        None => (0, None),
+        Some(DmcDmaState::Halt) => (1, None),
+        Some(DmcDmaState::Dummy(address)) => (2, address),
+        Some(DmcDmaState::Alignment(address)) => (3, address),
+        Some(DmcDmaState::Read) => (4, None),
+        Some(DmcDmaState::OamDmaAlignment) => (5, None),
+    };
+    writer.write_u8(tag);
+    writer.write_option_u16(address);
+}
+
+fn load_dmc_dma(reader: &mut StateReader) -> Result<Option<DmcDmaState>, SaveStateError> {
+    let tag = reader.read_u8()?;
+    let address = reader.read_option_u16()?;
+    match tag {
+        0 => Ok(None),
+        1 => Ok(Some(DmcDmaState::Halt)),
+        2 => Ok(Some(DmcDmaState::Dummy(address))),
+        3 => Ok(Some(DmcDmaState::Alignment(address))),
+        4 => Ok(Some(DmcDmaState::Read)),
+        5 => Ok(Some(DmcDmaState::OamDmaAlignment)),
+        _ => Err(SaveStateError::new("Invalid DMC DMA state")),
+    }
+}
+
+impl SaveState for Cpu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.state.save_state(writer);
+        writer.write_u8(self.registers.a);
+        writer.write_u8(self.registers.x);
+        writer.write_u8(self.registers.y);
+        writer.write_u8(self.registers.stack_pointer);
+        writer.write_u16(self.registers.program_counter);
+        writer.write_u8(self.registers.status_register.bits());
+        writer.write_u32(self.cycles);
+        writer.write_u8(self.cpu_cycle_counter);
+        writer.write_bytes(&self.ram);
+        writer.write_bool(self.trigger_dma);
+        writer.write_u16(self.dma_address);
+        save_interrupt(self.polled_interrupt, writer);
+        save_dmc_dma(self.dmc_dma, writer);
+        self.apu.save_state(writer);
+        self.io.save_state(writer);
+        self.ppu.save_state(writer);
+        self.prg_address_bus.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.state.load_state(reader)?;
+        self.registers.a = reader.read_u8()?;
+        self.registers.x = reader.read_u8()?;
+        self.registers.y = reader.read_u8()?;
+        self.registers.stack_pointer = reader.read_u8()?;
+        self.registers.program_counter = reader.read_u16()?;
+        self.registers.status_register = StatusFlags::from_bits_truncate(reader.read_u8()?);
+        self.cycles = reader.read_u32()?;
+        self.cpu_cycle_counter = reader.read_u8()?;
+        reader.read_bytes(&mut self.ram)?;
+        self.trigger_dma = reader.read_bool()?;
+        self.dma_address = reader.read_u16()?;
+        self.polled_interrupt = load_interrupt(reader)?;
+        self.dmc_dma = load_dmc_dma(reader)?;
+        self.at_instruction_boundary = self.cpu_cycle_counter + self.region.ppu_clock_divider()
+            > self.region.cpu_clock_divider()
+            && matches!(self.state, State::Cpu(CpuState::FetchOpcode));
+        self.apu.load_state(reader)?;
+        self.io.load_state(reader)?;
+        self.ppu.load_state(reader)?;
+        self.prg_address_bus.load_state(reader)
+    }
+}
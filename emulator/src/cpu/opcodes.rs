@@ -4,44 +4,90 @@ use cpu::Cpu;
 use cpu::CpuState;
 use cpu::InterruptState;
 use cpu::State;
+use disassembler::Instruction;
 use log::error;
 
 #[derive(Debug, PartialEq)]
-pub(super) struct Opcode {
+pub(crate) struct Opcode {
     pub(super) opcode: u8,
-    pub(super) operation: Operation,
-    pub(super) address_mode: AddressingMode,
+    pub(crate) operation: Operation,
+    pub(crate) address_mode: AddressingMode,
     is_illegal: bool,
 }
 
 impl Opcode {
-    pub(super) fn nes_test_log(&self, pc_1: u8, pc_2: u8) -> String {
-        match self.address_mode.instruction_length() {
-            InstructionLength::One => format!(
-                "{:02X}       {:}{:?} {:027}",
-                self.opcode,
-                if self.is_illegal { "*" } else { " " },
-                self.operation,
-                " "
-            ),
-            InstructionLength::Two => format!(
-                "{:02X} {:02X}    {:}{:?} {:027}",
-                self.opcode,
-                pc_1,
-                if self.is_illegal { "*" } else { " " },
-                self.operation,
-                " "
-            ),
-            InstructionLength::Three => format!(
-                "{:02X} {:02X} {:02X} {:}{:?} {:027}",
-                self.opcode,
-                pc_1,
-                pc_2,
-                if self.is_illegal { "*" } else { " " },
-                self.operation,
-                " "
-            ),
-        }
+    /// Disassemble the instruction in the style of nestest.log for the trace
+    /// logger, appending the effective address & the value there to the
+    /// operand. Gives the text & the effective address, memory is peeked so
+    /// this has no side effects.
+    pub(super) fn trace_disassembly(&self, cpu: &Cpu, instruction: &Instruction) -> (String, Option<u16>) {
+        let registers = &cpu.registers;
+        let target = instruction.target.unwrap_or(0);
+        let zero_page_word = |address: u8| {
+            cpu.peek_byte(address as u16) as u16 | (cpu.peek_byte(address.wrapping_add(1) as u16) as u16) << 8
+        };
+
+        let (annotation, address) = match self.address_mode {
+            AddressingMode::Accumulator | AddressingMode::Implied | AddressingMode::Immediate => (String::new(), None),
+            AddressingMode::Relative => (String::new(), Some(target)),
+            AddressingMode::Absolute => match self.operation {
+                Operation::JMP | Operation::JSR => (String::new(), Some(target)),
+                _ => (format!(" = {:02X}", cpu.peek_byte(target)), Some(target)),
+            },
+            AddressingMode::AbsoluteXIndexed | AddressingMode::AbsoluteYIndexed => {
+                let index = if self.address_mode == AddressingMode::AbsoluteXIndexed {
+                    registers.x
+                } else {
+                    registers.y
+                };
+                let address = target.wrapping_add(index as u16);
+                (
+                    format!(" @ {:04X} = {:02X}", address, cpu.peek_byte(address)),
+                    Some(address),
+                )
+            }
+            AddressingMode::Indirect => {
+                // The high byte of the pointer isn't incremented, JMP ($xxFF) wraps within the page
+                let high = (target & 0xFF00) | (target.wrapping_add(1) & 0x00FF);
+                let address = cpu.peek_byte(target) as u16 | (cpu.peek_byte(high) as u16) << 8;
+                (format!(" = {:04X}", address), Some(address))
+            }
+            AddressingMode::IndirectXIndexed => {
+                let pointer = (target as u8).wrapping_add(registers.x);
+                let address = zero_page_word(pointer);
+                (
+                    format!(" @ {:02X} = {:04X} = {:02X}", pointer, address, cpu.peek_byte(address)),
+                    Some(address),
+                )
+            }
+            AddressingMode::IndirectYIndexed => {
+                let base = zero_page_word(target as u8);
+                let address = base.wrapping_add(registers.y as u16);
+                (
+                    format!(" = {:04X} @ {:04X} = {:02X}", base, address, cpu.peek_byte(address)),
+                    Some(address),
+                )
+            }
+            AddressingMode::ZeroPage => (format!(" = {:02X}", cpu.peek_byte(target)), Some(target)),
+            AddressingMode::ZeroPageXIndexed | AddressingMode::ZeroPageYIndexed => {
+                let index = if self.address_mode == AddressingMode::ZeroPageXIndexed {
+                    registers.x
+                } else {
+                    registers.y
+                };
+                let address = (target as u8).wrapping_add(index) as u16;
+                (
+                    format!(" @ {:02X} = {:02X}", address, cpu.peek_byte(address)),
+                    Some(address),
+                )
+            }
+        };
+
+        (format!("{}{}", instruction, annotation), address)
+    }
+
+    pub(crate) fn is_illegal(&self) -> bool {
+        self.is_illegal
     }
 
     pub(super) fn execute(&self, cpu: &mut Cpu, operand: Option<u8>, address: Option<u16>) -> State {
@@ -60,16 +106,53 @@ impl Opcode {
                 cpu.adc(operand.unwrap());
                 State::Cpu(CpuState::FetchOpcode)
             }
-            Operation::AHX => todo!(),
-            Operation::ALR => todo!(),
-            Operation::ANC => todo!(),
+            Operation::AHX => {
+                let value = cpu.registers.a & cpu.registers.x;
+                unstable_store(address.unwrap(), cpu.registers.y, value)
+            }
+            Operation::ALR => {
+                cpu.poll_for_interrupts(true);
+                let value = cpu.registers.a & operand.unwrap();
+                cpu.registers
+                    .status_register
+                    .set(StatusFlags::CARRY_FLAG, value & 1 == 1);
+                cpu.registers.a = value >> 1;
+                cpu.set_negative_zero_flags(cpu.registers.a);
+                State::Cpu(CpuState::FetchOpcode)
+            }
+            Operation::ANC => {
+                cpu.poll_for_interrupts(true);
+                cpu.registers.a &= operand.unwrap();
+                cpu.set_negative_zero_flags(cpu.registers.a);
+                cpu.registers
+                    .status_register
+                    .set(StatusFlags::CARRY_FLAG, cpu.registers.a & 0b1000_0000 != 0);
+                State::Cpu(CpuState::FetchOpcode)
+            }
             Operation::AND => {
                 cpu.poll_for_interrupts(true);
                 cpu.registers.a &= operand.unwrap();
                 cpu.set_negative_zero_flags(cpu.registers.a);
                 State::Cpu(CpuState::FetchOpcode)
             }
-            Operation::ARR => todo!(),
+            Operation::ARR => {
+                cpu.poll_for_interrupts(true);
+                let carry_in = match cpu.registers.status_register.contains(StatusFlags::CARRY_FLAG) {
+                    true => 0b1000_0000,
+                    false => 0,
+                };
+                cpu.registers.a = ((cpu.registers.a & operand.unwrap()) >> 1) | carry_in;
+                cpu.set_negative_zero_flags(cpu.registers.a);
+                // Carry & overflow come from the adder rather than the shift
+                cpu.registers
+                    .status_register
+                    .set(StatusFlags::CARRY_FLAG, cpu.registers.a & 0b0100_0000 != 0);
+                cpu.registers.status_register.set(
+                    StatusFlags::OVERFLOW_FLAG,
+                    ((cpu.registers.a >> 6) ^ (cpu.registers.a >> 5)) & 1 == 1,
+                );
+                State::Cpu(CpuState::FetchOpcode)
+            }
             Operation::ASL => {
                 let result = operand.unwrap() << 1;
                 cpu.registers
@@ -90,7 +173,16 @@ impl Opcode {
                     }),
                 }
             }
-            Operation::AXS => todo!(),
+            Operation::AXS => {
+                cpu.poll_for_interrupts(true);
+                let value = cpu.registers.a & cpu.registers.x;
+                cpu.registers
+                    .status_register
+                    .set(StatusFlags::CARRY_FLAG, value >= operand.unwrap());
+                cpu.registers.x = value.wrapping_sub(operand.unwrap());
+                cpu.set_negative_zero_flags(cpu.registers.x);
+                State::Cpu(CpuState::FetchOpcode)
+            }
             Operation::BCC
             | Operation::BCS
             | Operation::BEQ
@@ -238,11 +330,19 @@ impl Opcode {
                 address: address.unwrap(),
             }),
             Operation::KIL => {
-                // Illegal opcode - KIL
-                error!("KIL opcode");
-                panic!();
+                // Illegal opcode - KIL, the CPU locks up until it's reset
+                error!("KIL opcode at {:04X}", cpu.registers.program_counter.wrapping_sub(1));
+                State::Cpu(CpuState::Jammed)
+            }
+            Operation::LAS => {
+                cpu.poll_for_interrupts(true);
+                let value = operand.unwrap() & cpu.registers.stack_pointer;
+                cpu.registers.a = value;
+                cpu.registers.x = value;
+                cpu.registers.stack_pointer = value;
+                cpu.set_negative_zero_flags(value);
+                State::Cpu(CpuState::FetchOpcode)
             }
-            Operation::LAS => todo!(),
             Operation::LAX => {
                 cpu.poll_for_interrupts(true);
                 cpu.registers.a = operand.unwrap();
@@ -441,8 +541,8 @@ impl Opcode {
                     .insert(StatusFlags::INTERRUPT_DISABLE_FLAG);
                 State::Cpu(CpuState::FetchOpcode)
             }
-            Operation::SHX => todo!(),
-            Operation::SHY => todo!(),
+            Operation::SHX => unstable_store(address.unwrap(), cpu.registers.y, cpu.registers.x),
+            Operation::SHY => unstable_store(address.unwrap(), cpu.registers.x, cpu.registers.y),
             Operation::SLO => {
                 let result = operand.unwrap() << 1;
                 cpu.registers
@@ -486,7 +586,10 @@ impl Opcode {
                 address: address.unwrap(),
                 dummy: false,
             }),
-            Operation::TAS => todo!(),
+            Operation::TAS => {
+                cpu.registers.stack_pointer = cpu.registers.a & cpu.registers.x;
+                unstable_store(address.unwrap(), cpu.registers.y, cpu.registers.stack_pointer)
+            }
             Operation::TAX => {
                 cpu.poll_for_interrupts(true);
                 cpu.registers.x = cpu.registers.a;
@@ -522,11 +625,35 @@ impl Opcode {
                 cpu.set_negative_zero_flags(cpu.registers.a);
                 State::Cpu(CpuState::FetchOpcode)
             }
-            Operation::XAA => todo!(),
+            Operation::XAA => {
+                cpu.poll_for_interrupts(true);
+                cpu.registers.a = (cpu.registers.a | cpu.xaa_magic) & cpu.registers.x & operand.unwrap();
+                cpu.set_negative_zero_flags(cpu.registers.a);
+                State::Cpu(CpuState::FetchOpcode)
+            }
         }
     }
 }
 
+/// The stores that AND the value with the high byte of the unindexed
+/// address + 1 (SHA/AHX, SHX, SHY & TAS). When indexing crosses a page the
+/// high byte of the address written to is replaced by the value as well.
+fn unstable_store(address: u16, index: u8, value: u8) -> State {
+    let unindexed_address = address.wrapping_sub(index as u16);
+    let value = value & ((unindexed_address >> 8) as u8).wrapping_add(1);
+    let address = if (unindexed_address ^ address) & 0xFF00 != 0 {
+        (address & 0x00FF) | ((value as u16) << 8)
+    } else {
+        address
+    };
+
+    State::Cpu(CpuState::WritingResult {
+        address,
+        value,
+        dummy: false,
+    })
+}
+
 #[derive(Debug, PartialEq)]
 pub(super) enum InstructionType {
     Read,
@@ -539,14 +666,14 @@ pub(super) enum InstructionType {
 }
 
 #[derive(Debug, PartialEq)]
-pub(super) enum InstructionLength {
-    One,
-    Two,
-    Three,
+pub(crate) enum InstructionLength {
+    One = 1,
+    Two = 2,
+    Three = 3,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub(super) enum AddressingMode {
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressingMode {
     Accumulator,
     Absolute,
     AbsoluteXIndexed,
@@ -563,7 +690,7 @@ pub(super) enum AddressingMode {
 }
 
 impl AddressingMode {
-    pub(super) fn instruction_length(&self) -> InstructionLength {
+    pub(crate) fn instruction_length(&self) -> InstructionLength {
         match self {
             AddressingMode::Accumulator => InstructionLength::One,
             AddressingMode::Absolute => InstructionLength::Three,
@@ -582,9 +709,9 @@ impl AddressingMode {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(u8)]
-pub(super) enum Operation {
+pub enum Operation {
     ADC,
     AHX,
     ALR,
@@ -666,9 +793,14 @@ impl Operation {
     pub(super) fn instruction_type(&self) -> InstructionType {
         match self {
             Operation::JMP | Operation::JSR => InstructionType::Jump,
-            Operation::STA | Operation::STX | Operation::STY | Operation::SAX | Operation::SHX | Operation::SHY => {
-                InstructionType::Write
-            }
+            Operation::STA
+            | Operation::STX
+            | Operation::STY
+            | Operation::SAX
+            | Operation::SHX
+            | Operation::SHY
+            | Operation::AHX
+            | Operation::TAS => InstructionType::Write,
             Operation::ASL
             | Operation::LSR
             | Operation::ROL
@@ -694,6 +826,12 @@ impl Operation {
             | Operation::CPY
             | Operation::BIT
             | Operation::LAX
+            | Operation::LAS
+            | Operation::ANC
+            | Operation::ALR
+            | Operation::ARR
+            | Operation::AXS
+            | Operation::XAA
             | Operation::NOP => InstructionType::Read,
             Operation::BCC
             | Operation::BCS
@@ -726,13 +864,13 @@ impl Operation {
             | Operation::TSX
             | Operation::TXA
             | Operation::TXS
-            | Operation::TYA => InstructionType::NoMemoryAccess,
-            _ => todo!("Not yet defined instruction type for {:?}", self),
+            | Operation::TYA
+            | Operation::KIL => InstructionType::NoMemoryAccess,
         }
     }
 }
 
-pub(super) const OPCODE_TABLE: [Opcode; 0x100] = [
+pub(crate) const OPCODE_TABLE: [Opcode; 0x100] = [
     // 0x00-0x0F
     Opcode {
         opcode: 0x00,
@@ -1,6 +1,6 @@
 use cpu::status_flags::StatusFlags;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub(super) struct Registers {
     // Accumulator
     pub(super) a: u8,
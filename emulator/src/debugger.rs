@@ -0,0 +1,170 @@
+use std::ops::RangeInclusive;
+use std::vec::Drain;
+
+/// Identifies a breakpoint so that it can later be removed or matched
+/// against the reason execution stopped
+pub type BreakpointId = u32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressSpace {
+    Cpu,
+    Ppu,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessType {
+    Read,
+    Write,
+}
+
+/// A single read or write seen on one of the address buses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub space: AddressSpace,
+    pub access: AccessType,
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stop before executing the instruction at the address
+    Execute(u16),
+    /// Stop on the cycle that an address in the range is accessed. PPU
+    /// watchpoints only see accesses made through PPUDATA (2007), not the
+    /// fetches made while rendering.
+    Watch {
+        space: AddressSpace,
+        access: AccessType,
+        addresses: RangeInclusive<u16>,
+    },
+    /// Stop before the first instruction of the NMI handler
+    Nmi,
+    /// Stop before the first instruction of the IRQ handler (but not on BRK)
+    Irq,
+    /// Stop when the PPU reaches the dot, scanline 261 (311 on PAL & Dendy) is the pre-render line
+    Dot { scanline: u16, dot: u16 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Run a single instruction, if an interrupt fires this stops at the
+    /// start of its handler
+    Instruction,
+    /// As `Instruction` but a JSR is run through to its return
+    Over,
+    /// Run until the current subroutine or interrupt handler returns
+    Out,
+    /// Run until the PPU starts the next scanline
+    Scanline,
+    /// Run until the visible portion of the next frame is complete, as `Nes::run_frame`
+    Frame,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    StepComplete,
+    /// Watchpoints also give the access that triggered them
+    Breakpoint {
+        id: BreakpointId,
+        access: Option<MemoryAccess>,
+    },
+}
+
+/// A snapshot of the CPU registers, the status register is as it would be
+/// pushed by PHP (i.e. with bits 4 & 5 set)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuRegisters {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub stack_pointer: u8,
+    pub program_counter: u16,
+    pub status: u8,
+}
+
+/// The breakpoints set on a console, these are only checked when running
+/// with `Nes::debug_step`
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<(BreakpointId, Breakpoint)>,
+    next_id: BreakpointId,
+}
+
+impl Debugger {
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push((id, breakpoint));
+
+        id
+    }
+
+    /// Returns false if there was no breakpoint with the id
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|(i, _)| *i != id);
+
+        self.breakpoints.len() != count
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &Breakpoint)> {
+        self.breakpoints.iter().map(|(id, breakpoint)| (*id, breakpoint))
+    }
+
+    pub(crate) fn find<F: Fn(&Breakpoint) -> bool>(&self, predicate: F) -> Option<BreakpointId> {
+        self.breakpoints
+            .iter()
+            .find(|(_, breakpoint)| predicate(breakpoint))
+            .map(|(id, _)| *id)
+    }
+
+    pub(crate) fn is_watching(&self, space: AddressSpace) -> bool {
+        self.find(|b| matches!(b, Breakpoint::Watch { space: s, .. } if *s == space))
+            .is_some()
+    }
+
+    pub(crate) fn find_watchpoint(&self, memory_access: &MemoryAccess) -> Option<BreakpointId> {
+        self.find(|b| match b {
+            Breakpoint::Watch {
+                space,
+                access,
+                addresses,
+            } => {
+                *space == memory_access.space
+                    && *access == memory_access.access
+                    && addresses.contains(&memory_access.address)
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Bus accesses made since the debugger last looked, only recorded while
+/// there's a watchpoint on the bus so that normal running isn't slowed down
+#[derive(Default)]
+pub(crate) struct AccessLog {
+    pub(crate) enabled: bool,
+    accesses: Vec<MemoryAccess>,
+}
+
+impl AccessLog {
+    pub(crate) fn record(&mut self, space: AddressSpace, access: AccessType, address: u16, value: u8) {
+        if self.enabled {
+            self.accesses.push(MemoryAccess {
+                space,
+                access,
+                address,
+                value,
+            });
+        }
+    }
+
+    pub(crate) fn drain(&mut self) -> Drain<'_, MemoryAccess> {
+        self.accesses.drain(..)
+    }
+}
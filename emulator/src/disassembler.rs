@@ -0,0 +1,281 @@
+use cpu::{AddressingMode, Operation, OPCODE_TABLE};
+use std::collections::HashMap;
+use std::fmt;
+use Nes;
+
+const HARDWARE_LABELS: [(u16, &str); 33] = [
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4000, "SQ1_VOL"),
+    (0x4001, "SQ1_SWEEP"),
+    (0x4002, "SQ1_LO"),
+    (0x4003, "SQ1_HI"),
+    (0x4004, "SQ2_VOL"),
+    (0x4005, "SQ2_SWEEP"),
+    (0x4006, "SQ2_LO"),
+    (0x4007, "SQ2_HI"),
+    (0x4008, "TRI_LINEAR"),
+    (0x400A, "TRI_LO"),
+    (0x400B, "TRI_HI"),
+    (0x400C, "NOISE_VOL"),
+    (0x400E, "NOISE_LO"),
+    (0x400F, "NOISE_HI"),
+    (0x4010, "DMC_FREQ"),
+    (0x4011, "DMC_RAW"),
+    (0x4012, "DMC_START"),
+    (0x4013, "DMC_LEN"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "SND_CHN"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+    (0xFFFA, "NMI_VECTOR"),
+    (0xFFFC, "RESET_VECTOR"),
+    (0xFFFE, "IRQ_VECTOR"),
+];
+
+/// The interrupt vectors and the labels given to their handlers by `add_vector_labels`
+pub const VECTORS: [(u16, &str); 3] = [(0xFFFA, "NMI"), (0xFFFC, "RESET"), (0xFFFE, "IRQ")];
+
+/// A single decoded instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    /// The opcode followed by its operand bytes
+    pub bytes: Vec<u8>,
+    pub operation: Operation,
+    pub addressing_mode: AddressingMode,
+    /// Set for the unofficial opcodes
+    pub illegal: bool,
+    /// The address the operand refers to before any indexing, for branches
+    /// this is the destination rather than the offset
+    pub target: Option<u16>,
+    /// The operand in assembler syntax (e.g. `($80),Y`) with symbols
+    /// substituted for any addresses that have one
+    pub operand: String,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operand.is_empty() {
+            write!(f, "{:?}", self.operation)
+        } else {
+            write!(f, "{:?} {}", self.operation, self.operand)
+        }
+    }
+}
+
+/// Decodes 6502 machine code, either from a slice (e.g. a PRG ROM bank) or
+/// from the live CPU address space of a console
+///
+/// The default disassembler has no symbols at all, `with_hardware_labels`
+/// names the PPU/APU/IO registers & the interrupt vectors.
+#[derive(Clone, Debug, Default)]
+pub struct Disassembler {
+    symbols: HashMap<u16, String>,
+}
+
+impl Disassembler {
+    pub fn with_hardware_labels() -> Self {
+        let mut disassembler = Disassembler::default();
+        for (address, name) in HARDWARE_LABELS.iter() {
+            disassembler.add_symbol(*address, name);
+        }
+
+        disassembler
+    }
+
+    /// Name an address, replacing any existing name
+    pub fn add_symbol(&mut self, address: u16, name: &str) {
+        self.symbols.insert(address, name.to_string());
+    }
+
+    /// Add the symbols from a VICE label file as written by `ld65 -Ln`
+    /// (lines of the form `al 00C000 .reset`), lines in any other form are
+    /// skipped. Returns the number of symbols added.
+    pub fn add_vice_labels(&mut self, labels: &str) -> usize {
+        let mut count = 0;
+        for line in labels.lines() {
+            let mut parts = line.split_whitespace();
+            if let (Some("al"), Some(address), Some(name)) = (parts.next(), parts.next(), parts.next()) {
+                if let Ok(address) = u32::from_str_radix(address, 16) {
+                    self.add_symbol(address as u16, name.trim_start_matches('.'));
+                    count += 1;
+                }
+            }
+        }
+
+        count
+    }
+
+    /// Name the handlers pointed to by the NMI, RESET & IRQ vectors, `read`
+    /// gives the bytes at FFFA-FFFF
+    pub fn add_vector_labels<F: Fn(u16) -> u8>(&mut self, read: F) {
+        for (vector, name) in VECTORS.iter() {
+            let handler = read(*vector) as u16 | (read(vector + 1) as u16) << 8;
+            self.symbols.entry(handler).or_insert_with(|| name.to_string());
+        }
+    }
+
+    pub fn symbol(&self, address: u16) -> Option<&str> {
+        self.symbols.get(&address).map(String::as_str)
+    }
+
+    /// Decode the instruction at `address`, `read` gives the byte at an address
+    pub fn decode<F: Fn(u16) -> u8>(&self, address: u16, read: F) -> Instruction {
+        let opcode = &OPCODE_TABLE[read(address) as usize];
+        let bytes: Vec<u8> = (0..opcode.address_mode.instruction_length() as u16)
+            .map(|i| read(address.wrapping_add(i)))
+            .collect();
+        let byte = bytes.get(1).copied().unwrap_or(0);
+        let word = byte as u16 | (bytes.get(2).copied().unwrap_or(0) as u16) << 8;
+
+        let target = match opcode.address_mode {
+            AddressingMode::Accumulator | AddressingMode::Implied | AddressingMode::Immediate => None,
+            AddressingMode::Relative => Some(address.wrapping_add(2).wrapping_add(byte as i8 as u16)),
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteXIndexed
+            | AddressingMode::AbsoluteYIndexed
+            | AddressingMode::Indirect => Some(word),
+            AddressingMode::IndirectXIndexed
+            | AddressingMode::IndirectYIndexed
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageXIndexed
+            | AddressingMode::ZeroPageYIndexed => Some(byte as u16),
+        };
+        let name = |width: usize| match target.and_then(|t| self.symbol(t)) {
+            Some(symbol) => symbol.to_string(),
+            None => format!("${:0width$X}", target.unwrap_or(0), width = width),
+        };
+
+        let operand = match opcode.address_mode {
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Immediate => format!("#${:02X}", byte),
+            AddressingMode::Relative | AddressingMode::Absolute => name(4),
+            AddressingMode::AbsoluteXIndexed => format!("{},X", name(4)),
+            AddressingMode::AbsoluteYIndexed => format!("{},Y", name(4)),
+            AddressingMode::Indirect => format!("({})", name(4)),
+            AddressingMode::IndirectXIndexed => format!("({},X)", name(2)),
+            AddressingMode::IndirectYIndexed => format!("({}),Y", name(2)),
+            AddressingMode::ZeroPage => name(2),
+            AddressingMode::ZeroPageXIndexed => format!("{},X", name(2)),
+            AddressingMode::ZeroPageYIndexed => format!("{},Y", name(2)),
+        };
+
+        Instruction {
+            address,
+            bytes,
+            operation: opcode.operation,
+            addressing_mode: opcode.address_mode,
+            illegal: opcode.is_illegal(),
+            target,
+            operand,
+        }
+    }
+
+    /// Decode a block of code (e.g. a PRG ROM bank) that appears in CPU
+    /// address space at `base_address`. Decoding is linear so data mixed in
+    /// with the code is shown as instructions, a final instruction that
+    /// would run past the end of `bytes` is left off.
+    pub fn disassemble_bytes(&self, bytes: &[u8], base_address: u16) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let instruction = self.decode(base_address.wrapping_add(offset as u16), |address| {
+                bytes
+                    .get(address.wrapping_sub(base_address) as usize)
+                    .copied()
+                    .unwrap_or(0)
+            });
+            offset += instruction.bytes.len();
+            if offset > bytes.len() {
+                break;
+            }
+            instructions.push(instruction);
+        }
+
+        instructions
+    }
+
+    /// Decode `count` instructions from the CPU address space of a running
+    /// console, without side effects (so registers read as FF)
+    pub fn disassemble_cpu(&self, nes: &Nes, address: u16, count: usize) -> Vec<Instruction> {
+        let mut instructions = Vec::with_capacity(count);
+        let mut address = address;
+        for _ in 0..count {
+            let instruction = self.decode(address, |a| nes.peek_cpu_memory(a));
+            address = address.wrapping_add(instruction.bytes.len() as u16);
+            instructions.push(instruction);
+        }
+
+        instructions
+    }
+}
+
+#[cfg(test)]
+mod disassembler_tests {
+    use super::Disassembler;
+
+    #[test]
+    fn test_operands() {
+        let code = [
+            0xA9, 0x10, // LDA #$10
+            0x8D, 0x00, 0x20, // STA PPUCTRL
+            0xB1, 0x80, // LDA ($80),Y
+            0xBD, 0x00, 0x03, // LDA $0300,X
+            0x0A, // ASL A
+            0xD0, 0xF3, // BNE $8000
+            0x6C, 0xFC, 0xFF, // JMP (RESET_VECTOR)
+            0xA7, 0x10, // *LAX $10
+            0xEA, 0x20, // NOP, then a truncated JSR
+        ];
+        let instructions = Disassembler::with_hardware_labels().disassemble_bytes(&code, 0x8000);
+        let text: Vec<String> = instructions.iter().map(|i| i.to_string()).collect();
+
+        assert_eq!(
+            text,
+            vec![
+                "LDA #$10",
+                "STA PPUCTRL",
+                "LDA ($80),Y",
+                "LDA $0300,X",
+                "ASL A",
+                "BNE $8000",
+                "JMP (RESET_VECTOR)",
+                "LAX $10",
+                "NOP",
+            ]
+        );
+        assert_eq!(instructions[5].target, Some(0x8000));
+        assert_eq!(instructions[7].address, 0x8010);
+        assert!(instructions[7].illegal);
+        assert!(!instructions[8].illegal);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut disassembler = Disassembler::default();
+        let labels = "al 00C000 .reset\nal 000010 .frame_counter\nnot a label\n";
+        assert_eq!(disassembler.add_vice_labels(labels), 2);
+
+        let code = [0x20, 0x00, 0xC0, 0xE6, 0x10, 0x8D, 0x00, 0x20];
+        let text: Vec<String> = disassembler
+            .disassemble_bytes(&code, 0x8000)
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(text, vec!["JSR reset", "INC frame_counter", "STA $2000"]);
+
+        let vectors = [0x00, 0x90, 0x00, 0xC0, 0x34, 0x12];
+        disassembler.add_vector_labels(|address| vectors[(address - 0xFFFA) as usize]);
+        assert_eq!(disassembler.symbol(0x9000), Some("NMI"));
+        assert_eq!(disassembler.symbol(0xC000), Some("reset"));
+        assert_eq!(disassembler.symbol(0x1234), Some("IRQ"));
+    }
+}
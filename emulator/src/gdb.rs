@@ -0,0 +1,424 @@
+//! GDB remote serial protocol stub
+//!
+//! Lets a debugger that speaks the GDB RSP attach over TCP (e.g. `target
+//! remote localhost:2345`). The host drives the stub by calling
+//! `GdbStub::run_frame` in place of `Nes::run_frame`, which services any
+//! packets from the debugger without blocking and then runs a frame unless
+//! the debugger has the console halted.
+//!
+//! There's no standard GDB register layout for the 6502 so `g`/`G` use the
+//! order of `CpuRegisters`: A, X, Y & SP as one byte each, PC as two bytes
+//! (little endian) and finally P. The same numbering (0-5) is used by `p`/`P`.
+//!
+//! Supported packets are `?`, `g`, `G`, `p`, `P`, `m`, `M`, `c`, `s`, `Z0`-`Z4`
+//! (software/hardware breakpoints and write/read/access watchpoints), `z0`-`z4`,
+//! `D`, `k`, `H` and the `q` queries GDB sends on connecting. Anything else
+//! gets the empty "unsupported" reply. Ctrl-C (0x03) halts the console.
+
+use debugger::{AccessType, AddressSpace, Breakpoint, BreakpointId, CpuRegisters, MemoryAccess, Step, StopReason};
+use log::info;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use Nes;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const INTERRUPT: u8 = 0x03;
+
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    /// Bytes received that don't yet make up a whole packet
+    buffer: Vec<u8>,
+    /// False while the debugger has the console halted
+    running: bool,
+    /// The debugger's breakpoints keyed by the arguments of the Z packet
+    /// that set them (type, address & kind) as that's how z removes them
+    breakpoints: Vec<(String, Vec<BreakpointId>)>,
+}
+
+impl GdbStub {
+    /// Start listening for a debugger, the console runs as normal until one connects
+    pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(GdbStub {
+            listener,
+            client: None,
+            buffer: Vec::new(),
+            running: true,
+            breakpoints: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Returns true unless the debugger has halted the console
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Handle anything sent by the debugger then run a single frame, or
+    /// until a breakpoint is hit, if the console isn't halted
+    pub fn run_frame(&mut self, nes: &mut Nes) -> io::Result<()> {
+        if self.client.is_none() {
+            self.accept()?;
+        }
+        if self.client.is_some() {
+            self.receive(nes)?;
+        }
+
+        if self.client.is_none() {
+            nes.run_frame();
+        } else if self.running {
+            if let StopReason::Breakpoint { access, .. } = nes.debug_step(Step::Frame) {
+                self.running = false;
+                self.send(&stop_reply(SIGTRAP, access))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        match self.listener.accept() {
+            Ok((stream, address)) => {
+                info!("GDB connected from {}", address);
+                stream.set_nonblocking(true)?;
+                stream.set_nodelay(true)?;
+                self.client = Some(stream);
+                self.buffer.clear();
+                // The debugger expects the target to be stopped when it attaches
+                self.running = false;
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remove the debugger's breakpoints and let the console run freely
+    fn disconnect(&mut self, nes: &mut Nes) {
+        info!("GDB disconnected");
+        for (_, ids) in self.breakpoints.drain(..) {
+            for id in ids {
+                nes.debugger_mut().remove_breakpoint(id);
+            }
+        }
+        self.client = None;
+        self.running = true;
+    }
+
+    fn receive(&mut self, nes: &mut Nes) -> io::Result<()> {
+        let mut bytes = [0; 1024];
+        loop {
+            let client = match self.client.as_mut() {
+                Some(client) => client,
+                None => return Ok(()),
+            };
+            match client.read(&mut bytes) {
+                Ok(0) => {
+                    self.disconnect(nes);
+                    return Ok(());
+                }
+                Ok(count) => self.buffer.extend_from_slice(&bytes[..count]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(_) => {
+                    self.disconnect(nes);
+                    return Ok(());
+                }
+            }
+        }
+
+        while let Some(first) = self.buffer.first() {
+            match *first {
+                b'$' => {
+                    let end = match self.buffer.iter().position(|b| *b == b'#') {
+                        Some(end) if self.buffer.len() >= end + 3 => end,
+                        _ => break,
+                    };
+                    let packet = String::from_utf8_lossy(&self.buffer[1..end]).into_owned();
+                    let checksum = String::from_utf8_lossy(&self.buffer[end + 1..end + 3]).into_owned();
+                    self.buffer.drain(..end + 3);
+
+                    if u8::from_str_radix(&checksum, 16).ok() != Some(checksum_of(&packet)) {
+                        self.write(b"-")?;
+                        continue;
+                    }
+                    self.write(b"+")?;
+                    if let Some(reply) = self.handle_packet(nes, &packet) {
+                        self.send(&reply)?;
+                    }
+                    if packet == "D" || packet == "k" {
+                        self.disconnect(nes);
+                        return Ok(());
+                    }
+                }
+                INTERRUPT => {
+                    self.buffer.remove(0);
+                    if self.running {
+                        self.running = false;
+                        self.send(&stop_reply(SIGINT, None))?;
+                    }
+                }
+                // Acks (we never resend so they're ignored) and line noise
+                _ => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Act on a packet, returning the reply if there's one to send now
+    fn handle_packet(&mut self, nes: &mut Nes, packet: &str) -> Option<String> {
+        info!("GDB packet {}", packet);
+        if packet.is_empty() {
+            return Some(String::new());
+        }
+        let (command, arguments) = packet.split_at(1);
+
+        let reply = match command {
+            "?" => Some(stop_reply(SIGTRAP, None)),
+            "g" => Some(encode_hex(&registers_to_bytes(&nes.cpu_registers()))),
+            "G" => decode_hex(arguments).filter(|bytes| bytes.len() == 7).map(|bytes| {
+                nes.set_cpu_registers(registers_from_bytes(&bytes));
+                "OK".to_string()
+            }),
+            "p" => parse_hex(arguments).and_then(|register| {
+                let bytes = registers_to_bytes(&nes.cpu_registers());
+                match register {
+                    0..=3 => Some(encode_hex(&bytes[register as usize..=register as usize])),
+                    4 => Some(encode_hex(&bytes[4..6])),
+                    5 => Some(encode_hex(&bytes[6..7])),
+                    _ => None,
+                }
+            }),
+            "P" => {
+                let mut parts = arguments.splitn(2, '=');
+                match (parts.next().and_then(parse_hex), parts.next().and_then(decode_hex)) {
+                    (Some(register), Some(value)) if register <= 5 && !value.is_empty() => {
+                        let mut bytes = registers_to_bytes(&nes.cpu_registers());
+                        match register {
+                            0..=3 => bytes[register as usize] = value[0],
+                            4 => {
+                                bytes[4] = value[0];
+                                bytes[5] = value.get(1).copied().unwrap_or(0);
+                            }
+                            _ => bytes[6] = value[0],
+                        }
+                        nes.set_cpu_registers(registers_from_bytes(&bytes));
+                        Some(())
+                    }
+                    _ => None,
+                }
+                .map(|_| "OK".to_string())
+            }
+            "m" => parse_range(arguments).map(|(address, length)| {
+                let bytes: Vec<u8> = (0..length)
+                    .map(|i| nes.peek_cpu_memory(address.wrapping_add(i as u16)))
+                    .collect();
+                encode_hex(&bytes)
+            }),
+            "M" => {
+                let mut parts = arguments.splitn(2, ':');
+                match (parts.next().and_then(parse_range), parts.next().and_then(decode_hex)) {
+                    (Some((address, length)), Some(bytes)) if bytes.len() == length as usize => {
+                        for (i, byte) in bytes.iter().enumerate() {
+                            nes.write_cpu_memory(address.wrapping_add(i as u16), *byte);
+                        }
+                        Some("OK".to_string())
+                    }
+                    _ => None,
+                }
+            }
+            "Z" => self.add_breakpoint(nes, arguments),
+            "z" => self.remove_breakpoint(nes, arguments),
+            "c" => {
+                if let Some(address) = parse_hex(arguments) {
+                    set_program_counter(nes, address as u16);
+                }
+                self.running = true;
+                return None;
+            }
+            "s" => {
+                if let Some(address) = parse_hex(arguments) {
+                    set_program_counter(nes, address as u16);
+                }
+                let access = match nes.debug_step(Step::Instruction) {
+                    StopReason::Breakpoint { access, .. } => access,
+                    StopReason::StepComplete => None,
+                };
+                Some(stop_reply(SIGTRAP, access))
+            }
+            "D" | "H" => Some("OK".to_string()),
+            "k" => return None,
+            "q" => Some(
+                match arguments.split(':').next().unwrap_or("") {
+                    "Supported" => "PacketSize=1000",
+                    "Attached" => "1",
+                    "C" => "QC1",
+                    "fThreadInfo" => "m1",
+                    "sThreadInfo" => "l",
+                    _ => "",
+                }
+                .to_string(),
+            ),
+            _ => Some(String::new()),
+        };
+
+        Some(reply.unwrap_or_else(|| "E01".to_string()))
+    }
+
+    fn add_breakpoint(&mut self, nes: &mut Nes, arguments: &str) -> Option<String> {
+        let mut parts = arguments.splitn(2, ',');
+        let (kind, address, length) = match (parts.next(), parts.next().and_then(parse_range)) {
+            (Some(kind), Some((address, length))) => (kind, address, length.max(1)),
+            _ => return None,
+        };
+        let watch = |access| Breakpoint::Watch {
+            space: AddressSpace::Cpu,
+            access,
+            addresses: address..=(u32::from(address) + length - 1) as u16,
+        };
+        let breakpoints = match kind {
+            "0" | "1" => vec![Breakpoint::Execute(address)],
+            "2" => vec![watch(AccessType::Write)],
+            "3" => vec![watch(AccessType::Read)],
+            "4" => vec![watch(AccessType::Read), watch(AccessType::Write)],
+            _ => return Some(String::new()),
+        };
+
+        let ids = breakpoints
+            .into_iter()
+            .map(|breakpoint| nes.debugger_mut().add_breakpoint(breakpoint))
+            .collect();
+        self.breakpoints.push((arguments.to_string(), ids));
+
+        Some("OK".to_string())
+    }
+
+    fn remove_breakpoint(&mut self, nes: &mut Nes, arguments: &str) -> Option<String> {
+        let index = self.breakpoints.iter().position(|(key, _)| key == arguments)?;
+        for id in self.breakpoints.remove(index).1 {
+            nes.debugger_mut().remove_breakpoint(id);
+        }
+
+        Some("OK".to_string())
+    }
+
+    fn send(&mut self, packet: &str) -> io::Result<()> {
+        info!("GDB reply {}", packet);
+        let framed = format!("${}#{:02x}", packet, checksum_of(packet));
+        self.write(framed.as_bytes())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.client.as_mut() {
+            Some(client) => write_all(client, bytes),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `write_all` on a non blocking socket, the replies are small so waiting
+/// for the socket to drain is rare
+fn write_all(client: &mut TcpStream, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        match client.write(bytes) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "GDB connection closed")),
+            Ok(count) => bytes = &bytes[count..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+fn stop_reply(signal: u8, access: Option<MemoryAccess>) -> String {
+    match access {
+        Some(access) => format!(
+            "T{:02x}{}:{:x};",
+            signal,
+            match access.access {
+                AccessType::Read => "rwatch",
+                AccessType::Write => "watch",
+            },
+            access.address
+        ),
+        None => format!("S{:02x}", signal),
+    }
+}
+
+fn set_program_counter(nes: &mut Nes, address: u16) {
+    let mut registers = nes.cpu_registers();
+    registers.program_counter = address;
+    nes.set_cpu_registers(registers);
+}
+
+fn registers_to_bytes(registers: &CpuRegisters) -> [u8; 7] {
+    [
+        registers.a,
+        registers.x,
+        registers.y,
+        registers.stack_pointer,
+        registers.program_counter as u8,
+        (registers.program_counter >> 8) as u8,
+        registers.status,
+    ]
+}
+
+fn registers_from_bytes(bytes: &[u8]) -> CpuRegisters {
+    CpuRegisters {
+        a: bytes[0],
+        x: bytes[1],
+        y: bytes[2],
+        stack_pointer: bytes[3],
+        program_counter: bytes[4] as u16 | (bytes[5] as u16) << 8,
+        status: bytes[6],
+    }
+}
+
+fn checksum_of(packet: &str) -> u8 {
+    packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// Parse `address,length` making sure it's within the 64KB address space,
+/// the length is left as a u32 as the whole space (0x10000) doesn't fit a u16
+fn parse_range(text: &str) -> Option<(u16, u32)> {
+    let mut parts = text.split(',');
+    match (parts.next().and_then(parse_hex), parts.next().and_then(parse_hex)) {
+        (Some(address), Some(length)) if address <= 0xFFFF && length <= 0x10000 - address => {
+            Some((address as u16, length))
+        }
+        _ => None,
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() & 1 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
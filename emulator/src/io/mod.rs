@@ -1,4 +1,5 @@
 use log::debug;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 #[repr(u8)]
 #[derive(Debug)]
@@ -144,3 +145,42 @@ impl Io {
         }
     }
 }
+
+impl SaveState for ControllerState {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.all_data);
+        // Buttons are stored as their bit index with 8 meaning all buttons have been read
+        writer.write_u8(match &self.reading_button {
+            Some(button) => button.bitflag().trailing_zeros() as u8,
+            None => 8,
+        });
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.all_data = reader.read_u8()?;
+        let reading_button_index = reader.read_u8()?;
+        if reading_button_index > 8 {
+            return Err(SaveStateError::new("Invalid controller button index"));
+        }
+        self.reading_button = Some(Button::A);
+        for _ in 0..reading_button_index {
+            self.reading_button = self.reading_button.as_ref().and_then(|b| b.next());
+        }
+        Ok(())
+    }
+}
+
+impl SaveState for Io {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.controller_1_state.save_state(writer);
+        self.controller_2_state.save_state(writer);
+        writer.write_bool(self.strobe_register);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.controller_1_state.load_state(reader)?;
+        self.controller_2_state.load_state(reader)?;
+        self.strobe_register = reader.read_bool()?;
+        Ok(())
+    }
+}
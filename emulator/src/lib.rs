@@ -7,14 +7,18 @@ extern crate zip;
 pub mod apu;
 pub mod cartridge;
 pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod gdb;
 pub mod io;
+mod nes;
 pub mod ppu;
+pub mod region;
+pub mod save_state;
+pub mod trace;
 
-use apu::Apu;
 use cartridge::{CartridgeError, CartridgeHeader, CpuCartridgeAddressBus, PpuCartridgeAddressBus};
-use cpu::Cpu;
-use io::Io;
-use ppu::Ppu;
+pub use nes::Nes;
 use ppu::SCREEN_HEIGHT;
 use ppu::SCREEN_WIDTH;
 
@@ -31,14 +35,11 @@ pub fn get_cartridge(rom_file: &str) -> Result<Cartridge, CartridgeError> {
 
 /// Run a rom for N cycles and return the CRC32 checksum of the framebuffer
 pub fn run_headless_cycles(cartridge: Cartridge, cycles: usize) -> [u8; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize] {
-    let mut apu = Apu::new();
-    let mut io = Io::new();
-    let mut ppu = Ppu::new(cartridge.1);
-    let mut cpu = Cpu::new(cartridge.0, &mut apu, &mut io, &mut ppu);
+    let mut nes = Nes::new(cartridge);
 
     for _ in 0..cycles {
-        cpu.next();
+        nes.step_cycle();
     }
 
-    *cpu.get_framebuffer()
+    *nes.frame_buffer()
 }
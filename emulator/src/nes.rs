@@ -0,0 +1,548 @@
+use apu::Apu;
+use cartridge::{CartridgeError, CartridgeHeader};
+use cpu::interrupts::Interrupt;
+use cpu::{Cpu, RamPattern};
+use debugger::{AddressSpace, Breakpoint, CpuRegisters, Debugger, Step, StopReason};
+use io::{Button, Controller, Io};
+use ppu::palette::Palette;
+use ppu::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use region::Region;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter, SAVE_STATE_MAGIC, SAVE_STATE_VERSION};
+use trace::Tracer;
+use Cartridge;
+
+const JSR_OPCODE: u8 = 0x20;
+const RTI_OPCODE: u8 = 0x40;
+const RTS_OPCODE: u8 = 0x60;
+
+/// An entire NES console with a cartridge inserted.
+///
+/// Unlike wiring up a `Cpu` by hand, the console owns every component (CPU,
+/// PPU, APU, IO and both halves of the cartridge) so it can be stored in a
+/// struct, boxed or moved to another thread.
+pub struct Nes {
+    cpu: Cpu,
+    header: CartridgeHeader,
+    region: Region,
+    debugger: Debugger,
+    tracer: Option<Tracer>,
+    frames: u32,
+    /// The state of every component straight after `new`, restored by `power_cycle`
+    power_on_state: Vec<u8>,
+    ram_pattern: RamPattern,
+}
+
+impl Nes {
+    /// Create a console from an already loaded cartridge, the region is
+    /// taken from the cartridge header (NTSC unless it says otherwise)
+    pub fn new(cartridge: Cartridge) -> Self {
+        let region = Region::from_timing(cartridge.2.timing);
+        Nes::with_region(cartridge, region)
+    }
+
+    /// Create a console for a particular region whatever the cartridge
+    /// header says, most iNES roms don't say which region they're for
+    pub fn with_region(cartridge: Cartridge, region: Region) -> Self {
+        let (prg_address_bus, chr_address_bus, header) = cartridge;
+        let apu = Apu::new(region);
+        let io = Io::new();
+        let ppu = Ppu::new(chr_address_bus, region);
+
+        let cpu = Cpu::new(prg_address_bus, apu, io, ppu, region);
+        let mut power_on_state = StateWriter::new();
+        cpu.save_state(&mut power_on_state);
+
+        Nes {
+            cpu,
+            header,
+            region,
+            debugger: Debugger::default(),
+            tracer: None,
+            frames: 0,
+            power_on_state: power_on_state.into_bytes(),
+            ram_pattern: RamPattern::Zeros,
+        }
+    }
+
+    /// Load a cartridge from a rom file (raw or zipped) and create a console with it inserted
+    pub fn from_file(rom_file: &str) -> Result<Self, CartridgeError> {
+        ::get_cartridge(rom_file).map(Nes::new)
+    }
+
+    /// The header of the inserted cartridge
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    /// The console variant being emulated
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// The number of CPU cycles executed since power on
+    pub fn cpu_cycles(&self) -> u32 {
+        self.cpu.cycles
+    }
+
+    /// Press the reset button, the console restarts from the RESET vector
+    /// with RAM, VRAM & most mapper state intact (c.f. `Cpu::reset`)
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    /// Turn the console off and on again. Everything returns to the state
+    /// it was in when the console was created except that battery backed
+    /// RAM is kept and the internal RAM is filled with the `RamPattern`
+    /// chosen by `set_ram_pattern`.
+    pub fn power_cycle(&mut self) {
+        let battery_ram = self.battery_ram().map(|ram| ram.to_vec());
+
+        self.cpu.power_cycle(&self.power_on_state, self.ram_pattern);
+        if let (Some(data), Some(ram)) = (battery_ram, self.cpu.prg_ram_mut()) {
+            ram.copy_from_slice(&data);
+        }
+        self.frames = 0;
+    }
+
+    /// Choose the contents of the internal RAM after the next `power_cycle`,
+    /// consoles created by `new` always start with `RamPattern::Zeros`
+    pub fn set_ram_pattern(&mut self, pattern: RamPattern) {
+        self.ram_pattern = pattern;
+    }
+
+    /// The number of frames completed since power on
+    pub fn frame_count(&self) -> u32 {
+        self.frames
+    }
+
+    /// Run a single PPU cycle, the CPU & APU are clocked on every third call
+    /// (or 5 in every 16 on PAL)
+    pub fn step_cycle(&mut self) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            if self.cpu.is_instruction_boundary() {
+                let registers = self.cpu.registers();
+                if tracer.is_tracing(registers.program_counter, self.frames) {
+                    tracer.trace(&self.cpu.trace_record(self.frames));
+                }
+            }
+        }
+
+        self.cpu.next();
+
+        if self.cpu.is_frame_complete_cycle() {
+            self.frames = self.frames.wrapping_add(1);
+        }
+    }
+
+    /// Run until the current instruction (or interrupt/DMA sequence) has
+    /// completed and the CPU is about to fetch the next opcode, or for a
+    /// single cycle if the CPU has been jammed by a KIL opcode
+    pub fn step_instruction(&mut self) {
+        loop {
+            self.step_cycle();
+            if self.cpu.is_instruction_boundary() || self.cpu.is_jammed() {
+                break;
+            }
+        }
+    }
+
+    /// Returns true once a KIL opcode has locked up the CPU
+    pub fn is_cpu_jammed(&self) -> bool {
+        self.cpu.is_jammed()
+    }
+
+    /// Set the value the unstable XAA ($8B) opcode ORs with A before ANDing
+    /// with X & the operand, defaults to `cpu::DEFAULT_XAA_MAGIC`
+    pub fn set_xaa_magic(&mut self, magic: u8) {
+        self.cpu.set_xaa_magic(magic);
+    }
+
+    /// Run until the PPU has finished outputting the visible portion of the
+    /// next frame, at which point the framebuffer is complete
+    pub fn run_frame(&mut self) {
+        loop {
+            self.step_cycle();
+            if self.cpu.is_frame_complete_cycle() {
+                break;
+            }
+        }
+    }
+
+    /// Returns true if the last cycle completed the visible portion of a frame
+    pub fn is_frame_complete(&self) -> bool {
+        self.cpu.is_frame_complete_cycle()
+    }
+
+    /// The framebuffer in BGRA byte order (4 bytes per pixel)
+    pub fn frame_buffer(&self) -> &[u8; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize] {
+        self.cpu.get_framebuffer()
+    }
+
+    /// Start (or stop) keeping the 9 bit colour of each pixel as the PPU
+    /// generated it, before the palette turns it into RGB. Off by default.
+    pub fn set_indexed_output(&mut self, enabled: bool) {
+        self.cpu.ppu_mut().set_indexed_output(enabled);
+    }
+
+    /// The colour of each pixel as the palette entry (bits 0-5) and
+    /// PPUMASK emphasis bits (6-8, red, green, blue), the same index as used
+    /// by `Palette::colour`. Only available after `set_indexed_output(true)`,
+    /// pixels not yet drawn since then are 0.
+    pub fn indexed_frame_buffer(&self) -> Option<&[u16; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize]> {
+        self.cpu.ppu().indexed_frame_buffer()
+    }
+
+    /// Which third of a cycle of the NTSC colour subcarrier the current (or
+    /// if in vblank the last drawn) frame started on, 0-2. Used by
+    /// `NtscFilter` to move the dot crawl between frames as a real console does.
+    pub fn frame_phase(&self) -> u8 {
+        self.cpu.ppu().frame_phase()
+    }
+
+    /// Change the colours the framebuffer is drawn with, from the next pixel
+    /// onwards. Defaults to `BuiltinPalette::Ntsc2C02`.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.cpu.ppu_mut().set_palette(palette);
+    }
+
+    /// Set the rate (in Hz) at which audio samples are generated, defaults
+    /// to `apu::DEFAULT_SAMPLE_RATE` (44.1kHz). Pending samples are discarded.
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        assert!(sample_rate > 0, "Audio sample rate must be non zero");
+        self.cpu.set_audio_sample_rate(sample_rate);
+    }
+
+    pub fn audio_sample_rate(&self) -> u32 {
+        self.cpu.audio_sample_rate()
+    }
+
+    /// Move all audio generated since the last drain onto the end of
+    /// `buffer` as mono samples in the range -1.0..=1.0
+    ///
+    /// Intended to be called once per `run_frame`, giving ~735 samples per
+    /// frame at 44.1kHz. At most one second of audio is held if the samples
+    /// are never drained.
+    pub fn drain_audio_samples_f32(&mut self, buffer: &mut Vec<f32>) {
+        buffer.extend_from_slice(self.cpu.audio_samples());
+        self.cpu.clear_audio_samples();
+    }
+
+    /// As `drain_audio_samples_f32` but converted to signed 16 bit samples
+    pub fn drain_audio_samples_i16(&mut self, buffer: &mut Vec<i16>) {
+        buffer.extend(
+            self.cpu
+                .audio_samples()
+                .iter()
+                .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+        );
+        self.cpu.clear_audio_samples();
+    }
+
+    pub fn button_down(&mut self, controller: Controller, button: Button) {
+        self.cpu.button_down(controller, button);
+    }
+
+    pub fn button_up(&mut self, controller: Controller, button: Button) {
+        self.cpu.button_up(controller, button);
+    }
+
+    /// The contents of the cartridges PRG RAM if it's battery backed, this
+    /// is what a frontend should write to a .sav file so that progress is
+    /// kept between runs
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        if self.header.ram_is_battery_backed {
+            self.cpu.prg_ram()
+        } else {
+            None
+        }
+    }
+
+    /// Restore battery backed PRG RAM previously exported with `battery_ram`
+    ///
+    /// Should be called before the first frame is run. Fails without
+    /// modifying the RAM if the cartridge has no battery or the data is the
+    /// wrong size.
+    pub fn load_battery_ram(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let mapper = Some(self.header.mapper);
+        if !self.header.ram_is_battery_backed {
+            return Err(CartridgeError {
+                message: "Cartridge has no battery backed RAM".to_string(),
+                mapper,
+            });
+        }
+
+        match self.cpu.prg_ram_mut() {
+            Some(ram) if ram.len() == data.len() => {
+                ram.copy_from_slice(data);
+                Ok(())
+            }
+            Some(ram) => Err(CartridgeError {
+                message: format!("Battery RAM is {} bytes, expected {}", data.len(), ram.len()),
+                mapper,
+            }),
+            None => Err(CartridgeError {
+                message: "Cartridge has no PRG RAM".to_string(),
+                mapper,
+            }),
+        }
+    }
+
+    /// Snapshot the complete machine state (CPU, PPU, APU, IO & cartridge)
+    ///
+    /// The state can be taken at any cycle, not just on frame or instruction
+    /// boundaries, and is only valid for loading into a console running the
+    /// same rom.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(SAVE_STATE_MAGIC);
+        writer.write_u16(SAVE_STATE_VERSION);
+        writer.write_u16(self.header.mapper);
+        writer.write_u8(self.header.submapper);
+        writer.write_u16(self.header.prg_rom_16kb_units);
+        writer.write_u16(self.header.chr_rom_8kb_units);
+        writer.write_u8(self.region as u8);
+        self.cpu.save_state(&mut writer);
+
+        writer.into_bytes()
+    }
+
+    /// Restore a snapshot taken with `save_state`
+    ///
+    /// If the state is invalid the console is left exactly as it was before
+    /// the call.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(state);
+
+        let mut magic = [0; 4];
+        reader.read_bytes(&mut magic)?;
+        if &magic != SAVE_STATE_MAGIC {
+            return Err(SaveStateError::new("Not a save state"));
+        }
+        let version = reader.read_u16()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError {
+                message: format!(
+                    "Unsupported save state version {}, expected {}",
+                    version, SAVE_STATE_VERSION
+                ),
+            });
+        }
+        if reader.read_u16()? != self.header.mapper
+            || reader.read_u8()? != self.header.submapper
+            || reader.read_u16()? != self.header.prg_rom_16kb_units
+            || reader.read_u16()? != self.header.chr_rom_8kb_units
+        {
+            return Err(SaveStateError::new("Save state was taken from a different cartridge"));
+        }
+        if reader.read_u8()? != self.region as u8 {
+            return Err(SaveStateError::new(
+                "Save state was taken from a console of a different region",
+            ));
+        }
+
+        // Loading may fail part way through, so keep a copy of the current
+        // state to roll back to
+        let mut backup = StateWriter::new();
+        self.cpu.save_state(&mut backup);
+        let backup = backup.into_bytes();
+        let result = self.cpu.load_state(&mut reader).and_then(|_| {
+            if reader.is_empty() {
+                Ok(())
+            } else {
+                Err(SaveStateError::new("Unexpected trailing data in save state"))
+            }
+        });
+
+        if result.is_err() {
+            let mut backup_reader = StateReader::new(&backup);
+            self.cpu
+                .load_state(&mut backup_reader)
+                .expect("Failed to restore state after failed load");
+        }
+
+        result
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    /// Add & remove breakpoints, these only have an effect on `debug_step`
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Start passing each instruction to the tracer before it's executed,
+    /// replacing any existing tracer. Tracing works with both normal running
+    /// and `debug_step`.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Stop tracing, giving back the tracer so that e.g. a `TraceWriter` is
+    /// flushed when it's dropped
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Run until the step completes or a breakpoint is hit
+    ///
+    /// At least one cycle is always run so that continuing from a breakpoint
+    /// doesn't immediately stop on it again. Execution, NMI & IRQ
+    /// breakpoints stop on an instruction boundary, watchpoints & dot
+    /// breakpoints stop part way through the instruction.
+    pub fn debug_step(&mut self, step: Step) -> StopReason {
+        self.cpu.set_access_logging(
+            self.debugger.is_watching(AddressSpace::Cpu),
+            self.debugger.is_watching(AddressSpace::Ppu),
+        );
+        self.cpu.drain_accesses().for_each(drop);
+
+        let start = self.cpu.registers();
+        let start_scanline = self.cpu.ppu().current_scanline();
+        let return_address = match self.cpu.peek_byte(start.program_counter) {
+            JSR_OPCODE if step == Step::Over => Some(start.program_counter.wrapping_add(3)),
+            _ => None,
+        };
+        let mut last_opcode = self.cpu.peek_byte(start.program_counter);
+        let mut entered_interrupt = None;
+
+        loop {
+            self.step_cycle();
+
+            if let Some(reason) = self.check_watchpoints() {
+                return reason;
+            }
+
+            let (scanline, dot) = self.ppu_position();
+            if let Some(id) = self.debugger.find(|b| *b == Breakpoint::Dot { scanline, dot }) {
+                return StopReason::Breakpoint { id, access: None };
+            }
+
+            match step {
+                Step::Scanline if scanline != start_scanline => return StopReason::StepComplete,
+                Step::Frame if self.is_frame_complete() => return StopReason::StepComplete,
+                _ => (),
+            }
+
+            if let Some(interrupt) = self.cpu.entering_interrupt() {
+                entered_interrupt = Some(interrupt);
+            }
+            // A jammed CPU never reaches the next instruction
+            if self.cpu.is_jammed() && step != Step::Frame && step != Step::Scanline {
+                return StopReason::StepComplete;
+            }
+            if !self.cpu.is_instruction_boundary() {
+                continue;
+            }
+
+            let registers = self.cpu.registers();
+            let interrupt_breakpoint = match entered_interrupt.take() {
+                Some(Interrupt::NMI(_)) => self.debugger.find(|b| *b == Breakpoint::Nmi),
+                Some(_) => self.debugger.find(|b| *b == Breakpoint::Irq),
+                None => None,
+            };
+            let breakpoint = interrupt_breakpoint.or_else(|| {
+                self.debugger
+                    .find(|b| *b == Breakpoint::Execute(registers.program_counter))
+            });
+            if let Some(id) = breakpoint {
+                return StopReason::Breakpoint { id, access: None };
+            }
+
+            let complete = match step {
+                Step::Instruction => true,
+                Step::Over => match return_address {
+                    Some(address) => {
+                        registers.program_counter == address && registers.stack_pointer == start.stack_pointer
+                    }
+                    None => true,
+                },
+                Step::Out => {
+                    (last_opcode == RTS_OPCODE || last_opcode == RTI_OPCODE)
+                        && registers.stack_pointer > start.stack_pointer
+                }
+                Step::Scanline | Step::Frame => false,
+            };
+            if complete {
+                return StopReason::StepComplete;
+            }
+
+            last_opcode = self.cpu.peek_byte(registers.program_counter);
+        }
+    }
+
+    fn check_watchpoints(&mut self) -> Option<StopReason> {
+        let debugger = &self.debugger;
+        // Accesses after the first hit are dropped so they can't trigger on the next step
+        let hit = self.cpu.drain_accesses().fold(None, |hit, access| {
+            hit.or_else(|| debugger.find_watchpoint(&access).map(|id| (id, access)))
+        });
+
+        hit.map(|(id, access)| StopReason::Breakpoint {
+            id,
+            access: Some(access),
+        })
+    }
+
+    pub fn cpu_registers(&self) -> CpuRegisters {
+        self.cpu.registers()
+    }
+
+    /// Overwrite the CPU registers, changes to the program counter should
+    /// only be made on an instruction boundary (e.g. after `step_instruction`)
+    pub fn set_cpu_registers(&mut self, registers: CpuRegisters) {
+        self.cpu.set_registers(registers);
+    }
+
+    /// Read a byte of CPU address space without any side effects, the
+    /// memory mapped registers (0x2000-0x401F) always read as FF
+    pub fn peek_cpu_memory(&self, address: u16) -> u8 {
+        self.cpu.peek_byte(address)
+    }
+
+    /// Write a byte to CPU address space as if the CPU had written it, so
+    /// writes to registers (PPU, APU & mapper) have their usual effect
+    pub fn write_cpu_memory(&mut self, address: u16, value: u8) {
+        self.cpu.poke_byte(address, value);
+    }
+
+    /// Read a byte of PPU address space, note that mappers which watch PPU
+    /// reads (e.g. the MMC2 latches) see this as a normal read
+    pub fn read_ppu_memory(&mut self, address: u16) -> u8 {
+        self.cpu.ppu_mut().read_memory(address)
+    }
+
+    pub fn write_ppu_memory(&mut self, address: u16, value: u8) {
+        self.cpu.ppu_mut().write_memory(address, value);
+    }
+
+    pub fn oam(&self) -> &[u8; 0x100] {
+        self.cpu.ppu().oam()
+    }
+
+    /// The scanline & dot the PPU will render next
+    pub fn ppu_position(&self) -> (u16, u16) {
+        (
+            self.cpu.ppu().current_scanline(),
+            self.cpu.ppu().current_scanline_cycle(),
+        )
+    }
+
+    /// Debug function to dump the VRAM & OAM RAM contents
+    pub fn dump_ppu_state(&mut self, vram_copy: &mut [u8; 0x4000]) -> &[u8; 0x100] {
+        self.cpu.dump_ppu_state(vram_copy)
+    }
+}
+
+#[cfg(test)]
+mod nes_tests {
+    use super::*;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn test_nes_is_send() {
+        assert_send::<Nes>();
+    }
+}
@@ -1,15 +1,21 @@
-mod palette;
+pub mod ntsc;
+mod open_bus;
+pub mod palette;
 mod registers;
 mod sprites;
 
 use cartridge::PpuCartridgeAddressBus;
-use cpu::interrupts::Interrupt;
+use cpu::interrupts::{load_interrupt, save_interrupt, Interrupt};
+use debugger::{AccessLog, AccessType, AddressSpace};
 use log::{debug, info};
-use ppu::palette::PaletteRam;
+use ppu::open_bus::OpenBus;
+use ppu::palette::{Palette, PaletteRam};
 use ppu::registers::ppuctrl::{IncrementMode, PpuCtrl};
 use ppu::registers::ppumask::PpuMask;
 use ppu::registers::ppustatus::PpuStatus;
 use ppu::sprites::SpriteData;
+use region::Region;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 pub(crate) const SCREEN_WIDTH: u32 = 256;
 pub(crate) const SCREEN_HEIGHT: u32 = 240;
@@ -35,12 +41,12 @@ struct ScanlineState {
 }
 
 impl ScanlineState {
-    fn next_cycle(&mut self) {
+    fn next_cycle(&mut self, scanlines_per_frame: u16) {
         self.dot += 1;
         if self.dot == 341 {
             self.dot = 0;
             self.scanline += 1;
-            if self.scanline == 262 {
+            if self.scanline == scanlines_per_frame {
                 self.scanline = 0;
             }
         }
@@ -153,6 +159,7 @@ impl InternalRegisters {
 
 pub struct Ppu {
     pub(crate) total_cycles: PpuCycle,
+    region: Region,
     scanline_state: ScanlineState,
     sprite_data: SpriteData,
     palette_ram: PaletteRam,
@@ -161,19 +168,38 @@ pub struct Ppu {
     ppu_status: PpuStatus,
     last_ppu_status_read_cycle: PpuCycle,
     internal_registers: InternalRegisters,
-    ppu_data_buffer: u8,   // Internal buffer returned on PPUDATA reads
-    last_written_byte: u8, // Stores the value last written onto the latch - TODO implement decay over time
-    is_short_frame: bool,  // Every other frame the pre-render scanline takes one fewer cycle
+    ppu_data_buffer: u8, // Internal buffer returned on PPUDATA reads
+    /// The buffer isn't refilled until a few dots after a PPUDATA read, a
+    /// second read on the next CPU cycle still returns the value from before
+    /// the first read
+    last_ppu_data_read_cycle: PpuCycle,
+    stale_ppu_data_buffer: u8,
+    open_bus: OpenBus,
+    is_short_frame: bool, // Every other frame the pre-render scanline takes one fewer cycle
+    /// Which third of a cycle of the NTSC colour subcarrier the current dot,
+    /// and the first dot of the current frame, started on
+    colour_phase: u8,
+    frame_phase: u8,
+    /// Set by a reset until the pre-render scanline, during which writes to
+    /// PPUCTRL, PPUMASK, PPUSCROLL & PPUADDR are ignored
+    reset_write_protect: bool,
     nmi_interrupt: Option<Interrupt>,
-    pub(crate) frame_buffer: [u8; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize],
-    priorities: [u8; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize],
+    /// The output colour for each combination of emphasis bits & palette entry
+    palette: Palette,
+    pub(crate) frame_buffer: Box<[u8; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize]>,
+    priorities: Box<[u8; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize]>,
+    /// The palette index & emphasis bits (as used to index `Palette`) of
+    /// each pixel, only kept when asked for
+    indexed_frame_buffer: Option<Box<[u16; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize]>>,
     pub(crate) chr_address_bus: Box<dyn PpuCartridgeAddressBus>,
+    pub(crate) accesses: AccessLog,
 }
 
 impl Ppu {
-    pub fn new(chr_address_bus: Box<dyn PpuCartridgeAddressBus>) -> Self {
+    pub fn new(chr_address_bus: Box<dyn PpuCartridgeAddressBus>, region: Region) -> Self {
         Ppu {
             total_cycles: 27,
+            region,
             scanline_state: ScanlineState {
                 scanline: 0,
                 nametable_byte: 0,
@@ -201,13 +227,21 @@ impl Ppu {
                 write_toggle: false,
                 next_address: 0,
             },
-            last_written_byte: 0x0,
+            open_bus: OpenBus::new(),
             ppu_data_buffer: 0x0,
+            last_ppu_data_read_cycle: 0,
+            stale_ppu_data_buffer: 0x0,
             is_short_frame: false,
+            colour_phase: 0,
+            frame_phase: 0,
+            reset_write_protect: false,
             nmi_interrupt: None,
-            frame_buffer: [0; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize],
-            priorities: [0; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize],
+            palette: Palette::default(),
+            frame_buffer: Box::new([0; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize]),
+            priorities: Box::new([0; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize]),
+            indexed_frame_buffer: None,
             chr_address_bus,
+            accesses: AccessLog::default(),
         }
     }
 
@@ -215,6 +249,28 @@ impl Ppu {
         self.chr_address_bus.check_trigger_irq(clear)
     }
 
+    /// CPU reads from cartridge space which the PPU side of the cartridge
+    /// wants to handle (or just see), c.f. `PpuCartridgeAddressBus::cpu_read_byte`
+    pub(crate) fn cpu_read_cartridge(&mut self, address: u16) -> Option<u8> {
+        self.chr_address_bus.cpu_read_byte(address, self.total_cycles)
+    }
+
+    /// The reset line clears PPUCTRL, PPUMASK, the scroll & the read buffer
+    /// (OAM, palette RAM, VRAM, PPUSTATUS & PPUADDR are untouched) and holds
+    /// off writes to the control registers until the end of vblank
+    pub(crate) fn reset(&mut self) {
+        self.ppu_ctrl.write_byte(0);
+        self.ppu_mask.write_byte(0);
+        self.internal_registers.temp_vram_addr = 0;
+        self.internal_registers.fine_x_scroll = 0;
+        self.internal_registers.write_toggle = false;
+        self.ppu_data_buffer = 0;
+        self.stale_ppu_data_buffer = 0;
+        self.is_short_frame = false;
+        self.nmi_interrupt = None;
+        self.reset_write_protect = true;
+    }
+
     pub(crate) fn dump_state(&mut self, vram_copy: &mut [u8; 0x4000]) -> &[u8; 0x100] {
         for i in 0..=0x3FFF {
             vram_copy[i] = self.read_byte(i as u16);
@@ -223,6 +279,35 @@ impl Ppu {
         &self.sprite_data.oam_ram
     }
 
+    /// Read PPU address space as PPUDATA would (but without the read buffer)
+    pub(crate) fn read_memory(&mut self, address: u16) -> u8 {
+        self.read_byte(address & 0x3FFF)
+    }
+
+    pub(crate) fn write_memory(&mut self, address: u16, value: u8) {
+        self.write_byte(address & 0x3FFF, value);
+    }
+
+    pub(crate) fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub(crate) fn set_indexed_output(&mut self, enabled: bool) {
+        self.indexed_frame_buffer = if enabled {
+            Some(Box::new([0; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize]))
+        } else {
+            None
+        };
+    }
+
+    pub(crate) fn indexed_frame_buffer(&self) -> Option<&[u16; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize]> {
+        self.indexed_frame_buffer.as_deref()
+    }
+
+    pub(crate) fn oam(&self) -> &[u8; 0x100] {
+        &self.sprite_data.oam_ram
+    }
+
     pub(crate) fn check_ppu_nmi(&mut self, clear: bool) -> Option<Interrupt> {
         if let Some(Interrupt::NMI(cycles)) = self.nmi_interrupt {
             // Due to us checking for interrupts _after_ the last operation we might catch an interrupt
@@ -240,6 +325,10 @@ impl Ppu {
         None
     }
 
+    pub(crate) fn frame_phase(&self) -> u8 {
+        self.frame_phase
+    }
+
     pub(crate) fn current_scanline(&self) -> u16 {
         self.scanline_state.scanline
     }
@@ -260,14 +349,20 @@ impl Ppu {
         debug_assert!(address >= 0x2000 && address <= 0x2007);
         debug!("PPU register write {:04X}={:02X}", address, value);
 
-        self.last_written_byte = value;
+        self.open_bus.refresh(value, 0xFF);
+        if self.reset_write_protect && matches!(address, 0x2000 | 0x2001 | 0x2005 | 0x2006) {
+            info!("PPU register write {:04X} ignored after reset", address);
+            return;
+        }
+        self.chr_address_bus.ppu_register_write(address, value);
 
         match address {
             0x2000 => {
                 // PPUCTRL - Setting NMI enable during vblank from low to high will immediately cause an NMI
                 if !self.ppu_ctrl.nmi_enable && value & 0b1000_0000 != 0 && self.ppu_status.vblank_started {
                     // Doesn't affect if vblank about to be turned off
-                    if self.scanline_state.scanline != 261 || self.scanline_state.dot != 1 {
+                    if self.scanline_state.scanline != self.region.pre_render_scanline() || self.scanline_state.dot != 1
+                    {
                         self.nmi_interrupt = Some(Interrupt::NMI(self.total_cycles));
                         info!("Triggering NMI");
                     }
@@ -285,10 +380,18 @@ impl Ppu {
                 self.internal_registers.temp_vram_addr =
                     (self.internal_registers.temp_vram_addr & 0xF3FF) | ((value & 0b11) as u16) << 10;
             }
-            0x2001 => self.ppu_mask.write_byte(value),        // PPUMASK
-            0x2002 => (),                                     // PPUSTATUS
-            0x2003 => self.sprite_data.write_oam_addr(value), // OAMADDR
-            0x2004 => self.sprite_data.write_oam_data(value), // OAMDATA
+            0x2001 => {
+                // PPUMASK - Bits 5 & 6 (red & green emphasis) are the other way round on PAL
+                let value = if self.region.swaps_red_green_emphasis() {
+                    (value & 0b1001_1111) | (value & 0b0010_0000) << 1 | (value & 0b0100_0000) >> 1
+                } else {
+                    value
+                };
+                self.ppu_mask.write_byte(value);
+            }
+            0x2002 => (),                                                          // PPUSTATUS
+            0x2003 => self.sprite_data.write_oam_addr(value),                      // OAMADDR
+            0x2004 => self.sprite_data.write_oam_data(value, self.is_rendering()), // OAMDATA
             0x2005 => {
                 // PPUSCROLL
                 match self.internal_registers.write_toggle {
@@ -325,9 +428,11 @@ impl Ppu {
             }
             0x2007 => {
                 // PPUDATA
-                self.write_byte(self.internal_registers.vram_addr, value);
-                self.internal_registers
-                    .increment_vram_addr(&self.ppu_ctrl.increment_mode);
+                let address = self.internal_registers.vram_addr & 0x3FFF;
+                self.accesses
+                    .record(AddressSpace::Ppu, AccessType::Write, address, value);
+                self.write_byte(address, value);
+                self.increment_vram_addr_after_access();
             }
             _ => panic!("Write to {:04X} not valid for PPU ({:02X})", address, value),
         }
@@ -340,8 +445,7 @@ impl Ppu {
         //debug!("PPU register read {:04X}", address);
 
         match address {
-            0x2000 => self.last_written_byte,
-            0x2001 => self.last_written_byte,
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => self.open_bus.value(),
             // PPUSTATUS
             0x2002 => {
                 debug!(
@@ -361,32 +465,73 @@ impl Ppu {
                 }
                 self.internal_registers.write_toggle = false;
                 self.last_ppu_status_read_cycle = self.total_cycles;
-                self.ppu_status.read(self.last_written_byte)
+                // Only the top 3 bits are driven, the rest come from the open bus
+                let status = self.ppu_status.read();
+                self.open_bus.read(status, 0b1110_0000)
+            }
+            0x2004 => {
+                let value = self
+                    .sprite_data
+                    .read_oam_data(self.scanline_state.dot, self.is_rendering());
+                self.open_bus.read(value, 0xFF)
             }
-            0x2003 => self.last_written_byte,
-            0x2004 => self
-                .sprite_data
-                .read_oam_data(self.scanline_state.dot, self.ppu_mask.is_rendering_enabled()),
-            0x2005 => self.last_written_byte,
-            0x2006 => self.last_written_byte,
             0x2007 => {
-                let mut value = self.ppu_data_buffer;
-                self.ppu_data_buffer = match self.internal_registers.vram_addr {
-                    0x0000..=0x3EFF => self.read_byte(self.internal_registers.vram_addr),
+                // PPUDATA - Returns the byte fetched by the previous read and fills the buffer with the byte
+                // at the current address, palette reads bypass the buffer and fill it with the nametable
+                // byte "underneath" instead
+                let address = self.internal_registers.vram_addr & 0x3FFF;
+                // Within a CPU cycle (3 dots, or 4 on PAL) of the last read
+                let buffered = if self.total_cycles.wrapping_sub(self.last_ppu_data_read_cycle) <= 4 {
+                    self.stale_ppu_data_buffer
+                } else {
+                    self.ppu_data_buffer
+                };
+                self.stale_ppu_data_buffer = buffered;
+                self.last_ppu_data_read_cycle = self.total_cycles;
+                let (read, value) = match address {
+                    0x0000..=0x3EFF => {
+                        self.ppu_data_buffer = self.read_byte(address);
+                        (self.ppu_data_buffer, self.open_bus.read(buffered, 0xFF))
+                    }
                     0x3F00..=0x3FFF => {
-                        value = self.palette_ram.read_byte(self.internal_registers.vram_addr);
-                        self.read_byte(self.internal_registers.vram_addr - 0x1000)
+                        self.ppu_data_buffer = self.read_byte(address & 0x2FFF);
+                        let mut palette_value = self.palette_ram.read_byte(address);
+                        if self.ppu_mask.is_grayscale {
+                            palette_value &= 0x30;
+                        }
+                        // Palette RAM is only 6 bits wide, the top 2 bits are open bus
+                        (palette_value, self.open_bus.read(palette_value, 0b0011_1111))
                     }
-                    _ => panic!("Invalid address for PPU {:04X}", self.internal_registers.vram_addr),
+                    _ => panic!("Invalid address for PPU {:04X}", address),
                 };
-                self.internal_registers
-                    .increment_vram_addr(&self.ppu_ctrl.increment_mode);
+                self.accesses.record(AddressSpace::Ppu, AccessType::Read, address, read);
+                self.increment_vram_addr_after_access();
                 value
             }
             _ => panic!("Read from {:04X} not valid for PPU", address),
         }
     }
 
+    /// Whether the PPU is currently using VRAM & OAM to draw, i.e. rendering
+    /// is enabled and this is a visible or the pre-render scanline
+    fn is_rendering(&self) -> bool {
+        let scanline = self.scanline_state.scanline;
+        self.ppu_mask.is_rendering_enabled() && (scanline <= 239 || scanline == self.region.pre_render_scanline())
+    }
+
+    /// Move on the VRAM address after a PPUDATA access. While rendering the
+    /// PPU is using the same register to fetch tiles, so the access instead
+    /// bumps both the coarse X & Y scroll as if it were the end of a tile & line
+    fn increment_vram_addr_after_access(&mut self) {
+        if self.is_rendering() {
+            self.internal_registers.increment_effective_scroll_x();
+            self.internal_registers.increment_effective_scroll_y();
+        } else {
+            self.internal_registers
+                .increment_vram_addr(&self.ppu_ctrl.increment_mode);
+        }
+    }
+
     /// Reads from the PPU address space
     fn read_byte(&mut self, address: u16) -> u8 {
         debug_assert!(
@@ -435,7 +580,11 @@ impl Ppu {
             // nametable byte here instead.
             // Note that this is "not short frame" because that's already been reset by this point
             // Otherwise cycle 0 is always a blank cycle with no fetches
-            if !self.is_short_frame && self.ppu_mask.is_rendering_enabled() {
+            if self.scanline_state.scanline == 0
+                && self.region.has_short_frames()
+                && !self.is_short_frame
+                && self.ppu_mask.is_rendering_enabled()
+            {
                 self.scanline_state.nametable_byte =
                     self.read_byte(0x2000 | (self.internal_registers.vram_addr & 0x0FFF));
             }
@@ -484,6 +633,12 @@ impl Ppu {
                 }
             }
             3 => {
+                // The last two fetches of the line (337 & 339) are both unused nametable fetches, MMC5 relies
+                // on seeing the same nametable address three times in a row to detect the start of a scanline
+                if cycle == 339 {
+                    return;
+                }
+
                 self.internal_registers.next_address = 0x23C0
                     | (self.internal_registers.vram_addr & 0x0C00)
                     | ((self.internal_registers.vram_addr >> 4) & 0x38)
@@ -493,7 +648,7 @@ impl Ppu {
                 if cycle <= 256 || (cycle >= 321 && cycle <= 336) {
                     self.scanline_state.attribute_table_byte = self.read_byte(self.internal_registers.next_address);
                 } else {
-                    self.read_byte(self.internal_registers.next_address); // Garbage attribute byte during sprite read & end of line fetches
+                    self.read_byte(self.internal_registers.next_address); // Garbage attribute byte during sprite read, garbage nametable byte at dot 340
                 }
             }
             5 => {
@@ -528,7 +683,7 @@ impl Ppu {
         let y = scanline as u32;
         let offset = ((SCREEN_WIDTH * y + x) * 4) as usize;
 
-        let color = if self.ppu_mask.is_rendering_enabled() {
+        let palette_address = if self.ppu_mask.is_rendering_enabled() {
             // Get background pixel
             let bg_pixel = match (
                 self.ppu_mask.show_background,
@@ -575,16 +730,26 @@ impl Ppu {
                 (_, _, false) => bg_pixel,
             };
 
-            // Read the palette value for the current pixel
-            let palette_index = self.read_byte(0x3F00 | multiplexed_pixel as u16) & 0x3F;
-
-            palette::PALETTE_2C02[palette_index as usize]
+            0x3F00 | multiplexed_pixel as u16
         } else if self.internal_registers.vram_addr & 0x3F00 == 0x3F00 {
-            palette::PALETTE_2C02[self.internal_registers.vram_addr as usize & 0x1F]
+            // With rendering off the backdrop colour comes from wherever VRAM address points in palette RAM
+            self.internal_registers.vram_addr
         } else {
-            0x0
+            0x3F00
         };
 
+        // Greyscale limits the palette to the grey column, emphasis picks one of the darkened copies of the palette
+        let mut palette_index = self.read_byte(palette_address) & 0x3F;
+        if self.ppu_mask.is_grayscale {
+            palette_index &= 0x30;
+        }
+        let emphasis = self.ppu_mask.value() >> 5;
+        let index = (emphasis as u16) << 6 | palette_index as u16;
+        let color = self.palette.colour(index);
+        if let Some(indexed_frame_buffer) = self.indexed_frame_buffer.as_mut() {
+            indexed_frame_buffer[(SCREEN_WIDTH * y + x) as usize] = index;
+        }
+
         self.frame_buffer[offset] = (color & 0xFF) as u8; // Blue channel
         self.frame_buffer[offset + 1] = ((color >> 8) & 0xFF) as u8; // Green channel
         self.frame_buffer[offset + 2] = (color >> 16) as u8; // Red channel
@@ -600,6 +765,7 @@ impl Ppu {
             self.sprite_data.clear_sprites();
         } else if cycle == 1 {
             self.ppu_status.vblank_started = false;
+            self.reset_write_protect = false;
         } else if (cycle >= 280) && (cycle <= 304) && self.ppu_mask.is_rendering_enabled() {
             // Repeatedly copy vertical bits from temp addr to real addr to reinitialise pre-render
             self.internal_registers.vram_addr = (self.internal_registers.temp_vram_addr & 0b1111_1011_1110_0000)
@@ -613,18 +779,23 @@ impl Iterator for Ppu {
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut trigger_cycle_skip = false;
+        let pre_render_scanline = self.region.pre_render_scanline();
 
         if self.scanline_state.scanline == 0 && self.scanline_state.dot == 0 {
-            self.is_short_frame = !self.is_short_frame;
+            self.frame_phase = self.colour_phase;
+            self.open_bus.end_frame();
+            if self.region.has_short_frames() {
+                self.is_short_frame = !self.is_short_frame;
+            }
         }
 
         match self.scanline_state.scanline {
-            0..=239 | 261 => {
+            s if s <= 239 || s == pre_render_scanline => {
                 if self.ppu_mask.is_rendering_enabled() {
                     // Background registers shift on dots 2-256 322-337 inclusive EXCEPT on pre-render where they only shift during 322-337
                     if (self.scanline_state.dot >= 2
                         && self.scanline_state.dot <= 256
-                        && self.scanline_state.scanline != 261)
+                        && self.scanline_state.scanline != pre_render_scanline)
                         || (self.scanline_state.dot >= 322 && self.scanline_state.dot <= 337)
                     {
                         self.scanline_state.shift_bg_registers();
@@ -633,29 +804,35 @@ impl Iterator for Ppu {
                     self.fetch_data(self.scanline_state.dot);
 
                     self.process_sprite_cycle(
+                        self.scanline_state.scanline == pre_render_scanline,
                         self.scanline_state.scanline,
                         self.scanline_state.dot,
                         self.ppu_ctrl.sprite_size.pixels(),
                         self.ppu_ctrl.sprite_tile_table_select,
                     );
 
-                    if self.scanline_state.scanline == 261 && self.scanline_state.dot == 339 && self.is_short_frame {
+                    if self.scanline_state.scanline == pre_render_scanline
+                        && self.scanline_state.dot == 339
+                        && self.is_short_frame
+                    {
                         trigger_cycle_skip = true;
                     }
                 }
 
-                if self.scanline_state.scanline != 261 && self.scanline_state.dot >= 1 && self.scanline_state.dot <= 256
+                if self.scanline_state.scanline != pre_render_scanline
+                    && self.scanline_state.dot >= 1
+                    && self.scanline_state.dot <= 256
                 {
                     self.draw_pixel(self.scanline_state.scanline, self.scanline_state.dot);
                 }
 
-                if self.scanline_state.scanline == 261 {
+                if self.scanline_state.scanline == pre_render_scanline {
                     self.handle_prerender_scanline_cycle(self.scanline_state.dot);
                 }
             }
-            240..=260 => {
-                // PPU in idle state during scanline 240 and during VBlank except for triggering NMI
-                if self.scanline_state.dot == 1 && self.scanline_state.scanline == 241 {
+            s if s < pre_render_scanline => {
+                // PPU in idle state after the visible lines and during VBlank except for triggering NMI
+                if self.scanline_state.dot == 1 && self.scanline_state.scanline == self.region.vblank_scanline() {
                     info!("Vblank set cycle {}", self.total_cycles);
                     if self.last_ppu_status_read_cycle != self.total_cycles {
                         self.ppu_status.vblank_started = true;
@@ -673,10 +850,13 @@ impl Iterator for Ppu {
             _ => panic!("Invalid scanline {:}", self.scanline_state.scanline),
         };
 
-        self.scanline_state.next_cycle();
+        let scanlines_per_frame = self.region.scanlines_per_frame();
+        self.scanline_state.next_cycle(scanlines_per_frame);
         if trigger_cycle_skip && self.ppu_mask.is_rendering_enabled() {
-            self.scanline_state.next_cycle()
+            self.scanline_state.next_cycle(scanlines_per_frame)
         }
+        // Each dot lasts 8 of the 12 phases of the subcarrier, a skipped dot takes no time
+        self.colour_phase = (self.colour_phase + 2) % 3;
 
         // Track total PPU cycles for components which need to know. Bit sketchy here that it wraps
         self.total_cycles = self.total_cycles.wrapping_add(1);
@@ -685,15 +865,122 @@ impl Iterator for Ppu {
     }
 }
 
+impl SaveState for Ppu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.total_cycles);
+
+        let scanline_state = &self.scanline_state;
+        writer.write_u8(scanline_state.nametable_byte);
+        writer.write_u8(scanline_state.attribute_table_byte);
+        writer.write_u8(scanline_state.bg_low_byte);
+        writer.write_u8(scanline_state.bg_high_byte);
+        writer.write_u16(scanline_state.scanline);
+        writer.write_u16(scanline_state.dot);
+        writer.write_u16(scanline_state.bg_shift_register_high);
+        writer.write_u16(scanline_state.bg_shift_register_low);
+        writer.write_u8(scanline_state.at_shift_register_high);
+        writer.write_u8(scanline_state.at_shift_register_low);
+        writer.write_u8(scanline_state.at_shift_latch_high);
+        writer.write_u8(scanline_state.at_shift_latch_low);
+
+        self.sprite_data.save_state(writer);
+        writer.write_bytes(&self.palette_ram.data);
+        writer.write_u8(self.ppu_ctrl.value());
+        writer.write_u8(self.ppu_mask.value());
+        writer.write_bool(self.ppu_status.sprite_overflow);
+        writer.write_bool(self.ppu_status.sprite_zero_hit);
+        writer.write_bool(self.ppu_status.vblank_started);
+        writer.write_u32(self.last_ppu_status_read_cycle);
+
+        let internal_registers = &self.internal_registers;
+        writer.write_u16(internal_registers.vram_addr);
+        writer.write_u16(internal_registers.temp_vram_addr);
+        writer.write_u8(internal_registers.fine_x_scroll);
+        writer.write_bool(internal_registers.write_toggle);
+        writer.write_u16(internal_registers.next_address);
+
+        writer.write_u8(self.ppu_data_buffer);
+        writer.write_u32(self.last_ppu_data_read_cycle);
+        writer.write_u8(self.stale_ppu_data_buffer);
+        self.open_bus.save_state(writer);
+        writer.write_bool(self.is_short_frame);
+        writer.write_u8(self.colour_phase);
+        writer.write_u8(self.frame_phase);
+        writer.write_bool(self.reset_write_protect);
+        save_interrupt(self.nmi_interrupt, writer);
+        writer.write_bytes(&self.frame_buffer[..]);
+        self.chr_address_bus.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.total_cycles = reader.read_u32()?;
+
+        let scanline_state = &mut self.scanline_state;
+        scanline_state.nametable_byte = reader.read_u8()?;
+        scanline_state.attribute_table_byte = reader.read_u8()?;
+        scanline_state.bg_low_byte = reader.read_u8()?;
+        scanline_state.bg_high_byte = reader.read_u8()?;
+        scanline_state.scanline = reader.read_u16()?;
+        scanline_state.dot = reader.read_u16()?;
+        scanline_state.bg_shift_register_high = reader.read_u16()?;
+        scanline_state.bg_shift_register_low = reader.read_u16()?;
+        scanline_state.at_shift_register_high = reader.read_u8()?;
+        scanline_state.at_shift_register_low = reader.read_u8()?;
+        scanline_state.at_shift_latch_high = reader.read_u8()?;
+        scanline_state.at_shift_latch_low = reader.read_u8()?;
+        if scanline_state.scanline >= self.region.scanlines_per_frame() || scanline_state.dot >= 341 {
+            return Err(SaveStateError::new("Invalid PPU scanline/dot"));
+        }
+
+        self.sprite_data.load_state(reader)?;
+        reader.read_bytes(&mut self.palette_ram.data)?;
+        self.ppu_ctrl.write_byte(reader.read_u8()?);
+        self.ppu_mask.write_byte(reader.read_u8()?);
+        self.ppu_status.sprite_overflow = reader.read_bool()?;
+        self.ppu_status.sprite_zero_hit = reader.read_bool()?;
+        self.ppu_status.vblank_started = reader.read_bool()?;
+        self.last_ppu_status_read_cycle = reader.read_u32()?;
+
+        let internal_registers = &mut self.internal_registers;
+        internal_registers.vram_addr = reader.read_u16()?;
+        internal_registers.temp_vram_addr = reader.read_u16()?;
+        internal_registers.fine_x_scroll = reader.read_u8()? & 0b111;
+        internal_registers.write_toggle = reader.read_bool()?;
+        internal_registers.next_address = reader.read_u16()?;
+
+        self.ppu_data_buffer = reader.read_u8()?;
+        self.last_ppu_data_read_cycle = reader.read_u32()?;
+        self.stale_ppu_data_buffer = reader.read_u8()?;
+        self.open_bus.load_state(reader)?;
+        self.is_short_frame = reader.read_bool()?;
+        self.colour_phase = reader.read_u8()? % 3;
+        self.frame_phase = reader.read_u8()? % 3;
+        self.reset_write_protect = reader.read_bool()?;
+        self.nmi_interrupt = load_interrupt(reader)?;
+        reader.read_bytes(&mut self.frame_buffer[..])?;
+        self.chr_address_bus.load_state(reader)
+    }
+}
+
 #[cfg(test)]
 mod ppu_tests {
     use cartridge::PpuCartridgeAddressBus;
     use cpu::CpuCycle;
     use ppu::Ppu;
     use ppu::PpuCycle;
+    use region::Region;
+    use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
     struct FakeCartridge {}
 
+    impl SaveState for FakeCartridge {
+        fn save_state(&self, _: &mut StateWriter) {}
+
+        fn load_state(&mut self, _: &mut StateReader) -> Result<(), SaveStateError> {
+            Ok(())
+        }
+    }
+
     impl PpuCartridgeAddressBus for FakeCartridge {
         fn check_trigger_irq(&mut self, _: bool) -> bool {
             false
@@ -712,7 +999,7 @@ mod ppu_tests {
 
     #[test]
     fn test_setting_vram_addr() {
-        let mut ppu = Ppu::new(Box::new(FakeCartridge {}));
+        let mut ppu = Ppu::new(Box::new(FakeCartridge {}), Region::Ntsc);
         ppu.write_register(0x2000, 0);
         ppu.read_register(0x2002);
         ppu.write_register(0x2005, 0x7D);
@@ -731,7 +1018,7 @@ mod ppu_tests {
 
     #[test]
     fn test_setting_vram_addr_v2() {
-        let mut ppu = Ppu::new(Box::new(FakeCartridge {}));
+        let mut ppu = Ppu::new(Box::new(FakeCartridge {}), Region::Ntsc);
         ppu.write_register(0x2006, 0x04);
         assert_eq!(ppu.internal_registers.temp_vram_addr, 0b0000100_00000000);
         ppu.write_register(0x2005, 0x3E);
@@ -745,4 +1032,54 @@ mod ppu_tests {
         assert_eq!(ppu.internal_registers.vram_addr, 0b1100100_11101111);
         assert_eq!(ppu.internal_registers.fine_x_scroll, 0b101);
     }
+
+    fn run_to(ppu: &mut Ppu, scanline: u16, dot: u16) {
+        while ppu.scanline_state.scanline != scanline || ppu.scanline_state.dot != dot {
+            ppu.next();
+        }
+    }
+
+    #[test]
+    fn test_oam_addr_corrupts_oam_when_rendering_starts() {
+        let mut ppu = Ppu::new(Box::new(FakeCartridge {}), Region::Ntsc);
+        ppu.write_register(0x2003, 0);
+        for value in 0..=0xFF {
+            ppu.write_register(0x2004, value);
+        }
+        let oam = ppu.sprite_data.oam_ram;
+
+        run_to(&mut ppu, 241, 0);
+        ppu.write_register(0x2003, 0x13);
+        ppu.write_register(0x2001, 0b0001_1000);
+        run_to(&mut ppu, 261, 9);
+
+        // The row of 8 bytes containing OAMADDR is copied over the first row
+        assert_eq!(ppu.sprite_data.oam_ram[0..8], oam[0x10..0x18]);
+        assert_eq!(ppu.sprite_data.oam_ram[8..], oam[8..]);
+    }
+
+    #[test]
+    fn test_oam_data_during_rendering() {
+        let mut ppu = Ppu::new(Box::new(FakeCartridge {}), Region::Ntsc);
+        ppu.write_register(0x2003, 0);
+        for value in 0..=0xFF {
+            ppu.write_register(0x2004, value);
+        }
+        ppu.write_register(0x2001, 0b0001_1000);
+
+        // Secondary OAM is being cleared so reads give FF
+        run_to(&mut ppu, 10, 30);
+        assert_eq!(ppu.read_register(0x2004), 0xFF);
+
+        // During evaluation reads see the byte evaluation last read
+        run_to(&mut ppu, 10, 66);
+        assert_eq!(ppu.read_register(0x2004), ppu.sprite_data.oam_ram[0]);
+
+        // Writes are dropped but bump the top 6 bits of OAMADDR
+        let oam_addr = ppu.sprite_data.oam_addr;
+        let oam = ppu.sprite_data.oam_ram;
+        ppu.write_register(0x2004, 0xAA);
+        assert_eq!(ppu.sprite_data.oam_addr, oam_addr.wrapping_add(4));
+        assert_eq!(ppu.sprite_data.oam_ram[..], oam[..]);
+    }
 }
@@ -0,0 +1,251 @@
+use ppu::palette::{decoder_angle, ntsc_signal, yiq_to_rgb, NtscParameters};
+use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Width of the filtered image, each PPU pixel becomes roughly 2.35 output
+/// pixels which (when shown 480 lines high) gives the 8:7 pixel aspect ratio
+/// of an NTSC TV
+pub const NTSC_OUTPUT_WIDTH: u32 = 602;
+
+/// The PPU outputs 8 samples of the composite signal per pixel, with 12
+/// samples making up a cycle of the colour subcarrier
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_LINE: usize = SCREEN_WIDTH as usize * SAMPLES_PER_PIXEL;
+
+/// Samples of the edge pixel repeated either side of the picture so the
+/// filters have something to work on at the edges, a multiple of 12 to keep
+/// each sample's phase the same as its position within the line
+const PADDING: usize = 24;
+
+/// Chroma is decoded from 2 cycles of the subcarrier, giving the much lower
+/// colour resolution of a TV
+const CHROMA_WINDOW: usize = 24;
+
+const GAMMA_STEPS: usize = 0x400;
+
+/// Settings for the NTSC filter, on top of the usual picture settings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NtscFilterSettings {
+    /// Hue, saturation etc. as for a palette generated from the composite signal
+    pub picture: NtscParameters,
+    /// -1.0 to 1.0, sharper images let more of the colour subcarrier through
+    /// into the brightness giving stronger dot crawl & artifact patterns
+    pub sharpness: f32,
+    /// 0.0 to 1.0, how much colour bleeds out from sharp changes in
+    /// brightness (the source of the NES's artifact colours)
+    pub fringing: f32,
+    /// 0.0 to 1.0, how much of the colour signal's effect on brightness is
+    /// kept, at 0.0 there is no dot crawl
+    pub artifacts: f32,
+}
+
+impl Default for NtscFilterSettings {
+    fn default() -> Self {
+        NtscFilterSettings {
+            picture: NtscParameters::default(),
+            sharpness: 0.0,
+            fringing: 1.0,
+            artifacts: 1.0,
+        }
+    }
+}
+
+/// A software simulation of the NES's composite video output as decoded by
+/// a TV, in the spirit of blargg's nes_ntsc. It takes the indexed colours
+/// from `Nes::indexed_frame_buffer` and generates the composite signal for
+/// each pixel, then decodes it back into a (wider) RGB image complete with
+/// the colour fringes and dot crawl that the signal's limited bandwidth gives.
+pub struct NtscFilter {
+    picture: NtscParameters,
+    luma_window: usize,
+    /// The contribution to Y, I & Q of each colour's signal at each phase of
+    /// the subcarrier, before filtering
+    samples: Box<[[[f32; 3]; 12]; 0x200]>,
+    /// Output level for each 1/GAMMA_STEPS of input, saves a powf per component
+    gamma: Box<[u8; GAMMA_STEPS + 1]>,
+}
+
+impl NtscFilter {
+    pub fn new(settings: &NtscFilterSettings) -> Self {
+        let hue = settings.picture.hue.to_radians();
+
+        let mut carrier = [(0.0, 0.0); 12];
+        for (phase, carrier) in carrier.iter_mut().enumerate() {
+            let angle = decoder_angle(phase as u8, hue);
+            *carrier = (2.0 * angle.cos(), 2.0 * angle.sin());
+        }
+
+        let fringing = settings.fringing.clamp(0.0, 1.0);
+        let artifacts = settings.artifacts.clamp(0.0, 1.0);
+        let mut samples = Box::new([[[0.0; 3]; 12]; 0x200]);
+        for (index, samples) in samples.iter_mut().enumerate() {
+            let levels: Vec<f32> = (0..12).map(|phase| ntsc_signal(index as u16, phase)).collect();
+
+            // The colour decoded in isolation, used in place of the composite signal to the
+            // extent fringing & artifacts are turned down
+            let mut clean = [0.0; 3];
+            for (level, carrier) in levels.iter().zip(carrier.iter()) {
+                clean[0] += level / 12.0;
+                clean[1] += level * carrier.0 / 12.0;
+                clean[2] += level * carrier.1 / 12.0;
+            }
+
+            for (phase, sample) in samples.iter_mut().enumerate() {
+                let level = levels[phase];
+                *sample = [
+                    artifacts * level + (1.0 - artifacts) * clean[0],
+                    fringing * level * carrier[phase].0 + (1.0 - fringing) * clean[1],
+                    fringing * level * carrier[phase].1 + (1.0 - fringing) * clean[2],
+                ];
+            }
+        }
+
+        // A 12 sample luma window exactly cancels out the subcarrier in areas of flat colour
+        let luma_window = (12.0 - 6.0 * settings.sharpness.clamp(-1.0, 1.0)).round() as usize;
+
+        let mut gamma = Box::new([0; GAMMA_STEPS + 1]);
+        for (step, level) in gamma.iter_mut().enumerate() {
+            let component = (step as f32 / GAMMA_STEPS as f32).powf(1.0 / settings.picture.gamma);
+            *level = (component * 255.0).round() as u8;
+        }
+
+        NtscFilter {
+            picture: settings.picture,
+            luma_window,
+            samples,
+            gamma,
+        }
+    }
+
+    /// Filter a frame of indexed pixels into `output` (which must hold
+    /// NTSC_OUTPUT_WIDTH x SCREEN_HEIGHT pixels) in the same BGRA layout as
+    /// `Nes::frame_buffer`. `frame_phase` is the `Nes::frame_phase` the
+    /// frame was drawn with, which moves the dot crawl from frame to frame.
+    pub fn filter(&self, pixels: &[u16; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize], frame_phase: u8, output: &mut [u8]) {
+        assert_eq!(output.len(), (NTSC_OUTPUT_WIDTH * SCREEN_HEIGHT * 4) as usize);
+
+        // Running totals of Y, I & Q along the line so any window can be averaged in constant time
+        let mut sums = vec![[0.0f32; 3]; SAMPLES_PER_LINE + 2 * PADDING + 1];
+
+        for (line, (pixels, output)) in pixels
+            .chunks_exact(SCREEN_WIDTH as usize)
+            .zip(output.chunks_exact_mut(NTSC_OUTPUT_WIDTH as usize * 4))
+            .enumerate()
+        {
+            // Each line of 341 dots moves the subcarrier on by a third of a cycle
+            let line_phase = (frame_phase as usize + line) % 3 * 4;
+
+            let mut total = [0.0; 3];
+            let mut phase = line_phase;
+            for (sample, sum) in sums[1..].iter_mut().enumerate() {
+                let pixel = (sample.max(PADDING) - PADDING) / SAMPLES_PER_PIXEL;
+                let colour = pixels[pixel.min(SCREEN_WIDTH as usize - 1)] as usize & 0x1FF;
+                let yiq = &self.samples[colour][phase];
+
+                total[0] += yiq[0];
+                total[1] += yiq[1];
+                total[2] += yiq[2];
+                *sum = total;
+                phase = if phase == 11 { 0 } else { phase + 1 };
+            }
+
+            let average = |component: usize, centre: usize, window: usize| {
+                let start = centre - window / 2;
+                (sums[start + window][component] - sums[start][component]) / window as f32
+            };
+
+            for (x, output) in output.chunks_exact_mut(4).enumerate() {
+                let centre = PADDING + (x * SAMPLES_PER_LINE + SAMPLES_PER_LINE / 2) / NTSC_OUTPUT_WIDTH as usize;
+                let [red, green, blue] = yiq_to_rgb(
+                    average(0, centre, self.luma_window),
+                    average(1, centre, CHROMA_WINDOW),
+                    average(2, centre, CHROMA_WINDOW),
+                    &self.picture,
+                );
+                let gamma = |component: f32| self.gamma[(component.clamp(0.0, 1.0) * GAMMA_STEPS as f32) as usize];
+
+                output[0] = gamma(blue); // Blue channel
+                output[1] = gamma(green); // Green channel
+                output[2] = gamma(red); // Red channel
+                output[3] = 0x00; // Alpha channel
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod ntsc_tests {
+    use super::{NtscFilter, NtscFilterSettings, NTSC_OUTPUT_WIDTH};
+    use ppu::palette::{NtscParameters, Palette};
+    use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    const FRAME_PIXELS: usize = (SCREEN_WIDTH * SCREEN_HEIGHT) as usize;
+    const OUTPUT_BYTES: usize = (NTSC_OUTPUT_WIDTH * SCREEN_HEIGHT * 4) as usize;
+
+    fn filter(settings: &NtscFilterSettings, pixels: &[u16; FRAME_PIXELS], frame_phase: u8) -> Vec<u8> {
+        let mut output = vec![0; OUTPUT_BYTES];
+        NtscFilter::new(settings).filter(pixels, frame_phase, &mut output);
+        output
+    }
+
+    /// Alternating columns of colours, which a TV can't resolve
+    fn stripes() -> Box<[u16; FRAME_PIXELS]> {
+        let mut pixels = Box::new([0; FRAME_PIXELS]);
+        for (index, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = if index & 1 == 0 { 0x30 } else { 0x0F };
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_flat_colour_matches_composite_palette() {
+        let palette = Palette::generate_ntsc(&NtscParameters::default());
+
+        for &colour in &[0x00, 0x0F, 0x16, 0x2A, 0x30, 0x12 | 0x40, 0x38 | 0x1C0] {
+            let output = filter(&NtscFilterSettings::default(), &[colour; FRAME_PIXELS], 0);
+            let expected = palette.colour(colour);
+
+            for pixel in output.chunks_exact(4) {
+                for component in 0..3 {
+                    let difference = pixel[component] as i32 - ((expected >> (component * 8)) & 0xFF) as i32;
+                    assert!(difference.abs() <= 1, "colour {:03X} gave {:?}", colour, pixel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dot_crawl_follows_frame_phase() {
+        let pixels = stripes();
+        let settings = NtscFilterSettings::default();
+
+        let frames: Vec<Vec<u8>> = (0..3).map(|phase| filter(&settings, &pixels, phase)).collect();
+        assert_ne!(frames[0], frames[1]);
+        assert_ne!(frames[1], frames[2]);
+
+        // Each line is a third of a cycle on from the last
+        let line = NTSC_OUTPUT_WIDTH as usize * 4;
+        assert_eq!(frames[0][line..2 * line], frames[1][..line]);
+    }
+
+    #[test]
+    fn test_no_artifacts_or_fringing_is_phase_independent() {
+        let pixels = stripes();
+        let settings = NtscFilterSettings {
+            fringing: 0.0,
+            artifacts: 0.0,
+            ..NtscFilterSettings::default()
+        };
+
+        assert_eq!(filter(&settings, &pixels, 0), filter(&settings, &pixels, 1));
+    }
+
+    #[test]
+    fn test_stripes_produce_artifact_colours() {
+        let output = filter(&NtscFilterSettings::default(), &stripes(), 0);
+
+        // Black & white stripes come out coloured in the middle of the picture
+        let middle = (NTSC_OUTPUT_WIDTH as usize * 120 + 300) * 4;
+        let pixel = &output[middle..middle + 3];
+        assert!(pixel[0] != pixel[1] || pixel[1] != pixel[2], "{:?}", pixel);
+    }
+}
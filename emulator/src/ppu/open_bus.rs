@@ -0,0 +1,105 @@
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+/// Roughly how long (600ms) a bit on the PPU's data bus holds a 1 after it
+/// was last driven high, c.f. http://wiki.nesdev.com/w/index.php/Open_bus_behavior#PPU_open_bus
+const DECAY_FRAMES: u8 = 36;
+
+/// The PPU's internal data bus (the "decay register"). Reading a write only
+/// register, or the bits of a register the PPU doesn't drive, returns
+/// whatever was last put on the bus. Each bit fades back to 0 some time after
+/// it was last refreshed.
+pub(super) struct OpenBus {
+    value: u8,
+    /// Frames left until each bit decays
+    decay: [u8; 8],
+}
+
+impl OpenBus {
+    pub(super) fn new() -> Self {
+        OpenBus {
+            value: 0,
+            decay: [0; 8],
+        }
+    }
+
+    pub(super) fn value(&self) -> u8 {
+        self.value
+    }
+
+    /// Drive the bits in `mask` with `value`, those which are driven high
+    /// restart their decay
+    pub(super) fn refresh(&mut self, value: u8, mask: u8) {
+        self.value = (self.value & !mask) | (value & mask);
+        for (bit, decay) in self.decay.iter_mut().enumerate() {
+            if mask & value & (1 << bit) != 0 {
+                *decay = DECAY_FRAMES;
+            }
+        }
+    }
+
+    /// Merge the bits the PPU drives for a register read with the rest of
+    /// the bus, driving the bus with the bits read
+    pub(super) fn read(&mut self, value: u8, mask: u8) -> u8 {
+        self.refresh(value, mask);
+        self.value
+    }
+
+    /// Called once per frame to decay any bits which haven't been refreshed
+    pub(super) fn end_frame(&mut self) {
+        for (bit, decay) in self.decay.iter_mut().enumerate() {
+            if *decay > 0 {
+                *decay -= 1;
+                if *decay == 0 {
+                    self.value &= !(1 << bit);
+                }
+            }
+        }
+    }
+}
+
+impl SaveState for OpenBus {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.value);
+        writer.write_bytes(&self.decay);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.value = reader.read_u8()?;
+        reader.read_bytes(&mut self.decay)
+    }
+}
+
+#[cfg(test)]
+mod open_bus_tests {
+    use super::{OpenBus, DECAY_FRAMES};
+
+    #[test]
+    fn test_bits_decay_after_refresh() {
+        let mut bus = OpenBus::new();
+        bus.refresh(0xF0, 0xFF);
+        for _ in 0..DECAY_FRAMES / 2 {
+            bus.end_frame();
+        }
+        bus.refresh(0x30, 0x3F);
+        assert_eq!(bus.value(), 0xF0);
+
+        for _ in 0..DECAY_FRAMES / 2 {
+            bus.end_frame();
+        }
+        assert_eq!(bus.value(), 0x30);
+
+        for _ in 0..DECAY_FRAMES {
+            bus.end_frame();
+        }
+        assert_eq!(bus.value(), 0x00);
+    }
+
+    #[test]
+    fn test_read_merges_undriven_bits() {
+        let mut bus = OpenBus::new();
+        bus.refresh(0xFF, 0xFF);
+
+        assert_eq!(bus.read(0x80, 0xE0), 0x9F);
+        assert_eq!(bus.value(), 0x9F);
+    }
+}
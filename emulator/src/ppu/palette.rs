@@ -1,7 +1,11 @@
 use log::info;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs;
 
 #[rustfmt::skip]
-pub(super) const PALETTE_2C02: [u32; 0x40] = [
+const PALETTE_2C02: [u32; 0x40] = [
     0x7C7C7C, 0x0000FC, 0x0000BC, 0x4428BC, 0x940084, 0xA80020, 0xA81000, 0x881400, 0x503000, 0x007800, 0x006800,
     0x005800, 0x004058, 0x000000, 0x000000, 0x000000, 0xBCBCBC, 0x0078F8, 0x0058F8, 0x6844FC, 0xD800CC, 0xE40058,
     0xF83800, 0xE45C10, 0xAC7C00, 0x00B800, 0x00A800, 0x00A844, 0x008888, 0x000000, 0x000000, 0x000000, 0xF8F8F8,
@@ -10,12 +14,280 @@ pub(super) const PALETTE_2C02: [u32; 0x40] = [
     0xFCE0A8, 0xF8D878, 0xD8F878, 0xB8F8B8, 0xB8F8D8, 0x00FCFC, 0xF8D8F8, 0x000000, 0x000000,
 ];
 
+/// The RGB PPU (2C03, also used by the 2C05) palette with 3 bits for each
+/// of red, green & blue, c.f. http://wiki.nesdev.com/w/index.php/PPU_palettes
+#[rustfmt::skip]
+const PALETTE_2C03: [u16; 0x40] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+/// Each emphasis bit darkens the other two colour components by roughly
+/// this much, c.f. http://wiki.nesdev.com/w/index.php/NTSC_video
+const EMPHASIS_ATTENUATION: f32 = 0.816_328;
+
+/// Composite video levels (in volts relative to sync) for each of the 4
+/// luma levels when the colour wave is low and high
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f32 = 0.518;
+const SIGNAL_WHITE: f32 = 1.962;
+const SIGNAL_EMPHASIS_ATTENUATION: f32 = 0.746;
+
+/// Offset (in twelfths of a colour cycle) of the decoder's I axis, this
+/// lines the decoded hues up with the usual NTSC palettes
+const DECODER_PHASE_OFFSET: f32 = 4.0;
+
+/// The palettes available without loading a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuiltinPalette {
+    /// The hand tuned 2C02 palette used by default
+    Ntsc2C02,
+    /// The 2C02 palette decoded from its composite output with the default
+    /// `NtscParameters`
+    Composite2C02,
+    /// The RGB PPU found in PlayChoice-10 and some Vs. System boards (the
+    /// 2C05 uses the same colours)
+    Rgb2C03,
+}
+
+/// Settings for decoding the composite video signal into a palette, much
+/// like the knobs on the front of a TV
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NtscParameters {
+    /// Hue rotation in degrees
+    pub hue: f32,
+    /// Multiplier for the colour (chroma) signal, 0 gives greyscale
+    pub saturation: f32,
+    /// Multiplier for the whole signal
+    pub contrast: f32,
+    /// Added to the brightness (luma) of every colour, -1.0 to 1.0
+    pub brightness: f32,
+    /// Gamma applied to each output component, 1.0 leaves the decoded colours unchanged
+    pub gamma: f32,
+}
+
+impl Default for NtscParameters {
+    fn default() -> Self {
+        NtscParameters {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+/// Represents any error which occurs when loading a palette
+#[derive(Debug)]
+pub struct PaletteError {
+    pub message: String,
+}
+impl Error for PaletteError {}
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error loading palette: {}", self.message)
+    }
+}
+
+/// The colours (as 0x00RRGGBB) output by the PPU for each of the 64 palette
+/// entries with every combination of the PPUMASK emphasis bits. The index
+/// is the palette entry in bits 0-5 and the emphasis bits (red, green, blue)
+/// in bits 6-8, the same layout as a 512 colour .pal file.
+#[derive(Clone)]
+pub struct Palette {
+    colours: Box<[u32; 0x200]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::builtin(BuiltinPalette::Ntsc2C02)
+    }
+}
+
+impl Palette {
+    pub fn builtin(palette: BuiltinPalette) -> Self {
+        match palette {
+            BuiltinPalette::Ntsc2C02 => Palette::with_emphasis(&PALETTE_2C02),
+            BuiltinPalette::Composite2C02 => Palette::generate_ntsc(&NtscParameters::default()),
+            BuiltinPalette::Rgb2C03 => {
+                let mut colours = Box::new([0; 0x200]);
+                for (index, colour) in colours.iter_mut().enumerate() {
+                    let rgb = PALETTE_2C03[index & 0x3F] as u32;
+                    let emphasis = index as u32 >> 6;
+
+                    // The RGB PPUs turn a component fully on instead of darkening the others
+                    *colour = [(rgb >> 6, 0b001), (rgb >> 3, 0b010), (rgb, 0b100)].iter().fold(
+                        0,
+                        |acc, (level, emphasis_bit)| {
+                            let level = if emphasis & emphasis_bit != 0 { 7 } else { level & 7 };
+                            (acc << 8) | (level * 255 / 7)
+                        },
+                    );
+                }
+                Palette { colours }
+            }
+        }
+    }
+
+    /// Load a palette from the contents of a .pal file, either 64 colours
+    /// (emphasis is then approximated) or 512 colours of 3 bytes (R, G, B)
+    pub fn from_pal_bytes(bytes: &[u8]) -> Result<Self, PaletteError> {
+        let colour = |rgb: &[u8]| (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+
+        match bytes.len() {
+            0xC0 => {
+                let mut palette = [0; 0x40];
+                for (entry, rgb) in palette.iter_mut().zip(bytes.chunks(3)) {
+                    *entry = colour(rgb);
+                }
+                Ok(Palette::with_emphasis(&palette))
+            }
+            0x600 => {
+                let mut colours = Box::new([0; 0x200]);
+                for (entry, rgb) in colours.iter_mut().zip(bytes.chunks(3)) {
+                    *entry = colour(rgb);
+                }
+                Ok(Palette { colours })
+            }
+            length => Err(PaletteError {
+                message: format!("expected 192 or 1536 bytes but found {}", length),
+            }),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, PaletteError> {
+        let bytes = fs::read(path).map_err(|e| PaletteError {
+            message: format!("{}: {}", path, e),
+        })?;
+        Palette::from_pal_bytes(&bytes)
+    }
+
+    /// The palette as a 512 colour .pal file
+    pub fn to_pal_bytes(&self) -> Vec<u8> {
+        self.colours
+            .iter()
+            .flat_map(|colour| vec![(colour >> 16) as u8, (colour >> 8) as u8, *colour as u8])
+            .collect()
+    }
+
+    /// Decode the 2C02's composite video output for every colour, c.f.
+    /// http://wiki.nesdev.com/w/index.php/NTSC_video
+    pub fn generate_ntsc(parameters: &NtscParameters) -> Self {
+        let mut colours = Box::new([0; 0x200]);
+        let hue = parameters.hue.to_radians();
+
+        for (index, colour) in colours.iter_mut().enumerate() {
+            // Sample the signal at 12 points through a colour cycle and demodulate it into YIQ
+            let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+            for phase in 0..12 {
+                let level = ntsc_signal(index as u16, phase);
+                let angle = decoder_angle(phase, hue);
+                y += level;
+                i += level * angle.cos();
+                q += level * angle.sin();
+            }
+            *colour = yiq_to_rgb(y / 12.0, i / 6.0, q / 6.0, parameters)
+                .iter()
+                .fold(0, |acc, component| {
+                    let component = component.clamp(0.0, 1.0).powf(1.0 / parameters.gamma);
+                    (acc << 8) | (component * 255.0).round() as u32
+                });
+        }
+
+        Palette { colours }
+    }
+
+    /// Extend a 64 colour palette to the 512 colours seen with every
+    /// combination of the emphasis bits
+    fn with_emphasis(palette: &[u32; 0x40]) -> Self {
+        let mut colours = Box::new([0; 0x200]);
+        for (index, colour) in colours.iter_mut().enumerate() {
+            let base = palette[index & 0x3F];
+            let emphasis = index >> 6;
+
+            *colour = (0..3).fold(0, |acc, component| {
+                // Component 0 is blue, 1 green and 2 red (the reverse of the emphasis bits)
+                let mut value = ((base >> (component * 8)) & 0xFF) as f32;
+                for bit in 0..3 {
+                    if emphasis & (1 << bit) != 0 && bit != 2 - component {
+                        value *= EMPHASIS_ATTENUATION;
+                    }
+                }
+                acc | (value.round() as u32) << (component * 8)
+            });
+        }
+
+        Palette { colours }
+    }
+
+    /// The colour for a palette entry (bits 0-5) with emphasis (bits 6-8)
+    #[inline]
+    pub fn colour(&self, index: u16) -> u32 {
+        self.colours[index as usize & 0x1FF]
+    }
+}
+
+/// The composite signal level (0 is black, 1 is white) output for a colour
+/// with emphasis at one of the 12 phases of the colour subcarrier
+pub(crate) fn ntsc_signal(index: u16, phase: u8) -> f32 {
+    let colour = index & 0x0F;
+    let emphasis = index >> 6;
+    // Colours E & F are always output at level 1
+    let level = if colour > 0xD { 1 } else { (index >> 4) as usize & 3 };
+    let in_colour_phase = |colour: u16| (colour + phase as u16) % 12 < 6;
+
+    // Colour 0 is only ever high and D-F only ever low, the rest are a square wave
+    let signal = match colour {
+        0x0 => SIGNAL_HIGH[level],
+        0xD..=0xF => SIGNAL_LOW[level],
+        _ if in_colour_phase(colour) => SIGNAL_HIGH[level],
+        _ => SIGNAL_LOW[level],
+    };
+
+    // Each emphasis bit attenuates the signal for the third of the colour cycle it covers
+    let emphasised = (emphasis & 0b001 != 0 && in_colour_phase(0x0))
+        || (emphasis & 0b010 != 0 && in_colour_phase(0x4))
+        || (emphasis & 0b100 != 0 && in_colour_phase(0x8));
+    let signal = if emphasised {
+        signal * SIGNAL_EMPHASIS_ATTENUATION
+    } else {
+        signal
+    };
+
+    (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+/// The angle (in radians) of the decoder's I axis at one of the 12 phases of
+/// the colour subcarrier
+pub(super) fn decoder_angle(phase: u8, hue: f32) -> f32 {
+    PI * (phase as f32 + DECODER_PHASE_OFFSET) / 6.0 + hue
+}
+
+/// Apply the picture settings (other than hue, which is part of decoding and
+/// gamma) to a decoded colour and convert it to red, green & blue
+pub(super) fn yiq_to_rgb(y: f32, i: f32, q: f32, parameters: &NtscParameters) -> [f32; 3] {
+    let y = y * parameters.contrast + parameters.brightness;
+    let i = i * parameters.contrast * parameters.saturation;
+    let q = q * parameters.contrast * parameters.saturation;
+
+    // FCC YIQ to RGB
+    [
+        y + 0.956 * i + 0.621 * q,
+        y - 0.272 * i - 0.647 * q,
+        y - 1.106 * i + 1.703 * q,
+    ]
+}
+
 #[rustfmt::skip]
 const PALETTE_MIRRORS: [Option<usize>; 0x20] = [
-    Some(0x10), None, None, None, None, None, None, None,
-    Some(0x18), None, None, None, None, None, None, None,
-    Some(0x00), None, None, None, None, None, None, None,
-    Some(0x08), None, None, None, None, None, None, None,
+    Some(0x10), None, None, None, Some(0x14), None, None, None,
+    Some(0x18), None, None, None, Some(0x1C), None, None, None,
+    Some(0x00), None, None, None, Some(0x04), None, None, None,
+    Some(0x08), None, None, None, Some(0x0C), None, None, None,
 ];
 
 pub(super) struct PaletteRam {
@@ -46,7 +318,58 @@ impl PaletteRam {
 
 #[cfg(test)]
 mod palette_ram_tests {
-    use super::PaletteRam;
+    use super::{BuiltinPalette, NtscParameters, Palette, PaletteRam, PALETTE_2C02};
+
+    #[test]
+    fn test_emphasis_darkens_other_components() {
+        let palette = Palette::builtin(BuiltinPalette::Ntsc2C02);
+
+        assert_eq!(palette.colour(0x30), PALETTE_2C02[0x30]);
+        // Red emphasis on white (FCFCFC) leaves red alone
+        assert_eq!(palette.colour(0x40 | 0x30), 0xFCCECE);
+        // All three together darken every component twice
+        assert_eq!(palette.colour(0x1C0 | 0x30), 0xA8A8A8);
+        assert_eq!(palette.colour(0x1C0 | 0x0F), 0x000000);
+    }
+
+    #[test]
+    fn test_rgb_emphasis_saturates_component() {
+        let palette = Palette::builtin(BuiltinPalette::Rgb2C03);
+
+        assert_eq!(palette.colour(0x01), 0x002491);
+        assert_eq!(palette.colour(0x80 | 0x01), 0x00FF91);
+    }
+
+    #[test]
+    fn test_pal_file_round_trip() {
+        let palette = Palette::generate_ntsc(&NtscParameters::default());
+        let bytes = palette.to_pal_bytes();
+        assert_eq!(bytes.len(), 0x600);
+
+        let loaded = Palette::from_pal_bytes(&bytes).unwrap();
+        assert!((0..0x200).all(|index| loaded.colour(index) == palette.colour(index)));
+
+        // 64 colour files have emphasis added
+        let loaded = Palette::from_pal_bytes(&bytes[..0xC0]).unwrap();
+        assert_eq!(loaded.colour(0x20), palette.colour(0x20));
+        assert!(Palette::from_pal_bytes(&bytes[..0xBF]).is_err());
+    }
+
+    #[test]
+    fn test_generated_palette_greys() {
+        let palette = Palette::generate_ntsc(&NtscParameters::default());
+
+        assert_eq!(palette.colour(0x00), 0x666666);
+        assert_eq!(palette.colour(0x0F), 0x000000);
+        assert_eq!(palette.colour(0x20), 0xFFFFFF);
+
+        let greyscale = Palette::generate_ntsc(&NtscParameters {
+            saturation: 0.0,
+            ..NtscParameters::default()
+        });
+        let colour = greyscale.colour(0x16);
+        assert!(colour >> 16 == colour & 0xFF && (colour >> 8) & 0xFF == colour & 0xFF);
+    }
 
     #[test]
     fn test_mirrors() {
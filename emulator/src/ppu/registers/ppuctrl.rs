@@ -68,4 +68,21 @@ impl PpuCtrl {
         self.ppu_master_slave = value & 0b100_0000 != 0;
         self.nmi_enable = value & 0b1000_0000 != 0; // TODO - This should trigger immediate interrupt if in vblank area
     }
+
+    /// Reconstruct the byte which was written to give the current register state
+    pub(crate) fn value(&self) -> u8 {
+        ((self.base_name_table_select >> 10) & 0b11) as u8
+            | match self.increment_mode {
+                IncrementMode::Add1GoingAcross => 0,
+                IncrementMode::Add32GoingDown => 0b100,
+            }
+            | if self.sprite_tile_table_select == 0 { 0 } else { 0b1000 }
+            | if self.background_tile_table_select == 0 { 0 } else { 0b1_0000 }
+            | match self.sprite_size {
+                SpriteSize::X8 => 0,
+                SpriteSize::X16 => 0b10_0000,
+            }
+            | if self.ppu_master_slave { 0b100_0000 } else { 0 }
+            | if self.nmi_enable { 0b1000_0000 } else { 0 }
+    }
 }
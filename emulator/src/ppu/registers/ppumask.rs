@@ -30,11 +30,28 @@ impl PpuMask {
         self.show_sprites_left_side = value & 0b100 == 0b100;
         self.show_background = value & 0b1000 == 0b1000;
         self.show_sprites = value & 0b1_0000 == 0b1_0000;
-        self.emphasize_red = value & 0b10_0000 == 0b10_0000; // TODO - Actually use these masks!
+        self.emphasize_red = value & 0b10_0000 == 0b10_0000;
         self.emphasize_green = value & 0b100_0000 == 0b100_0000;
         self.emphasize_blue = value & 0b1000_0000 == 0b1000_0000;
     }
 
+    /// Reconstruct the byte which was written to give the current register state
+    pub(crate) fn value(&self) -> u8 {
+        [
+            self.is_grayscale,
+            self.show_background_left_side,
+            self.show_sprites_left_side,
+            self.show_background,
+            self.show_sprites,
+            self.emphasize_red,
+            self.emphasize_green,
+            self.emphasize_blue,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (bit, set)| if *set { acc | (1 << bit) } else { acc })
+    }
+
     pub(crate) fn is_rendering_enabled(&self) -> bool {
         self.show_background || self.show_sprites
     }
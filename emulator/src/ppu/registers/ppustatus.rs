@@ -17,8 +17,8 @@ impl PpuStatus {
         }
     }
 
-    pub(crate) fn read(&mut self, last_written_byte: u8) -> u8 {
-        let mut result = last_written_byte & 0b0001_1111;
+    pub(crate) fn read(&mut self) -> u8 {
+        let mut result = 0;
         if self.sprite_overflow {
             result |= 0b0010_0000
         };
@@ -1,4 +1,5 @@
 use log::info;
+use save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 pub(super) const MAX_SPRITES: usize = 64;
 pub(super) const MAX_SPRITES_PER_LINE: usize = 8;
@@ -79,7 +80,7 @@ struct Sprite {
 
 pub(super) struct SpriteData {
     /// PPU register 0x2003
-    oam_addr: u8,
+    pub(super) oam_addr: u8,
     pub(super) oam_ram: [u8; MAX_SPRITES * 4],
     secondary_oam_ram: [u8; MAX_SPRITES_PER_LINE * 4],
     sprites: Vec<Sprite>,
@@ -90,6 +91,9 @@ pub(super) struct SpriteData {
     /// We need to know whether sprite zero is loaded into secondary OAM RAM to
     /// know whether a sprite at output unit 0 triggers a sprite zero hit
     sprite_zero_visible: bool,
+    /// The byte last read by sprite evaluation or fetching, which is what
+    /// OAMDATA returns while rendering
+    oam_latch: u8,
 }
 
 impl SpriteData {
@@ -115,6 +119,7 @@ impl SpriteData {
             eval_state: SpriteEvaluation::ReadY,
             fetch_state: SpriteFetch::ReadY { sprite_index: 0 },
             sprite_zero_visible: false,
+            oam_latch: 0xFF,
         }
     }
 
@@ -129,7 +134,13 @@ impl SpriteData {
         self.oam_addr = value;
     }
 
-    pub(super) fn write_oam_data(&mut self, value: u8) {
+    pub(super) fn write_oam_data(&mut self, value: u8, is_rendering: bool) {
+        // Whilst rendering the write is lost and OAMADDR gets a glitchy increment of only its top 6 bits
+        if is_rendering {
+            self.oam_addr = self.oam_addr.wrapping_add(4);
+            return;
+        }
+
         // Attribute byte bits always read 0, fix at set time to remove cost of masking on read
         let masked_value = if self.oam_addr & 0b11 == 0b10 {
             value & 0xE3
@@ -141,13 +152,19 @@ impl SpriteData {
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
-    pub(super) fn read_oam_data(&self, cycle: u16, rendering_enabled: bool) -> u8 {
-        match (cycle, rendering_enabled) {
-            (0..=64, true) => 0xFF, // Return FF whilst clearing secondary OAM RAM
-            _ => self.oam_ram[self.oam_addr as usize],
+    pub(super) fn read_oam_data(&self, cycle: u16, is_rendering: bool) -> u8 {
+        match (cycle, is_rendering) {
+            (_, false) => self.oam_ram[self.oam_addr as usize],
+            (1..=64, true) => 0xFF, // Return FF whilst clearing secondary OAM RAM
+            (_, true) => self.oam_latch,
         }
     }
 
+    fn read_secondary_oam(&mut self, index: usize) -> u8 {
+        self.oam_latch = self.secondary_oam_ram[index];
+        self.oam_latch
+    }
+
     pub(super) fn dma_write(&mut self, value: u8, dma_byte: u8) {
         // Attribute byte bits always read 0, fix at set time to remove cost of masking on read
         let masked_value = if dma_byte & 0b11 == 0b10 { value & 0xE3 } else { value };
@@ -204,19 +221,28 @@ impl super::Ppu {
 
     pub(super) fn process_sprite_cycle(
         &mut self,
+        is_pre_render: bool,
         scanline: u16,
         cycle: u16,
         sprite_height: u8,
         pattern_table_base: u16,
     ) {
         match cycle {
-            0 => (),
+            0 => self.sprite_data.oam_latch = self.sprite_data.secondary_oam_ram[0],
             // Clear secondary OAM RAM
-            1..=64 => self.sprite_data.secondary_oam_ram[(cycle - 1) as usize >> 1] = 0xFF,
+            1..=64 => {
+                // If OAMADDR isn't in the first 8 bytes when rendering starts then the row of OAM it points
+                // at is copied over the first row
+                if is_pre_render && cycle <= 8 && self.sprite_data.oam_addr >= 8 {
+                    let source = (self.sprite_data.oam_addr & 0xF8) as usize + cycle as usize - 1;
+                    self.sprite_data.oam_ram[cycle as usize - 1] = self.sprite_data.oam_ram[source];
+                }
+                self.sprite_data.secondary_oam_ram[(cycle - 1) as usize >> 1] = 0xFF
+            }
             // Sprite evaluation
             65..=256 => {
                 // Skip sprite evaluation on pre-render
-                if scanline != 261 {
+                if !is_pre_render {
                     if cycle == 65 {
                         self.sprite_data.secondary_oam_ram_pointer = 0;
                         self.sprite_data.eval_state = SpriteEvaluation::ReadY;
@@ -232,8 +258,8 @@ impl super::Ppu {
                 self.sprite_data.oam_addr = 0;
                 self.step_sprite_fetch_machine(scanline, sprite_height, pattern_table_base)
             }
-            // Read from secondary OAM RAM (but not tracking that read anywhere atm)
-            321..=340 => (),
+            // Read the first byte of secondary OAM RAM (only visible through OAMDATA)
+            321..=340 => self.sprite_data.oam_latch = self.sprite_data.secondary_oam_ram[0],
             _ => panic!("Shouldn't be calling sprite handler at dot {}", cycle),
         };
     }
@@ -242,9 +268,9 @@ impl super::Ppu {
         self.sprite_data.eval_state = match self.sprite_data.eval_state {
             SpriteEvaluation::ReadY => {
                 if (self.sprite_data.oam_addr as usize) < self.sprite_data.oam_ram.len() {
-                    SpriteEvaluation::WriteY {
-                        y: self.sprite_data.oam_ram[self.sprite_data.oam_addr as usize],
-                    }
+                    let y = self.sprite_data.oam_ram[self.sprite_data.oam_addr as usize];
+                    self.sprite_data.oam_latch = y;
+                    SpriteEvaluation::WriteY { y }
                 } else {
                     SpriteEvaluation::Completed
                 }
@@ -304,6 +330,7 @@ impl super::Ppu {
             SpriteEvaluation::ReadByte { count } => {
                 if (self.sprite_data.oam_addr as usize) < self.sprite_data.oam_ram.len() {
                     let value = self.sprite_data.oam_ram[self.sprite_data.oam_addr as usize];
+                    self.sprite_data.oam_latch = value;
 
                     SpriteEvaluation::WriteByte { count, value }
                 } else {
@@ -316,7 +343,11 @@ impl super::Ppu {
                     self.sprite_data.secondary_oam_ram_pointer += 1;
                 }
 
-                if (self.sprite_data.oam_addr as usize) >= self.sprite_data.oam_ram.len() - 1 {
+                // Once the overflowing sprite has been read no more sprites are checked for the rest of the line
+                if (self.sprite_data.oam_addr as usize) >= self.sprite_data.oam_ram.len() - 1
+                    || (count == 3
+                        && self.sprite_data.secondary_oam_ram_pointer > self.sprite_data.secondary_oam_ram.len())
+                {
                     SpriteEvaluation::Completed
                 } else if count == 3 {
                     self.sprite_data.oam_addr += 1;
@@ -335,24 +366,22 @@ impl super::Ppu {
     /// Fetch tile from PPU whilst refetching X from secondary OAM (4 cycles)
     fn step_sprite_fetch_machine(&mut self, scanline: u16, sprite_height: u8, pattern_table_base: u16) {
         self.sprite_data.fetch_state = match self.sprite_data.fetch_state {
-            SpriteFetch::ReadY { sprite_index } => SpriteFetch::ReadTile {
-                sprite_index,
-                y: self.sprite_data.secondary_oam_ram[sprite_index * 4],
-            },
-            SpriteFetch::ReadTile { sprite_index, y } => SpriteFetch::ReadAttr {
-                sprite_index,
-                y,
-                tile: self.sprite_data.secondary_oam_ram[sprite_index * 4 + 1],
-            },
+            SpriteFetch::ReadY { sprite_index } => {
+                let y = self.sprite_data.read_secondary_oam(sprite_index * 4);
+                SpriteFetch::ReadTile { sprite_index, y }
+            }
+            SpriteFetch::ReadTile { sprite_index, y } => {
+                let tile = self.sprite_data.read_secondary_oam(sprite_index * 4 + 1);
+                SpriteFetch::ReadAttr { sprite_index, y, tile }
+            }
             SpriteFetch::ReadAttr { sprite_index, y, tile } => {
-                self.sprite_data.sprites[sprite_index]
-                    .attribute_latch
-                    .set(self.sprite_data.secondary_oam_ram[sprite_index * 4 + 2]);
+                let attribute = self.sprite_data.read_secondary_oam(sprite_index * 4 + 2);
+                self.sprite_data.sprites[sprite_index].attribute_latch.set(attribute);
                 SpriteFetch::ReadX { sprite_index, y, tile }
             }
             SpriteFetch::ReadX { sprite_index, y, tile } => {
                 self.sprite_data.sprites[sprite_index].x_location =
-                    self.sprite_data.secondary_oam_ram[sprite_index * 4 + 3];
+                    self.sprite_data.read_secondary_oam(sprite_index * 4 + 3);
                 SpriteFetch::FetchByte {
                     sprite_index,
                     y,
@@ -455,6 +484,137 @@ fn get_sprite_address(
     top_tile_byte + fine_y + if is_high_byte { 8 } else { 0 }
 }
 
+impl SpriteAttribute {
+    fn value(&self) -> u8 {
+        self.palette
+            | if self.priority { 0 } else { 0b0010_0000 }
+            | if self.flipped_horizontal { 0b0100_0000 } else { 0 }
+            | if self.flipped_vertical { 0b1000_0000 } else { 0 }
+    }
+}
+
+impl SaveState for SpriteData {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.oam_addr);
+        writer.write_bytes(&self.oam_ram);
+        writer.write_bytes(&self.secondary_oam_ram);
+        for sprite in &self.sprites {
+            writer.write_u8(sprite.high_byte_shift_register);
+            writer.write_u8(sprite.low_byte_shift_register);
+            writer.write_u8(sprite.attribute_latch.value());
+            writer.write_u8(sprite.x_location);
+            writer.write_bool(sprite.visible);
+        }
+        writer.write_usize(self.secondary_oam_ram_pointer);
+
+        // State machines are written as a tag followed by every possible field
+        let (eval_tag, eval_a, eval_b) = match self.eval_state {
+            SpriteEvaluation::ReadY => (0, 0, 0),
+            SpriteEvaluation::WriteY { y } => (1, y, 0),
+            SpriteEvaluation::ReadByte { count } => (2, count, 0),
+            SpriteEvaluation::WriteByte { count, value } => (3, count, value),
+            SpriteEvaluation::Completed => (4, 0, 0),
+        };
+        writer.write_u8(eval_tag);
+        writer.write_u8(eval_a);
+        writer.write_u8(eval_b);
+
+        let (fetch_tag, sprite_index, y, tile, value, is_high_byte) = match self.fetch_state {
+            SpriteFetch::ReadY { sprite_index } => (0, sprite_index, 0, 0, 0, false),
+            SpriteFetch::ReadTile { sprite_index, y } => (1, sprite_index, y, 0, 0, false),
+            SpriteFetch::ReadAttr { sprite_index, y, tile } => (2, sprite_index, y, tile, 0, false),
+            SpriteFetch::ReadX { sprite_index, y, tile } => (3, sprite_index, y, tile, 0, false),
+            SpriteFetch::FetchByte {
+                sprite_index,
+                y,
+                tile,
+                is_high_byte,
+            } => (4, sprite_index, y, tile, 0, is_high_byte),
+            SpriteFetch::WriteByte {
+                sprite_index,
+                y,
+                tile,
+                value,
+                is_high_byte,
+            } => (5, sprite_index, y, tile, value, is_high_byte),
+            SpriteFetch::Completed => (6, 0, 0, 0, 0, false),
+        };
+        writer.write_u8(fetch_tag);
+        writer.write_usize(sprite_index);
+        writer.write_u8(y);
+        writer.write_u8(tile);
+        writer.write_u8(value);
+        writer.write_bool(is_high_byte);
+
+        writer.write_bool(self.sprite_zero_visible);
+        writer.write_u8(self.oam_latch);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.oam_addr = reader.read_u8()?;
+        reader.read_bytes(&mut self.oam_ram)?;
+        reader.read_bytes(&mut self.secondary_oam_ram)?;
+        for sprite in &mut self.sprites {
+            sprite.high_byte_shift_register = reader.read_u8()?;
+            sprite.low_byte_shift_register = reader.read_u8()?;
+            sprite.attribute_latch.set(reader.read_u8()?);
+            sprite.x_location = reader.read_u8()?;
+            sprite.visible = reader.read_bool()?;
+        }
+        self.secondary_oam_ram_pointer = reader.read_usize()?;
+
+        let eval_tag = reader.read_u8()?;
+        let eval_a = reader.read_u8()?;
+        let eval_b = reader.read_u8()?;
+        self.eval_state = match eval_tag {
+            0 => SpriteEvaluation::ReadY,
+            1 => SpriteEvaluation::WriteY { y: eval_a },
+            2 => SpriteEvaluation::ReadByte { count: eval_a },
+            3 => SpriteEvaluation::WriteByte {
+                count: eval_a,
+                value: eval_b,
+            },
+            4 => SpriteEvaluation::Completed,
+            _ => return Err(SaveStateError::new("Invalid sprite evaluation state")),
+        };
+
+        let fetch_tag = reader.read_u8()?;
+        let sprite_index = reader.read_usize()?;
+        let y = reader.read_u8()?;
+        let tile = reader.read_u8()?;
+        let value = reader.read_u8()?;
+        let is_high_byte = reader.read_bool()?;
+        if sprite_index >= MAX_SPRITES_PER_LINE {
+            return Err(SaveStateError::new("Invalid sprite index"));
+        }
+        self.fetch_state = match fetch_tag {
+            0 => SpriteFetch::ReadY { sprite_index },
+            1 => SpriteFetch::ReadTile { sprite_index, y },
+            2 => SpriteFetch::ReadAttr { sprite_index, y, tile },
+            3 => SpriteFetch::ReadX { sprite_index, y, tile },
+            4 => SpriteFetch::FetchByte {
+                sprite_index,
+                y,
+                tile,
+                is_high_byte,
+            },
+            5 => SpriteFetch::WriteByte {
+                sprite_index,
+                y,
+                tile,
+                value,
+                is_high_byte,
+            },
+            6 => SpriteFetch::Completed,
+            _ => return Err(SaveStateError::new("Invalid sprite fetch state")),
+        };
+
+        self.sprite_zero_visible = reader.read_bool()?;
+        self.oam_latch = reader.read_u8()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod sprite_tests {
     use super::get_sprite_address;
@@ -0,0 +1,98 @@
+use cartridge::TimingMode;
+
+/// The console variant being emulated, this decides the relative speed of
+/// the CPU & PPU, the length of a frame and various APU timings.
+/// c.f. http://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    /// RP2A03/RP2C02, North America & Japan
+    Ntsc,
+    /// RP2A07/RP2C07, Europe & Australia
+    Pal,
+    /// UA6527P/UA6538, the most common Famiclone. PAL frame rate with NTSC
+    /// CPU/PPU ratio and APU
+    Dendy,
+}
+
+impl Region {
+    /// The region the cartridge header asks for, multi region carts run as NTSC
+    pub fn from_timing(timing: TimingMode) -> Self {
+        match timing {
+            TimingMode::Ntsc | TimingMode::MultiRegion => Region::Ntsc,
+            TimingMode::Pal => Region::Pal,
+            TimingMode::Dendy => Region::Dendy,
+        }
+    }
+
+    /// CPU cycles per second
+    pub fn cpu_clock_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    /// Frames output per second
+    pub fn frame_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    /// Master clock ticks per CPU cycle
+    pub(crate) fn cpu_clock_divider(self) -> u8 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Master clock ticks per PPU dot
+    pub(crate) fn ppu_clock_divider(self) -> u8 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    /// Scanlines in a frame including the pre-render line
+    pub(crate) fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// The last scanline of each frame, which prepares the first visible line
+    pub(crate) fn pre_render_scanline(self) -> u16 {
+        self.scanlines_per_frame() - 1
+    }
+
+    /// The scanline on which the vblank flag is set (and NMI raised), Dendy
+    /// has a long post-render period instead of a long vblank
+    pub(crate) fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Only the NTSC PPU drops a dot from the pre-render line on odd frames
+    pub(crate) fn has_short_frames(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    /// The PAL PPU (and Dendy clones of it) swap the meaning of the red &
+    /// green emphasis bits in PPUMASK
+    pub(crate) fn swaps_red_green_emphasis(self) -> bool {
+        self != Region::Ntsc
+    }
+
+    /// The APU is a copy of the NTSC one on Dendy consoles
+    pub(crate) fn has_pal_apu(self) -> bool {
+        self == Region::Pal
+    }
+}
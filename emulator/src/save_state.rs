@@ -0,0 +1,258 @@
+//! Binary save state format
+//!
+//! A save state is a small fixed header (magic bytes, format version and the
+//! mapper number of the cartridge it was taken from) followed by the state of
+//! each component in a fixed order. Each component is responsible for writing
+//! and reading its own fields through a `StateWriter`/`StateReader` so there
+//! are no dependencies on a serialization framework.
+//!
+//! The version number must be incremented whenever any component changes the
+//! fields it writes, older states are then rejected rather than misread.
+use std::error::Error;
+use std::fmt;
+
+pub(crate) const SAVE_STATE_MAGIC: &[u8; 4] = b"NESS";
+pub const SAVE_STATE_VERSION: u16 = 12;
+
+/// Represents any error which occurs when restoring a save state
+#[derive(Debug)]
+pub struct SaveStateError {
+    pub message: String,
+}
+impl Error for SaveStateError {}
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error loading save state: {}", self.message)
+    }
+}
+impl SaveStateError {
+    pub(crate) fn new(message: &str) -> Self {
+        SaveStateError {
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Accumulates the binary representation of the machine state
+#[derive(Default)]
+pub struct StateWriter {
+    buffer: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { buffer: Vec::new() }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.buffer.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    pub fn write_option_u8(&mut self, value: Option<u8>) {
+        self.write_bool(value.is_some());
+        self.write_u8(value.unwrap_or(0));
+    }
+
+    pub fn write_option_u16(&mut self, value: Option<u16>) {
+        self.write_bool(value.is_some());
+        self.write_u16(value.unwrap_or(0));
+    }
+
+    pub fn write_option_u32(&mut self, value: Option<u32>) {
+        self.write_bool(value.is_some());
+        self.write_u32(value.unwrap_or(0));
+    }
+
+    /// Write a block of bytes whose length is known to the reader (e.g. a fixed size array)
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Write a block of bytes prefixed with its length
+    pub fn write_vec(&mut self, bytes: &[u8]) {
+        self.write_usize(bytes.len());
+        self.write_bytes(bytes);
+    }
+}
+
+/// Reads back a binary state written by a `StateWriter`
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, position: 0 }
+    }
+
+    /// Returns true if every byte of the state has been consumed
+    pub fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() - self.position < length {
+            return Err(SaveStateError::new("Unexpected end of save state"));
+        }
+
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(SaveStateError {
+                message: format!("Invalid boolean value {:02X}", v),
+            }),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, SaveStateError> {
+        Ok(self.read_u64()? as usize)
+    }
+
+    pub fn read_option_u8(&mut self) -> Result<Option<u8>, SaveStateError> {
+        let is_some = self.read_bool()?;
+        let value = self.read_u8()?;
+        Ok(if is_some { Some(value) } else { None })
+    }
+
+    pub fn read_option_u16(&mut self) -> Result<Option<u16>, SaveStateError> {
+        let is_some = self.read_bool()?;
+        let value = self.read_u16()?;
+        Ok(if is_some { Some(value) } else { None })
+    }
+
+    pub fn read_option_u32(&mut self) -> Result<Option<u32>, SaveStateError> {
+        let is_some = self.read_bool()?;
+        let value = self.read_u32()?;
+        Ok(if is_some { Some(value) } else { None })
+    }
+
+    /// Fill a buffer of known length, the counterpart to `StateWriter::write_bytes`
+    pub fn read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), SaveStateError> {
+        buffer.copy_from_slice(self.take(buffer.len())?);
+        Ok(())
+    }
+
+    /// Read a length prefixed block of bytes into a buffer which must be exactly that length
+    ///
+    /// Used for ROM/RAM regions whose size is fixed by the cartridge so a
+    /// mismatch means the state was taken from a different game.
+    pub fn read_vec_into(&mut self, buffer: &mut [u8]) -> Result<(), SaveStateError> {
+        let length = self.read_usize()?;
+        if length != buffer.len() {
+            return Err(SaveStateError {
+                message: format!("Memory block size mismatch, expected {} got {}", buffer.len(), length),
+            });
+        }
+        self.read_bytes(buffer)
+    }
+
+    /// Read a length prefixed block of bytes
+    pub fn read_vec(&mut self) -> Result<Vec<u8>, SaveStateError> {
+        let length = self.read_usize()?;
+        Ok(self.take(length)?.to_vec())
+    }
+}
+
+/// Implemented by every component which holds state that must survive a
+/// save/load cycle
+pub trait SaveState {
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+#[cfg(test)]
+mod save_state_tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_primitives() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0xAB);
+        writer.write_bool(true);
+        writer.write_u16(0x1234);
+        writer.write_u32(0xDEAD_BEEF);
+        writer.write_u64(0x0123_4567_89AB_CDEF);
+        writer.write_vec(&[1, 2, 3]);
+        writer.write_option_u16(Some(0xBEEF));
+        writer.write_option_u8(None);
+        let bytes = writer.into_bytes();
+
+        let mut reader = StateReader::new(&bytes);
+        assert_eq!(reader.read_u8().unwrap(), 0xAB);
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(reader.read_u16().unwrap(), 0x1234);
+        assert_eq!(reader.read_u32().unwrap(), 0xDEAD_BEEF);
+        assert_eq!(reader.read_u64().unwrap(), 0x0123_4567_89AB_CDEF);
+        assert_eq!(reader.read_vec().unwrap(), vec![1, 2, 3]);
+        assert_eq!(reader.read_option_u16().unwrap(), Some(0xBEEF));
+        assert_eq!(reader.read_option_u8().unwrap(), None);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_truncated_state_errors() {
+        let mut reader = StateReader::new(&[0x01]);
+        assert!(reader.read_u16().is_err());
+    }
+
+    #[test]
+    fn test_mismatched_block_size_errors() {
+        let mut writer = StateWriter::new();
+        writer.write_vec(&[0; 4]);
+        let bytes = writer.into_bytes();
+
+        let mut buffer = [0; 8];
+        assert!(StateReader::new(&bytes).read_vec_into(&mut buffer).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use debugger::CpuRegisters;
+use log::error;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// An instruction as the CPU is about to execute it, i.e. with the
+/// registers & memory as they were before the instruction ran
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub program_counter: u16,
+    /// The opcode followed by its operand bytes
+    pub bytes: Vec<u8>,
+    /// The instruction in the style of nestest.log, with the effective
+    /// address & the value there appended (e.g. `LDA $0300,X @ 0305 = 89`).
+    /// Memory mapped registers are shown as FF as reading them for real
+    /// would change their state.
+    pub disassembly: String,
+    /// Set for the unofficial opcodes
+    pub illegal: bool,
+    /// The address the instruction reads/writes or jumps to, if any
+    pub effective_address: Option<u16>,
+    /// Unlike `Nes::cpu_registers` bit 4 of the status register is clear,
+    /// it's only ever set on the copy pushed to the stack
+    pub registers: CpuRegisters,
+    pub scanline: u16,
+    pub dot: u16,
+    pub cpu_cycles: u32,
+    /// The number of frames completed since power on
+    pub frame: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// `C000  4C F5 C5  JMP $C5F5    A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+    Nestest,
+    /// `C000  4C F5 C5  JMP $C5F5    A:00 X:00 Y:00 S:FD P:nvUbdIzc V:0   H:21  Fr:0 Cyc:7`
+    Mesen,
+}
+
+impl TraceRecord {
+    pub fn format(&self, format: TraceFormat) -> String {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let registers = &self.registers;
+
+        match format {
+            TraceFormat::Nestest => format!(
+                "{:04X}  {:<8} {}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+                registers.program_counter,
+                bytes,
+                if self.illegal { "*" } else { " " },
+                self.disassembly,
+                registers.a,
+                registers.x,
+                registers.y,
+                registers.status,
+                registers.stack_pointer,
+                self.scanline,
+                self.dot,
+                self.cpu_cycles
+            ),
+            TraceFormat::Mesen => {
+                let flags = "NVUBDIZC"
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        if registers.status & (0x80 >> i) != 0 {
+                            c
+                        } else {
+                            c.to_ascii_lowercase()
+                        }
+                    })
+                    .collect::<String>();
+                format!(
+                    "{:04X}  {:<8}  {:<32} A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{} V:{:<3} H:{:<3} Fr:{} Cyc:{}",
+                    registers.program_counter,
+                    bytes,
+                    self.disassembly,
+                    registers.a,
+                    registers.x,
+                    registers.y,
+                    registers.stack_pointer,
+                    flags,
+                    self.scanline,
+                    self.dot,
+                    self.frame,
+                    self.cpu_cycles
+                )
+            }
+        }
+    }
+}
+
+/// Receives every traced instruction, implemented for closures taking a
+/// `&TraceRecord` as well as `TraceWriter`
+pub trait TraceSink {
+    fn trace(&mut self, record: &TraceRecord);
+}
+
+impl<F: FnMut(&TraceRecord)> TraceSink for F {
+    fn trace(&mut self, record: &TraceRecord) {
+        self(record)
+    }
+}
+
+/// Writes each record as a line of text in the chosen format
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    format: TraceFormat,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W, format: TraceFormat) -> Self {
+        TraceWriter { writer, format }
+    }
+}
+
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        if let Err(e) = writeln!(self.writer, "{}", record.format(self.format)) {
+            error!("Failed to write trace: {}", e);
+        }
+    }
+}
+
+impl<W: Write> Drop for TraceWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// A sink along with the instructions it's interested in, attach it to a
+/// console with `Nes::set_tracer`
+pub struct Tracer {
+    sink: Box<dyn TraceSink + Send>,
+    program_counters: RangeInclusive<u16>,
+    frames: RangeInclusive<u32>,
+}
+
+impl Tracer {
+    /// Trace every instruction
+    pub fn new<S: TraceSink + Send + 'static>(sink: S) -> Self {
+        Tracer {
+            sink: Box::new(sink),
+            program_counters: 0x0000..=0xFFFF,
+            frames: 0..=u32::MAX,
+        }
+    }
+
+    /// Only trace instructions whose address is in the range
+    pub fn with_program_counters(mut self, program_counters: RangeInclusive<u16>) -> Self {
+        self.program_counters = program_counters;
+        self
+    }
+
+    /// Only trace instructions run during the frames in the range (counting from 0 at power on)
+    pub fn with_frames(mut self, frames: RangeInclusive<u32>) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    pub(crate) fn is_tracing(&self, program_counter: u16, frame: u32) -> bool {
+        self.program_counters.contains(&program_counter) && self.frames.contains(&frame)
+    }
+
+    pub(crate) fn trace(&mut self, record: &TraceRecord) {
+        self.sink.trace(record);
+    }
+}
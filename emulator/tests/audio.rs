@@ -0,0 +1,96 @@
+extern crate rust_nes;
+
+use rust_nes::debugger::{Breakpoint, Step, StopReason};
+use rust_nes::Nes;
+use std::path::Path;
+
+/// The rom plays a short beep part way through the first second, which is
+/// the only part of it that isn't silent
+fn len_ctr_rom() -> Nes {
+    let rom_path = Path::new("..")
+        .join("roms")
+        .join("test")
+        .join("apu_test")
+        .join("rom_singles")
+        .join("1-len_ctr.nes");
+    Nes::from_file(rom_path.to_str().unwrap()).unwrap()
+}
+
+#[test]
+fn audio_samples_are_generated_each_frame() {
+    let mut nes = len_ctr_rom();
+    let mut samples = Vec::new();
+    for _ in 0..60 {
+        let before = samples.len();
+        nes.run_frame();
+        nes.drain_audio_samples_f32(&mut samples);
+
+        // 44100 / 60.0988 frames per second
+        let frame_samples = samples.len() - before;
+        assert!((660..=740).contains(&frame_samples), "{}", frame_samples);
+    }
+
+    let peak = samples.iter().fold(0f32, |max, s| max.max(s.abs()));
+    assert!(peak > 0.05 && peak <= 1.0, "{}", peak);
+}
+
+#[test]
+fn audio_sample_rate_is_configurable() {
+    let mut nes = len_ctr_rom();
+    nes.set_audio_sample_rate(48_000);
+    assert_eq!(nes.audio_sample_rate(), 48_000);
+
+    let mut samples = Vec::new();
+    for _ in 0..60 {
+        nes.run_frame();
+        nes.drain_audio_samples_i16(&mut samples);
+    }
+
+    // 60 frames is very slightly less than a second
+    assert!(samples.len() > 47_000 && samples.len() < 48_000, "{}", samples.len());
+    assert!(samples.iter().any(|&s| s.abs() > 1000));
+}
+
+/// The dmc_tests roms report their result by ear so all that can be checked
+/// is that they get through the test to the `JMP` to itself they finish on
+/// while playing a steady tone
+fn run_dmc_test(file: &str, finished_loop: u16, irq_handler: Option<u16>) {
+    let rom_path = Path::new("..").join("roms").join("test").join("dmc_tests").join(file);
+    let mut nes = Nes::from_file(rom_path.to_str().unwrap()).unwrap();
+
+    let mut breakpoints = Vec::new();
+    if let Some(address) = irq_handler {
+        breakpoints.push(nes.debugger_mut().add_breakpoint(Breakpoint::Execute(address)));
+    }
+    breakpoints.push(nes.debugger_mut().add_breakpoint(Breakpoint::Execute(finished_loop)));
+
+    for id in breakpoints {
+        let reason = (0..120)
+            .map(|_| nes.debug_step(Step::Frame))
+            .find(|reason| *reason != StopReason::StepComplete);
+        assert_eq!(reason, Some(StopReason::Breakpoint { id, access: None }));
+        nes.debugger_mut().remove_breakpoint(id);
+    }
+}
+
+#[test]
+fn dmc_buffer_retained() {
+    run_dmc_test("buffer_retained.nes", 0xE149, None);
+}
+
+#[test]
+fn dmc_latency() {
+    run_dmc_test("latency.nes", 0xE162, None);
+}
+
+/// Spins on $4015 until the sample has finished playing
+#[test]
+fn dmc_status() {
+    run_dmc_test("status.nes", 0xE14E, None);
+}
+
+/// The sample raises an IRQ on finishing which silences the DMC
+#[test]
+fn dmc_status_irq() {
+    run_dmc_test("status_irq.nes", 0xE154, Some(0xE12E));
+}
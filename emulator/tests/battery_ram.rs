@@ -0,0 +1,46 @@
+extern crate rust_nes;
+
+use rust_nes::Nes;
+use std::path::Path;
+
+fn load_rom(name: &str) -> Nes {
+    let rom_path = Path::new("..")
+        .join("roms")
+        .join("test")
+        .join("holy_mapperel")
+        .join(name);
+    Nes::from_file(rom_path.to_str().unwrap()).unwrap()
+}
+
+#[test]
+fn battery_ram_round_trips() {
+    let mut nes = load_rom("M1_P128K_C32K_S8K.nes");
+    for _ in 0..1_000_000 {
+        nes.step_cycle();
+    }
+
+    // The test rom writes a pattern to RAM on startup
+    let saved = nes.battery_ram().unwrap().to_vec();
+    assert_eq!(saved.len(), 0x2000);
+    assert!(saved.iter().any(|b| *b != 0));
+
+    let mut restored = load_rom("M1_P128K_C32K_S8K.nes");
+    restored.load_battery_ram(&saved).unwrap();
+    assert_eq!(restored.battery_ram().unwrap(), &saved[..]);
+}
+
+#[test]
+fn battery_ram_of_wrong_size_is_rejected() {
+    let mut nes = load_rom("M1_P128K_C32K_S8K.nes");
+    let before = nes.battery_ram().unwrap().to_vec();
+
+    assert!(nes.load_battery_ram(&[0xFF; 0x1000]).is_err());
+    assert_eq!(nes.battery_ram().unwrap(), &before[..]);
+}
+
+#[test]
+fn no_battery_ram_without_battery() {
+    let mut nes = load_rom("M1_P128K_C32K_W8K.nes");
+    assert!(nes.battery_ram().is_none());
+    assert!(nes.load_battery_ram(&[0; 0x2000]).is_err());
+}
@@ -0,0 +1,171 @@
+extern crate rust_nes;
+
+use rust_nes::debugger::{AccessType, AddressSpace, Breakpoint, MemoryAccess, Step, StopReason};
+use rust_nes::Nes;
+use std::path::Path;
+
+fn load_rom(path: &[&str]) -> Nes {
+    let rom_path = path
+        .iter()
+        .fold(Path::new("..").join("roms").join("test"), |p, part| p.join(part));
+    Nes::from_file(rom_path.to_str().unwrap()).unwrap()
+}
+
+/// Run whole frames until something other than the end of a frame stops execution
+fn run_until_break(nes: &mut Nes, max_frames: usize) -> StopReason {
+    for _ in 0..max_frames {
+        let reason = nes.debug_step(Step::Frame);
+        if reason != StopReason::StepComplete {
+            return reason;
+        }
+    }
+
+    StopReason::StepComplete
+}
+
+/// nestest run from C000 in its automated mode, c.f. nestest.log
+fn nestest() -> Nes {
+    let mut nes = load_rom(&["nestest.nes"]);
+    let mut registers = nes.cpu_registers();
+    registers.program_counter = 0xC000;
+    nes.set_cpu_registers(registers);
+    nes
+}
+
+#[test]
+fn step_instruction_follows_nestest_log() {
+    let mut nes = nestest();
+    let expected = [0xC5F5, 0xC5F7, 0xC5F9, 0xC5FB, 0xC5FD, 0xC72D, 0xC72E];
+    for pc in expected.iter() {
+        assert_eq!(nes.debug_step(Step::Instruction), StopReason::StepComplete);
+        assert_eq!(nes.cpu_registers().program_counter, *pc);
+    }
+    assert_eq!(nes.cpu_registers().stack_pointer, 0xFB);
+}
+
+#[test]
+fn step_over_and_out_of_subroutine() {
+    let mut nes = nestest();
+    while nes.cpu_registers().program_counter != 0xC5FD {
+        nes.debug_step(Step::Instruction);
+    }
+    let before = nes.cpu_registers();
+
+    nes.debug_step(Step::Over);
+    assert_eq!(nes.cpu_registers().program_counter, 0xC600);
+    assert_eq!(nes.cpu_registers().stack_pointer, before.stack_pointer);
+
+    let mut nes = nestest();
+    while nes.cpu_registers().program_counter != 0xC72D {
+        nes.debug_step(Step::Instruction);
+    }
+    nes.debug_step(Step::Out);
+    assert_eq!(nes.cpu_registers().program_counter, 0xC600);
+    assert_eq!(nes.cpu_registers().stack_pointer, before.stack_pointer);
+}
+
+#[test]
+fn execute_breakpoint() {
+    let mut nes = nestest();
+    let id = nes.debugger_mut().add_breakpoint(Breakpoint::Execute(0xC72D));
+
+    assert_eq!(nes.debug_step(Step::Frame), StopReason::Breakpoint { id, access: None });
+    assert_eq!(nes.cpu_registers().program_counter, 0xC72D);
+
+    // Continuing doesn't stop on the same breakpoint again straight away
+    assert!(nes.debugger_mut().remove_breakpoint(id));
+    assert!(!nes.debugger_mut().remove_breakpoint(id));
+    assert_eq!(nes.debug_step(Step::Instruction), StopReason::StepComplete);
+    assert_eq!(nes.cpu_registers().program_counter, 0xC72E);
+}
+
+#[test]
+fn cpu_watchpoint() {
+    let mut nes = nestest();
+    let id = nes.debugger_mut().add_breakpoint(Breakpoint::Watch {
+        space: AddressSpace::Cpu,
+        access: AccessType::Write,
+        addresses: 0x0010..=0x0011,
+    });
+
+    // STX $10 at C5F9
+    assert_eq!(
+        nes.debug_step(Step::Frame),
+        StopReason::Breakpoint {
+            id,
+            access: Some(MemoryAccess {
+                space: AddressSpace::Cpu,
+                access: AccessType::Write,
+                address: 0x0010,
+                value: 0x00,
+            })
+        }
+    );
+    assert_eq!(nes.cpu_registers().program_counter, 0xC5FB);
+}
+
+#[test]
+fn ppu_watchpoint_and_nmi_breakpoint() {
+    let mut nes = load_rom(&["holy_mapperel", "M0_P32K_C8K_V.nes"]);
+    let watch_id = nes.debugger_mut().add_breakpoint(Breakpoint::Watch {
+        space: AddressSpace::Ppu,
+        access: AccessType::Write,
+        addresses: 0x2000..=0x2FFF,
+    });
+    match run_until_break(&mut nes, 10) {
+        StopReason::Breakpoint {
+            id,
+            access: Some(access),
+        } => {
+            assert_eq!(id, watch_id);
+            assert_eq!(access.space, AddressSpace::Ppu);
+            assert!(access.address >= 0x2000 && access.address <= 0x2FFF);
+        }
+        reason => panic!("Unexpected stop {:?}", reason),
+    }
+
+    nes.debugger_mut().clear_breakpoints();
+    let nmi_id = nes.debugger_mut().add_breakpoint(Breakpoint::Nmi);
+    let nmi_vector = nes.peek_cpu_memory(0xFFFA) as u16 | (nes.peek_cpu_memory(0xFFFB) as u16) << 8;
+    assert_eq!(
+        run_until_break(&mut nes, 10),
+        StopReason::Breakpoint {
+            id: nmi_id,
+            access: None
+        }
+    );
+    assert_eq!(nes.cpu_registers().program_counter, nmi_vector);
+    assert_eq!(nes.ppu_position().0, 241);
+}
+
+#[test]
+fn dot_breakpoint_and_scanline_step() {
+    let mut nes = nestest();
+    let id = nes
+        .debugger_mut()
+        .add_breakpoint(Breakpoint::Dot { scanline: 100, dot: 5 });
+    assert_eq!(nes.debug_step(Step::Frame), StopReason::Breakpoint { id, access: None });
+    assert_eq!(nes.ppu_position(), (100, 5));
+
+    assert_eq!(nes.debug_step(Step::Scanline), StopReason::StepComplete);
+    assert_eq!(nes.ppu_position(), (101, 0));
+}
+
+#[test]
+fn memory_inspection_and_modification() {
+    let mut nes = nestest();
+    nes.write_cpu_memory(0x0300, 0x42);
+    assert_eq!(nes.peek_cpu_memory(0x0300), 0x42);
+    assert_eq!(nes.peek_cpu_memory(0x0B00), 0x42); // RAM mirror
+    assert_eq!(nes.peek_cpu_memory(0xC000), 0x4C);
+
+    nes.write_ppu_memory(0x2105, 0x99);
+    assert_eq!(nes.read_ppu_memory(0x2105), 0x99);
+    nes.write_ppu_memory(0x3F01, 0x2A);
+    assert_eq!(nes.read_ppu_memory(0x3F01), 0x2A);
+
+    let mut registers = nes.cpu_registers();
+    registers.a = 0x80;
+    nes.set_cpu_registers(registers);
+    assert_eq!(nes.cpu_registers().a, 0x80);
+}
@@ -0,0 +1,169 @@
+extern crate rust_nes;
+
+use rust_nes::gdb::GdbStub;
+use rust_nes::Nes;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::thread;
+
+/// A scripted debugger talking to the stub over a real socket
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn connect(address: SocketAddr) -> Self {
+        Client {
+            stream: TcpStream::connect(address).unwrap(),
+        }
+    }
+
+    fn send(&mut self, packet: &str) {
+        let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", packet, checksum).unwrap();
+    }
+
+    /// Wait for the next packet, skipping acks
+    fn receive(&mut self) -> String {
+        let mut byte = [0];
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        let mut packet = Vec::new();
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            packet.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+
+        String::from_utf8(packet).unwrap()
+    }
+
+    fn request(&mut self, packet: &str) -> String {
+        self.send(packet);
+        self.receive()
+    }
+
+    fn program_counter(&mut self) -> u16 {
+        let registers = self.request("g");
+        u16::from_str_radix(&format!("{}{}", &registers[10..12], &registers[8..10]), 16).unwrap()
+    }
+}
+
+/// Run the console through the stub until the script has finished
+fn run_script<F: FnOnce(Client) + Send + 'static>(nes: &mut Nes, script: F) {
+    let mut stub = GdbStub::listen("127.0.0.1:0").unwrap();
+    let address = stub.local_addr().unwrap();
+    let client = thread::spawn(move || script(Client::connect(address)));
+
+    while !client.is_finished() {
+        stub.run_frame(nes).unwrap();
+    }
+    client.join().unwrap();
+}
+
+fn holy_mapperel() -> Nes {
+    let rom_path = Path::new("..")
+        .join("roms")
+        .join("test")
+        .join("holy_mapperel")
+        .join("M0_P32K_C8K_V.nes");
+    Nes::from_file(rom_path.to_str().unwrap()).unwrap()
+}
+
+#[test]
+fn registers_memory_and_breakpoints() {
+    let mut nes = holy_mapperel();
+
+    run_script(&mut nes, move |mut gdb| {
+        assert_eq!(gdb.request("qSupported:multiprocess+"), "PacketSize=1000");
+        assert_eq!(gdb.request("?"), "S05");
+        assert_eq!(gdb.request("vMustReplyEmpty"), "");
+
+        // The console runs until the debugger connects and is then halted
+        let halted_at = gdb.program_counter();
+        assert_eq!(gdb.program_counter(), halted_at);
+        let nmi_vector = gdb.request("mfffa,2");
+        let nmi_handler = u16::from_str_radix(&format!("{}{}", &nmi_vector[2..4], &nmi_vector[0..2]), 16).unwrap();
+
+        assert_eq!(gdb.request("M0300,3:a1b2c3"), "OK");
+        assert_eq!(gdb.request("m300,3"), "a1b2c3");
+        assert_eq!(gdb.request("m10000,1"), "E01");
+
+        assert_eq!(gdb.request("P0=5a"), "OK");
+        assert_eq!(gdb.request("p0"), "5a");
+
+        // Single step moves on by one instruction
+        assert_eq!(gdb.request("s"), "S05");
+        assert_ne!(gdb.program_counter(), halted_at);
+
+        // Continue runs until the breakpoint on the NMI handler is hit
+        assert_eq!(gdb.request(&format!("Z0,{:x},1", nmi_handler)), "OK");
+        gdb.send("c");
+        assert_eq!(gdb.receive(), "S05");
+        assert_eq!(gdb.program_counter(), nmi_handler);
+        assert_eq!(gdb.request(&format!("z0,{:x},1", nmi_handler)), "OK");
+
+        // A write watchpoint on the stack page catches the next push
+        assert_eq!(gdb.request("Z2,100,100"), "OK");
+        gdb.send("c");
+        assert!(gdb.receive().starts_with("T05watch:1"));
+        assert_eq!(gdb.request("z2,100,100"), "OK");
+
+        // Ctrl-C halts a running console
+        gdb.send("c");
+        gdb.stream.write_all(&[0x03]).unwrap();
+        assert_eq!(gdb.receive(), "S02");
+
+        assert_eq!(gdb.request("D"), "OK");
+    });
+
+    assert!(nes.debugger().breakpoints().next().is_none());
+}
+
+#[test]
+fn memory_ranges_covering_the_whole_address_space() {
+    let mut nes = holy_mapperel();
+
+    run_script(&mut nes, move |mut gdb| {
+        assert_eq!(gdb.request("?"), "S05");
+
+        // A length of 0x10000 doesn't fit in 16 bits but is still in range from 0
+        let memory = gdb.request("m0,10000");
+        assert_eq!(memory.len(), 0x20000);
+        assert_eq!(&memory[0x600..0x606], gdb.request("m300,3"));
+        assert_eq!(gdb.request("m1,10000"), "E01");
+        assert_eq!(gdb.request("m0,ffffffff"), "E01");
+
+        assert_eq!(gdb.request("D"), "OK");
+    });
+}
+
+#[test]
+fn watchpoints_covering_the_whole_address_space() {
+    let mut nes = holy_mapperel();
+
+    run_script(&mut nes, move |mut gdb| {
+        assert_eq!(gdb.request("?"), "S05");
+
+        assert_eq!(gdb.request("Z2,ff00,10000"), "E01");
+        assert_eq!(gdb.request("Z3,ffff,2"), "E01");
+        assert_eq!(gdb.request("Z2,0,10000"), "OK");
+        gdb.send("c");
+        assert!(gdb.receive().starts_with("T05watch:"));
+        assert_eq!(gdb.request("z2,0,10000"), "OK");
+
+        assert_eq!(gdb.request("D"), "OK");
+    });
+
+    assert!(nes.debugger().breakpoints().next().is_none());
+}
@@ -0,0 +1,180 @@
+extern crate rust_nes;
+
+use rust_nes::debugger::{CpuRegisters, Step, StopReason};
+use rust_nes::Nes;
+use std::path::Path;
+
+const CARRY: u8 = 0b0000_0001;
+const ZERO: u8 = 0b0000_0010;
+const OVERFLOW: u8 = 0b0100_0000;
+const NEGATIVE: u8 = 0b1000_0000;
+
+/// Name, code and register setup for an instruction
+type Instruction = (&'static str, &'static [u8], fn(&mut CpuRegisters));
+
+/// nestest with `code` copied into RAM at 0400 and the CPU about to run it
+fn load(code: &[u8]) -> Nes {
+    let rom_path = Path::new("..").join("roms").join("test").join("nestest.nes");
+    let mut nes = Nes::from_file(rom_path.to_str().unwrap()).unwrap();
+    for (offset, byte) in code.iter().enumerate() {
+        nes.write_cpu_memory(0x0400 + offset as u16, *byte);
+    }
+    let mut registers = nes.cpu_registers();
+    registers.program_counter = 0x0400;
+    nes.set_cpu_registers(registers);
+    nes
+}
+
+/// Run a single instruction with the registers set up by `setup`
+fn execute<F: FnOnce(&mut CpuRegisters)>(code: &[u8], setup: F) -> CpuRegisters {
+    let mut nes = load(code);
+    let mut registers = nes.cpu_registers();
+    setup(&mut registers);
+    nes.set_cpu_registers(registers);
+
+    assert_eq!(nes.debug_step(Step::Instruction), StopReason::StepComplete);
+    nes.cpu_registers()
+}
+
+#[test]
+fn xaa_magic_and_kil_jam() {
+    let mut nes = load(&[0x8B, 0x0F, 0x02]); // XAA #$0F, KIL
+    nes.set_xaa_magic(0xFF);
+    let mut registers = nes.cpu_registers();
+    registers.a = 0x11;
+    registers.x = 0x3C;
+    nes.set_cpu_registers(registers);
+
+    assert_eq!(nes.debug_step(Step::Instruction), StopReason::StepComplete);
+    assert_eq!(nes.cpu_registers().a, 0x0C);
+    assert!(!nes.is_cpu_jammed());
+
+    // Stepping a jammed CPU must still return
+    assert_eq!(nes.debug_step(Step::Instruction), StopReason::StepComplete);
+    assert_eq!(nes.debug_step(Step::Instruction), StopReason::StepComplete);
+    assert!(nes.is_cpu_jammed());
+    assert_eq!(nes.cpu_registers().program_counter, 0x0403);
+}
+
+#[test]
+fn arr_carry_and_overflow_come_from_bits_6_and_5() {
+    // (A, carry in) => (A, flags out) for ARR #$FF
+    let cases = [
+        ((0xFF, true), (0xFF, NEGATIVE | CARRY)),
+        ((0x80, false), (0x40, CARRY | OVERFLOW)),
+        ((0x40, false), (0x20, OVERFLOW)),
+        ((0x01, true), (0x80, NEGATIVE)),
+        ((0x01, false), (0x00, ZERO)),
+    ];
+    for &((a, carry), (result, flags)) in cases.iter() {
+        let registers = execute(&[0x6B, 0xFF], |registers| {
+            registers.a = a;
+            registers.status = if carry { 0x24 | CARRY } else { 0x24 };
+        });
+        assert_eq!(registers.a, result, "{:02X}", a);
+        assert_eq!(
+            registers.status & (NEGATIVE | OVERFLOW | ZERO | CARRY),
+            flags,
+            "{:02X}",
+            a
+        );
+    }
+}
+
+#[test]
+fn axs_subtracts_from_a_and_x_without_borrow() {
+    // Carry in is ignored, carry out is set if there was no borrow
+    let registers = execute(&[0xCB, 0x10], |registers| {
+        registers.a = 0xF0;
+        registers.x = 0x3C;
+    });
+    assert_eq!(registers.x, 0x20);
+    assert_eq!(registers.a, 0xF0);
+    assert_eq!(registers.status & (NEGATIVE | ZERO | CARRY), CARRY);
+
+    let registers = execute(&[0xCB, 0x40], |registers| {
+        registers.a = 0xF0;
+        registers.x = 0x3C;
+        registers.status |= CARRY;
+    });
+    assert_eq!(registers.x, 0xF0);
+    assert_eq!(registers.status & (NEGATIVE | ZERO | CARRY), NEGATIVE);
+}
+
+#[test]
+fn las_ands_memory_with_the_stack_pointer() {
+    let mut nes = load(&[0xBB, 0x00, 0x05]); // LAS $0500,Y
+    nes.write_cpu_memory(0x0510, 0x5E);
+    let mut registers = nes.cpu_registers();
+    registers.y = 0x10;
+    registers.stack_pointer = 0xF3;
+    nes.set_cpu_registers(registers);
+
+    assert_eq!(nes.debug_step(Step::Instruction), StopReason::StepComplete);
+    let registers = nes.cpu_registers();
+    assert_eq!((registers.a, registers.x, registers.stack_pointer), (0x52, 0x52, 0x52));
+    assert_eq!(registers.status & (NEGATIVE | ZERO), 0);
+}
+
+#[test]
+fn unstable_stores_corrupt_the_address_when_crossing_a_page() {
+    // Each store is indexed from 06F0 by 0x20 and writes 0x03 (the value
+    // ANDed with 0x06 + 1), which replaces the high byte of 0710 so it
+    // lands at 0310 instead
+    let stores: [Instruction; 5] = [
+        ("SHX $06F0,Y", &[0x9E, 0xF0, 0x06], |r| {
+            r.x = 0x03;
+            r.y = 0x20;
+        }),
+        ("SHY $06F0,X", &[0x9C, 0xF0, 0x06], |r| {
+            r.x = 0x20;
+            r.y = 0x03;
+        }),
+        ("TAS $06F0,Y", &[0x9B, 0xF0, 0x06], |r| {
+            r.a = 0x1F;
+            r.x = 0x0B;
+            r.y = 0x20;
+        }),
+        ("AHX $06F0,Y", &[0x9F, 0xF0, 0x06], |r| {
+            r.a = 0x0F;
+            r.x = 0x33;
+            r.y = 0x20;
+        }),
+        ("AHX ($80),Y", &[0x93, 0x80], |r| {
+            r.a = 0x0F;
+            r.x = 0x33;
+            r.y = 0x20;
+        }),
+    ];
+    for &(name, code, setup) in stores.iter() {
+        let mut nes = load(code);
+        nes.write_cpu_memory(0x0080, 0xF0);
+        nes.write_cpu_memory(0x0081, 0x06);
+        nes.write_cpu_memory(0x0310, 0xAA);
+        nes.write_cpu_memory(0x0710, 0xAA);
+        let mut registers = nes.cpu_registers();
+        setup(&mut registers);
+        nes.set_cpu_registers(registers);
+
+        assert_eq!(nes.debug_step(Step::Instruction), StopReason::StepComplete);
+        assert_eq!(nes.peek_cpu_memory(0x0310), 0x03, "{}", name);
+        assert_eq!(nes.peek_cpu_memory(0x0710), 0xAA, "{}", name);
+    }
+
+    // TAS also leaves A & X in the stack pointer
+    let registers = execute(&[0x9B, 0xF0, 0x06], |r| {
+        r.a = 0x1F;
+        r.x = 0x0B;
+        r.y = 0x20;
+    });
+    assert_eq!(registers.stack_pointer, 0x0B);
+
+    // Without a page cross only the value is affected
+    let mut nes = load(&[0x9E, 0x00, 0x06]); // SHX $0600,Y
+    let mut registers = nes.cpu_registers();
+    registers.x = 0xFF;
+    registers.y = 0x10;
+    nes.set_cpu_registers(registers);
+    assert_eq!(nes.debug_step(Step::Instruction), StopReason::StepComplete);
+    assert_eq!(nes.peek_cpu_memory(0x0610), 0x07);
+}
@@ -0,0 +1,271 @@
+extern crate crc32fast;
+extern crate rust_nes;
+
+use crc32fast::Hasher;
+use rust_nes::cpu::RamPattern;
+use rust_nes::region::Region;
+use rust_nes::Nes;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn framebuffer_crc32(nes: &Nes) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(&nes.frame_buffer()[..]);
+    hasher.finalize()
+}
+
+/// Each test runs the rom to an arbitrary (mid frame, mid CPU cycle) point,
+/// takes a snapshot and then checks that both the original console and a
+/// freshly created one restored from the snapshot finish with the same
+/// framebuffer as the corresponding test in test_roms.rs
+macro_rules! save_state_tests {
+    ($($name:ident: $value:expr,)*) => {
+    $(
+        #[test]
+        fn $name() {
+            let (snapshot_cycles, cycles, expected_crc32, rom_path) = $value;
+            let rom_file = rom_path.to_str().unwrap();
+
+            let mut nes = Nes::from_file(rom_file).unwrap();
+            for _ in 0..snapshot_cycles {
+                nes.step_cycle();
+            }
+            let state = nes.save_state();
+            for _ in snapshot_cycles..cycles {
+                nes.step_cycle();
+            }
+            assert_eq!(framebuffer_crc32(&nes), expected_crc32);
+
+            let mut restored = Nes::from_file(rom_file).unwrap();
+            restored.load_state(&state).unwrap();
+            for _ in snapshot_cycles..cycles {
+                restored.step_cycle();
+            }
+            assert_eq!(framebuffer_crc32(&restored), expected_crc32);
+            assert_eq!(restored.save_state(), nes.save_state());
+        }
+    )*
+    }
+}
+
+save_state_tests! {
+    save_state_mapper_0_p32k_cr8k_v: (0x28A000 * 3 + 1, 0x50D915 * 3, 3621921473, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M0_P32K_CR8K_V.nes")),
+    save_state_mapper_1_p128k_c32k_w8k: (0x1E3313 * 3 + 2, 0x3C6627 * 3, 3934498320, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K_C32K_W8K.nes")),
+    save_state_mapper_7_p128k: (0x131100 * 3, 0x262201 * 3, 2603256516, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M7_P128K.nes")),
+    save_state_mapper_9_p128k_c64k: (0x27AEE * 3 + 1, 0x4F5DD * 3, 3084268463, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M9_P128K_C64K.nes")),
+    save_state_mmc3_irq_clocking: (0x82909 * 3 + 2, 0x105218 * 3, 4185058565, Path::new("..").join("roms").join("test").join("mmc3_test").join("rom_singles").join("1-clocking.nes")),
+    save_state_apu_test_3_irq_flag: (0xEBFD4 * 3 + 1, 0x1D7FA9 * 3, 902361631, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("3-irq_flag.nes")),
+    save_state_mmc5_exram: (0xF7047 * 3 + 1, 0x1EE08E * 3, 2025859641, Path::new("..").join("roms").join("test").join("exram").join("mmc5exram.nes")),
+    save_state_mapper_69_p128k_c64k_w8k: (0x302811 * 3 + 2, 0x605023 * 3, 2474697884, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M69_P128K_C64K_W8K.nes")),
+}
+
+/// LDA #value, STA address for each register write
+fn writes(registers: &[(u16, u8)]) -> Vec<u8> {
+    registers
+        .iter()
+        .flat_map(|&(address, value)| vec![0xA9, value, 0x8D, address as u8, (address >> 8) as u8])
+        .collect()
+}
+
+/// Writes a NES 2.0 rom (32KB PRG, 8KB CHR & 8KB PRG RAM) for `mapper` to the
+/// temp directory, for mappers without a test rom which exercises their
+/// expansion audio or CPU clocked IRQ. It runs `setup` from the fixed bank at
+/// E000 and then spins with interrupts enabled, counting IRQs at 0300 after
+/// `irq_handler` has acknowledged each one.
+fn mapper_rom(name: &str, mapper: u16, setup: &[u8], irq_handler: &[u8]) -> PathBuf {
+    let mut code = vec![0x78, 0xA2, 0xFF, 0x9A]; // SEI, LDX #$FF, TXS
+    code.extend_from_slice(setup);
+    let spin = 0xE000 + code.len() as u16 + 1;
+    code.extend_from_slice(&[0x58, 0x4C, spin as u8, (spin >> 8) as u8]); // CLI, JMP *
+    let irq = 0xE000 + code.len() as u16;
+    code.push(0x48); // PHA
+    code.extend_from_slice(irq_handler);
+    code.extend_from_slice(&[0xEE, 0x00, 0x03, 0x68, 0x40]); // INC $0300, PLA, RTI
+    let rti = 0xE000 + code.len() as u16 - 1;
+
+    let mut rom = vec![
+        b'N',
+        b'E',
+        b'S',
+        0x1A,
+        2,
+        1,
+        (mapper as u8) << 4,
+        (mapper as u8 & 0xF0) | 0b1000,
+        (mapper >> 8) as u8,
+        0,
+        0x07,
+        0,
+        0,
+        0,
+        0,
+        1,
+    ];
+    let mut prg_rom = vec![0; 0x8000];
+    prg_rom[0x6000..0x6000 + code.len()].copy_from_slice(&code);
+    prg_rom[0x7FFA..].copy_from_slice(&[rti as u8, (rti >> 8) as u8, 0x00, 0xE0, irq as u8, (irq >> 8) as u8]);
+    rom.extend_from_slice(&prg_rom);
+    rom.extend_from_slice(&[0; 0x2000]);
+
+    let path = std::env::temp_dir().join(format!("rust_nes_save_state_{}.nes", name));
+    fs::write(&path, rom).unwrap();
+    path
+}
+
+/// Each test takes a snapshot part way through the third frame of a rom from
+/// `mapper_rom` and checks that a console restored from it produces the same
+/// audio, IRQs & state over the next few frames as the original. The
+/// framebuffer of these roms never changes, so it's the audio & IRQ count
+/// that show up mapper state missing from the save state.
+macro_rules! mapper_save_state_tests {
+    ($($name:ident: $value:expr,)*) => {
+    $(
+        #[test]
+        fn $name() {
+            let (mapper, setup, irq_handler, expansion_audio): (u16, Vec<u8>, Vec<u8>, bool) = $value;
+            let rom_path = mapper_rom(stringify!($name), mapper, &setup, &irq_handler);
+            let rom_file = rom_path.to_str().unwrap();
+
+            let mut nes = Nes::from_file(rom_file).unwrap();
+            for _ in 0..250_001 {
+                nes.step_cycle();
+            }
+            let state = nes.save_state();
+            let irq_count = nes.peek_cpu_memory(0x0300);
+
+            let mut restored = Nes::from_file(rom_file).unwrap();
+            restored.load_state(&state).unwrap();
+            assert_eq!(restored.save_state(), state);
+
+            // The audio filters aren't part of the save state, so both start afresh
+            nes.set_audio_sample_rate(44_100);
+            restored.set_audio_sample_rate(44_100);
+            let (mut samples, mut restored_samples) = (Vec::new(), Vec::new());
+            for _ in 0..5 {
+                nes.run_frame();
+                nes.drain_audio_samples_f32(&mut samples);
+                restored.run_frame();
+                restored.drain_audio_samples_f32(&mut restored_samples);
+            }
+
+            assert_ne!(nes.peek_cpu_memory(0x0300), irq_count);
+            assert_eq!(samples.iter().any(|sample| sample.abs() > 0.01), expansion_audio);
+            assert_eq!(samples, restored_samples);
+            assert_eq!(restored.save_state(), nes.save_state());
+        }
+    )*
+    }
+}
+
+mapper_save_state_tests! {
+    // Both pulses & PCM, with the scanline IRQ (so rendering on), acknowledged by reading 5204
+    save_state_mmc5_audio_and_irq: (
+        5,
+        writes(&[(0x5015, 0x03), (0x5000, 0xBF), (0x5002, 0xFD), (0x5003, 0x08), (0x5004, 0x7F), (0x5006, 0x80), (0x5007, 0x09), (0x5011, 0x40), (0x5203, 0x40), (0x5204, 0x80), (0x2001, 0x18)]),
+        vec![0xAD, 0x04, 0x52],
+        true,
+    ),
+    // Both pulses & the sawtooth, with the IRQ in cycle mode
+    save_state_vrc6_audio_and_irq: (
+        24,
+        writes(&[(0xB003, 0x80), (0x9003, 0x00), (0x9000, 0x3F), (0x9001, 0x00), (0x9002, 0x82), (0xA000, 0x2A), (0xA001, 0x80), (0xA002, 0x81), (0xB000, 0x0F), (0xB001, 0x00), (0xB002, 0x83), (0xF000, 0x00), (0xF001, 0x07)]),
+        writes(&[(0xF002, 0x00)]),
+        true,
+    ),
+    // A built in OPLL instrument keyed on, with the IRQ in cycle mode
+    save_state_vrc7_audio_and_irq: (
+        85,
+        writes(&[(0xE000, 0x80), (0x9010, 0x10), (0x9030, 0x80), (0x9010, 0x30), (0x9030, 0x10), (0x9010, 0x20), (0x9030, 0x1C), (0xE010, 0x00), (0xF000, 0x07)]),
+        writes(&[(0xF010, 0x00)]),
+        true,
+    ),
+    // An iNES VRC2/VRC4 board which turns out to be a VRC4f from its IRQ
+    // writes, then uses swap mode
+    save_state_vrc4_irq: (
+        23,
+        writes(&[(0xF000, 0x00), (0xF001, 0x00), (0xF002, 0x07), (0x9002, 0x03), (0x8000, 0x01), (0xA000, 0x02)]),
+        writes(&[(0xF003, 0x00)]),
+        false,
+    ),
+    // 5B tone A following a repeating envelope, with the IRQ counter reloaded each time
+    save_state_fme7_audio_and_irq: (
+        69,
+        writes(&[(0x8000, 0x08), (0xA000, 0xC0), (0xC000, 0x07), (0xE000, 0x3E), (0xC000, 0x00), (0xE000, 0x40), (0xC000, 0x01), (0xE000, 0x00), (0xC000, 0x08), (0xE000, 0x10), (0xC000, 0x0B), (0xE000, 0x20), (0xC000, 0x0C), (0xE000, 0x00), (0xC000, 0x0D), (0xE000, 0x0E), (0x8000, 0x0E), (0xA000, 0x00), (0x8000, 0x0F), (0xA000, 0x10), (0x8000, 0x0D), (0xA000, 0x81)]),
+        writes(&[(0x8000, 0x0E), (0xA000, 0x00), (0x8000, 0x0F), (0xA000, 0x10), (0x8000, 0x0D), (0xA000, 0x81)]),
+        true,
+    ),
+    // Two wavetable channels sharing a 16 sample wave, with the IRQ counter reloaded each time
+    save_state_namco_163_audio_and_irq: (
+        19,
+        writes(&[(0xE000, 0x00), (0xF800, 0x80), (0x4800, 0x10), (0x4800, 0x32), (0x4800, 0x54), (0x4800, 0x76), (0x4800, 0x98), (0x4800, 0xBA), (0x4800, 0xDC), (0x4800, 0xFE), (0xF800, 0xF0), (0x4800, 0x00), (0x4800, 0x00), (0x4800, 0x30), (0x4800, 0x00), (0x4800, 0xF0), (0x4800, 0x00), (0x4800, 0x00), (0x4800, 0x0A), (0x4800, 0x00), (0x4800, 0x00), (0x4800, 0x20), (0x4800, 0x00), (0x4800, 0xF0), (0x4800, 0x00), (0x4800, 0x00), (0x4800, 0x1F), (0x5000, 0x00), (0x5800, 0xF0)]),
+        writes(&[(0x5000, 0x00), (0x5800, 0xF0)]),
+        true,
+    ),
+}
+
+#[test]
+fn save_state_from_different_cartridge_is_rejected() {
+    let rom_path = Path::new("..").join("roms").join("test").join("holy_mapperel");
+    let nrom = Nes::from_file(rom_path.join("M0_P32K_C8K_V.nes").to_str().unwrap()).unwrap();
+    let mut mmc1 = Nes::from_file(rom_path.join("M1_P128K.nes").to_str().unwrap()).unwrap();
+
+    assert!(mmc1.load_state(&nrom.save_state()).is_err());
+}
+
+#[test]
+fn save_state_from_different_region_is_rejected() {
+    let rom_path = Path::new("..")
+        .join("roms")
+        .join("test")
+        .join("holy_mapperel")
+        .join("M0_P32K_C8K_V.nes");
+    let ntsc = Nes::from_file(rom_path.to_str().unwrap()).unwrap();
+    let cartridge = rust_nes::get_cartridge(rom_path.to_str().unwrap()).unwrap();
+    let mut pal = Nes::with_region(cartridge, Region::Pal);
+
+    assert!(pal.load_state(&ntsc.save_state()).is_err());
+}
+
+#[test]
+fn failed_load_leaves_console_untouched() {
+    let rom_path = Path::new("..")
+        .join("roms")
+        .join("test")
+        .join("holy_mapperel")
+        .join("M0_P32K_C8K_V.nes");
+    let mut nes = Nes::from_file(rom_path.to_str().unwrap()).unwrap();
+    for _ in 0..100_000 {
+        nes.step_cycle();
+    }
+    let before = nes.save_state();
+
+    let mut other = Nes::from_file(rom_path.to_str().unwrap()).unwrap();
+    other.run_frame();
+    let mut truncated = other.save_state();
+    truncated.truncate(truncated.len() - 10);
+
+    assert!(nes.load_state(&truncated).is_err());
+    assert!(nes.load_state(b"not a save state").is_err());
+    assert_eq!(nes.save_state(), before);
+}
+
+#[test]
+fn power_cycle_returns_to_power_on_state() {
+    let rom_path = Path::new("..")
+        .join("roms")
+        .join("test")
+        .join("holy_mapperel")
+        .join("M1_P128K_C32K_W8K.nes");
+    let fresh = Nes::from_file(rom_path.to_str().unwrap()).unwrap();
+    let mut nes = Nes::from_file(rom_path.to_str().unwrap()).unwrap();
+    for _ in 0..10 {
+        nes.run_frame();
+    }
+
+    nes.power_cycle();
+    assert_eq!(nes.save_state(), fresh.save_state());
+
+    nes.set_ram_pattern(RamPattern::Ones);
+    nes.power_cycle();
+    assert!((0..0x800).all(|address| nes.peek_cpu_memory(address) == 0xFF));
+}
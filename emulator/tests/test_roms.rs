@@ -2,26 +2,35 @@ extern crate crc32fast;
 extern crate rust_nes;
 
 use crc32fast::Hasher;
+use rust_nes::region::Region;
+use rust_nes::Nes;
 use std::path::Path;
 
+/// Frames to hold off pressing reset once a test asks for it, blargg's
+/// tests want at least 100ms
+const RESET_DELAY_FRAMES: u32 = 10;
+
 macro_rules! rom_tests {
     ($($name:ident: $value:expr,)*) => {
     $(
         #[test]
         fn $name() {
             let (cycles, expected_crc32, rom_path) = $value;
-            let cartridge = rust_nes::get_cartridge(rom_path.to_str().unwrap()).unwrap();
-            let framebuffer = rust_nes::run_headless_cycles(cartridge, cycles);
-            let mut hasher = Hasher::new();
-            hasher.update(&framebuffer);
-            let actual_crc32 = hasher.finalize();
-
-            assert_eq!(
-                actual_crc32,
-                expected_crc32,
-                "{}",
-                framebuffer_to_ascii_art(framebuffer)
-            );
+            check_framebuffer(run_cycles(&rom_path, None, cycles), expected_crc32);
+        }
+    )*
+    }
+}
+
+/// As rom_tests but for roms which need a particular region (most don't say
+/// which they want in their header)
+macro_rules! region_rom_tests {
+    ($($name:ident: $value:expr,)*) => {
+    $(
+        #[test]
+        fn $name() {
+            let (region, cycles, expected_crc32, rom_path) = $value;
+            check_framebuffer(run_cycles(&rom_path, Some(region), cycles), expected_crc32);
         }
     )*
     }
@@ -30,10 +39,23 @@ macro_rules! rom_tests {
 rom_tests! {
     // ----- General CPU Tests -----
     blargg_nes_cpu_test_official: (0x13399B3 * 3 as usize, 2605351162, Path::new("..").join("roms").join("test").join("blargg_nes_cpu_test5").join("official.nes")),
+    blargg_nes_cpu_test_all: (0x2001A1B * 3 as usize, 2605351162, Path::new("..").join("roms").join("test").join("blargg_nes_cpu_test5").join("cpu.nes")),
     instr_test_official_only: (0x33B7410 * 3 as usize, 216765697, Path::new("..").join("roms").join("test").join("instr_test-v3").join("official_only.nes")),
+    instr_test_all_instrs: (0x4274F99 * 3 as usize, 216765697, Path::new("..").join("roms").join("test").join("instr_test-v3").join("all_instrs.nes")),
     cpu_timing_test: (0x11EB284 * 3 as usize, 377355712, Path::new("..").join("roms").join("test").join("cpu_timing_test6").join("cpu_timing_test.nes")),
-    // instr_misc:  (0x11EB284 * 3 as usize, 377355712, Path::new("..").join("roms").join("test").join("instr_misc").join("instr_misc.nes")), - Requires unofficial opcodes
-    // instr_timing:  (0x11EB284 * 3 as usize, 377355712, Path::new("..").join("roms").join("test").join("instr_timing").join("instr_timing.nes")), - Requires unofficial opcodes
+    instr_misc: (0x6AC3CD * 3 as usize, 1186420065, Path::new("..").join("roms").join("test").join("instr_misc").join("instr_misc.nes")),
+    instr_timing: (0x252CE5B * 3 as usize, 1579151107, Path::new("..").join("roms").join("test").join("instr_timing").join("instr_timing.nes")),
+    nes_instr_test_01_implied: (0x21263D * 3 as usize, 4169284716, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("01-implied.nes")),
+    nes_instr_test_02_immediate: (0x1E6C41 * 3 as usize, 2177097374, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("02-immediate.nes")),
+    nes_instr_test_03_zero_page: (0x269A35 * 3 as usize, 480290586, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("03-zero_page.nes")),
+    nes_instr_test_04_zp_xy: (0x5396F3 * 3 as usize, 1730330780, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("04-zp_xy.nes")),
+    nes_instr_test_05_absolute: (0x24C8E2 * 3 as usize, 444256688, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("05-absolute.nes")),
+    nes_instr_test_06_abs_xy: (0x72F1C5 * 3 as usize, 1018472223, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("06-abs_xy.nes")),
+    nes_instr_test_07_ind_x: (0x33C7CC * 3 as usize, 3979056080, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("07-ind_x.nes")),
+    nes_instr_test_08_ind_y: (0x318225 * 3 as usize, 3880475298, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("08-ind_y.nes")),
+    nes_instr_test_09_branches: (0x138451 * 3 as usize, 3473570636, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("09-branches.nes")),
+    nes_instr_test_10_stack: (0x3E3B68 * 3 as usize, 3098301673, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("10-stack.nes")),
+    nes_instr_test_11_special: (0x910B6 * 3 as usize, 2789903936, Path::new("..").join("roms").join("test").join("nes_instr_test").join("rom_singles").join("11-special.nes")),
     cpu_dummy_reads: (0x18F464 * 3 as usize, 2170164011, Path::new("..").join("roms").join("test").join("cpu_dummy_reads").join("cpu_dummy_reads.nes")),
     cpu_dummy_writes_oam: (0xB45D59 * 3 as usize, 3847704951, Path::new("..").join("roms").join("test").join("cpu_dummy_writes").join("cpu_dummy_writes_oam.nes")),
     // cpu_dummy_writes_ppumem: (0xB45D59 * 3 as usize, 3847704951, Path::new("..").join("roms").join("test").join("cpu_dummy_writes").join("cpu_dummy_writes_ppumem.nes")), # Opcodes are fine but open bus behaviour is wrong apparently
@@ -44,20 +66,38 @@ rom_tests! {
     cpu_interrupts_1_cli_delay:  (0x8987A * 3 as usize, 459637199, Path::new("..").join("roms").join("test").join("cpu_interrupts_v2").join("rom_singles").join("1-cli_latency.nes")),
     //cpu_interrupts_2_nmi_brk:  (0x138066 * 3 as usize, 459637199, Path::new("..").join("roms").join("test").join("cpu_interrupts_v2").join("rom_singles").join("2-nmi_and_brk.nes")),
 
+    // ----- Reset Tests -----
+    cpu_reset_ram_after_reset: (0x4AF495 * 3 as usize, 4175055718, Path::new("..").join("roms").join("test").join("cpu_reset").join("ram_after_reset.nes")),
+    cpu_reset_registers: (0x4CC5E7 * 3 as usize, 3171764897, Path::new("..").join("roms").join("test").join("cpu_reset").join("registers.nes")),
+    apu_reset_4015_cleared: (0x13F8A3 * 3 as usize, 1321021581, Path::new("..").join("roms").join("test").join("apu_reset").join("4015_cleared.nes")),
+    apu_reset_4017_timing: (0x1BB242 * 3 as usize, 3627816985, Path::new("..").join("roms").join("test").join("apu_reset").join("4017_timing.nes")),
+    apu_reset_4017_written: (0x228335 * 3 as usize, 3632505875, Path::new("..").join("roms").join("test").join("apu_reset").join("4017_written.nes")),
+    apu_reset_irq_flag_cleared: (0x14E14C * 3 as usize, 817144836, Path::new("..").join("roms").join("test").join("apu_reset").join("irq_flag_cleared.nes")),
+    apu_reset_len_ctrs_enabled: (0x1555A1 * 3 as usize, 4164270913, Path::new("..").join("roms").join("test").join("apu_reset").join("len_ctrs_enabled.nes")),
+    apu_reset_works_immediately: (0x16B29F * 3 as usize, 529948495, Path::new("..").join("roms").join("test").join("apu_reset").join("works_immediately.nes")),
+
     // ----- General PPU Tests -----
     blargg_nes_ppu_test_palette_ram: (0xD23D0 * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("blargg_ppu_tests_2005.09.15b").join("palette_ram.nes")),
     blargg_nes_ppu_test_sprite_ram: (0xD23D0 * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("blargg_ppu_tests_2005.09.15b").join("sprite_ram.nes")),
     blargg_nes_ppu_test_vbl_clear_time: (0xD23D0 * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("blargg_ppu_tests_2005.09.15b").join("vbl_clear_time.nes")),
     blargg_nes_ppu_test_vram_access: (0xD23D0 * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("blargg_ppu_tests_2005.09.15b").join("vram_access.nes")),
-    // ppu_open_bus: (0x1C22B4 * 3 as usize, 3764449243, Path::new("..").join("roms").join("test").join("ppu_open_bus").join("ppu_open_bus.nes")), - Not working, claims because no decay
-    // ppu_read_buffer: (0x1C22B4 * 3 as usize, 3764449243, Path::new("..").join("roms").join("test").join("ppu_read_buffer").join("test_ppu_read_buffer.nes")), - Fails on several counts, likely quite badly implemented read buffer
+    ppu_open_bus: (0x73661B * 3 as usize, 4048974158, Path::new("..").join("roms").join("test").join("ppu_open_bus").join("ppu_open_bus.nes")),
+    // Captured after the long "DMA with RAM" test, every row OK & "Passed"
+    ppu_read_buffer: (0x8802AF7 * 3 as usize, 2969645576, Path::new("..").join("roms").join("test").join("ppu_read_buffer").join("test_ppu_read_buffer.nes")),
+
+    full_palette: (0x1055FF * 3 as usize, 1342736040, Path::new("..").join("roms").join("test").join("full_palette").join("full_palette.nes")),
+    full_palette_smooth: (0x1055FF * 3 as usize, 1737436042, Path::new("..").join("roms").join("test").join("full_palette").join("full_palette_smooth.nes")),
 
     // ----- DMA/DMC Specific Tests -----
-    //dma_2007_read: (0xD23D0 * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("dmc_dma_during_read4").join("dma_2007_read.nes")), - Fails, unclear why
+    dma_2007_read: (0xD23D0 * 3 as usize, 2773288387, Path::new("..").join("roms").join("test").join("dmc_dma_during_read4").join("dma_2007_read.nes")),
     dma_2007_write: (0xFDDCD * 3 as usize, 1314372172, Path::new("..").join("roms").join("test").join("dmc_dma_during_read4").join("dma_2007_write.nes")),
-    //dma_4016_read: (0xD23D0 * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("dmc_dma_during_read4").join("dma_4016_read.nes")), - Fails, unclear why
-    //double_2007_read: (0xD23D0 * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("dmc_dma_during_read4").join("double_2007_read.nes")), - Fails, unclear why
+    dma_4016_read: (0xD23D0 * 3 as usize, 405774534, Path::new("..").join("roms").join("test").join("dmc_dma_during_read4").join("dma_4016_read.nes")),
+    // Prints 85CFD627, one of the results the rom's source lists for hardware (depending on CPU-PPU alignment)
+    double_2007_read: (0xD23D0 * 3 as usize, 2525414151, Path::new("..").join("roms").join("test").join("dmc_dma_during_read4").join("double_2007_read.nes")),
     read_write_2007: (0xFDDCD * 3 as usize, 2762297165, Path::new("..").join("roms").join("test").join("dmc_dma_during_read4").join("read_write_2007.nes")),
+    //sprdma_and_dmc_dma: (0x58223A * 3 as usize, 824118475, Path::new("..").join("roms").join("test").join("sprdma_and_dmc_dma").join("sprdma_and_dmc_dma.nes")), - Fails, the table of cycle counts has CRC 0AD2A18E but the rom expects FBADA48D (DMC DMA at the start of OAM DMA)
+    //sprdma_and_dmc_dma_512: (0x582239 * 3 as usize, 4294284654, Path::new("..").join("roms").join("test").join("sprdma_and_dmc_dma").join("sprdma_and_dmc_dma_512.nes")), - Fails, the table of cycle counts has CRC E39B9BA3 but the rom expects F1A58F55 (DMC DMA at the end of OAM DMA)
+    // dmc_tests/*.nes report their result by sound so are run in tests/audio.rs instead
 
     // ----- OAM Specific Tests -----
     oam_read: (0x1C22B4 * 3 as usize, 3764449243, Path::new("..").join("roms").join("test").join("oam_read").join("oam_read.nes")),
@@ -87,38 +127,44 @@ rom_tests! {
 
     // ----- Sprite Overflow Tests
     sprite_overflow: (0xDAFD85 * 3 as usize, 1808572613, Path::new("..").join("roms").join("test").join("ppu_sprite_overflow").join("ppu_sprite_overflow.nes")),
+    sprite_overflow_01_basics: (0xD27B0 * 3 as usize, 2445173019, Path::new("..").join("roms").join("test").join("ppu_sprite_overflow").join("rom_singles").join("01-basics.nes")),
+    sprite_overflow_02_details: (0x1055FF * 3 as usize, 3107147523, Path::new("..").join("roms").join("test").join("ppu_sprite_overflow").join("rom_singles").join("02-details.nes")),
+    sprite_overflow_03_timing: (0x933534 * 3 as usize, 1995381274, Path::new("..").join("roms").join("test").join("ppu_sprite_overflow").join("rom_singles").join("03-timing.nes")),
+    sprite_overflow_04_obscure: (0xE105A * 3 as usize, 1075496939, Path::new("..").join("roms").join("test").join("ppu_sprite_overflow").join("rom_singles").join("04-obscure.nes")),
+    sprite_overflow_05_emulator: (0xA6DB3 * 3 as usize, 1262883020, Path::new("..").join("roms").join("test").join("ppu_sprite_overflow").join("rom_singles").join("05-emulator.nes")),
 
     // ----- Mapper Tests -----
-    mapper_0_p32k_c8k_v: (0x309599 * 3 as usize, 1798638175, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M0_P32K_C8K_V.nes")),
-    mapper_0_p32k_cr8k_v: (0x50D915 * 3 as usize, 3474562170, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M0_P32K_CR8K_V.nes")),
-    // TODO - Below is likely wrong, we don't have 32KB CHR RAM in the screenshot
-    mapper_0_p32k_cr32k_v: (0x4C4DC8 * 3 as usize, 3474562170, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M0_P32K_CR32K_V.nes")),
-    mapper_1_no_chrom: (0x4F7C0F * 3 as usize, 1531525988, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K.nes")),
-    mapper_1_p128k_c32k: (0x3C6627 * 3 as usize, 3934498320, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K_C32K.nes")),
+    mapper_0_p32k_c8k_v: (0x309599 * 3 as usize, 1942926564, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M0_P32K_C8K_V.nes")),
+    mapper_0_p32k_cr8k_v: (0x50D915 * 3 as usize, 3621921473, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M0_P32K_CR8K_V.nes")),
+    // NROM has no CHR banking so only 8KB of the 32KB CHR RAM in the header can be seen, the screen says 8K instead
+    //mapper_0_p32k_cr32k_v: (0x4C4DC8 * 3 as usize, 3621921473, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M0_P32K_CR32K_V.nes")), - Fails, detected as 8K CHR RAM
+    mapper_1_no_chrom: (0x4F7C0F * 3 as usize, 3715851250, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K.nes")),
+    mapper_1_p128k_c32k: (0x3C6627 * 3 as usize, 59408438, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K_C32K.nes")),
     mapper_1_p128k_c32k_s8k: (0x3C6627 * 3 as usize, 3934498320, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K_C32K_S8K.nes")),
     mapper_1_p128k_c32k_w8k: (0x3C6627 * 3 as usize, 3934498320, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K_C32K_W8K.nes")),
-    mapper_1_p128k_c128k: (0x3C6627 * 3 as usize, 2354549445, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K_C128K.nes")),
+    mapper_1_p128k_c128k: (0x3C6627 * 3 as usize, 3898902527, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K_C128K.nes")),
     mapper_1_p128k_c128k_s8k: (0x3C6627 * 3 as usize, 2354549445, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K_C128K_S8K.nes")),
     mapper_1_p128k_c128k_w8k: (0x3C6627 * 3 as usize, 2354549445, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M1_P128K_C128K_W8K.nes")),
     mapper_2_p128k_cr8k_v: (0x253959 * 3 as usize, 1058817094, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M2_P128K_CR8K_V.nes")),
     mapper_2_p128k_v: (0x24C505 * 3 as usize, 3178533875, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M2_P128K_V.nes")),
-    mapper_3: (0x32DB40 * 3 as usize, 2221445495, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M3_P32K_C32K_H.nes")),
+    mapper_3: (0x32DB40 * 3 as usize, 2209195700, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M3_P32K_C32K_H.nes")),
     mapper_4_no_chrom: (0x30213C * 3 as usize, 3944012330, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M4_P128K.nes")),
     mapper_4_p128k_cr8k: (0x277EF7 * 3 as usize, 1769737631, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M4_P128K_CR8K.nes")),
-    // TODO - Below is likely wrong, we don't have 32KB CHR RAM in the screenshot
-    mapper_4_p128k_cr32k: (0x28DBF4 * 3 as usize, 1769737631, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M4_P128K_CR32K.nes")),
+    mapper_4_p128k_cr32k: (0x9006EC * 3 as usize, 3224606539, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M4_P128K_CR32K.nes")),
     mapper_4_p256k_c256k: (0xC3B1E * 3 as usize, 502837231, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M4_P256K_C256K.nes")),
     mapper_7_p128k: (0x262201 * 3 as usize, 2603256516, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M7_P128K.nes")),
     mapper_7_p128k_cr8k: (0x262201 * 3 as usize, 423779697, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M7_P128K_CR8K.nes")),
     mapper_9_p128k_c64k: (0x4F5DD * 3 as usize, 3084268463, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M9_P128K_C64K.nes")),
-    mapper_10_p128k_c64k_s8k: (0x1C9707 * 3 as usize, 2938351879, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M10_P128K_C64K_S8K.nes")),
-    mapper_10_p128k_c64k_w8k: (0x10521E * 3 as usize, 2938351879, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M10_P128K_C64K_W8K.nes")),
+    mapper_10_p128k_c64k_s8k: (0x302525 * 3 as usize, 3084390332, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M10_P128K_C64K_S8K.nes")),
+    mapper_10_p128k_c64k_w8k: (0x302525 * 3 as usize, 3084390332, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M10_P128K_C64K_W8K.nes")),
     mapper_11_p64k_c64k_v: (0x113AC6 * 3 as usize, 2383587170, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M11_P64K_C64K_V.nes")),
     // TODO - Below renders as BNROM in holy mapperel instead of color dreams because I don't bank CHRRAM
     // mapper_11_p64k_c64k_v: (0x113AC6 * 3 as usize, 2383587170, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M11_P64K_CR32K_V.nes")),
     mapper_34_p128k_h: (0x38C38A * 3 as usize, 3229261591, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M34_P128K_H.nes")),
     mapper_34_p128k_cr8k_h: (0x2A38FA * 3 as usize, 1108494498, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M34_P128K_CR8K_H.nes")),
     mapper_66_p64k_c16k_v: (0x19DD0C * 3 as usize, 2221445495, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M66_P64K_C16K_V.nes")),
+    mapper_69_p128k_c64k_s8k: (0x605023 * 3 as usize, 2474697884, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M69_P128K_C64K_S8K.nes")),
+    mapper_69_p128k_c64k_w8k: (0x605023 * 3 as usize, 2474697884, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M69_P128K_C64K_W8K.nes")),
     mapper_180_p128k_cr8k_h: (0x2A38FA * 3 as usize, 3038721105, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M180_P128K_CR8K_H.nes")),
     mapper_180_p128k_h: (0x2B95F7 * 3 as usize, 930604004, Path::new("..").join("roms").join("test").join("holy_mapperel").join("M180_P128K_H.nes")),
 
@@ -130,6 +176,9 @@ rom_tests! {
     mmc3_irq_mmc3: (0x163A62 * 3 as usize, 144123581, Path::new("..").join("roms").join("test").join("mmc3_test").join("rom_singles").join("5-MMC3.nes")),
     //mmc3_irq_mmc3_alt: (0x90CD6 * 3 as usize, 3691845950, Path::new("..").join("roms").join("test").join("mmc3_test").join("rom_singles").join("6-MMC3_alt.nes")), // Failed #2 - Don't think I support the MMC3 alternate board
 
+    // ----- MMC5 Tests -----
+    mmc5_exram: (0x1EE08E * 3 as usize, 2025859641, Path::new("..").join("roms").join("test").join("exram").join("mmc5exram.nes")),
+
     // ----- APU Tests -----
     apu_test_1_length_counter: (0x1551B8 * 3 as usize, 1135491406, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("1-len_ctr.nes")),
     apu_test_2_length_table: (0x1AC5AD * 3 as usize, 1850311913, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("2-len_table.nes")),
@@ -137,8 +186,8 @@ rom_tests! {
     apu_test_4_jitter: (0x18F45C * 3 as usize, 2672842930, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("4-jitter.nes")),
     apu_test_5_length_timing: (0x3B7D82 * 3 as usize, 1825584722, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("5-len_timing.nes")),
     apu_test_6_irq_flag_timing: (0x146910 * 3 as usize, 1222179157, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("6-irq_flag_timing.nes")),
-    //apu_test_7_dmc_basics: (0x1AC5AD * 3 as usize, 1850311913, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("7-dmc_basics.nes")), // DMC channel not implemented
-    //apu_test_8_dmc_rates: (0x1AC5AD * 3 as usize, 1850311913, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("8-dmc_rates.nes")), // DMC channel not implemented
+    apu_test_7_dmc_basics: (0x401000 * 3 as usize, 3989170813, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("7-dmc_basics.nes")),
+    apu_test_8_dmc_rates: (0x401000 * 3 as usize, 4281565915, Path::new("..").join("roms").join("test").join("apu_test").join("rom_singles").join("8-dmc_rates.nes")),
     apu_test_01_length_counter: (0x1551B9 * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("blargg_apu_2005.07.30").join("01.len_ctr.nes")),
     apu_test_02_length_table: (0x10C66A * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("blargg_apu_2005.07.30").join("02.len_table.nes")),
     apu_test_03_irq_flag: (0x163A61 * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("blargg_apu_2005.07.30").join("03.irq_flag.nes")),
@@ -152,6 +201,64 @@ rom_tests! {
     // apu_test_11_len_reload_timing: (0xF696D * 3 as usize, 1300901188, Path::new("..").join("roms").join("test").join("blargg_apu_2005.07.30").join("11.len_reload_timing.nes")), // Failing #04
 }
 
+region_rom_tests! {
+    // ----- PAL APU Tests -----
+    pal_apu_test_01_length_counter: (Region::Pal, 0x205BC7 * 3 as usize, 1741287031, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("01.len_ctr.nes")),
+    pal_apu_test_02_length_table: (Region::Pal, 0x205BC7 * 3 as usize, 3241448699, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("02.len_table.nes")),
+    pal_apu_test_03_irq_flag: (Region::Pal, 0x205BC7 * 3 as usize, 152197957, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("03.irq_flag.nes")),
+    pal_apu_test_04_clock_jitter: (Region::Pal, 0x205BC7 * 3 as usize, 4281493712, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("04.clock_jitter.nes")),
+    pal_apu_test_05_len_timing_mode0: (Region::Pal, 0x205BC7 * 3 as usize, 4255735659, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("05.len_timing_mode0.nes")),
+    pal_apu_test_06_len_timing_mode1: (Region::Pal, 0x205BC7 * 3 as usize, 2371155804, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("06.len_timing_mode1.nes")),
+    pal_apu_test_07_irq_flag_timing: (Region::Pal, 0x205BC7 * 3 as usize, 600502799, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("07.irq_flag_timing.nes")),
+    //pal_apu_test_08_irq_timing: (Region::Pal, 0x205BC7 * 3 as usize, 134282763, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("08.irq_timing.nes")), - Failing #3 as on NTSC
+    //pal_apu_test_10_len_halt_timing: (Region::Pal, 0x205BC7 * 3 as usize, 4255341393, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("10.len_halt_timing.nes")), - Failing #3 as on NTSC
+    //pal_apu_test_11_len_reload_timing: (Region::Pal, 0x205BC7 * 3 as usize, 145485468, Path::new("..").join("roms").join("test").join("pal_apu_tests").join("11.len_reload_timing.nes")), - Failing #4 as on NTSC
+}
+
+fn check_framebuffer(framebuffer: [u8; (256 * 240 * 4) as usize], expected_crc32: u32) {
+    let mut hasher = Hasher::new();
+    hasher.update(&framebuffer);
+    let actual_crc32 = hasher.finalize();
+
+    assert_eq!(
+        actual_crc32,
+        expected_crc32,
+        "{}",
+        framebuffer_to_ascii_art(framebuffer)
+    );
+}
+
+/// Run a rom for N PPU cycles and return the framebuffer, the region comes
+/// from the rom header unless given. Tests which report through 0x6000 write
+/// 0x81 there when they need the reset button pressing, which is done (once)
+/// a few frames later.
+fn run_cycles(rom_path: &Path, region: Option<Region>, cycles: usize) -> [u8; (256 * 240 * 4) as usize] {
+    let cartridge = rust_nes::get_cartridge(rom_path.to_str().unwrap()).unwrap();
+    let mut nes = match region {
+        Some(region) => Nes::with_region(cartridge, region),
+        None => Nes::new(cartridge),
+    };
+    let mut frames_waiting_for_reset = 0;
+
+    for _ in 0..cycles {
+        nes.step_cycle();
+
+        if nes.is_frame_complete() {
+            let signature = [0x6001, 0x6002, 0x6003].iter().map(|a| nes.peek_cpu_memory(*a));
+            if signature.eq([0xDE, 0xB0, 0x61].iter().copied()) && nes.peek_cpu_memory(0x6000) == 0x81 {
+                frames_waiting_for_reset += 1;
+                if frames_waiting_for_reset == RESET_DELAY_FRAMES {
+                    nes.reset();
+                }
+            } else {
+                frames_waiting_for_reset = 0;
+            }
+        }
+    }
+
+    *nes.frame_buffer()
+}
+
 const ASCII_GRAYSCALE_ARRAY: [char; 96] = [
     '.', '-', '`', '\'', ',', ':', '_', ';', '~', '\\', '"', '/', '!', '|', '\\', '\\', 'i', '^', 't', 'r', 'c', '*',
     'v', '?', 's', '(', ')', '+', 'l', 'j', '1', '=', 'e', '{', '[', ']', 'z', '}', '<', 'x', 'o', '7', 'f', '>', 'a',
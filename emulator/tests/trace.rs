@@ -0,0 +1,121 @@
+extern crate rust_nes;
+
+use rust_nes::trace::{TraceFormat, TraceRecord, Tracer};
+use rust_nes::Nes;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::channel;
+
+const NESTEST_LOG_REGISTERS_END: usize =
+    "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD".len();
+
+fn load_rom(path: &[&str]) -> Nes {
+    let rom_path = path
+        .iter()
+        .fold(Path::new("..").join("roms").join("test"), |p, part| p.join(part));
+    Nes::from_file(rom_path.to_str().unwrap()).unwrap()
+}
+
+/// nestest run from C000 in its automated mode, c.f. nestest.log
+fn nestest() -> Nes {
+    let mut nes = load_rom(&["nestest.nes"]);
+    let mut registers = nes.cpu_registers();
+    registers.program_counter = 0xC000;
+    nes.set_cpu_registers(registers);
+    nes
+}
+
+/// Run a number of instructions, returning the records the tracer saw
+fn trace(nes: &mut Nes, instructions: usize, filter: fn(Tracer) -> Tracer) -> Vec<TraceRecord> {
+    let (sender, receiver) = channel();
+    nes.set_tracer(filter(Tracer::new(move |record: &TraceRecord| {
+        sender.send(record.clone()).unwrap()
+    })));
+
+    for _ in 0..instructions {
+        nes.step_instruction();
+    }
+    nes.take_tracer();
+
+    receiver.try_iter().collect()
+}
+
+#[test]
+fn trace_matches_nestest_log() {
+    let log = fs::read_to_string(Path::new("..").join("roms").join("test").join("nestest.log")).unwrap();
+    let expected: Vec<&str> = log.lines().collect();
+
+    let mut nes = nestest();
+    let records = trace(&mut nes, expected.len(), |tracer| tracer);
+    assert_eq!(records.len(), expected.len());
+
+    for (i, (record, line)) in records.iter().zip(expected.iter()).enumerate() {
+        // Nintendulator shows the target of JMP ($02FF) without the page wrapping
+        // bug, but then jumps to the right place (0300) on the next line
+        if line.starts_with("DBB5") {
+            continue;
+        }
+        assert_eq!(
+            &record.format(TraceFormat::Nestest)[..NESTEST_LOG_REGISTERS_END],
+            &line[..NESTEST_LOG_REGISTERS_END],
+            "nestest.log line {}",
+            i + 1
+        );
+    }
+
+    // The log gives the PPU position of each instruction ("CYC:  0 SL:241"),
+    // compare the time taken by each instruction rather than absolute values
+    let dot = |line: &str| {
+        let cyc = line[line.find("CYC:").unwrap() + 4..]
+            .split_whitespace()
+            .next()
+            .unwrap();
+        // The pre-render line is shown as -1
+        let scanline = match line[line.find("SL:").unwrap() + 3..].trim() {
+            "-1" => 261,
+            scanline => scanline.parse::<u32>().unwrap(),
+        };
+        scanline * 341 + cyc.parse::<u32>().unwrap()
+    };
+    for (i, (records, lines)) in records.windows(2).zip(expected.windows(2)).enumerate() {
+        let expected_dots = (dot(lines[1]) + 341 * 262 - dot(lines[0])) % (341 * 262);
+        assert_eq!(
+            (records[1].cpu_cycles - records[0].cpu_cycles) * 3,
+            expected_dots,
+            "nestest.log line {}",
+            i + 1
+        );
+    }
+}
+
+#[test]
+fn trace_filters() {
+    let records = trace(&mut nestest(), 100, |tracer| {
+        tracer.with_program_counters(0xC72D..=0xC72E)
+    });
+    assert!(!records.is_empty());
+    assert!(records
+        .iter()
+        .all(|r| r.program_counter == 0xC72D || r.program_counter == 0xC72E));
+    assert_eq!(records[0].disassembly, "NOP");
+    assert_eq!(records[1].disassembly, "SEC");
+
+    let mut nes = load_rom(&["holy_mapperel", "M0_P32K_C8K_V.nes"]);
+    let records = trace(&mut nes, 50_000, |tracer| tracer.with_frames(2..=3));
+    assert!(!records.is_empty());
+    assert!(records.iter().all(|r| r.frame == 2 || r.frame == 3));
+    assert_eq!(records.first().unwrap().frame, 2);
+    assert_eq!(records.last().unwrap().frame, 3);
+}
+
+#[test]
+fn mesen_format() {
+    let records = trace(&mut nestest(), 1, |tracer| tracer);
+    assert_eq!(
+        records[0].format(TraceFormat::Mesen),
+        format!(
+            "C000  4C F5 C5  JMP $C5F5                        A:00 X:00 Y:00 S:FD P:nvUbdIzc V:{:<3} H:{:<3} Fr:0 Cyc:{}",
+            records[0].scanline, records[0].dot, records[0].cpu_cycles
+        )
+    );
+}
@@ -0,0 +1,59 @@
+extern crate rust_nes;
+
+use rust_nes::ppu::palette::Palette;
+use rust_nes::Nes;
+use std::path::Path;
+
+fn full_palette_rom() -> String {
+    Path::new("..")
+        .join("roms")
+        .join("test")
+        .join("full_palette")
+        .join("full_palette.nes")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn indexed_frame_buffer_is_off_by_default() {
+    let mut nes = Nes::from_file(&full_palette_rom()).unwrap();
+    assert!(nes.indexed_frame_buffer().is_none());
+
+    nes.set_indexed_output(true);
+    assert!(nes.indexed_frame_buffer().is_some());
+
+    nes.set_indexed_output(false);
+    assert!(nes.indexed_frame_buffer().is_none());
+}
+
+#[test]
+fn indexed_frame_buffer_matches_rgb_frame_buffer() {
+    let mut nes = Nes::from_file(&full_palette_rom()).unwrap();
+    nes.set_indexed_output(true);
+    for _ in 0..0x1055FF * 3 {
+        nes.step_cycle();
+    }
+
+    let palette = Palette::default();
+    let indexed = nes.indexed_frame_buffer().unwrap();
+    let rgb = nes.frame_buffer();
+    let mut seen = [false; 0x200];
+    for (pixel, &index) in indexed.iter().enumerate() {
+        assert!(index < 0x200);
+        seen[index as usize] = true;
+
+        let colour = palette.colour(index);
+        let expected = [colour as u8, (colour >> 8) as u8, (colour >> 16) as u8];
+        assert_eq!(
+            rgb[pixel * 4..pixel * 4 + 3],
+            expected,
+            "pixel {} index {:03X}",
+            pixel,
+            index
+        );
+    }
+
+    // The rom draws all 64 colours under every combination of emphasis bits
+    assert!(seen.iter().filter(|&&s| s).count() > 0x100);
+}
@@ -16,9 +16,10 @@ struct Opts {
 #[derive(Debug, Serialize)]
 struct RomResult {
     filename: String,
-    mapper: Option<u8>,
-    prg_16kb_units: Option<u8>,
-    chr_8kb_banks: Option<u8>,
+    mapper: Option<u16>,
+    submapper: Option<u8>,
+    prg_16kb_units: Option<u16>,
+    chr_8kb_banks: Option<u16>,
     failure: Option<String>,
 }
 
@@ -39,6 +40,7 @@ fn main() -> std::io::Result<()> {
             Err(why) => RomResult {
                 filename,
                 mapper: why.mapper,
+                submapper: None,
                 prg_16kb_units: None,
                 chr_8kb_banks: None,
                 failure: Some(why.message),
@@ -46,6 +48,7 @@ fn main() -> std::io::Result<()> {
             Ok((_, _, header)) => RomResult {
                 filename,
                 mapper: Some(header.mapper),
+                submapper: Some(header.submapper),
                 prg_16kb_units: Some(header.prg_rom_16kb_units),
                 chr_8kb_banks: Some(header.chr_rom_8kb_units),
                 failure: None,
@@ -0,0 +1,83 @@
+use log::{error, info};
+use rust_nes::Nes;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How often battery RAM is checked for changes and written out, so that a
+/// crash or kill doesn't lose more than a few seconds of progress
+const FLUSH_INTERVAL_FRAMES: u32 = 300;
+
+/// Keeps a .sav file next to the rom in sync with the cartridges battery
+/// backed PRG RAM
+pub(crate) struct BatterySave {
+    path: PathBuf,
+    last_saved: Vec<u8>,
+    frames_since_flush: u32,
+}
+
+impl BatterySave {
+    /// Load any existing .sav file into the console, returns None if the
+    /// cartridge has no battery backed RAM
+    pub(crate) fn load(rom_file: &str, nes: &mut Nes) -> Option<Self> {
+        let path = Path::new(rom_file).with_extension("sav");
+        let initial = nes.battery_ram()?.to_vec();
+
+        let last_saved = match fs::read(&path) {
+            Ok(data) => match nes.load_battery_ram(&data) {
+                Ok(()) => {
+                    info!("Loaded battery RAM from {:?}", path);
+                    data
+                }
+                Err(why) => {
+                    error!("Ignoring {:?}: {}", path, why.message);
+                    initial
+                }
+            },
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => initial,
+            Err(why) => {
+                error!("Failed to read {:?}: {}", path, why);
+                initial
+            }
+        };
+
+        Some(BatterySave {
+            path,
+            last_saved,
+            frames_since_flush: 0,
+        })
+    }
+
+    /// Called once per frame, periodically writes the RAM out if it changed
+    pub(crate) fn frame_complete(&mut self, nes: &Nes) {
+        self.frames_since_flush += 1;
+        if self.frames_since_flush >= FLUSH_INTERVAL_FRAMES {
+            self.flush(nes);
+        }
+    }
+
+    /// Write the RAM to disk if it has changed since it was last written
+    pub(crate) fn flush(&mut self, nes: &Nes) {
+        self.frames_since_flush = 0;
+        let ram = match nes.battery_ram() {
+            Some(ram) if ram != &self.last_saved[..] => ram,
+            _ => return,
+        };
+
+        match self.write(ram) {
+            Ok(()) => {
+                info!("Saved battery RAM to {:?}", self.path);
+                self.last_saved = ram.to_vec();
+            }
+            Err(why) => error!("Failed to write {:?}: {}", self.path, why),
+        }
+    }
+
+    /// Write to a temporary file and rename it over the real one so that an
+    /// interrupted write never leaves a truncated .sav behind
+    fn write(&self, ram: &[u8]) -> io::Result<()> {
+        let temp_path = self.path.with_extension("sav.tmp");
+        fs::write(&temp_path, ram)?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
@@ -1,3 +1,4 @@
+mod battery;
 mod sdl2_app;
 
 extern crate clap;
@@ -9,6 +10,17 @@ extern crate sdl2;
 
 use clap::Clap;
 use log::info;
+use rust_nes::cpu::RamPattern;
+use rust_nes::debugger::Breakpoint;
+use rust_nes::gdb::GdbStub;
+use rust_nes::ppu::ntsc::{NtscFilter, NtscFilterSettings};
+use rust_nes::ppu::palette::{BuiltinPalette, NtscParameters, Palette};
+use rust_nes::region::Region;
+use rust_nes::trace::{TraceFormat, TraceWriter, Tracer};
+use std::fs::File;
+use std::io::BufWriter;
+use std::num::ParseIntError;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Clap)]
 #[clap(version = "1.0", author = "David Tyler <davet.code@gmail.com>")]
@@ -20,6 +32,57 @@ struct Opts {
     screen_width: u32,
     #[clap(short = 'h', long = "height", default_value = "240")]
     screen_height: u32,
+    /// Pause when the CPU reaches this (hex) address, may be given more than once
+    #[clap(short = 'b', long = "break", parse(try_from_str = parse_address))]
+    breakpoints: Vec<u16>,
+    /// Write a trace of every instruction executed to this file
+    #[clap(short = 't', long = "trace")]
+    trace_file: Option<String>,
+    #[clap(long = "trace_format", default_value = "nestest", possible_values = &["nestest", "mesen"])]
+    trace_format: String,
+    /// Listen on this port for a debugger speaking the GDB remote protocol
+    #[clap(short = 'g', long = "gdb")]
+    gdb_port: Option<u16>,
+    /// What the console RAM holds at power on
+    #[clap(long = "ram_pattern", default_value = "zeros", possible_values = &["zeros", "ones", "alternating", "random"])]
+    ram_pattern: String,
+    /// The console to emulate, by default the one the rom header asks for
+    #[clap(long = "region", possible_values = &["ntsc", "pal", "dendy"])]
+    region: Option<String>,
+    /// A built in palette (2c02, composite or 2c03) or a .pal file to draw with
+    #[clap(long = "palette", default_value = "2c02")]
+    palette: String,
+    /// Hue rotation in degrees for the composite palette & NTSC filter
+    #[clap(long = "hue", default_value = "0")]
+    hue: f32,
+    /// Colour saturation for the composite palette & NTSC filter
+    #[clap(long = "saturation", default_value = "1")]
+    saturation: f32,
+    /// Contrast for the composite palette & NTSC filter
+    #[clap(long = "contrast", default_value = "1")]
+    contrast: f32,
+    /// Brightness (-1 to 1) for the composite palette & NTSC filter
+    #[clap(long = "brightness", default_value = "0", allow_hyphen_values = true)]
+    brightness: f32,
+    /// Gamma for the composite palette & NTSC filter
+    #[clap(long = "gamma", default_value = "1")]
+    gamma: f32,
+    /// Simulate the composite video signal as seen on a TV, ignores --palette
+    #[clap(long = "ntsc")]
+    ntsc: bool,
+    /// Sharpness (-1 to 1) of the NTSC filter
+    #[clap(long = "sharpness", default_value = "0", allow_hyphen_values = true)]
+    sharpness: f32,
+    /// How much colour fringing (0 to 1) the NTSC filter shows around sharp edges
+    #[clap(long = "fringing", default_value = "1")]
+    fringing: f32,
+    /// How much dot crawl (0 to 1) the NTSC filter shows
+    #[clap(long = "artifacts", default_value = "1")]
+    artifacts: f32,
+}
+
+fn parse_address(address: &str) -> Result<u16, ParseIntError> {
+    u16::from_str_radix(address.trim_start_matches('$').trim_start_matches("0x"), 16)
 }
 
 fn main() -> std::io::Result<()> {
@@ -28,18 +91,88 @@ fn main() -> std::io::Result<()> {
 
     info!("Logging Configured");
 
-    let (prg_address_bus, chr_address_bus, cartridge_header) = match rust_nes::get_cartridge(&opts.rom_file) {
+    let cartridge = match rust_nes::get_cartridge(&opts.rom_file) {
         Err(why) => panic!("Failed to load cartridge: {}", why.message),
         Ok(cartridge) => cartridge,
     };
+    let mut nes = match opts.region.as_deref() {
+        Some("pal") => rust_nes::Nes::with_region(cartridge, Region::Pal),
+        Some("dendy") => rust_nes::Nes::with_region(cartridge, Region::Dendy),
+        Some(_) => rust_nes::Nes::with_region(cartridge, Region::Ntsc),
+        None => rust_nes::Nes::new(cartridge),
+    };
+
+    let ram_pattern = match opts.ram_pattern.as_str() {
+        "ones" => RamPattern::Ones,
+        "alternating" => RamPattern::Alternating,
+        "random" => RamPattern::Random(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64),
+        _ => RamPattern::Zeros,
+    };
+    if ram_pattern != RamPattern::Zeros {
+        nes.set_ram_pattern(ram_pattern);
+        nes.power_cycle();
+    }
+
+    let picture = NtscParameters {
+        hue: opts.hue,
+        saturation: opts.saturation,
+        contrast: opts.contrast,
+        brightness: opts.brightness,
+        gamma: opts.gamma,
+    };
+    let palette = match opts.palette.as_str() {
+        "2c02" => Palette::builtin(BuiltinPalette::Ntsc2C02),
+        "2c03" => Palette::builtin(BuiltinPalette::Rgb2C03),
+        "composite" => Palette::generate_ntsc(&picture),
+        file => match Palette::from_file(file) {
+            Err(why) => panic!("Failed to load palette: {}", why.message),
+            Ok(palette) => palette,
+        },
+    };
+    nes.set_palette(palette);
+
+    let ntsc_filter = if opts.ntsc {
+        nes.set_indexed_output(true);
+        Some(NtscFilter::new(&NtscFilterSettings {
+            picture,
+            sharpness: opts.sharpness,
+            fringing: opts.fringing,
+            artifacts: opts.artifacts,
+        }))
+    } else {
+        None
+    };
+
+    for address in opts.breakpoints {
+        nes.debugger_mut().add_breakpoint(Breakpoint::Execute(address));
+    }
+
+    if let Some(trace_file) = opts.trace_file {
+        let format = match opts.trace_format.as_str() {
+            "mesen" => TraceFormat::Mesen,
+            _ => TraceFormat::Nestest,
+        };
+        let writer = TraceWriter::new(BufWriter::new(File::create(trace_file)?), format);
+        nes.set_tracer(Tracer::new(writer));
+    }
+
+    let gdb = match opts.gdb_port {
+        Some(port) => {
+            let gdb = GdbStub::listen(("127.0.0.1", port))?;
+            println!("Waiting for GDB on {}", gdb.local_addr()?);
+            Some(gdb)
+        }
+        None => None,
+    };
 
-    info!("Running cartridge {:?}", cartridge_header);
+    info!("Running cartridge {:?}", nes.header());
     sdl2_app::run(
         opts.screen_width,
         opts.screen_height,
-        prg_address_bus,
-        chr_address_bus,
-        cartridge_header,
+        &opts.rom_file,
+        nes,
+        gdb,
+        ntsc_filter,
     )?;
 
     Ok(())
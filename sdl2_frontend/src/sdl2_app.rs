@@ -1,11 +1,14 @@
+use battery::BatterySave;
 use crc32fast::Hasher;
 use log::info;
-use rust_nes::apu::Apu;
-use rust_nes::cartridge::{CartridgeHeader, CpuCartridgeAddressBus, PpuCartridgeAddressBus};
-use rust_nes::cpu::Cpu;
-use rust_nes::io::Io;
+use rust_nes::apu::DEFAULT_SAMPLE_RATE;
+use rust_nes::debugger::{Step, StopReason};
+use rust_nes::disassembler::Disassembler;
+use rust_nes::gdb::GdbStub;
 use rust_nes::io::{Button, Controller};
-use rust_nes::ppu::Ppu;
+use rust_nes::ppu::ntsc::{NtscFilter, NTSC_OUTPUT_WIDTH};
+use rust_nes::Nes;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
@@ -13,21 +16,49 @@ use std::fs::File;
 use std::io::Write;
 use std::{thread, time};
 
+/// Print the state of the console after the debugger has stopped
+fn print_cpu_state(nes: &Nes, reason: &StopReason) {
+    let registers = nes.cpu_registers();
+    let (scanline, dot) = nes.ppu_position();
+    let pc = registers.program_counter;
+    let instruction = &Disassembler::with_hardware_labels().disassemble_cpu(nes, pc, 1)[0];
+    println!(
+        "{:?} {:04X}  {:<16} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        reason,
+        pc,
+        instruction.to_string(),
+        registers.a,
+        registers.x,
+        registers.y,
+        registers.status,
+        registers.stack_pointer,
+        scanline,
+        dot,
+        nes.cpu_cycles()
+    );
+}
+
 pub(crate) fn run(
     screen_width: u32,
     screen_height: u32,
-    prg_address_bus: Box<dyn CpuCartridgeAddressBus>,
-    chr_address_bus: Box<dyn PpuCartridgeAddressBus>,
-    cartridge_header: CartridgeHeader,
+    rom_file: &str,
+    mut nes: Nes,
+    mut gdb: Option<GdbStub>,
+    ntsc_filter: Option<NtscFilter>,
 ) -> std::io::Result<()> {
+    let mut battery_save = BatterySave::load(rom_file, &mut nes);
+
+    // The NTSC filter outputs a wider image which already has the TV's aspect ratio
+    let (texture_width, window_width) = match ntsc_filter {
+        Some(_) => (NTSC_OUTPUT_WIDTH, NTSC_OUTPUT_WIDTH),
+        None => (screen_width, screen_width * 2),
+    };
+    let mut ntsc_frame_buffer = vec![0; (texture_width * screen_height * 4) as usize];
+
     let sdl = sdl2::init().unwrap();
     let video_subsystem = sdl.video().unwrap();
     let window = video_subsystem
-        .window(
-            &format!("NES - {:}", cartridge_header),
-            screen_width * 2,
-            screen_height * 2,
-        )
+        .window(&format!("NES - {:}", nes.header()), window_width, screen_height * 2)
         .build()
         .unwrap();
 
@@ -35,107 +66,173 @@ pub(crate) fn run(
     let texture_creator = canvas.texture_creator();
 
     let mut texture = texture_creator
-        .create_texture_streaming(PixelFormatEnum::ARGB8888, screen_width, screen_height)
+        .create_texture_streaming(PixelFormatEnum::ARGB8888, texture_width, screen_height)
         .map_err(|e| e.to_string())
         .unwrap();
 
+    let audio_subsystem = sdl.audio().unwrap();
+    let desired_spec = AudioSpecDesired {
+        freq: Some(DEFAULT_SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+    let audio_queue: AudioQueue<f32> = audio_subsystem.open_queue(None, &desired_spec).unwrap();
+    nes.set_audio_sample_rate(audio_queue.spec().freq as u32);
+    audio_queue.resume();
+    let mut audio_samples = Vec::new();
+
+    // Drop audio rather than let latency build up if emulation runs ahead of
+    // the audio device (e.g. because the sleep below overshoots)
+    let max_queued_bytes = audio_queue.spec().freq as u32 / 10 * std::mem::size_of::<f32>() as u32;
+
     let mut event_pump = sdl.event_pump().unwrap();
 
-    let mut apu = Apu::new();
-    let mut io = Io::new();
-    let mut ppu = Ppu::new(chr_address_bus);
-    let mut cpu = Cpu::new(prg_address_bus, &mut apu, &mut io, &mut ppu);
     let mut time_of_last_render = time::Instant::now();
-    let frame_duration = time::Duration::from_millis(17);
+    let frame_duration = time::Duration::from_secs_f64(1.0 / nes.region().frame_rate());
+
+    // Set when a breakpoint is hit, the debug keys then step the console
+    let mut paused = false;
 
     'main: loop {
-        cpu.next();
-
-        // Optionally re-render & poll for events this frame
-        if cpu.is_frame_complete_cycle() {
-            info!("Frame complete, polling for events and rendering");
-
-            let framebuffer = cpu.get_framebuffer();
-            texture.update(None, framebuffer, screen_width as usize * 4).unwrap();
-            canvas.clear();
-            canvas.copy(&texture, None, None).unwrap();
-            canvas.present();
-
-            for event in event_pump.poll_iter() {
-                info!("{:?}", event);
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => {
-                        info!("Quitting emulation");
-                        break 'main;
+        if !paused {
+            if let Some(gdb) = gdb.as_mut() {
+                // Once attached the debugger decides when the console runs
+                gdb.run_frame(&mut nes)?;
+            } else if nes.debugger().breakpoints().next().is_some() {
+                // Checking breakpoints slows emulation down so only do it when there are some
+                let reason = nes.debug_step(Step::Frame);
+                if reason != StopReason::StepComplete {
+                    print_cpu_state(&nes, &reason);
+                    paused = true;
+                }
+            } else {
+                nes.run_frame();
+            }
+            if let Some(battery_save) = battery_save.as_mut() {
+                battery_save.frame_complete(&nes);
+            }
+
+            audio_samples.clear();
+            nes.drain_audio_samples_f32(&mut audio_samples);
+            if audio_queue.size() < max_queued_bytes {
+                audio_queue.queue(&audio_samples);
+            }
+        }
+
+        // Render & poll for events once per frame
+        info!("Frame complete, polling for events and rendering");
+
+        match (ntsc_filter.as_ref(), nes.indexed_frame_buffer()) {
+            (Some(ntsc_filter), Some(indexed_frame_buffer)) => {
+                ntsc_filter.filter(indexed_frame_buffer, nes.frame_phase(), &mut ntsc_frame_buffer);
+                texture
+                    .update(None, &ntsc_frame_buffer, texture_width as usize * 4)
+                    .unwrap();
+            }
+            _ => {
+                let framebuffer = nes.frame_buffer();
+                texture.update(None, framebuffer, screen_width as usize * 4).unwrap();
+            }
+        }
+        canvas.clear();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+
+        for event in event_pump.poll_iter() {
+            info!("{:?}", event);
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    info!("Quitting emulation");
+                    break 'main;
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode), ..
+                } => match keycode {
+                    Keycode::Z => nes.button_down(Controller::One, Button::A),
+                    Keycode::X => nes.button_down(Controller::One, Button::B),
+                    Keycode::Return => nes.button_down(Controller::One, Button::Start),
+                    Keycode::Tab => nes.button_down(Controller::One, Button::Select),
+                    Keycode::Left => nes.button_down(Controller::One, Button::Left),
+                    Keycode::Right => nes.button_down(Controller::One, Button::Right),
+                    Keycode::Up => nes.button_down(Controller::One, Button::Up),
+                    Keycode::Down => nes.button_down(Controller::One, Button::Down),
+                    Keycode::T => {
+                        let framebuffer = nes.frame_buffer();
+                        let cycles = nes.cpu_cycles();
+                        let mut hasher = Hasher::new();
+                        hasher.update(framebuffer);
+                        let checksum = hasher.finalize();
+
+                        println!("Cycles: {:X}, FrameBuffer CRC32, {:}", cycles, checksum);
                     }
-                    Event::KeyDown {
-                        keycode: Some(keycode), ..
-                    } => match keycode {
-                        Keycode::Z => cpu.button_down(Controller::One, Button::A),
-                        Keycode::X => cpu.button_down(Controller::One, Button::B),
-                        Keycode::Return => cpu.button_down(Controller::One, Button::Start),
-                        Keycode::Tab => cpu.button_down(Controller::One, Button::Select),
-                        Keycode::Left => cpu.button_down(Controller::One, Button::Left),
-                        Keycode::Right => cpu.button_down(Controller::One, Button::Right),
-                        Keycode::Up => cpu.button_down(Controller::One, Button::Up),
-                        Keycode::Down => cpu.button_down(Controller::One, Button::Down),
-                        Keycode::T => {
-                            let framebuffer = cpu.get_framebuffer();
-                            let cycles = cpu.cycles;
-                            let mut hasher = Hasher::new();
-                            hasher.update(framebuffer);
-                            let checksum = hasher.finalize();
-
-                            println!("Cycles: {:X}, FrameBuffer CRC32, {:}", cycles, checksum);
+                    Keycode::R => nes.reset(),
+                    Keycode::C => nes.power_cycle(),
+                    Keycode::P => {
+                        paused = !paused;
+                        println!("{}", if paused { "Paused" } else { "Resumed" });
+                    }
+                    Keycode::N | Keycode::O | Keycode::U | Keycode::L | Keycode::F if paused => {
+                        let step = match keycode {
+                            Keycode::N => Step::Instruction,
+                            Keycode::O => Step::Over,
+                            Keycode::U => Step::Out,
+                            Keycode::L => Step::Scanline,
+                            _ => Step::Frame,
+                        };
+                        let reason = nes.debug_step(step);
+                        print_cpu_state(&nes, &reason);
+                    }
+                    Keycode::D => {
+                        // Dump contents of PPU
+                        let mut vram = [0; 0x4000];
+                        let oam_ram = nes.dump_ppu_state(&mut vram);
+                        let mut vram_file = File::create("vram.csv").unwrap();
+                        let mut oam_ram_file = File::create("oam_ram.csv").unwrap();
+
+                        for b in vram.iter() {
+                            writeln!(vram_file, "{:02X}", b)?;
                         }
-                        Keycode::D => {
-                            // Dump contents of PPU
-                            let mut vram = [0; 0x4000];
-                            let oam_ram = cpu.dump_ppu_state(&mut vram);
-                            let mut vram_file = File::create("vram.csv").unwrap();
-                            let mut oam_ram_file = File::create("oam_ram.csv").unwrap();
-
-                            for b in vram.iter() {
-                                writeln!(vram_file, "{:02X}", b)?;
-                            }
-
-                            for b in oam_ram.iter() {
-                                writeln!(oam_ram_file, "{:02X}", b)?;
-                            }
+
+                        for b in oam_ram.iter() {
+                            writeln!(oam_ram_file, "{:02X}", b)?;
                         }
-                        _ => (),
-                    },
-                    Event::KeyUp {
-                        keycode: Some(keycode), ..
-                    } => match keycode {
-                        Keycode::Z => cpu.button_up(Controller::One, Button::A),
-                        Keycode::X => cpu.button_up(Controller::One, Button::B),
-                        Keycode::Return => cpu.button_up(Controller::One, Button::Start),
-                        Keycode::Tab => cpu.button_up(Controller::One, Button::Select),
-                        Keycode::Left => cpu.button_up(Controller::One, Button::Left),
-                        Keycode::Right => cpu.button_up(Controller::One, Button::Right),
-                        Keycode::Up => cpu.button_up(Controller::One, Button::Up),
-                        Keycode::Down => cpu.button_up(Controller::One, Button::Down),
-                        _ => (),
-                    },
+                    }
                     _ => (),
-                };
-            }
+                },
+                Event::KeyUp {
+                    keycode: Some(keycode), ..
+                } => match keycode {
+                    Keycode::Z => nes.button_up(Controller::One, Button::A),
+                    Keycode::X => nes.button_up(Controller::One, Button::B),
+                    Keycode::Return => nes.button_up(Controller::One, Button::Start),
+                    Keycode::Tab => nes.button_up(Controller::One, Button::Select),
+                    Keycode::Left => nes.button_up(Controller::One, Button::Left),
+                    Keycode::Right => nes.button_up(Controller::One, Button::Right),
+                    Keycode::Up => nes.button_up(Controller::One, Button::Up),
+                    Keycode::Down => nes.button_up(Controller::One, Button::Down),
+                    _ => (),
+                },
+                _ => (),
+            };
+        }
 
-            // Wait so that we render at 60fps
-            let current_time = time::Instant::now();
-            let diff = current_time - time_of_last_render;
-            time_of_last_render = current_time;
-            if diff < frame_duration {
-                info!("Sleeping {:?}", frame_duration - diff);
-                thread::sleep(frame_duration - diff);
-            }
+        // Wait so that we render at the console's frame rate (~60fps NTSC, 50fps PAL)
+        let current_time = time::Instant::now();
+        let diff = current_time - time_of_last_render;
+        time_of_last_render = current_time;
+        if diff < frame_duration {
+            info!("Sleeping {:?}", frame_duration - diff);
+            thread::sleep(frame_duration - diff);
         }
     }
 
+    if let Some(battery_save) = battery_save.as_mut() {
+        battery_save.flush(&nes);
+    }
+
     Ok(())
 }